@@ -0,0 +1,177 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module wrapps [`sodiumoxide::crypto::sign::ed25519`] stuff,
+//! which is used for signing blocks and operations with tz1 accounts.
+//!
+//! Terminology:
+//!
+//! Seed - [`SEED_SIZE`]-bytes, b58check encoded as `edsk...` (54 chars)
+//! PublicKey - [`PUBLIC_KEY_SIZE`]-bytes, b58check encoded as `edpk...`
+//! Signature - [`SIGNATURE_SIZE`]-bytes, b58check encoded as `edsig...`
+
+use sodiumoxide::crypto::sign::ed25519;
+
+use crate::base58::FromBase58Check;
+use crate::blake2b;
+use crate::hash::{ContractTz1Hash, HashType, PublicKeyEd25519, SignatureEd25519};
+use crate::CryptoError;
+
+pub const SEED_SIZE: usize = ed25519::SEEDBYTES;
+pub const PUBLIC_KEY_SIZE: usize = ed25519::PUBLICKEYBYTES;
+pub const SIGNATURE_SIZE: usize = ed25519::SIGNATUREBYTES;
+
+/// Prefix of unencrypted secret keys used by tezos-client
+const UNENCRYPTED_PREFIX: &str = "unencrypted:";
+
+/// Ed25519 key pair created from the secret seed
+pub struct SigningKey {
+    public_key: ed25519::PublicKey,
+    secret_key: ed25519::SecretKey,
+}
+
+impl SigningKey {
+    pub fn from_seed(seed: &[u8]) -> Result<Self, CryptoError> {
+        let seed = ed25519::Seed::from_slice(seed).ok_or(CryptoError::InvalidKeySize {
+            expected: SEED_SIZE,
+            actual: seed.len(),
+        })?;
+        let (public_key, secret_key) = ed25519::keypair_from_seed(&seed);
+        Ok(Self {
+            public_key,
+            secret_key,
+        })
+    }
+
+    /// Creates key from the b58check encoded seed (`edsk...` or `unencrypted:edsk...`)
+    pub fn from_b58check(secret_key: &str) -> Result<Self, CryptoError> {
+        let secret_key = secret_key
+            .strip_prefix(UNENCRYPTED_PREFIX)
+            .unwrap_or(secret_key);
+        let decoded = secret_key
+            .from_base58check()
+            .map_err(|e| CryptoError::InvalidKey {
+                reason: format!("{:?}", e),
+            })?;
+
+        let prefix = HashType::SeedEd25519.base58check_prefix();
+        if !decoded.starts_with(prefix) {
+            return Err(CryptoError::InvalidKey {
+                reason: "expected ed25519 seed (edsk...)".to_string(),
+            });
+        }
+        Self::from_seed(&decoded[prefix.len()..])
+    }
+
+    pub fn public_key(&self) -> PublicKeyEd25519 {
+        self.public_key.as_ref().to_vec()
+    }
+
+    /// Generates public key hash (tz1) for public key
+    pub fn public_key_hash(&self) -> ContractTz1Hash {
+        blake2b::digest_160(self.public_key.as_ref())
+    }
+
+    /// Signs blake2b digest of `watermark` + `data`, the same way as tezos-client does
+    pub fn sign(&self, watermark: &[u8], data: &[u8]) -> SignatureEd25519 {
+        let mut message = Vec::with_capacity(watermark.len() + data.len());
+        message.extend_from_slice(watermark);
+        message.extend_from_slice(data);
+
+        let digest = blake2b::digest_256(&message);
+        ed25519::sign_detached(&digest, &self.secret_key)
+            .as_ref()
+            .to_vec()
+    }
+}
+
+/// Verifies signature created by [`SigningKey::sign`]
+pub fn verify(
+    public_key: &[u8],
+    signature: &[u8],
+    watermark: &[u8],
+    data: &[u8],
+) -> Result<bool, CryptoError> {
+    let public_key =
+        ed25519::PublicKey::from_slice(public_key).ok_or(CryptoError::InvalidKeySize {
+            expected: PUBLIC_KEY_SIZE,
+            actual: public_key.len(),
+        })?;
+    let signature =
+        ed25519::Signature::from_slice(signature).ok_or(CryptoError::InvalidKeySize {
+            expected: SIGNATURE_SIZE,
+            actual: signature.len(),
+        })?;
+
+    let mut message = Vec::with_capacity(watermark.len() + data.len());
+    message.extend_from_slice(watermark);
+    message.extend_from_slice(data);
+
+    Ok(ed25519::verify_detached(
+        &signature,
+        &blake2b::digest_256(&message),
+        &public_key,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing_key_from_b58check() -> Result<(), failure::Error> {
+        // sandbox bootstrap1 account
+        let key = SigningKey::from_b58check(
+            "unencrypted:edsk3gUfUPyBSfrS9CCgmCiQsTCHGkviBDusMxDJstFtojtc1zcpsh",
+        )?;
+
+        assert_eq!(
+            "edpkuBknW28nW72KG6RoHtYW7p12T6GKc7nAbwYX5m8Wd9sDVC9yav",
+            HashType::PublicKeyEd25519.hash_to_b58check(&key.public_key())
+        );
+        assert_eq!(
+            "tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx",
+            HashType::ContractTz1Hash.hash_to_b58check(&key.public_key_hash())
+        );
+
+        // public key is not a seed
+        assert!(SigningKey::from_b58check(
+            "edpkuBknW28nW72KG6RoHtYW7p12T6GKc7nAbwYX5m8Wd9sDVC9yav"
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_and_verify() -> Result<(), failure::Error> {
+        let key =
+            SigningKey::from_b58check("edsk3gUfUPyBSfrS9CCgmCiQsTCHGkviBDusMxDJstFtojtc1zcpsh")?;
+        let watermark = [1, 0x8e, 0xce, 0xda, 0x2f];
+
+        let signature = key.sign(&watermark, b"block header");
+        assert_eq!(SIGNATURE_SIZE, signature.len());
+        assert!(verify(
+            &key.public_key(),
+            &signature,
+            &watermark,
+            b"block header"
+        )?);
+        assert!(!verify(
+            &key.public_key(),
+            &signature,
+            &[2],
+            b"block header"
+        )?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_zero_signature() {
+        assert_eq!(
+            "edsigtXomBKi5CTRf5cjATJWSyaRvhfYNHqSUGrn4SdbYRcGwQrUGjzEfQDTuqHhuA8b2d8NarZjz8TRf65WkpQmo423BtomS8Q",
+            HashType::SignatureEd25519.hash_to_b58check(&[0; SIGNATURE_SIZE])
+        );
+    }
+}
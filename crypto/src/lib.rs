@@ -9,6 +9,7 @@ use failure::Fail;
 pub mod blake2b;
 pub mod base58;
 pub mod crypto_box;
+pub mod ed25519;
 pub mod nonce;
 pub mod proof_of_work;
 pub mod seeded_step;
@@ -0,0 +1,247 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+#![forbid(unsafe_code)]
+
+//! Replays context actions stored by the node (`--store-context-actions`) for a range of blocks
+//! against a fresh context storage, verifies hashes of the commits and reports latencies of the actions.
+//! So changes of the context storage can be benchmarked without the protocol runner.
+
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::{App, Arg};
+use failure::format_err;
+use rocksdb::Cache;
+use slog::{error, info, Drain, Level, Logger};
+
+use storage::block_storage::{self, BlockLevel};
+use storage::context::TezedgeContext;
+use storage::context_fsck::ContextFsckError;
+use storage::merkle_storage::{EntryHash, MerkleStorage};
+use storage::persistent::sequence::Sequences;
+use storage::persistent::{
+    open_cl, open_kv_store, CommitLogSchema, DbConfiguration, KeyValueStoreColumn,
+    PersistentStorage,
+};
+use storage::skip_list::{DatabaseBackedSkipList, Lane, ListValue};
+use storage::{
+    context_action_storage, context_fsck, context_history, context_replay,
+    operation_receipts_storage, operations_storage, BlockMetaStorage, BlockStorage,
+    BlockStorageReader, ChainMetaStorage, ContextActionStorage, ContextHistoryIndex,
+    MempoolStorage, OperationReceiptsStorage, OperationsMetaStorage, OperationsStorage,
+    PredecessorStorage, SystemStorage,
+};
+
+fn create_logger(log_level: Level) -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .build()
+    .filter_level(log_level)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+fn open_storage(path: &Path, cache: &Cache) -> Result<PersistentStorage, failure::Error> {
+    let columns = vec![
+        KeyValueStoreColumn::of::<block_storage::BlockPrimaryIndex>(),
+        KeyValueStoreColumn::of::<block_storage::BlockByLevelIndex>(),
+        KeyValueStoreColumn::of::<block_storage::BlockByContextHashIndex>(),
+        KeyValueStoreColumn::of::<BlockMetaStorage>(),
+        KeyValueStoreColumn::of::<OperationsStorage>(),
+        KeyValueStoreColumn::of::<operations_storage::OperationsByHashIndex>(),
+        KeyValueStoreColumn::of::<OperationsMetaStorage>(),
+        KeyValueStoreColumn::of::<OperationReceiptsStorage>(),
+        KeyValueStoreColumn::of::<operation_receipts_storage::OperationReceiptsByAccountIndex>(),
+        KeyValueStoreColumn::of::<context_action_storage::ContextActionByBlockHashIndex>(),
+        KeyValueStoreColumn::of::<context_action_storage::ContextActionByContractIndex>(),
+        KeyValueStoreColumn::of::<context_action_storage::ContextActionByTypeIndex>(),
+        KeyValueStoreColumn::of::<ContextActionStorage>(),
+        KeyValueStoreColumn::of::<MerkleStorage>(),
+        KeyValueStoreColumn::of::<SystemStorage>(),
+        KeyValueStoreColumn::of::<Sequences>(),
+        KeyValueStoreColumn::of::<MempoolStorage>(),
+        KeyValueStoreColumn::of::<ChainMetaStorage>(),
+        KeyValueStoreColumn::of::<PredecessorStorage>(),
+        KeyValueStoreColumn::of::<DatabaseBackedSkipList>(),
+        KeyValueStoreColumn::of::<Lane>(),
+        KeyValueStoreColumn::of::<ListValue>(),
+        KeyValueStoreColumn::of::<ContextHistoryIndex>(),
+        KeyValueStoreColumn::of::<context_history::ContextHistoryByPositionIndex>(),
+    ];
+    let kv = open_kv_store(path, columns, cache, &DbConfiguration::default())?;
+    let commit_logs = open_cl(path, vec![BlockStorage::descriptor()])?;
+
+    Ok(PersistentStorage::new(Arc::new(kv), Arc::new(commit_logs)))
+}
+
+fn replay(
+    db_path: &Path,
+    target_db_path: &Path,
+    from_level: BlockLevel,
+    to_level: Option<BlockLevel>,
+    log: &Logger,
+) -> Result<(), failure::Error> {
+    if target_db_path.exists() {
+        return Err(format_err!(
+            "Target database already exists: {:?}",
+            target_db_path
+        ));
+    }
+
+    // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv_store)
+    let cache = Cache::new_lru_cache(128 * 1024 * 1024)?; // 128 MB
+    let source = open_storage(db_path, &cache)?;
+
+    let chain_id = SystemStorage::new(source.kv())
+        .get_chain_id()?
+        .ok_or_else(|| format_err!("Chain id is not stored in the database: {:?}", db_path))?;
+    let blocks = context_fsck::load_blocks(&chain_id, from_level, to_level, &source)?;
+    let source_block_storage = BlockStorage::new(&source);
+    let first_block = blocks
+        .first_block_hash()
+        .map(|block_hash| source_block_storage.get(block_hash))
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| format_err!("No blocks stored from level: {}", from_level))?;
+    let predecessor = source_block_storage
+        .get(first_block.header.predecessor())?
+        .ok_or_else(|| format_err!("Predecessor of the first block is not stored"))?;
+
+    // replay starts from the context of the predecessor, commits assign contexts to the stored blocks
+    let target = open_storage(target_db_path, &cache)?;
+    let predecessor_context_hash: EntryHash = predecessor.header.context().as_slice().try_into()?;
+    let copied_entries = target
+        .merkle()
+        .read()
+        .expect("lock poisoning")
+        .copy_context_from(
+            &source.merkle().read().expect("lock poisoning"),
+            &predecessor_context_hash,
+        )?;
+    info!(log, "Context of the predecessor copied"; "level" => predecessor.header.level(), "entries" => copied_entries);
+
+    info!(log, "Replaying context actions"; "from_level" => from_level, "blocks" => blocks.len());
+    let target_block_storage = BlockStorage::new(&target);
+    let blocks = blocks.map(|block| {
+        let block = block?;
+        target_block_storage.put_block_header(&block)?;
+        Ok::<_, ContextFsckError>(block)
+    });
+    let mut context = TezedgeContext::new(BlockStorage::new(&target), target.merkle());
+    let report = context_replay::replay_blocks(
+        blocks,
+        &ContextActionStorage::new(&source),
+        &mut context,
+        log,
+    )?;
+
+    for (action_type, latencies) in &report.latencies {
+        info!(log, "Action latencies";
+                   "action" => action_type,
+                   "count" => latencies.count,
+                   "mean_us" => latencies.mean().as_micros() as u64,
+                   "min_us" => latencies.min.as_micros() as u64,
+                   "max_us" => latencies.max.as_micros() as u64,
+                   "total_ms" => latencies.total.as_millis() as u64);
+    }
+    info!(log, "Context actions replayed";
+               "replayed_blocks" => report.replayed_blocks,
+               "replayed_actions" => report.replayed_actions(),
+               "read_mismatches" => report.read_mismatches,
+               "total_ms" => report.total_time().as_millis() as u64);
+
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("Context Replay")
+        .version("1.0")
+        .about("Replays stored context actions against a fresh context storage and reports latencies of the actions")
+        .arg(
+            Arg::with_name("db-path")
+                .long("db-path")
+                .value_name("PATH")
+                .help("Path to the database of the node with stored context actions (--store-context-actions)")
+                .takes_value(true)
+                .empty_values(false)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("target-db-path")
+                .long("target-db-path")
+                .value_name("PATH")
+                .help("Path to the new database, the replayed context is stored to")
+                .takes_value(true)
+                .empty_values(false)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("from-level")
+                .long("from-level")
+                .value_name("LEVEL")
+                .help("Level of the first replayed block")
+                .takes_value(true)
+                .required(true)
+                .validator(|v| {
+                    v.parse::<BlockLevel>()
+                        .map(|_| ())
+                        .map_err(|_| "Value must be a valid number".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("to-level")
+                .long("to-level")
+                .value_name("LEVEL")
+                .help("Level of the last replayed block (current head, if not set)")
+                .takes_value(true)
+                .validator(|v| {
+                    v.parse::<BlockLevel>()
+                        .map(|_| ())
+                        .map_err(|_| "Value must be a valid number".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .takes_value(true)
+                .value_name("LEVEL")
+                .possible_values(&["critical", "error", "warn", "info", "debug", "trace"])
+                .help("Set log level"),
+        )
+        .get_matches();
+
+    let db_path = Path::new(matches.value_of("db-path").expect("Missing db-path value"));
+    let target_db_path = Path::new(
+        matches
+            .value_of("target-db-path")
+            .expect("Missing target-db-path value"),
+    );
+    let from_level = matches
+        .value_of("from-level")
+        .expect("Missing from-level value")
+        .parse::<BlockLevel>()
+        .expect("Provided value cannot be converted to number");
+    let to_level = matches.value_of("to-level").map(|value| {
+        value
+            .parse::<BlockLevel>()
+            .expect("Provided value cannot be converted to number")
+    });
+    let log_level = matches
+        .value_of("log-level")
+        .unwrap_or("info")
+        .parse::<slog::Level>()
+        .expect("Was expecting one value from slog::Level");
+
+    let log = create_logger(log_level);
+    if let Err(e) = replay(db_path, target_db_path, from_level, to_level, &log) {
+        error!(log, "Context replay failed"; "reason" => format!("{}", e));
+        // let async logger flush the record
+        drop(log);
+        std::process::exit(1);
+    }
+}
@@ -6,15 +6,21 @@ use std::env;
 use std::ffi::OsString;
 use std::fs;
 use std::io::{self, BufRead};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use clap::{App, Arg};
 
+use networking::p2p::bandwidth::BandwidthConfig;
+use networking::p2p::recorder::MessageRecorderConfig;
+use networking::p2p::replay::ReplayConfig;
+use rpc::{RpcAcl, RpcAddressRange, RpcLimitsConfiguration, RpcRateLimit, RpcTlsConfiguration};
 use shell::peer_manager::P2p;
 use shell::PeerConnectionThreshold;
-use storage::persistent::{DbConfiguration, DbConfigurationBuilder};
+use storage::context_fsck::ContextFsckConfig;
+use storage::merkle_storage::DEFAULT_TREE_CACHE_CAPACITY;
+use storage::persistent::{DbConfiguration, DbConfigurationBuilder, KeyValueStoreBackendType};
 use tezos_api::environment;
 use tezos_api::environment::TezosEnvironment;
 use tezos_api::ffi::PatchContext;
@@ -23,6 +29,10 @@ use tezos_wrapper::TezosApiConnectionPoolConfiguration;
 #[derive(Debug, Clone)]
 pub struct Rpc {
     pub listener_port: u16,
+    pub listen_addresses: Vec<SocketAddr>,
+    pub acl: RpcAcl,
+    pub tls: Option<RpcTlsConfiguration>,
+    pub limits: RpcLimitsConfiguration,
     pub websocket_address: SocketAddr,
 }
 
@@ -40,7 +50,18 @@ pub struct Storage {
     pub db_path: PathBuf,
     pub tezos_data_dir: PathBuf,
     pub store_context_actions: bool,
+    /// Index receipts (balance updates, statuses) of applied operations
+    pub index_operation_receipts: bool,
+    /// Index context values changed by applied blocks for historical queries
+    pub index_context_history: bool,
     pub patch_context: Option<PatchContext>,
+    pub context_fsck: Option<ContextFsckConfig>,
+    /// Re-encode context entries stored in the legacy encoding on startup
+    pub context_migrate_encoding: bool,
+    /// Rebuild index of stored operations by operation hash on startup
+    pub reindex_operations: bool,
+    /// Memory budget of the cache of decoded merkle trees (bytes)
+    pub context_tree_cache_capacity: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -129,7 +150,7 @@ pub fn tezos_app() -> App<'static, 'static> {
         .arg(Arg::with_name("validate-cfg-identity-and-stop")
             .long("validate-cfg-identity-and-stop")
             .takes_value(false)
-            .help("Validate configuration and generated identity, than just stops application"))
+            .help("Validate configuration and generated identity, then stops without running the node"))
         .arg(Arg::with_name("config-file")
             .long("config-file")
             .takes_value(true)
@@ -168,7 +189,7 @@ pub fn tezos_app() -> App<'static, 'static> {
             .long("identity-expected-pow")
             .takes_value(true)
             .value_name("NUM")
-            .help("Expected power of identity for node. It is used to generate new identity. Default: 26.0")
+            .help("Expected power of identity for node. It is used to generate new identity and to check identities of remote peers. Default: 26.0")
             .validator(parse_validator_fn!(f64, "Value must be a valid f64 number for expected_pow")))
         .arg(Arg::with_name("bootstrap-db-path")
             .long("bootstrap-db-path")
@@ -182,6 +203,18 @@ pub fn tezos_app() -> App<'static, 'static> {
             .value_name("NUM")
             .help("Max number of threads used by database configuration. If not specified, then number of threads equal to CPU cores.")
             .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("db-backend")
+            .long("db-backend")
+            .takes_value(true)
+            .value_name("BACKEND")
+            .possible_values(&["rocksdb", "sled", "inmem"])
+            .help("Key-value database engine used for storage. Default: rocksdb. sled requires the node built with the cargo feature sled-backend. In-memory database (inmem) is not persisted and is intended for testing only"))
+        .arg(Arg::with_name("context-tree-cache-mb")
+            .long("context-tree-cache-mb")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Memory budget (MB) of the cache of decoded context (merkle) trees shared by block application and RPC, 0 disables the cache. Default: 256")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
         .arg(Arg::with_name("bootstrap-lookup-address")
             .long("bootstrap-lookup-address")
             .takes_value(true)
@@ -218,6 +251,74 @@ pub fn tezos_app() -> App<'static, 'static> {
         .arg(Arg::with_name("disable-mempool")
             .long("disable-mempool")
             .help("Enable or disable mempool"))
+        .arg(Arg::with_name("p2p-max-upload-bandwidth")
+            .long("p2p-max-upload-bandwidth")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Max upload bandwidth of all peer connections together, in bytes per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-download-bandwidth")
+            .long("p2p-max-download-bandwidth")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Max download bandwidth of all peer connections together, in bytes per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-peer-max-upload-bandwidth")
+            .long("p2p-peer-max-upload-bandwidth")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Max upload bandwidth of one peer connection, in bytes per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-peer-max-download-bandwidth")
+            .long("p2p-peer-max-download-bandwidth")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Max download bandwidth of one peer connection, in bytes per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-message-size")
+            .long("p2p-max-message-size")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Max size of one incoming p2p message, peer sending bigger message is disconnected. Default: unlimited")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-peer-max-queued-messages")
+            .long("p2p-peer-max-queued-messages")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max count of outgoing messages waiting to be sent to one peer, newer messages are dropped. Default: unlimited")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-record-dir")
+            .long("p2p-record-dir")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Records all (decrypted) p2p messages exchanged with peers to rotating files in this directory"))
+        .arg(Arg::with_name("p2p-record-max-file-size")
+            .long("p2p-record-max-file-size")
+            .takes_value(true)
+            .value_name("BYTES")
+            .requires("p2p-record-dir")
+            .help("Max size of one p2p recording file, then new file is started. Default: 104857600 (100 MiB)")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-record-max-files")
+            .long("p2p-record-max-files")
+            .takes_value(true)
+            .value_name("NUM")
+            .requires("p2p-record-dir")
+            .help("Max count of kept p2p recording files, the oldest files are removed. Default: 10")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-replay")
+            .long("p2p-replay")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with("p2p-record-dir")
+            .help("Replays p2p messages recorded by --p2p-record-dir (file or directory) into the node instead of connecting to network"))
+        .arg(Arg::with_name("p2p-replay-speed")
+            .long("p2p-replay-speed")
+            .takes_value(true)
+            .value_name("NUM")
+            .requires("p2p-replay")
+            .help("Replay speed multiplier against the recorded time, 0 replays without delays. Default: 0")
+            .validator(parse_validator_fn!(f64, "Value must be a valid number")))
         .arg(Arg::with_name("private-node")
             .long("private-node")
             .takes_value(true)
@@ -242,6 +343,80 @@ pub fn tezos_app() -> App<'static, 'static> {
             .value_name("PORT")
             .help("Rust server RPC port for communication with rust node")
             .validator(parse_validator_fn!(u16, "Value must be a valid port number")))
+        .arg(Arg::with_name("rpc-listen-address")
+            .long("rpc-listen-address")
+            .takes_value(true)
+            .value_name("IP[:PORT]")
+            .help("Addresses where RPC server listens, delimited by a comma. Port defaults to --rpc-port. Format: IP1,IP2:PORT2,[IPV6]:PORT3. Default: 0.0.0.0")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(|address| parse_rpc_listen_address(address, 0))
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: IP1,IP2:PORT2,[IPV6]:PORT3", v))
+                }
+            }))
+        .arg(Arg::with_name("rpc-allow-all")
+            .long("rpc-allow-all")
+            .takes_value(true)
+            .value_name("IP[/PREFIX_LEN][:PORT]")
+            .help("Allow all RPC endpoints on matching listen addresses, delimited by a comma. Without this option (or explicit --rpc-acl-file entry) only loopback listen addresses allow all endpoints, other addresses deny injection, /dev, /stats, /workers and /network endpoints")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(|address| address.parse::<RpcAddressRange>())
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: IP1,IP2/PREFIX_LEN,IP3:PORT3", v))
+                }
+            }))
+        .arg(Arg::with_name("rpc-acl-file")
+            .long("rpc-acl-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Path to RPC access control list json file. Format: [{\"address\": \"IP[/PREFIX_LEN][:PORT]\", \"whitelist\"|\"blacklist\": [\"[METHOD] /path/*/pattern/**\"]}]"))
+        .arg(Arg::with_name("rpc-tls-cert")
+            .long("rpc-tls-cert")
+            .takes_value(true)
+            .value_name("PATH")
+            .requires("rpc-tls-key")
+            .help("Path to PEM encoded certificate chain, if set, RPC is served over HTTPS"))
+        .arg(Arg::with_name("rpc-tls-key")
+            .long("rpc-tls-key")
+            .takes_value(true)
+            .value_name("PATH")
+            .requires("rpc-tls-cert")
+            .help("Path to PEM encoded private key (PKCS8 or RSA) for --rpc-tls-cert"))
+        .arg(Arg::with_name("rpc-rate-limit")
+            .long("rpc-rate-limit")
+            .takes_value(true)
+            .value_name("TOKENS_PER_SEC")
+            .help("Per client (IP address) RPC rate limit. Every request costs tokens according to the route (1 for the most of routes, up to 20 for expensive ones). Default: no rate limit")
+            .validator(parse_validator_fn!(u32, "Value must be a valid number")))
+        .arg(Arg::with_name("rpc-rate-limit-burst")
+            .long("rpc-rate-limit-burst")
+            .takes_value(true)
+            .value_name("TOKENS")
+            .requires("rpc-rate-limit")
+            .help("Max tokens per client (size of the token bucket) for --rpc-rate-limit. Default: 10 x --rpc-rate-limit")
+            .validator(parse_validator_fn!(u32, "Value must be a valid number")))
+        .arg(Arg::with_name("rpc-max-concurrent-expensive")
+            .long("rpc-max-concurrent-expensive")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max count of concurrently processed expensive RPC requests (rights, raw context, actions), other requests wait. Default: 8")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("rpc-cache-max-entries")
+            .long("rpc-cache-max-entries")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max count of cached immutable RPC responses about blocks older than head, 0 disables cache. Default: 10000")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
         .arg(Arg::with_name("enable-testchain")
             .long("enable-testchain")
             .takes_value(true)
@@ -391,6 +566,40 @@ pub fn tezos_app() -> App<'static, 'static> {
             .takes_value(true)
             .value_name("BOOL")
             .help("Activate recording of context storage actions"))
+        .arg(Arg::with_name("index-operation-receipts")
+            .long("index-operation-receipts")
+            .takes_value(false)
+            .help("Index receipts of applied operations (balance updates, statuses, originated contracts) by account and operation hash"))
+        .arg(Arg::with_name("index-context-history")
+            .long("index-context-history")
+            .takes_value(false)
+            .help("Index context values changed by applied blocks, so historical context values are read without traversing the merkle tree. Blocks applied before are indexed on startup"))
+        .arg(Arg::with_name("context-fsck-from-level")
+            .long("context-fsck-from-level")
+            .takes_value(true)
+            .value_name("LEVEL")
+            .help("Check integrity of the context storage for blocks from the level (up to --context-fsck-to-level or current head), than just stops application")
+            .validator(parse_validator_fn!(i32, "Value must be a valid number")))
+        .arg(Arg::with_name("context-fsck-to-level")
+            .long("context-fsck-to-level")
+            .takes_value(true)
+            .value_name("LEVEL")
+            .requires("context-fsck-from-level")
+            .help("Last level checked by context integrity check. Default: current head")
+            .validator(parse_validator_fn!(i32, "Value must be a valid number")))
+        .arg(Arg::with_name("context-fsck-repair")
+            .long("context-fsck-repair")
+            .takes_value(false)
+            .requires("context-fsck-from-level")
+            .help("Re-apply blocks with broken context from stored context actions. Requires context actions stored by the node (--store-context-actions) for all broken blocks, nothing is re-applied otherwise"))
+        .arg(Arg::with_name("context-migrate-encoding")
+            .long("context-migrate-encoding")
+            .takes_value(false)
+            .help("Re-encode context entries stored by older versions to the compact encoding on startup"))
+        .arg(Arg::with_name("reindex-operations")
+            .long("reindex-operations")
+            .takes_value(false)
+            .help("Index operations stored by older versions by operation hash on startup, the index is built just once"))
         .arg(Arg::with_name("sandbox-patch-context-json-file")
             .long("sandbox-patch-context-json-file")
             .takes_value(true)
@@ -471,6 +680,18 @@ fn validate_required_args(args: &clap::ArgMatches) {
     // "bootstrap-lookup-address", "log-file" and "peers" are not required
 }
 
+// Context can be repaired only from stored context actions
+fn validate_context_fsck_args(args: &clap::ArgMatches) {
+    let store_context_actions = args
+        .value_of("store-context-actions")
+        .unwrap_or("true")
+        .parse::<bool>()
+        .expect("Provided value cannot be converted to bool");
+    if args.is_present("context-fsck-repair") && !store_context_actions {
+        panic!("\"context-fsck-repair\" arg requires \"store-context-actions\" to be enabled !!!");
+    }
+}
+
 // Validates single required arg. If missing, exit whole process
 pub fn validate_required_arg(args: &clap::ArgMatches, arg_name: &str) {
     if !args.is_present(arg_name) {
@@ -506,6 +727,18 @@ pub fn get_final_path(tezos_data_dir: &Path, path: PathBuf) -> PathBuf {
     final_path
 }
 
+// Parses RPC listen address in format IP[:PORT], default_port is used, when PORT is missing
+fn parse_rpc_listen_address(address: &str, default_port: u16) -> Result<SocketAddr, String> {
+    address
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            address
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, default_port))
+        })
+        .map_err(|_| format!("Invalid RPC listen address: {}", address))
+}
+
 // Parses config file and returns vector of OsString representing all argument strings from file
 // All lines that are empty or begin with "#" or "//" are ignored
 pub fn parse_config(config_path: PathBuf) -> Vec<OsString> {
@@ -568,6 +801,7 @@ impl Environment {
 
         // Validates required flags of args
         validate_required_args(&args);
+        validate_context_fsck_args(&args);
 
         let tezos_network: TezosEnvironment = args
             .value_of("network")
@@ -647,18 +881,159 @@ impl Environment {
                     .parse::<bool>()
                     .expect("Provided value cannot be converted to bool"),
                 disable_mempool: args.is_present("disable-mempool"),
+                expected_pow: args
+                    .value_of("identity-expected-pow")
+                    .unwrap_or("26.0")
+                    .parse::<f64>()
+                    .expect("Provided value cannot be converted to number"),
+                message_recorder: args.value_of("p2p-record-dir").map(|dir| {
+                    MessageRecorderConfig {
+                        dir: dir
+                            .parse::<PathBuf>()
+                            .expect("Provided value cannot be converted to path"),
+                        max_file_size: args
+                            .value_of("p2p-record-max-file-size")
+                            .unwrap_or("104857600")
+                            .parse::<u64>()
+                            .expect("Provided value cannot be converted to number"),
+                        max_files: args
+                            .value_of("p2p-record-max-files")
+                            .unwrap_or("10")
+                            .parse::<usize>()
+                            .expect("Provided value cannot be converted to number"),
+                    }
+                }),
+                bandwidth: BandwidthConfig {
+                    global_upload: args.value_of("p2p-max-upload-bandwidth").map(|v| {
+                        v.parse::<u64>()
+                            .expect("Provided value cannot be converted to number")
+                    }),
+                    global_download: args.value_of("p2p-max-download-bandwidth").map(|v| {
+                        v.parse::<u64>()
+                            .expect("Provided value cannot be converted to number")
+                    }),
+                    peer_upload: args.value_of("p2p-peer-max-upload-bandwidth").map(|v| {
+                        v.parse::<u64>()
+                            .expect("Provided value cannot be converted to number")
+                    }),
+                    peer_download: args.value_of("p2p-peer-max-download-bandwidth").map(|v| {
+                        v.parse::<u64>()
+                            .expect("Provided value cannot be converted to number")
+                    }),
+                    max_message_size: args.value_of("p2p-max-message-size").map(|v| {
+                        v.parse::<usize>()
+                            .expect("Provided value cannot be converted to number")
+                    }),
+                    max_peer_queue: args.value_of("p2p-peer-max-queued-messages").map(|v| {
+                        v.parse::<usize>()
+                            .expect("Provided value cannot be converted to number")
+                    }),
+                },
+                replay: args.value_of("p2p-replay").map(|path| ReplayConfig {
+                    path: path
+                        .parse::<PathBuf>()
+                        .expect("Provided value cannot be converted to path"),
+                    speed: args
+                        .value_of("p2p-replay-speed")
+                        .unwrap_or("0")
+                        .parse::<f64>()
+                        .expect("Provided value cannot be converted to number"),
+                }),
             },
-            rpc: crate::configuration::Rpc {
-                listener_port: args
+            rpc: {
+                let listener_port = args
                     .value_of("rpc-port")
                     .unwrap_or("")
                     .parse::<u16>()
-                    .expect("Was expecting value of rpc-port"),
-                websocket_address: args
-                    .value_of("websocket-address")
-                    .unwrap_or("")
-                    .parse()
-                    .expect("Provided value cannot be converted into valid uri"),
+                    .expect("Was expecting value of rpc-port");
+                crate::configuration::Rpc {
+                    listener_port,
+                    listen_addresses: args
+                        .value_of("rpc-listen-address")
+                        .unwrap_or("0.0.0.0")
+                        .split(',')
+                        .map(|address| {
+                            parse_rpc_listen_address(address, listener_port)
+                                .expect("Was expecting IP[:PORT]")
+                        })
+                        .collect(),
+                    acl: {
+                        let acl = match args.value_of("rpc-acl-file") {
+                            Some(acl_file) => {
+                                let acl_file = acl_file
+                                    .parse::<PathBuf>()
+                                    .expect("Provided value cannot be converted to path");
+                                RpcAcl::from_json_file(&acl_file).unwrap_or_else(|e| {
+                                    panic!(
+                                        "Failed to load RPC ACL file: {:?}, reason: {}",
+                                        acl_file, e
+                                    )
+                                })
+                            }
+                            None => RpcAcl::default(),
+                        };
+                        args.value_of("rpc-allow-all")
+                            .map(|addresses| addresses.split(',').collect::<Vec<_>>())
+                            .unwrap_or_default()
+                            .into_iter()
+                            .fold(acl, |acl, address| {
+                                acl.allow_all(
+                                    address
+                                        .parse()
+                                        .expect("Was expecting IP[/PREFIX_LEN][:PORT]"),
+                                )
+                            })
+                    },
+                    tls: match (args.value_of("rpc-tls-cert"), args.value_of("rpc-tls-key")) {
+                        (Some(cert_path), Some(key_path)) => Some(RpcTlsConfiguration::new(
+                            cert_path
+                                .parse::<PathBuf>()
+                                .expect("Provided value cannot be converted to path"),
+                            key_path
+                                .parse::<PathBuf>()
+                                .expect("Provided value cannot be converted to path"),
+                        )),
+                        _ => None,
+                    },
+                    limits: {
+                        let defaults = RpcLimitsConfiguration::default();
+                        RpcLimitsConfiguration::new(
+                            args.value_of("rpc-rate-limit").map(|value| {
+                                let tokens_per_second = value
+                                    .parse::<u32>()
+                                    .expect("Provided value cannot be converted to number");
+                                let burst = args
+                                    .value_of("rpc-rate-limit-burst")
+                                    .map(|value| {
+                                        value
+                                            .parse::<u32>()
+                                            .expect("Provided value cannot be converted to number")
+                                    })
+                                    .unwrap_or_else(|| tokens_per_second.saturating_mul(10));
+                                RpcRateLimit::new(tokens_per_second, burst)
+                            }),
+                            args.value_of("rpc-max-concurrent-expensive")
+                                .map(|value| {
+                                    value
+                                        .parse::<usize>()
+                                        .expect("Provided value cannot be converted to number")
+                                })
+                                .unwrap_or_else(|| defaults.max_concurrent_expensive_requests()),
+                            args.value_of("rpc-cache-max-entries")
+                                .map(|value| {
+                                    value
+                                        .parse::<usize>()
+                                        .expect("Provided value cannot be converted to number")
+                                })
+                                .unwrap_or_else(|| defaults.response_cache_max_entries()),
+                        )
+                    },
+                    websocket_address: args
+                        .value_of("websocket-address")
+                        .unwrap_or("")
+                        .parse()
+                        .expect("Provided value cannot be converted into valid uri"),
+                }
             },
             logging: crate::configuration::Logging {
                 ocaml_log_enabled: args
@@ -701,6 +1076,14 @@ impl Environment {
                         db_cfg.max_threads(Some(max_treads));
                     }
 
+                    if let Some(value) = args.value_of("db-backend") {
+                        db_cfg.backend(
+                            value
+                                .parse::<KeyValueStoreBackendType>()
+                                .expect("Provided value cannot be converted to database backend"),
+                        );
+                    }
+
                     db_cfg.build().unwrap()
                 },
                 db_path: {
@@ -716,6 +1099,8 @@ impl Environment {
                     .unwrap_or("true")
                     .parse::<bool>()
                     .expect("Provided value cannot be converted to bool"),
+                index_operation_receipts: args.is_present("index-operation-receipts"),
+                index_context_history: args.is_present("index-context-history"),
                 patch_context: {
                     match args.value_of("sandbox-patch-context-json-file") {
                         Some(path) => {
@@ -756,6 +1141,31 @@ impl Environment {
                         }
                     }
                 },
+                context_fsck: args.value_of("context-fsck-from-level").map(|from_level| {
+                    ContextFsckConfig {
+                        from_level: from_level
+                            .parse::<i32>()
+                            .expect("Provided value cannot be converted to number"),
+                        to_level: args.value_of("context-fsck-to-level").map(|to_level| {
+                            to_level
+                                .parse::<i32>()
+                                .expect("Provided value cannot be converted to number")
+                        }),
+                        repair: args.is_present("context-fsck-repair"),
+                    }
+                }),
+                context_migrate_encoding: args.is_present("context-migrate-encoding"),
+                reindex_operations: args.is_present("reindex-operations"),
+                context_tree_cache_capacity: args
+                    .value_of("context-tree-cache-mb")
+                    .map(|value| {
+                        value
+                            .parse::<usize>()
+                            .expect("Provided value cannot be converted to number")
+                            * 1024
+                            * 1024
+                    })
+                    .unwrap_or(DEFAULT_TREE_CACHE_CAPACITY),
             },
             identity: crate::configuration::Identity {
                 identity_json_file_path: {
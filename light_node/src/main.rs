@@ -11,11 +11,15 @@ use riker::actors::*;
 use rocksdb::Cache;
 use slog::{crit, debug, error, info, o, warn, Drain, Logger};
 
+use crypto::hash::{ChainId, HashType};
 use logging::detailed_json;
 use logging::file::FileAppenderBuilder;
 use monitoring::{Monitor, WebsocketHandler};
+use networking::p2p::bandwidth::BandwidthThrottle;
 use networking::p2p::network_channel::NetworkChannel;
+use networking::p2p::replay::{replay, ReplayStart};
 use rpc::rpc_actor::RpcServer;
+use rpc::RpcServerConfiguration;
 use shell::chain_feeder::ChainFeeder;
 use shell::chain_manager::ChainManager;
 use shell::context_listener::ContextListener;
@@ -23,14 +27,20 @@ use shell::mempool::init_mempool_state_storage;
 use shell::mempool::mempool_prevalidator::MempoolPrevalidator;
 use shell::peer_manager::PeerManager;
 use shell::shell_channel::{ShellChannel, ShellChannelTopic, ShuttingDown};
+use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::context::TezedgeContext;
+use storage::context_fsck::{self, ContextFsckConfig, ContextFsckError};
 use storage::merkle_storage::MerkleStorage;
 use storage::persistent::sequence::Sequences;
-use storage::persistent::{open_cl, open_kv, CommitLogSchema, KeyValueSchema, PersistentStorage};
+use storage::persistent::{
+    open_cl, open_kv_store, CommitLogSchema, KeyValueStoreColumn, PersistentStorage,
+};
+use storage::skip_list::{DatabaseBackedSkipList, Lane, ListValue};
 use storage::{
-    block_storage, check_database_compatibility, context_action_storage,
-    resolve_storage_init_chain_data, BlockMetaStorage, BlockStorage, ChainMetaStorage,
-    ContextActionStorage, MempoolStorage, OperationsMetaStorage, OperationsStorage,
+    block_storage, check_database_compatibility, context_action_storage, context_history,
+    operation_receipts_storage, operations_storage, resolve_storage_init_chain_data,
+    BlockMetaStorage, BlockStorage, ChainMetaStorage, ContextActionStorage, ContextHistoryIndex,
+    MempoolStorage, OperationReceiptsStorage, OperationsMetaStorage, OperationsStorage,
     PredecessorStorage, StorageInitInfo, SystemStorage,
 };
 use tezos_api::environment;
@@ -49,10 +59,13 @@ mod configuration;
 mod identity;
 mod system;
 
-const DATABASE_VERSION: i64 = 16;
+const DATABASE_VERSION: i64 = 17;
 const SUPPORTED_DISTRIBUTED_DB_VERSION: u16 = 0;
 const SUPPORTED_P2P_VERSION: u16 = 1;
 
+/// Max time to wait for the actors consuming the replayed p2p messages
+const REPLAY_START_TIMEOUT: Duration = Duration::from_secs(60);
+
 macro_rules! shutdown_and_exit {
     ($err:expr, $sys:ident) => {{
         $err;
@@ -339,8 +352,15 @@ fn block_on_actors(
         &tezos_env,
         apply_block_protocol_commands,
         log.clone(),
+        env.storage.index_operation_receipts,
+        env.storage.index_context_history,
     )
     .expect("Failed to create chain feeder");
+    // replay must not start, before chain manager subscribes to the network channel
+    let replay_start =
+        env.p2p.replay.as_ref().map(|_| {
+            ReplayStart::watch(&actor_system).expect("Failed to create replay start watcher")
+        });
     let _ = ChainManager::actor(
         &actor_system,
         block_applier,
@@ -373,16 +393,45 @@ fn block_on_actors(
         .expect("Failed to create mempool prevalidator");
     }
     // and than open p2p and others
-    let _ = PeerManager::actor(
-        &actor_system,
-        network_channel.clone(),
-        shell_channel.clone(),
-        tokio_runtime.handle().clone(),
-        identity,
-        network_version.clone(),
-        env.p2p.clone(),
-    )
-    .expect("Failed to create peer manager");
+    let bandwidth = Arc::new(BandwidthThrottle::new(env.p2p.bandwidth.clone()));
+    if let (Some(replay_config), Some(replay_start)) = (env.p2p.replay.clone(), replay_start) {
+        // replay recorded messages instead of network communication
+        let actor_system = actor_system.clone();
+        let network_channel = network_channel.clone();
+        let tokio_executor = tokio_runtime.handle().clone();
+        let network_version = network_version.clone();
+        let log = log.clone();
+        thread::spawn(move || {
+            let result = replay_start
+                .wait_for(&[ChainManager::name()], REPLAY_START_TIMEOUT)
+                .and_then(|_| {
+                    replay(
+                        &actor_system,
+                        network_channel,
+                        tokio_executor,
+                        identity,
+                        network_version,
+                        &replay_config,
+                        &log,
+                    )
+                });
+            if let Err(e) = result {
+                error!(log, "Failed to replay recorded p2p messages"; "reason" => format!("{}", e));
+            }
+        });
+    } else {
+        let _ = PeerManager::actor(
+            &actor_system,
+            network_channel.clone(),
+            shell_channel.clone(),
+            tokio_runtime.handle().clone(),
+            identity,
+            network_version.clone(),
+            env.p2p.clone(),
+            bandwidth.clone(),
+        )
+        .expect("Failed to create peer manager");
+    }
     let websocket_handler =
         WebsocketHandler::actor(&actor_system, env.rpc.websocket_address, log.clone())
             .expect("Failed to start websocket actor");
@@ -391,12 +440,18 @@ fn block_on_actors(
         network_channel,
         websocket_handler,
         shell_channel.clone(),
+        bandwidth,
     )
     .expect("Failed to create monitor actor");
     let _ = RpcServer::actor(
         &actor_system,
         shell_channel.clone(),
-        ([0, 0, 0, 0], env.rpc.listener_port).into(),
+        RpcServerConfiguration::new(
+            env.rpc.listen_addresses.clone(),
+            env.rpc.acl.clone(),
+            env.rpc.tls.clone(),
+            env.rpc.limits.clone(),
+        ),
         &tokio_runtime.handle(),
         &persistent_storage,
         current_mempool_state_storage,
@@ -467,6 +522,132 @@ fn block_on_actors(
     });
 }
 
+/// Re-encodes context entries stored in the legacy encoding, see [storage::merkle_encoding]
+fn migrate_context_encoding(persistent_storage: &PersistentStorage, log: &Logger) {
+    info!(log, "Migrating context entries to the compact encoding");
+    let merkle = persistent_storage.merkle();
+    let merkle = merkle.read().expect("lock poisoning");
+    match merkle.migrate_entries_encoding() {
+        Ok(stats) => info!(log, "Context entries migrated";
+                                "checked_entries" => stats.checked_entries,
+                                "migrated_entries" => stats.migrated.entries,
+                                "compressed_blobs" => stats.migrated.compressed_blobs,
+                                "legacy_bytes" => stats.migrated.sampled_legacy_bytes,
+                                "compact_bytes" => stats.migrated.compact_bytes,
+                                "saved_bytes" => stats.migrated.saved_bytes()),
+        Err(e) => {
+            error!(log, "Failed to migrate context entries"; "reason" => format!("{}", e))
+        }
+    }
+}
+
+/// Indexes operations stored by older versions, the index is built just once (marked in the system storage)
+fn reindex_operations_by_hash(persistent_storage: &PersistentStorage, log: &Logger) {
+    let mut system_storage = SystemStorage::new(persistent_storage.kv());
+    match system_storage.is_operations_indexed_by_hash() {
+        Ok(true) => {
+            info!(
+                log,
+                "Stored operations are already indexed by operation hash"
+            );
+            return;
+        }
+        Ok(false) => (),
+        Err(e) => {
+            error!(log, "Failed to read operations index state"; "reason" => format!("{}", e));
+            return;
+        }
+    }
+
+    info!(log, "Indexing stored operations by operation hash");
+    match OperationsStorage::new(persistent_storage).reindex_by_hash() {
+        Ok(indexed) => {
+            info!(log, "Stored operations indexed"; "indexed_operations" => indexed);
+            if let Err(e) = system_storage.set_operations_indexed_by_hash() {
+                error!(log, "Failed to store operations index state"; "reason" => format!("{}", e));
+            }
+        }
+        Err(e) => error!(log, "Failed to index stored operations"; "reason" => format!("{}", e)),
+    }
+}
+
+/// Indexes context history of the blocks applied before the index was enabled
+fn backfill_context_history(
+    persistent_storage: &PersistentStorage,
+    chain_id: &ChainId,
+    log: &Logger,
+) {
+    let current_head = match ChainMetaStorage::new(persistent_storage).get_current_head(chain_id) {
+        Ok(Some(current_head)) => current_head,
+        Ok(None) => return,
+        Err(e) => {
+            error!(log, "Failed to read current head"; "reason" => format!("{}", e));
+            return;
+        }
+    };
+    info!(log, "Indexing context history of applied blocks"; "level" => *current_head.level());
+    match ContextHistoryIndex::new(persistent_storage).backfill(
+        &BlockStorage::new(persistent_storage),
+        current_head.block_hash(),
+    ) {
+        Ok(indexed) => info!(log, "Context history indexed"; "indexed_blocks" => indexed),
+        Err(e) => error!(log, "Failed to index context history"; "reason" => format!("{}", e)),
+    }
+}
+
+/// Checks (and repairs, if configured) the context storage, see [context_fsck]
+fn check_context_integrity(
+    cfg: &ContextFsckConfig,
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) {
+    let check = || {
+        let report = context_fsck::check_context(
+            chain_id,
+            cfg.from_level,
+            cfg.to_level,
+            persistent_storage,
+            log,
+        )?;
+        for block in &report.broken_blocks {
+            for issue in &block.issues {
+                warn!(log, "Broken context entry";
+                           "level" => block.level,
+                           "block_hash" => HashType::BlockHash.hash_to_b58check(&block.block_hash),
+                           "issue" => issue.to_string());
+            }
+        }
+        info!(log, "Context integrity checked";
+                   "checked_blocks" => report.checked_blocks,
+                   "checked_entries" => report.checked_entries,
+                   "broken_blocks" => report.broken_blocks.len(),
+                   "issues" => report.issues_count());
+        Ok::<_, ContextFsckError>(report)
+    };
+
+    let report = match check() {
+        Ok(report) => report,
+        Err(e) => {
+            error!(log, "Failed to check context integrity"; "reason" => format!("{}", e));
+            return;
+        }
+    };
+    if report.is_ok() || !cfg.repair {
+        return;
+    }
+
+    match context_fsck::repair_context(&report, persistent_storage, log) {
+        Ok(reapplied_blocks) => {
+            info!(log, "Broken blocks re-applied, checking context again"; "count" => reapplied_blocks.len());
+            if let Err(e) = check() {
+                error!(log, "Failed to check context integrity"; "reason" => format!("{}", e));
+            }
+        }
+        Err(e) => error!(log, "Failed to repair context"; "reason" => format!("{}", e)),
+    }
+}
+
 fn main() {
     // Parses config + cli args
     let env = crate::configuration::Environment::from_args();
@@ -515,38 +696,46 @@ fn main() {
         .expect("Failed to create actor system");
 
     // create common RocksDB block cache to be shared among column families
-    // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv)
+    // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv_store)
     let cache = Cache::new_lru_cache(128 * 1024 * 1024).unwrap(); // 128 MB
 
-    let schemas = vec![
-        block_storage::BlockPrimaryIndex::descriptor(&cache),
-        block_storage::BlockByLevelIndex::descriptor(&cache),
-        block_storage::BlockByContextHashIndex::descriptor(&cache),
-        BlockMetaStorage::descriptor(&cache),
-        OperationsStorage::descriptor(&cache),
-        OperationsMetaStorage::descriptor(&cache),
-        context_action_storage::ContextActionByBlockHashIndex::descriptor(&cache),
-        context_action_storage::ContextActionByContractIndex::descriptor(&cache),
-        context_action_storage::ContextActionByTypeIndex::descriptor(&cache),
-        ContextActionStorage::descriptor(&cache),
-        MerkleStorage::descriptor(&cache),
-        SystemStorage::descriptor(&cache),
-        Sequences::descriptor(&cache),
-        MempoolStorage::descriptor(&cache),
-        ChainMetaStorage::descriptor(&cache),
-        PredecessorStorage::descriptor(&cache),
+    let columns = vec![
+        KeyValueStoreColumn::of::<block_storage::BlockPrimaryIndex>(),
+        KeyValueStoreColumn::of::<block_storage::BlockByLevelIndex>(),
+        KeyValueStoreColumn::of::<block_storage::BlockByContextHashIndex>(),
+        KeyValueStoreColumn::of::<BlockMetaStorage>(),
+        KeyValueStoreColumn::of::<OperationsStorage>(),
+        KeyValueStoreColumn::of::<operations_storage::OperationsByHashIndex>(),
+        KeyValueStoreColumn::of::<OperationsMetaStorage>(),
+        KeyValueStoreColumn::of::<OperationReceiptsStorage>(),
+        KeyValueStoreColumn::of::<operation_receipts_storage::OperationReceiptsByAccountIndex>(),
+        KeyValueStoreColumn::of::<context_action_storage::ContextActionByBlockHashIndex>(),
+        KeyValueStoreColumn::of::<context_action_storage::ContextActionByContractIndex>(),
+        KeyValueStoreColumn::of::<context_action_storage::ContextActionByTypeIndex>(),
+        KeyValueStoreColumn::of::<ContextActionStorage>(),
+        KeyValueStoreColumn::of::<MerkleStorage>(),
+        KeyValueStoreColumn::of::<SystemStorage>(),
+        KeyValueStoreColumn::of::<Sequences>(),
+        KeyValueStoreColumn::of::<MempoolStorage>(),
+        KeyValueStoreColumn::of::<ChainMetaStorage>(),
+        KeyValueStoreColumn::of::<PredecessorStorage>(),
+        KeyValueStoreColumn::of::<DatabaseBackedSkipList>(),
+        KeyValueStoreColumn::of::<Lane>(),
+        KeyValueStoreColumn::of::<ListValue>(),
+        KeyValueStoreColumn::of::<ContextHistoryIndex>(),
+        KeyValueStoreColumn::of::<context_history::ContextHistoryByPositionIndex>(),
     ];
 
-    let rocks_db = match open_kv(&env.storage.db_path, schemas, &env.storage.db_cfg) {
-        Ok(db) => Arc::new(db),
+    let kv = match open_kv_store(&env.storage.db_path, columns, &cache, &env.storage.db_cfg) {
+        Ok(kv) => Arc::new(kv),
         Err(e) => shutdown_and_exit!(
-            error!(log, "Failed to create RocksDB database at '{:?}'", &env.storage.db_path; "reason" => e),
+            error!(log, "Failed to create key-value database at '{:?}'", &env.storage.db_path; "backend" => format!("{:?}", env.storage.db_cfg.backend()), "reason" => e),
             actor_system
         ),
     };
-    debug!(log, "Loaded RocksDB database");
+    debug!(log, "Loaded key-value database"; "backend" => format!("{:?}", kv.backend_type()));
 
-    match check_database_compatibility(rocks_db.clone(), DATABASE_VERSION, &tezos_env, &log) {
+    match check_database_compatibility(kv.clone(), DATABASE_VERSION, &tezos_env, &log) {
         Ok(false) => shutdown_and_exit!(
             crit!(log, "Database incompatibility detected"),
             actor_system
@@ -569,11 +758,20 @@ fn main() {
             ),
         };
 
-        let persistent_storage = PersistentStorage::new(rocks_db, commit_logs);
+        let persistent_storage = PersistentStorage::with_merkle_tree_cache(
+            kv,
+            commit_logs,
+            env.storage.context_tree_cache_capacity,
+        );
         let tezedge_context = TezedgeContext::new(
             BlockStorage::new(&persistent_storage),
             persistent_storage.merkle(),
         );
+        let tezedge_context = if env.storage.index_context_history {
+            tezedge_context.with_history_index(ContextHistoryIndex::new(&persistent_storage))
+        } else {
+            tezedge_context
+        };
         match resolve_storage_init_chain_data(
             &tezos_env,
             &env.storage.db_path,
@@ -581,16 +779,37 @@ fn main() {
             &env.storage.patch_context,
             &log,
         ) {
-            Ok(init_data) => block_on_actors(
-                env,
-                tezos_env,
-                init_data,
-                Arc::new(tezos_identity),
-                actor_system,
-                persistent_storage,
-                tezedge_context,
-                log,
-            ),
+            Ok(init_data) => {
+                if env.storage.context_migrate_encoding {
+                    migrate_context_encoding(&persistent_storage, &log);
+                }
+                if env.storage.reindex_operations {
+                    reindex_operations_by_hash(&persistent_storage, &log);
+                }
+                if env.storage.index_context_history {
+                    backfill_context_history(&persistent_storage, &init_data.chain_id, &log);
+                }
+                if let Some(context_fsck) = env.storage.context_fsck.clone() {
+                    // check context storage instead of running the node
+                    check_context_integrity(
+                        &context_fsck,
+                        &init_data.chain_id,
+                        &persistent_storage,
+                        &log,
+                    );
+                    shutdown_and_exit!(info!(log, "Context integrity check done"), actor_system);
+                }
+                block_on_actors(
+                    env,
+                    tezos_env,
+                    init_data,
+                    Arc::new(tezos_identity),
+                    actor_system,
+                    persistent_storage,
+                    tezedge_context,
+                    log,
+                )
+            }
             Err(e) => shutdown_and_exit!(
                 error!(log, "Failed to resolve init storage chain data."; "reason" => e),
                 actor_system
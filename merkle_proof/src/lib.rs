@@ -0,0 +1,435 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! # Merkle proofs
+//!
+//! Proofs of presence (inclusion) or absence (non-inclusion) of a value under a key
+//! in a context committed to the TezEdge merkle storage (`storage::merkle_storage::MerkleStorage`).
+//!
+//! A proof contains, for every tree on the path from the root tree down to the key, all children
+//! of that tree except the one the path continues through. Together with the commit fields this
+//! is enough to recompute the context (commit) hash bottom-up from the proved value:
+//!
+//! ```no_compile
+//! [commit] ---> [tree1] --a--> [tree2] --b--> [blob]
+//!                  |              |
+//!                  x              y
+//!                  |              |
+//!               (sibling)      (sibling)
+//! ```
+//!
+//! Proof of `a/b` consists of commit fields, siblings `{x}` of tree1 and `{y}` of tree2.
+//!
+//! This crate depends only on hashing and serialization, so verification can be done
+//! by light clients without any storage. Hashing functions are shared with the merkle storage,
+//! so proofs are always verified with the same encoding the context hashes are computed with.
+#![forbid(unsafe_code)]
+
+use std::array::TryFromSliceError;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use blake2::digest::{Update, VariableOutput};
+use blake2::VarBlake2b;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+pub const HASH_LEN: usize = 32;
+
+pub type EntryHash = [u8; HASH_LEN];
+
+/// Child of a tree on the proof path (identified by its name in [MerkleProofStep::siblings])
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProofNode {
+    pub leaf: bool,
+    #[serde(with = "hex_entry_hash")]
+    pub hash: EntryHash,
+}
+
+/// One tree on the path from the root tree down to the proved key. Contains all children
+/// of the tree except the one the path continues through (under `key`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProofStep {
+    pub key: String,
+    pub siblings: BTreeMap<String, MerkleProofNode>,
+}
+
+/// Where the proof path ends
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum MerkleProofEnd {
+    /// There is a value under the key (inclusion proof), its hash is computed from the value
+    Value,
+    /// Last tree on the path has no child under the key chunk (non-inclusion proof)
+    Missing,
+    /// Path ends in a node which cannot hold the value: a value in the middle of the key,
+    /// or a tree under the whole key (non-inclusion proof)
+    Node(MerkleProofNode),
+}
+
+/// Everything (besides the value itself) needed to recompute the commit hash from a value:
+/// commit fields and sibling hashes of every tree on the path, starting from the root tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    #[serde(with = "hex_entry_hash_option")]
+    pub parent_commit_hash: Option<EntryHash>,
+    pub time: u64,
+    pub author: String,
+    pub message: String,
+    pub steps: Vec<MerkleProofStep>,
+    pub end: MerkleProofEnd,
+}
+
+#[derive(Debug, Fail)]
+pub enum MerkleProofError {
+    #[fail(display = "Proof path does not follow the key {:?}", key)]
+    PathMismatch { key: String },
+    #[fail(display = "Proof is not an {} proof", expected)]
+    UnexpectedProofEnd { expected: String },
+    #[fail(
+        display = "Context hash mismatch! Expected={}, computed={}",
+        expected, computed
+    )]
+    HashMismatch { expected: String, computed: String },
+    #[fail(display = "Failed to convert hash to array: {}", error)]
+    HashConversionError { error: TryFromSliceError },
+}
+
+impl From<TryFromSliceError> for MerkleProofError {
+    fn from(error: TryFromSliceError) -> Self {
+        MerkleProofError::HashConversionError { error }
+    }
+}
+
+impl MerkleProof {
+    /// Verify that `value` is stored under `key` in context identified by `context_hash`
+    pub fn verify_inclusion(
+        &self,
+        context_hash: &EntryHash,
+        key: &[String],
+        value: &[u8],
+    ) -> Result<(), MerkleProofError> {
+        if self.end != MerkleProofEnd::Value || self.steps.len() != key.len() {
+            return Err(MerkleProofError::UnexpectedProofEnd {
+                expected: "inclusion".to_string(),
+            });
+        }
+
+        let value_node = MerkleProofNode {
+            leaf: true,
+            hash: hash_blob(value)?,
+        };
+        self.verify(context_hash, key, Some(value_node))
+    }
+
+    /// Verify that there is no value under `key` in context identified by `context_hash`
+    pub fn verify_non_inclusion(
+        &self,
+        context_hash: &EntryHash,
+        key: &[String],
+    ) -> Result<(), MerkleProofError> {
+        let end_node = match &self.end {
+            MerkleProofEnd::Missing => None,
+            // only a value can block the path in the middle of the key
+            // and only a tree can be under the whole key
+            MerkleProofEnd::Node(node) if node.leaf != (self.steps.len() == key.len()) => {
+                Some(node.clone())
+            }
+            _ => {
+                return Err(MerkleProofError::UnexpectedProofEnd {
+                    expected: "non-inclusion".to_string(),
+                })
+            }
+        };
+        self.verify(context_hash, key, end_node)
+    }
+
+    fn verify(
+        &self,
+        context_hash: &EntryHash,
+        key: &[String],
+        end_node: Option<MerkleProofNode>,
+    ) -> Result<(), MerkleProofError> {
+        // proof path must follow the key and must not contain the key chunk among siblings
+        let path_matches = !self.steps.is_empty()
+            && self.steps.len() <= key.len()
+            && self
+                .steps
+                .iter()
+                .zip(key)
+                .all(|(step, chunk)| step.key == *chunk && !step.siblings.contains_key(chunk));
+        if !path_matches {
+            return Err(MerkleProofError::PathMismatch { key: key.join("/") });
+        }
+
+        // recompute tree hashes bottom-up
+        let mut child = end_node;
+        for step in self.steps.iter().rev() {
+            let mut tree: BTreeMap<&str, &MerkleProofNode> = step
+                .siblings
+                .iter()
+                .map(|(name, node)| (name.as_str(), node))
+                .collect();
+            if let Some(child) = &child {
+                tree.insert(step.key.as_str(), child);
+            }
+            let hash = hash_tree_nodes(
+                tree.len(),
+                tree.iter()
+                    .map(|(name, node)| (*name, node.leaf, &node.hash)),
+            )?;
+            child = Some(MerkleProofNode { leaf: false, hash });
+        }
+        // path is never empty, so there is always a root
+        let root_hash = child.map(|root| root.hash).unwrap_or_default();
+
+        let computed = hash_commit(
+            &root_hash,
+            self.parent_commit_hash.as_ref(),
+            self.time,
+            &self.author,
+            &self.message,
+        )?;
+        if computed == *context_hash {
+            Ok(())
+        } else {
+            Err(MerkleProofError::HashMismatch {
+                expected: hex::encode(context_hash),
+                computed: hex::encode(computed),
+            })
+        }
+    }
+}
+
+fn encode_irmin_node_kind(leaf: bool) -> [u8; 8] {
+    if leaf {
+        [255, 0, 0, 0, 0, 0, 0, 0]
+    } else {
+        [0, 0, 0, 0, 0, 0, 0, 0]
+    }
+}
+
+// Calculates hash of tree from its (ordered) child nodes
+// uses BLAKE2 binary 256 length hash function
+// hash is calculated as:
+// <number of child nodes (8 bytes)><CHILD NODE>
+// where:
+// - CHILD NODE - <NODE TYPE><length of string (1 byte)><string/path bytes><length of hash (8bytes)><hash bytes>
+// - NODE TYPE - leaf node(0xff0000000000000000) or internal node (0x0000000000000000)
+pub fn hash_tree_nodes<'a>(
+    len: usize,
+    nodes: impl Iterator<Item = (&'a str, bool, &'a EntryHash)>,
+) -> Result<EntryHash, TryFromSliceError> {
+    let mut hasher = VarBlake2b::new(HASH_LEN).unwrap();
+
+    hasher.update(&(len as u64).to_be_bytes());
+    nodes.for_each(|(name, leaf, hash)| {
+        hasher.update(encode_irmin_node_kind(leaf));
+        hasher.update(&[name.len() as u8]);
+        hasher.update(name.as_bytes());
+        hasher.update(&(HASH_LEN as u64).to_be_bytes());
+        hasher.update(hash);
+    });
+
+    hasher.finalize_boxed().as_ref().try_into()
+}
+
+// Calculates hash of BLOB
+// uses BLAKE2 binary 256 length hash function
+// hash is calculated as <length of data (8 bytes)><data>
+pub fn hash_blob(blob: &[u8]) -> Result<EntryHash, TryFromSliceError> {
+    let mut hasher = VarBlake2b::new(HASH_LEN).unwrap();
+    hasher.update(&(blob.len() as u64).to_be_bytes());
+    hasher.update(blob);
+
+    hasher.finalize_boxed().as_ref().try_into()
+}
+
+// Calculates hash of commit
+// uses BLAKE2 binary 256 length hash function
+// hash is calculated as:
+// <hash length (8 bytes)><tree hash bytes>
+// <length of parent hash (8bytes)><parent hash bytes>
+// <time in epoch format (8bytes)
+// <commit author name length (8bytes)><commit author name bytes>
+// <commit message length (8bytes)><commit message bytes>
+pub fn hash_commit(
+    root_hash: &EntryHash,
+    parent_commit_hash: Option<&EntryHash>,
+    time: u64,
+    author: &str,
+    message: &str,
+) -> Result<EntryHash, TryFromSliceError> {
+    let mut hasher = VarBlake2b::new(HASH_LEN).unwrap();
+    hasher.update(&(HASH_LEN as u64).to_be_bytes());
+    hasher.update(root_hash);
+
+    match parent_commit_hash {
+        None => hasher.update(&(0_u64).to_be_bytes()),
+        Some(parent_commit_hash) => {
+            hasher.update(&(1_u64).to_be_bytes()); // # of parents; we support only 1
+            hasher.update(&(parent_commit_hash.len() as u64).to_be_bytes());
+            hasher.update(parent_commit_hash);
+        }
+    }
+    hasher.update(&time.to_be_bytes());
+    hasher.update(&(author.len() as u64).to_be_bytes());
+    hasher.update(author.as_bytes());
+    hasher.update(&(message.len() as u64).to_be_bytes());
+    hasher.update(message.as_bytes());
+
+    hasher.finalize_boxed().as_ref().try_into()
+}
+
+/// Serializes [EntryHash] as hex string (for JSON RPCs)
+mod hex_entry_hash {
+    use std::convert::TryInto;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::EntryHash;
+
+    pub fn serialize<S: Serializer>(hash: &EntryHash, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<EntryHash, D::Error> {
+        let bytes = hex::decode(String::deserialize(d)?).map_err(de::Error::custom)?;
+        bytes.as_slice().try_into().map_err(de::Error::custom)
+    }
+}
+
+/// Serializes optional [EntryHash] as hex string or null (for JSON RPCs)
+mod hex_entry_hash_option {
+    use std::convert::TryInto;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::EntryHash;
+
+    pub fn serialize<S: Serializer>(hash: &Option<EntryHash>, s: S) -> Result<S::Ok, S::Error> {
+        match hash {
+            Some(hash) => s.serialize_some(&hex::encode(hash)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<EntryHash>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(hash) => {
+                let bytes = hex::decode(hash).map_err(de::Error::custom)?;
+                Ok(Some(
+                    bytes.as_slice().try_into().map_err(de::Error::custom)?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> Vec<String> {
+        key.split('/').map(str::to_string).collect()
+    }
+
+    fn node(leaf: bool, hash: EntryHash) -> MerkleProofNode {
+        MerkleProofNode { leaf, hash }
+    }
+
+    /// Context with values a/b = [1, 2] and x = [3], proof of a/b
+    fn proof_and_context_hash() -> (MerkleProof, EntryHash) {
+        let blob_ab = hash_blob(&[1, 2]).unwrap();
+        let blob_x = hash_blob(&[3]).unwrap();
+        let tree_a = hash_tree_nodes(1, vec![("b", true, &blob_ab)].into_iter()).unwrap();
+        let root = hash_tree_nodes(
+            2,
+            vec![("a", false, &tree_a), ("x", true, &blob_x)].into_iter(),
+        )
+        .unwrap();
+        let context_hash = hash_commit(&root, None, 0, "Tezos", "Genesis").unwrap();
+
+        let mut root_siblings = BTreeMap::new();
+        root_siblings.insert("x".to_string(), node(true, blob_x));
+        let proof = MerkleProof {
+            parent_commit_hash: None,
+            time: 0,
+            author: "Tezos".to_string(),
+            message: "Genesis".to_string(),
+            steps: vec![
+                MerkleProofStep {
+                    key: "a".to_string(),
+                    siblings: root_siblings,
+                },
+                MerkleProofStep {
+                    key: "b".to_string(),
+                    siblings: BTreeMap::new(),
+                },
+            ],
+            end: MerkleProofEnd::Value,
+        };
+        (proof, context_hash)
+    }
+
+    #[test]
+    fn test_verify_inclusion() {
+        let (proof, context_hash) = proof_and_context_hash();
+        assert!(proof
+            .verify_inclusion(&context_hash, &key("a/b"), &[1, 2])
+            .is_ok());
+        assert!(matches!(
+            proof.verify_inclusion(&context_hash, &key("a/b"), &[1, 3]),
+            Err(MerkleProofError::HashMismatch { .. })
+        ));
+        assert!(matches!(
+            proof.verify_inclusion(&context_hash, &key("a/c"), &[1, 2]),
+            Err(MerkleProofError::PathMismatch { .. })
+        ));
+        assert!(matches!(
+            proof.verify_non_inclusion(&context_hash, &key("a/b")),
+            Err(MerkleProofError::UnexpectedProofEnd { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_non_inclusion() {
+        let (proof, context_hash) = proof_and_context_hash();
+        let blob_ab = hash_blob(&[1, 2]).unwrap();
+        let tree_a = hash_tree_nodes(1, vec![("b", true, &blob_ab)].into_iter()).unwrap();
+
+        // a/c is missing in tree a
+        let mut missing = proof.clone();
+        missing.steps[1].key = "c".to_string();
+        missing.steps[1]
+            .siblings
+            .insert("b".to_string(), node(true, blob_ab));
+        missing.end = MerkleProofEnd::Missing;
+        assert!(missing
+            .verify_non_inclusion(&context_hash, &key("a/c"))
+            .is_ok());
+        assert!(missing
+            .verify_non_inclusion(&context_hash, &key("a/b"))
+            .is_err());
+
+        // a/b/c is blocked by value a/b
+        let mut blocked = proof.clone();
+        blocked.end = MerkleProofEnd::Node(node(true, blob_ab));
+        assert!(blocked
+            .verify_non_inclusion(&context_hash, &key("a/b/c"))
+            .is_ok());
+        assert!(blocked
+            .verify_non_inclusion(&context_hash, &key("a/b"))
+            .is_err());
+
+        // a is a tree, not a value
+        let mut tree = proof;
+        tree.steps.truncate(1);
+        tree.end = MerkleProofEnd::Node(node(false, tree_a));
+        assert!(tree.verify_non_inclusion(&context_hash, &key("a")).is_ok());
+        assert!(tree
+            .verify_non_inclusion(&context_hash, &key("a/b"))
+            .is_err());
+    }
+}
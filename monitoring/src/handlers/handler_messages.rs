@@ -6,6 +6,8 @@ use std::iter::FromIterator;
 use serde::Serialize;
 use slog_derive::SerdeValue;
 
+use networking::p2p::bandwidth::BandwidthStats;
+
 use crate::monitors::ChainMonitor;
 use crate::monitors::PeerMonitor;
 
@@ -106,6 +108,45 @@ impl PeerMetrics {
     }
 }
 
+// -------------------------- BANDWIDTH STATS MESSAGE -------------------------- //
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthMetrics {
+    sent_bytes: u64,
+    received_bytes: u64,
+    current_upload_speed: f32,
+    current_download_speed: f32,
+    upload_limit: Option<u64>,
+    download_limit: Option<u64>,
+    upload_throttled_millis: u64,
+    download_throttled_millis: u64,
+    dropped_messages: u64,
+    rejected_messages: u64,
+}
+
+impl BandwidthMetrics {
+    pub fn new(
+        stats: BandwidthStats,
+        current_upload_speed: f32,
+        current_download_speed: f32,
+        upload_limit: Option<u64>,
+        download_limit: Option<u64>,
+    ) -> Self {
+        Self {
+            sent_bytes: stats.bytes_sent,
+            received_bytes: stats.bytes_received,
+            current_upload_speed,
+            current_download_speed,
+            upload_limit,
+            download_limit,
+            upload_throttled_millis: stats.upload_throttled_millis,
+            download_throttled_millis: stats.download_throttled_millis,
+            dropped_messages: stats.dropped_messages,
+            rejected_messages: stats.rejected_messages,
+        }
+    }
+}
+
 // -------------------------- PEER CONNECTING/DISCONNECTING MESSAGE -------------------------- //
 #[derive(Clone, Serialize, Debug)]
 #[serde(rename_all = "camelCase", tag = "status", content = "id")]
@@ -130,6 +171,7 @@ impl PeerConnectionStatus {
 pub enum HandlerMessage {
     PeersMetrics { payload: Vec<PeerMetrics> },
     PeerStatus { payload: PeerConnectionStatus },
+    BandwidthStatus { payload: BandwidthMetrics },
     IncomingTransfer { payload: IncomingTransferMetrics },
     BlockStatus { payload: Vec<BlockMetrics> },
     BlockApplicationStatus { payload: BlockApplicationMessage },
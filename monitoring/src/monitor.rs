@@ -7,6 +7,7 @@ use std::time::Duration;
 use riker::{actor::*, actors::SystemMsg, system::SystemEvent, system::Timer};
 use slog::{warn, Logger};
 
+use networking::p2p::bandwidth::BandwidthThrottleRef;
 use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, PeerMessageReceived};
 use shell::shell_channel::{ShellChannelMsg, ShellChannelRef};
 use shell::subscription::{
@@ -39,6 +40,7 @@ pub struct Monitor {
     blocks_monitor: BlocksMonitor,
     block_application_monitor: ApplicationMonitor,
     chain_monitor: ChainMonitor,
+    bandwidth_monitor: BandwidthMonitor,
 }
 
 impl Monitor {
@@ -51,10 +53,11 @@ impl Monitor {
         event_channel: NetworkChannelRef,
         msg_channel: ActorRef<WebsocketHandlerMsg>,
         shell_channel: ShellChannelRef,
+        bandwidth: BandwidthThrottleRef,
     ) -> Result<MonitorRef, CreateError> {
         sys.actor_of_props::<Monitor>(
             Self::name(),
-            Props::new_args((event_channel, msg_channel, shell_channel)),
+            Props::new_args((event_channel, msg_channel, shell_channel, bandwidth)),
         )
     }
 
@@ -95,13 +98,15 @@ impl
         NetworkChannelRef,
         ActorRef<WebsocketHandlerMsg>,
         ShellChannelRef,
+        BandwidthThrottleRef,
     )> for Monitor
 {
     fn create_args(
-        (event_channel, msg_channel, shell_channel): (
+        (event_channel, msg_channel, shell_channel, bandwidth): (
             NetworkChannelRef,
             ActorRef<WebsocketHandlerMsg>,
             ShellChannelRef,
+            BandwidthThrottleRef,
         ),
     ) -> Self {
         Self {
@@ -113,6 +118,7 @@ impl
             blocks_monitor: BlocksMonitor::new(4096, 0),
             block_application_monitor: ApplicationMonitor::new(),
             chain_monitor: ChainMonitor::new(),
+            bandwidth_monitor: BandwidthMonitor::new(bandwidth),
         }
     }
 }
@@ -188,6 +194,15 @@ impl Receive<BroadcastSignal> for Monitor {
             BroadcastSignal::PublishPeerStatistics => {
                 let peer_stats: HandlerMessage = self.peer_monitors.values_mut().collect();
                 self.msg_channel.tell(peer_stats, ctx.myself().into());
+
+                // counters are collected only with configured limits
+                if self.bandwidth_monitor.is_enabled() {
+                    let payload = self.bandwidth_monitor.snapshot();
+                    self.msg_channel.tell(
+                        HandlerMessage::BandwidthStatus { payload },
+                        ctx.myself().into(),
+                    );
+                }
             }
             BroadcastSignal::PublishBlocksStatistics => {
                 let bootstrap_stats: HandlerMessage = self.bootstrap_monitor.snapshot().into();
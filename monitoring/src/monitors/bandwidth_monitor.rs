@@ -0,0 +1,53 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::time::Instant;
+
+use networking::p2p::bandwidth::{BandwidthStats, BandwidthThrottleRef};
+
+use crate::handlers::handler_messages::BandwidthMetrics;
+
+/// Transport-level counters of all peer connections together (including throttling)
+pub(crate) struct BandwidthMonitor {
+    bandwidth: BandwidthThrottleRef,
+    last_stats: BandwidthStats,
+    last_snapshot: Instant,
+}
+
+impl BandwidthMonitor {
+    pub fn new(bandwidth: BandwidthThrottleRef) -> Self {
+        Self {
+            last_stats: bandwidth.stats(),
+            bandwidth,
+            last_snapshot: Instant::now(),
+        }
+    }
+
+    /// Peers are not throttled nor counted, if no limit is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.bandwidth.config().is_unlimited()
+    }
+
+    pub fn snapshot(&mut self) -> BandwidthMetrics {
+        let stats = self.bandwidth.stats();
+        let snapshot_end = Instant::now();
+        let snapshot_duration = (snapshot_end - self.last_snapshot).as_secs_f32();
+
+        let upload_speed =
+            (stats.bytes_sent - self.last_stats.bytes_sent) as f32 / snapshot_duration;
+        let download_speed =
+            (stats.bytes_received - self.last_stats.bytes_received) as f32 / snapshot_duration;
+
+        self.last_snapshot = snapshot_end;
+        self.last_stats = stats.clone();
+
+        let config = self.bandwidth.config();
+        BandwidthMetrics::new(
+            stats,
+            upload_speed,
+            download_speed,
+            config.global_upload,
+            config.global_download,
+        )
+    }
+}
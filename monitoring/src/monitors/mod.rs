@@ -1,12 +1,14 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+mod bandwidth_monitor;
 mod block_application_monitor;
 mod blocks_monitor;
 mod bootstrap_monitor;
 mod chain_monitor;
 mod peer_monitor;
 
+pub(crate) use bandwidth_monitor::BandwidthMonitor;
 pub(crate) use block_application_monitor::ApplicationMonitor;
 pub(crate) use blocks_monitor::BlocksMonitor;
 pub(crate) use bootstrap_monitor::BootstrapMonitor;
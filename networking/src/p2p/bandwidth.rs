@@ -0,0 +1,325 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Transport-level bandwidth limits.
+//!
+//! Upload/download of the encrypted chunks is throttled by token buckets, one global bucket shared by all peers
+//! and one bucket per peer. Bucket allows bursts up to one second of its rate, then the stream waits for the tokens.
+//! All transferred bytes, throttling waits and dropped/rejected messages are counted for monitoring.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::time::delay_for;
+
+/// Bandwidth limits configuration, `None` means unlimited
+#[derive(Clone, Debug, Default)]
+pub struct BandwidthConfig {
+    /// Max upload of all peers together (bytes per second)
+    pub global_upload: Option<u64>,
+    /// Max download of all peers together (bytes per second)
+    pub global_download: Option<u64>,
+    /// Max upload to one peer (bytes per second)
+    pub peer_upload: Option<u64>,
+    /// Max download from one peer (bytes per second)
+    pub peer_download: Option<u64>,
+    /// Max size of one incoming message (bytes), peer sending bigger message is disconnected
+    pub max_message_size: Option<usize>,
+    /// Max count of outgoing messages waiting to be sent to one peer, newer messages are dropped
+    pub max_peer_queue: Option<usize>,
+}
+
+impl BandwidthConfig {
+    /// Returns true, if no limit is configured
+    pub fn is_unlimited(&self) -> bool {
+        self.global_upload.is_none()
+            && self.global_download.is_none()
+            && self.peer_upload.is_none()
+            && self.peer_download.is_none()
+            && self.max_message_size.is_none()
+            && self.max_peer_queue.is_none()
+    }
+}
+
+/// Token bucket limiting transferred bytes per second
+struct RateLimiter {
+    bytes_per_sec: u64,
+    state: Mutex<RateLimiterState>,
+}
+
+struct RateLimiterState {
+    /// Available bytes, negative value means bytes already reserved for the future
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec,
+            state: Mutex::new(RateLimiterState {
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Reserves `bytes` and returns how long the caller has to wait before transferring them
+    fn reserve(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_sec.max(1) as f64;
+        let mut state = self.state.lock().unwrap();
+
+        let now = Instant::now();
+        let refill = now.duration_since(state.last_refill).as_secs_f64() * rate;
+        state.tokens = (state.tokens + refill).min(rate);
+        state.last_refill = now;
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+/// Snapshot of the bandwidth counters, counted only if any limit is configured
+#[derive(Clone, Debug, Default)]
+pub struct BandwidthStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Total time spent waiting for the upload limits
+    pub upload_throttled_millis: u64,
+    /// Total time spent waiting for the download limits
+    pub download_throttled_millis: u64,
+    /// Outgoing messages dropped because of full peer queue
+    pub dropped_messages: u64,
+    /// Incoming messages rejected because of max message size
+    pub rejected_messages: u64,
+}
+
+#[derive(Default)]
+struct BandwidthCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    upload_throttled_millis: AtomicU64,
+    download_throttled_millis: AtomicU64,
+    dropped_messages: AtomicU64,
+    rejected_messages: AtomicU64,
+}
+
+/// Thread-safe reference to the global bandwidth limits
+pub type BandwidthThrottleRef = Arc<BandwidthThrottle>;
+
+/// Global bandwidth limits and counters shared by all peers
+pub struct BandwidthThrottle {
+    config: BandwidthConfig,
+    upload: Option<RateLimiter>,
+    download: Option<RateLimiter>,
+    counters: BandwidthCounters,
+}
+
+impl BandwidthThrottle {
+    pub fn new(config: BandwidthConfig) -> Self {
+        BandwidthThrottle {
+            upload: config.global_upload.map(RateLimiter::new),
+            download: config.global_download.map(RateLimiter::new),
+            counters: BandwidthCounters::default(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &BandwidthConfig {
+        &self.config
+    }
+
+    /// Creates limits for the new peer, returns None if no limit is configured,
+    /// so the peer streams are not throttled (and not counted) at all
+    pub fn peer(self: &Arc<Self>) -> Option<PeerBandwidth> {
+        if self.config.is_unlimited() {
+            return None;
+        }
+        Some(PeerBandwidth {
+            upload: self.config.peer_upload.map(|rate| Arc::new(RateLimiter::new(rate))),
+            download: self.config.peer_download.map(|rate| Arc::new(RateLimiter::new(rate))),
+            queued: Arc::new(AtomicUsize::new(0)),
+            global: self.clone(),
+        })
+    }
+
+    pub fn stats(&self) -> BandwidthStats {
+        BandwidthStats {
+            bytes_sent: self.counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.counters.bytes_received.load(Ordering::Relaxed),
+            upload_throttled_millis: self.counters.upload_throttled_millis.load(Ordering::Relaxed),
+            download_throttled_millis: self.counters.download_throttled_millis.load(Ordering::Relaxed),
+            dropped_messages: self.counters.dropped_messages.load(Ordering::Relaxed),
+            rejected_messages: self.counters.rejected_messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bandwidth limits of one peer, clones share the same limits and queue
+#[derive(Clone)]
+pub struct PeerBandwidth {
+    global: BandwidthThrottleRef,
+    upload: Option<Arc<RateLimiter>>,
+    download: Option<Arc<RateLimiter>>,
+    /// Count of outgoing messages waiting to be sent
+    queued: Arc<AtomicUsize>,
+}
+
+impl PeerBandwidth {
+    /// Waits until `bytes` can be sent to peer
+    pub async fn throttle_upload(&self, bytes: usize) {
+        self.global.counters.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        let wait = reserve(self.global.upload.as_ref(), self.upload.as_deref(), bytes);
+        if wait > Duration::from_secs(0) {
+            self.global.counters.upload_throttled_millis.fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+            delay_for(wait).await;
+        }
+    }
+
+    /// Waits until `bytes` received from peer fit into download limits
+    pub async fn throttle_download(&self, bytes: usize) {
+        self.global.counters.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        let wait = reserve(self.global.download.as_ref(), self.download.as_deref(), bytes);
+        if wait > Duration::from_secs(0) {
+            self.global.counters.download_throttled_millis.fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+            delay_for(wait).await;
+        }
+    }
+
+    /// Returns false (and counts the message as rejected), if the incoming message exceeds max message size
+    pub fn check_message_size(&self, size: usize) -> bool {
+        match self.global.config.max_message_size {
+            Some(max_message_size) if size > max_message_size => {
+                self.global.counters.rejected_messages.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.global.config.max_message_size
+    }
+
+    /// Reserves place in the outgoing queue, returns false (and counts the message as dropped), if the queue is full
+    pub fn try_enqueue(&self) -> bool {
+        let max_peer_queue = match self.global.config.max_peer_queue {
+            Some(max_peer_queue) => max_peer_queue,
+            None => {
+                self.queued.fetch_add(1, Ordering::AcqRel);
+                return true;
+            }
+        };
+
+        let mut queued = self.queued.load(Ordering::Acquire);
+        loop {
+            if queued >= max_peer_queue {
+                self.global.counters.dropped_messages.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            match self.queued.compare_exchange(queued, queued + 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(actual) => queued = actual,
+            }
+        }
+    }
+
+    /// Releases place in the outgoing queue reserved by [`try_enqueue`](Self::try_enqueue)
+    pub fn dequeue(&self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Reserves bytes in both limiters and returns the longer wait
+fn reserve(global: Option<&RateLimiter>, peer: Option<&RateLimiter>, bytes: usize) -> Duration {
+    let global_wait = global.map(|limiter| limiter.reserve(bytes)).unwrap_or_default();
+    let peer_wait = peer.map(|limiter| limiter.reserve(bytes)).unwrap_or_default();
+    global_wait.max(peer_wait)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(config: BandwidthConfig) -> BandwidthThrottleRef {
+        Arc::new(BandwidthThrottle::new(config))
+    }
+
+    #[test]
+    fn test_rate_limiter_reserve() {
+        let limiter = RateLimiter::new(1000);
+
+        // burst up to one second of the rate is not delayed
+        assert_eq!(limiter.reserve(600), Duration::from_secs(0));
+        assert_eq!(limiter.reserve(300), Duration::from_secs(0));
+
+        // reserved bytes over the available tokens have to wait
+        let wait = limiter.reserve(1100);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_millis(1000), "wait: {:?}", wait);
+
+        // next reservation waits also for the previous one
+        let wait = limiter.reserve(500);
+        assert!(wait > Duration::from_millis(1400) && wait <= Duration::from_millis(1500), "wait: {:?}", wait);
+    }
+
+    #[test]
+    fn test_rate_limiter_reserve_zero_rate() {
+        let limiter = RateLimiter::new(0);
+        assert!(limiter.reserve(1) > Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_peer_without_limits() {
+        assert!(throttle(BandwidthConfig::default()).peer().is_none());
+        assert!(throttle(BandwidthConfig { max_peer_queue: Some(1), ..BandwidthConfig::default() }).peer().is_some());
+    }
+
+    #[test]
+    fn test_try_enqueue() {
+        let global = throttle(BandwidthConfig { max_peer_queue: Some(2), ..BandwidthConfig::default() });
+        let peer = global.peer().unwrap();
+        let other_peer = global.peer().unwrap();
+
+        assert!(peer.try_enqueue());
+        assert!(peer.clone().try_enqueue());
+        assert!(!peer.try_enqueue());
+        // queue is per peer
+        assert!(other_peer.try_enqueue());
+        assert_eq!(global.stats().dropped_messages, 1);
+
+        peer.dequeue();
+        assert!(peer.try_enqueue());
+        assert!(!peer.try_enqueue());
+        assert_eq!(global.stats().dropped_messages, 2);
+    }
+
+    #[test]
+    fn test_try_enqueue_unlimited_queue() {
+        let global = throttle(BandwidthConfig { peer_upload: Some(1000), ..BandwidthConfig::default() });
+        let peer = global.peer().unwrap();
+        for _ in 0..1000 {
+            assert!(peer.try_enqueue());
+        }
+        assert_eq!(global.stats().dropped_messages, 0);
+    }
+
+    #[test]
+    fn test_check_message_size() {
+        let global = throttle(BandwidthConfig { max_message_size: Some(100), ..BandwidthConfig::default() });
+        let peer = global.peer().unwrap();
+        assert!(peer.check_message_size(100));
+        assert!(!peer.check_message_size(101));
+        assert_eq!(peer.max_message_size(), Some(100));
+        assert_eq!(global.stats().rejected_messages, 1);
+
+        let global = throttle(BandwidthConfig { peer_download: Some(1000), ..BandwidthConfig::default() });
+        let peer = global.peer().unwrap();
+        assert!(peer.check_message_size(usize::MAX));
+        assert_eq!(global.stats().rejected_messages, 0);
+    }
+}
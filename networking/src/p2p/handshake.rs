@@ -0,0 +1,555 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! I/O-free state machine of the p2p handshake.
+//!
+//! Handshake consists of the (unencrypted) connection message exchange, after which both sides generate nonces
+//! and the precomputed key, and then (encrypted) exchange of metadata and ack messages:
+//!
+//! ```text
+//! new()          -> send ConnectionMessage          [ConnectionMessageSent]
+//! receive(conn)  -> check PoW, self connection,
+//!                   negotiate version, send Metadata [MetadataSent]
+//! receive(meta)  -> send Ack, or Nack (version,
+//!                   too many connections)            [AckSent] / [Rejected]
+//! receive(ack)   -> handshake result                 [Done] / [Rejected]
+//! ```
+//!
+//! The caller is responsible for the transport, it writes the returned chunks and feeds the received chunks back.
+
+use std::fmt;
+use std::sync::Arc;
+
+use failure::Fail;
+
+use crypto::crypto_box::{CryptoKey, PrecomputedKey, PublicKey};
+use crypto::hash::{CryptoboxPublicKeyHash, HashType};
+use crypto::nonce::{generate_nonces, Nonce, NoncePair};
+use crypto::proof_of_work::check_proof_of_work;
+use crypto::CryptoError;
+use tezos_encoding::binary_reader::BinaryReaderError;
+use tezos_identity::Identity;
+use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryMessage};
+use tezos_messages::p2p::encoding::ack::{NackInfo, NackMotive};
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[derive(Debug, Fail)]
+pub enum HandshakeError {
+    #[fail(display = "Message serialization error, reason: {}", error)]
+    SerializationError {
+        error: tezos_encoding::ser::Error
+    },
+    #[fail(display = "Message deserialization error, reason: {}", error)]
+    DeserializationError {
+        error: BinaryReaderError
+    },
+    #[fail(display = "Binary chunk error, reason: {}", error)]
+    BinaryChunkError {
+        error: BinaryChunkError
+    },
+    #[fail(display = "Crypto error, reason: {}", error)]
+    CryptoError {
+        error: CryptoError
+    },
+    #[fail(display = "Invalid proof of work of peer: {}, expected_pow: {}", peer_id, expected_pow)]
+    InvalidProofOfWork {
+        peer_id: String,
+        expected_pow: f64,
+    },
+    #[fail(display = "Connecting to self")]
+    ConnectingToSelf,
+    #[fail(display = "Unsupported protocol - supported_version: {} vs. {}", supported_version, incompatible_versions)]
+    UnsupportedProtocol {
+        supported_version: String,
+        incompatible_versions: String,
+    },
+    #[fail(display = "Too many connections, remote peer was rejected")]
+    TooManyConnections,
+    #[fail(display = "Received NACK from remote peer")]
+    NackReceived,
+    #[fail(display = "Received NACK from remote peer with info: {:?}", nack_info)]
+    NackWithMotiveReceived {
+        nack_info: NackInfo
+    },
+    #[fail(display = "Unexpected handshake input in state: {:?}", state)]
+    UnexpectedInput {
+        state: HandshakeState
+    },
+}
+
+impl From<tezos_encoding::ser::Error> for HandshakeError {
+    fn from(error: tezos_encoding::ser::Error) -> Self {
+        HandshakeError::SerializationError { error }
+    }
+}
+
+impl From<BinaryReaderError> for HandshakeError {
+    fn from(error: BinaryReaderError) -> Self {
+        HandshakeError::DeserializationError { error }
+    }
+}
+
+impl From<BinaryChunkError> for HandshakeError {
+    fn from(error: BinaryChunkError) -> Self {
+        HandshakeError::BinaryChunkError { error }
+    }
+}
+
+impl From<CryptoError> for HandshakeError {
+    fn from(error: CryptoError) -> Self {
+        HandshakeError::CryptoError { error }
+    }
+}
+
+/// Local side of the handshake
+#[derive(Clone, Debug)]
+pub struct HandshakeConfig {
+    /// Port where remote node can establish new connection
+    pub listener_port: u16,
+    pub identity: Arc<Identity>,
+    pub version: Arc<NetworkVersion>,
+    pub disable_mempool: bool,
+    pub private_node: bool,
+    /// Expected proof of work of the remote peer identity
+    pub expected_pow: f64,
+    /// Incoming (accepted) connection
+    pub incoming: bool,
+    /// Local node is over the connections limit, handshake ends with NACK after the metadata exchange
+    pub too_many_connections: bool,
+    /// Peers (IP:port) sent to the remote peer with NACK
+    pub potential_peers: Vec<String>,
+}
+
+/// State of the handshake, waiting for the next chunk from the remote peer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandshakeState {
+    /// Waiting for the remote connection message
+    ConnectionMessageSent,
+    /// Waiting for the remote metadata
+    MetadataSent,
+    /// Waiting for the remote ack
+    AckSent,
+    /// Handshake finished successfully
+    Done,
+    /// Handshake failed
+    Rejected,
+}
+
+/// Result of the one handshake step
+#[derive(Debug)]
+pub enum HandshakeAction {
+    /// Send chunks to the remote peer and wait for the next chunk
+    Send(Vec<BinaryChunk>),
+    /// Send chunks (e.g. NACK) to the remote peer and close the connection
+    Reject(Vec<BinaryChunk>, HandshakeError),
+    /// Handshake is done, encrypted communication can start
+    Done(HandshakeResult),
+}
+
+/// Output of the successful handshake
+pub struct HandshakeResult {
+    pub precomputed_key: PrecomputedKey,
+    /// Nonce of the next message sent to the remote peer
+    pub nonce_local: Nonce,
+    /// Nonce of the next message received from the remote peer
+    pub nonce_remote: Nonce,
+    pub peer_public_key_hash: CryptoboxPublicKeyHash,
+    pub peer_id_marker: String,
+    pub peer_metadata: MetadataMessage,
+    /// The first version supported by both sides
+    pub version: NetworkVersion,
+}
+
+impl fmt::Debug for HandshakeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakeResult")
+            .field("peer_id_marker", &self.peer_id_marker)
+            .field("peer_metadata", &self.peer_metadata)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+/// Values available after the connection messages exchange
+struct Session {
+    precomputed_key: PrecomputedKey,
+    nonce_local: Nonce,
+    nonce_remote: Nonce,
+    peer_public_key_hash: CryptoboxPublicKeyHash,
+    peer_id_marker: String,
+    remote_versions: Vec<NetworkVersion>,
+    peer_metadata: Option<MetadataMessage>,
+}
+
+pub struct Handshake {
+    config: HandshakeConfig,
+    state: HandshakeState,
+    /// Sent connection message chunk, used for nonces generation
+    connection_message_sent: BinaryChunk,
+    session: Option<Session>,
+}
+
+impl Handshake {
+    /// Starts handshake, returned chunk (connection message) has to be sent to the remote peer
+    pub fn new(config: HandshakeConfig, message_nonce: Nonce) -> Result<(Self, BinaryChunk), HandshakeError> {
+        let connection_message = ConnectionMessage::new(
+            config.listener_port,
+            &config.identity.public_key,
+            &config.identity.proof_of_work_stamp,
+            message_nonce,
+            vec![config.version.as_ref().clone()])?;
+        let connection_message_sent = BinaryChunk::from_content(&connection_message.as_bytes()?)?;
+
+        let handshake = Handshake {
+            config,
+            state: HandshakeState::ConnectionMessageSent,
+            connection_message_sent: connection_message_sent.clone(),
+            session: None,
+        };
+        Ok((handshake, connection_message_sent))
+    }
+
+    pub fn state(&self) -> HandshakeState {
+        self.state
+    }
+
+    /// Peer id of the remote peer, known after the connection message was received
+    pub fn peer_id_marker(&self) -> Option<&str> {
+        self.session.as_ref().map(|session| session.peer_id_marker.as_str())
+    }
+
+    /// Processes chunk received from the remote peer
+    pub fn receive(&mut self, chunk: BinaryChunk) -> Result<HandshakeAction, HandshakeError> {
+        let result = match self.state {
+            HandshakeState::ConnectionMessageSent => self.receive_connection_message(chunk),
+            HandshakeState::MetadataSent => self.receive_metadata(chunk),
+            HandshakeState::AckSent => self.receive_ack(chunk),
+            HandshakeState::Done | HandshakeState::Rejected => Err(HandshakeError::UnexpectedInput { state: self.state }),
+        };
+
+        match &result {
+            Ok(HandshakeAction::Send(_)) => (),
+            Ok(HandshakeAction::Done(_)) => self.state = HandshakeState::Done,
+            Ok(HandshakeAction::Reject(..)) | Err(_) => self.state = HandshakeState::Rejected,
+        }
+        result
+    }
+
+    fn receive_connection_message(&mut self, chunk: BinaryChunk) -> Result<HandshakeAction, HandshakeError> {
+        let connection_message = ConnectionMessage::from_bytes(chunk.content())?;
+
+        // create PublicKey from received bytes from remote peer
+        let peer_public_key = PublicKey::from_bytes(connection_message.public_key())?;
+        let peer_public_key_hash = peer_public_key.public_key_hash();
+        let peer_id_marker = HashType::CryptoboxPublicKeyHash.hash_to_b58check(&peer_public_key_hash);
+
+        // check proof of work of the remote identity
+        let pow_data = [connection_message.public_key().as_slice(), connection_message.proof_of_work_stamp().as_slice()].concat();
+        if check_proof_of_work(&pow_data, self.config.expected_pow).is_err() {
+            return Err(HandshakeError::InvalidProofOfWork { peer_id: peer_id_marker, expected_pow: self.config.expected_pow });
+        }
+
+        if peer_public_key == self.config.identity.public_key {
+            return Err(HandshakeError::ConnectingToSelf);
+        }
+
+        // generate local and remote nonce
+        let NoncePair { local: nonce_local, remote: nonce_remote } = generate_nonces(self.connection_message_sent.raw(), chunk.raw(), self.config.incoming);
+
+        // pre-compute encryption key, from now on all messages will be encrypted
+        let precomputed_key = PrecomputedKey::precompute(&peer_public_key, &self.config.identity.secret_key);
+
+        let mut session = Session {
+            precomputed_key,
+            nonce_local,
+            nonce_remote,
+            peer_public_key_hash,
+            peer_id_marker,
+            remote_versions: connection_message.versions().clone(),
+            peer_metadata: None,
+        };
+
+        // send metadata
+        let metadata = MetadataMessage::new(self.config.disable_mempool, self.config.private_node);
+        let metadata_chunk = session.encrypt(&metadata)?;
+        self.session = Some(session);
+        self.state = HandshakeState::MetadataSent;
+        Ok(HandshakeAction::Send(vec![metadata_chunk]))
+    }
+
+    fn receive_metadata(&mut self, chunk: BinaryChunk) -> Result<HandshakeAction, HandshakeError> {
+        let config = &self.config;
+        let session = self.session.as_mut().ok_or(HandshakeError::UnexpectedInput { state: self.state })?;
+        session.peer_metadata = Some(session.decrypt::<MetadataMessage>(&chunk)?);
+
+        // version negotiation
+        if negotiate_version(&config.version, &session.remote_versions).is_none() {
+            let motive = version_nack_motive(&config.version, &session.remote_versions);
+            let nack_chunk = session.encrypt(&nack_message(motive, &session.remote_versions, &config.potential_peers))?;
+            return Ok(HandshakeAction::Reject(vec![nack_chunk], HandshakeError::UnsupportedProtocol {
+                supported_version: format!("{:?}", &config.version),
+                incompatible_versions: format!("{:?}", &session.remote_versions),
+            }));
+        }
+
+        // connections limit, remote peer gets other peers to connect to
+        if config.too_many_connections {
+            let nack_chunk = session.encrypt(&nack_message(NackMotive::TooManyConnections, &session.remote_versions, &config.potential_peers))?;
+            return Ok(HandshakeAction::Reject(vec![nack_chunk], HandshakeError::TooManyConnections));
+        }
+
+        // send ack
+        let ack_chunk = session.encrypt(&AckMessage::Ack)?;
+        self.state = HandshakeState::AckSent;
+        Ok(HandshakeAction::Send(vec![ack_chunk]))
+    }
+
+    fn receive_ack(&mut self, chunk: BinaryChunk) -> Result<HandshakeAction, HandshakeError> {
+        let mut session = self.session.take().ok_or(HandshakeError::UnexpectedInput { state: self.state })?;
+        match session.decrypt::<AckMessage>(&chunk)? {
+            AckMessage::Ack => {
+                let version = negotiate_version(&self.config.version, &session.remote_versions)
+                    .ok_or(HandshakeError::UnexpectedInput { state: self.state })?;
+                let peer_metadata = session.peer_metadata
+                    .ok_or(HandshakeError::UnexpectedInput { state: self.state })?;
+                Ok(HandshakeAction::Done(HandshakeResult {
+                    precomputed_key: session.precomputed_key,
+                    nonce_local: session.nonce_local,
+                    nonce_remote: session.nonce_remote,
+                    peer_public_key_hash: session.peer_public_key_hash,
+                    peer_id_marker: session.peer_id_marker,
+                    peer_metadata,
+                    version,
+                }))
+            }
+            AckMessage::NackV0 => Err(HandshakeError::NackReceived),
+            AckMessage::Nack(nack_info) => Err(HandshakeError::NackWithMotiveReceived { nack_info }),
+        }
+    }
+}
+
+impl Session {
+    fn encrypt(&mut self, message: &impl BinaryMessage) -> Result<BinaryChunk, HandshakeError> {
+        let nonce = self.nonce_local.clone();
+        self.nonce_local = nonce.increment();
+        let encrypted = self.precomputed_key.encrypt(&message.as_bytes()?, &nonce)?;
+        Ok(BinaryChunk::from_content(&encrypted)?)
+    }
+
+    fn decrypt<M: BinaryMessage>(&mut self, chunk: &BinaryChunk) -> Result<M, HandshakeError> {
+        let nonce = self.nonce_remote.clone();
+        self.nonce_remote = nonce.increment();
+        let decrypted = self.precomputed_key.decrypt(chunk.content(), &nonce)?;
+        Ok(M::from_bytes(decrypted)?)
+    }
+}
+
+/// Returns the first remote version supported by the local version
+fn negotiate_version(local: &NetworkVersion, remote_versions: &[NetworkVersion]) -> Option<NetworkVersion> {
+    remote_versions.iter().find(|version| local.supports(version)).cloned()
+}
+
+fn version_nack_motive(local: &NetworkVersion, remote_versions: &[NetworkVersion]) -> NackMotive {
+    if remote_versions.iter().any(|version| version.chain_name() == local.chain_name()) {
+        NackMotive::DeprecatedDistributedDbVersion
+    } else {
+        NackMotive::UnknownChainName
+    }
+}
+
+/// NACK with motive is understood only by peers with p2p version >= 1
+fn nack_message(motive: NackMotive, remote_versions: &[NetworkVersion], potential_peers: &[String]) -> AckMessage {
+    if remote_versions.iter().all(|version| *version.p2p_version() < 1) {
+        AckMessage::NackV0
+    } else {
+        AckMessage::Nack(NackInfo::new(motive, potential_peers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    // Handshake transcript between the identity below (sending side, outgoing connection) and a carthagenet node,
+    // taken from `tezos/messages/benches/tezedge_communication.csv`
+    const IDENTITY: &str = r#"{"peer_id":"idqjq34sB6uHWdFzJDB97zwXAdHASb","public_key":"40a2c1e551c1e48b89e2a13bb58114f613801439ec6c4fc3396799768a748b6e","secret_key":"66adf08839c350109d8955a92c6561f966d6a048968763d12b7026c7e368fbe1","proof_of_work_stamp":"ff6d772406b595e2ae0fd5c640a62a47212f21cbbcaac6b4"}"#;
+    const CONNECTION_SENT: &str = "0086260440a2c1e551c1e48b89e2a13bb58114f613801439ec6c4fc3396799768a748b6eff6d772406b595e2ae0fd5c640a62a47212f21cbbcaac6b430f1069e8db55d9506a7bdb07be08805a6287d6848f5dfef0000002c54455a4f535f414c5048414e45545f43415254484147455f323031392d31312d32385431333a30323a31335a00000001";
+    const CONNECTION_RECEIVED: &str = "00862604e20639fd5df66ebbf5a480093778a12d3139e37d94fc97cba9992cbcb083a971815f987cc7eaf3b774cd500ed5c6c55e06e584fedd59767986605f5d933f3104f9b0164c826a21eaad5e9430e76d1cb60000002c54455a4f535f414c5048414e45545f43415254484147455f323031392d31312d32385431333a30323a31335a00000001";
+    const METADATA_SENT: &str = "0012501315db7509996b061e1b2823e70a6072bd";
+    const METADATA_RECEIVED: &str = "0012ee039c3446e496eb29bc1c65859c77aa30d9";
+    const ACK_SENT: &str = "001130e8b60f4cadb54fafdad7f40eef3e2385";
+    const ACK_RECEIVED: &str = "00112d52b63dedb4e6eae4a1f4fa62f08da66e";
+    /// The first message received after handshake
+    const FIRST_MESSAGE_RECEIVED: &str = "001a603f34e0ace75b55bb275b814e791d61c49c318d0bc2ea2625dc";
+
+    fn chunk(hex_data: &str) -> BinaryChunk {
+        BinaryChunk::try_from(hex::decode(hex_data).unwrap()).unwrap()
+    }
+
+    fn config(version: NetworkVersion, expected_pow: f64) -> HandshakeConfig {
+        HandshakeConfig {
+            listener_port: 9732,
+            identity: Arc::new(Identity::from_json(IDENTITY).unwrap()),
+            version: Arc::new(version),
+            disable_mempool: false,
+            private_node: false,
+            expected_pow,
+            incoming: false,
+            too_many_connections: false,
+            potential_peers: vec!["127.0.0.1:9733".to_string(), "[2001:db8::1]:9732".to_string()],
+        }
+    }
+
+    fn carthagenet_version() -> NetworkVersion {
+        NetworkVersion::new("TEZOS_ALPHANET_CARTHAGE_2019-11-28T13:02:13Z".to_string(), 0, 1)
+    }
+
+    /// Nonce of the sent connection message
+    fn message_nonce() -> Nonce {
+        let connection_sent = hex::decode(CONNECTION_SENT).unwrap();
+        // 2 chunk length + 2 port + 32 public key + 24 pow
+        Nonce::new(&connection_sent[60..84])
+    }
+
+    #[test]
+    fn test_handshake_transcript() -> Result<(), failure::Error> {
+        let (mut handshake, connection_sent) = Handshake::new(config(carthagenet_version(), 0.0), message_nonce())?;
+        assert_eq!(CONNECTION_SENT, hex::encode(connection_sent.raw()));
+        assert_eq!(HandshakeState::ConnectionMessageSent, handshake.state());
+
+        match handshake.receive(chunk(CONNECTION_RECEIVED))? {
+            HandshakeAction::Send(chunks) => {
+                assert_eq!(1, chunks.len());
+                assert_eq!(METADATA_SENT, hex::encode(chunks[0].raw()));
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+        assert_eq!(HandshakeState::MetadataSent, handshake.state());
+        assert!(handshake.peer_id_marker().is_some());
+
+        match handshake.receive(chunk(METADATA_RECEIVED))? {
+            HandshakeAction::Send(chunks) => {
+                assert_eq!(1, chunks.len());
+                assert_eq!(ACK_SENT, hex::encode(chunks[0].raw()));
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+        assert_eq!(HandshakeState::AckSent, handshake.state());
+
+        let result = match handshake.receive(chunk(ACK_RECEIVED))? {
+            HandshakeAction::Done(result) => result,
+            action => panic!("Unexpected action: {:?}", action),
+        };
+        assert_eq!(HandshakeState::Done, handshake.state());
+        assert_eq!(carthagenet_version(), result.version);
+
+        // nonces continue with the first message after handshake
+        let first_message = chunk(FIRST_MESSAGE_RECEIVED);
+        let decrypted = result.precomputed_key.decrypt(first_message.content(), &result.nonce_remote)?;
+        assert!(PeerMessageResponse::from_bytes(decrypted).is_ok());
+
+        // no more input is expected
+        assert!(matches!(handshake.receive(chunk(ACK_RECEIVED)), Err(HandshakeError::UnexpectedInput { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_invalid_proof_of_work() -> Result<(), failure::Error> {
+        let (mut handshake, _) = Handshake::new(config(carthagenet_version(), 255.0), message_nonce())?;
+        assert!(matches!(handshake.receive(chunk(CONNECTION_RECEIVED)), Err(HandshakeError::InvalidProofOfWork { .. })));
+        assert_eq!(HandshakeState::Rejected, handshake.state());
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_connecting_to_self() -> Result<(), failure::Error> {
+        let (mut handshake, connection_sent) = Handshake::new(config(carthagenet_version(), 0.0), message_nonce())?;
+        assert!(matches!(handshake.receive(connection_sent), Err(HandshakeError::ConnectingToSelf)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_unsupported_version_sends_nack_with_motive() -> Result<(), failure::Error> {
+        // local side does not support the remote chain
+        let remote_identity = Identity::generate(0f64);
+        let (mut local, local_connection) = Handshake::new(config(NetworkVersion::new("TEZOS_MAINNET".to_string(), 0, 1), 0.0), Nonce::random())?;
+        let (_, remote_connection) = Handshake::new(
+            HandshakeConfig {
+                identity: Arc::new(remote_identity.clone()),
+                incoming: true,
+                ..config(carthagenet_version(), 0.0)
+            },
+            Nonce::random())?;
+
+        assert!(matches!(local.receive(remote_connection.clone())?, HandshakeAction::Send(_)));
+
+        // remote side encrypts with the same key and nonces as the local side
+        let precomputed_key = PrecomputedKey::precompute(&local.config.identity.public_key, &remote_identity.secret_key);
+        let NoncePair { local: remote_nonce, remote: local_nonce } = generate_nonces(remote_connection.raw(), local_connection.raw(), true);
+        let remote_metadata = BinaryChunk::from_content(&precomputed_key.encrypt(&MetadataMessage::new(false, false).as_bytes()?, &remote_nonce)?)?;
+
+        let nack = match local.receive(remote_metadata)? {
+            HandshakeAction::Reject(mut chunks, error) => {
+                assert!(matches!(error, HandshakeError::UnsupportedProtocol { .. }));
+                chunks.remove(0)
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        };
+        assert_eq!(HandshakeState::Rejected, local.state());
+
+        // nack follows the local metadata
+        match AckMessage::from_bytes(precomputed_key.decrypt(nack.content(), &local_nonce.increment())?)? {
+            AckMessage::Nack(nack_info) => {
+                assert!(NackMotive::UnknownChainName == *nack_info.motive());
+                assert_eq!(&vec!["127.0.0.1:9733".to_string(), "[2001:db8::1]:9732".to_string()], nack_info.potential_peers_to_connect());
+            }
+            ack => panic!("Unexpected ack: {:?}", ack),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_too_many_connections_sends_nack_with_potential_peers() -> Result<(), failure::Error> {
+        // local side accepts the connection over the connections limit
+        let (mut local, local_connection) = Handshake::new(
+            HandshakeConfig {
+                incoming: true,
+                too_many_connections: true,
+                ..config(carthagenet_version(), 0.0)
+            },
+            Nonce::random())?;
+        let (mut remote, remote_connection) = Handshake::new(
+            HandshakeConfig {
+                identity: Arc::new(Identity::generate(0f64)),
+                ..config(carthagenet_version(), 0.0)
+            },
+            Nonce::random())?;
+
+        let local_metadata = match local.receive(remote_connection)? {
+            HandshakeAction::Send(mut chunks) => chunks.remove(0),
+            action => panic!("Unexpected action: {:?}", action),
+        };
+        let remote_metadata = match remote.receive(local_connection)? {
+            HandshakeAction::Send(mut chunks) => chunks.remove(0),
+            action => panic!("Unexpected action: {:?}", action),
+        };
+        assert!(matches!(remote.receive(local_metadata)?, HandshakeAction::Send(_)));
+
+        let nack = match local.receive(remote_metadata)? {
+            HandshakeAction::Reject(mut chunks, HandshakeError::TooManyConnections) => chunks.remove(0),
+            action => panic!("Unexpected action: {:?}", action),
+        };
+        assert_eq!(HandshakeState::Rejected, local.state());
+
+        match remote.receive(nack) {
+            Err(HandshakeError::NackWithMotiveReceived { nack_info }) => {
+                assert!(NackMotive::TooManyConnections == *nack_info.motive());
+                assert_eq!(&vec!["127.0.0.1:9733".to_string(), "[2001:db8::1]:9732".to_string()], nack_info.potential_peers_to_connect());
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        Ok(())
+    }
+}
@@ -3,6 +3,10 @@
 
 //! This module handles low level p2p communication.
 
+pub mod bandwidth;
+pub mod handshake;
 pub mod stream;
 pub mod peer;
 pub mod network_channel;
+pub mod recorder;
+pub mod replay;
@@ -14,19 +14,21 @@ use tokio::net::TcpStream;
 use tokio::runtime::Handle;
 use tokio::time::timeout;
 
-use crypto::crypto_box::{CryptoKey, PrecomputedKey, PublicKey};
-use crypto::hash::{CryptoboxPublicKeyHash, HashType};
-use crypto::nonce::{self, Nonce, NoncePair};
+use crypto::hash::CryptoboxPublicKeyHash;
+use crypto::nonce::Nonce;
 use tezos_encoding::binary_reader::BinaryReaderError;
 use tezos_identity::Identity;
-use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryMessage};
+use tezos_messages::p2p::binary_message::{BinaryChunkError, BinaryMessage};
 use tezos_messages::p2p::encoding::ack::{NackInfo, NackMotive};
 use tezos_messages::p2p::encoding::prelude::*;
 
 use crate::p2p::network_channel::NetworkChannelMsg;
 use crate::PeerId;
 
+use super::bandwidth::{BandwidthThrottle, BandwidthThrottleRef, PeerBandwidth};
+use super::handshake::{Handshake, HandshakeAction, HandshakeConfig, HandshakeError};
 use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerBootstrapFailed, PeerMessageReceived};
+use super::recorder::{MessageDirection, MessageRecorderRef, PeerMessageRecorder};
 use super::stream::{EncryptedMessageReader, EncryptedMessageWriter, MessageStream, StreamError};
 
 const IO_TIMEOUT: Duration = Duration::from_secs(6);
@@ -65,6 +67,10 @@ pub enum PeerError {
     CryptoError {
         error: crypto::CryptoError
     },
+    #[fail(display = "Handshake error, reason: {}", error)]
+    HandshakeError {
+        error: HandshakeError
+    },
 }
 
 impl From<tezos_encoding::ser::Error> for PeerError {
@@ -103,6 +109,21 @@ impl From<crypto::CryptoError> for PeerError {
     }
 }
 
+impl From<HandshakeError> for PeerError {
+    fn from(error: HandshakeError) -> Self {
+        match error {
+            HandshakeError::SerializationError { error } => PeerError::SerializationError { error },
+            HandshakeError::DeserializationError { error } => PeerError::DeserializationError { error },
+            HandshakeError::BinaryChunkError { error } => error.into(),
+            HandshakeError::CryptoError { error } => PeerError::CryptoError { error },
+            HandshakeError::UnsupportedProtocol { supported_version, incompatible_versions } => PeerError::UnsupportedProtocol { supported_version, incompatible_versions },
+            HandshakeError::NackReceived => PeerError::NackReceived,
+            HandshakeError::NackWithMotiveReceived { nack_info } => PeerError::NackWithMotiveReceived { nack_info },
+            error => PeerError::HandshakeError { error },
+        }
+    }
+}
+
 impl From<tokio::time::Elapsed> for PeerError {
     fn from(timeout: tokio::time::Elapsed) -> Self {
         PeerError::NetworkError {
@@ -120,15 +141,26 @@ pub struct Bootstrap {
     incoming: bool,
     disable_mempool: bool,
     private_node: bool,
+    /// Expected proof of work of the remote peer identity
+    expected_pow: f64,
+    /// Local node is over the connections limit, remote peer is rejected with NACK
+    too_many_connections: bool,
+    /// Peers sent to the rejected remote peer
+    potential_peers: Vec<SocketAddr>,
 }
 
 impl Bootstrap {
-    pub fn incoming(stream: Arc<Mutex<Option<TcpStream>>>, address: SocketAddr, disable_mempool: bool, private_node: bool) -> Self {
-        Bootstrap { stream, address, incoming: true, disable_mempool, private_node }
+    pub fn incoming(stream: Arc<Mutex<Option<TcpStream>>>, address: SocketAddr, disable_mempool: bool, private_node: bool, expected_pow: f64) -> Self {
+        Bootstrap { stream, address, incoming: true, disable_mempool, private_node, expected_pow, too_many_connections: false, potential_peers: vec![] }
     }
 
-    pub fn outgoing(stream: TcpStream, address: SocketAddr, disable_mempool: bool, private_node: bool) -> Self {
-        Bootstrap { stream: Arc::new(Mutex::new(Some(stream))), address, incoming: false, disable_mempool, private_node }
+    /// Incoming connection over the connections limit, handshake ends with `TooManyConnections` NACK carrying `potential_peers`
+    pub fn incoming_rejected(stream: Arc<Mutex<Option<TcpStream>>>, address: SocketAddr, disable_mempool: bool, private_node: bool, expected_pow: f64, potential_peers: Vec<SocketAddr>) -> Self {
+        Bootstrap { stream, address, incoming: true, disable_mempool, private_node, expected_pow, too_many_connections: true, potential_peers }
+    }
+
+    pub fn outgoing(stream: TcpStream, address: SocketAddr, disable_mempool: bool, private_node: bool, expected_pow: f64) -> Self {
+        Bootstrap { stream: Arc::new(Mutex::new(Some(stream))), address, incoming: false, disable_mempool, private_node, expected_pow, too_many_connections: false, potential_peers: vec![] }
     }
 }
 
@@ -154,6 +186,10 @@ struct Network {
     tx: Arc<Mutex<Option<EncryptedMessageWriter>>>,
     /// Socket address of the peer
     socket_address: SocketAddr,
+    /// Message recorder, set after successful bootstrap (if recording is enabled)
+    recorder: Arc<Mutex<Option<PeerMessageRecorder>>>,
+    /// Bandwidth limits of this peer
+    bandwidth: Option<PeerBandwidth>,
 }
 
 /// Local node info
@@ -164,6 +200,10 @@ pub struct Local {
     identity: Arc<Identity>,
     /// version of network protocol
     version: Arc<NetworkVersion>,
+    /// Optional recorder of all messages exchanged with peer
+    message_recorder: Option<MessageRecorderRef>,
+    /// Optional global bandwidth limits
+    bandwidth: Option<BandwidthThrottleRef>,
 }
 
 impl Local {
@@ -172,8 +212,20 @@ impl Local {
             listener_port,
             identity,
             version: network_version,
+            message_recorder: None,
+            bandwidth: None,
         }
     }
+
+    pub fn with_message_recorder(mut self, message_recorder: Option<MessageRecorderRef>) -> Self {
+        self.message_recorder = message_recorder;
+        self
+    }
+
+    pub fn with_bandwidth(mut self, bandwidth: Option<BandwidthThrottleRef>) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
 }
 
 pub type PeerRef = ActorRef<PeerMsg>;
@@ -195,15 +247,20 @@ pub struct Peer {
 
 impl Peer {
     /// Create instance of a peer actor.
+    #[allow(clippy::too_many_arguments)]
     pub fn actor(sys: &impl ActorRefFactory,
                  network_channel: NetworkChannelRef,
                  listener_port: u16,
                  node_identity: Arc<Identity>,
                  version: Arc<NetworkVersion>,
                  tokio_executor: Handle,
-                 socket_address: &SocketAddr) -> Result<PeerRef, CreateError>
+                 socket_address: &SocketAddr,
+                 message_recorder: Option<MessageRecorderRef>,
+                 bandwidth: Option<BandwidthThrottleRef>) -> Result<PeerRef, CreateError>
     {
-        let info = Local::new(listener_port, node_identity, version);
+        let info = Local::new(listener_port, node_identity, version)
+            .with_message_recorder(message_recorder)
+            .with_bandwidth(bandwidth);
         let props = Props::new_args::<Peer, _>((network_channel, Arc::new(info), tokio_executor, *socket_address));
         let actor_id = ACTOR_ID_GENERATOR.fetch_add(1, Ordering::SeqCst);
         sys.actor_of_props(&format!("peer-{}", actor_id), props)
@@ -219,6 +276,8 @@ impl ActorFactoryArgs<(NetworkChannelRef, Arc<Local>, Handle, SocketAddr)> for P
                 rx_run: Arc::new(AtomicBool::new(false)),
                 tx: Arc::new(Mutex::new(None)),
                 socket_address,
+                recorder: Arc::new(Mutex::new(None)),
+                bandwidth: info.bandwidth.as_ref().and_then(BandwidthThrottle::peer),
             },
             tokio_executor,
             remote_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)),
@@ -251,15 +310,17 @@ impl Receive<Bootstrap> for Peer {
         self.remote_addr = msg.address;
 
         self.tokio_executor.spawn(async move {
-            async fn setup_net(net: &Network, tx: EncryptedMessageWriter) {
+            async fn setup_net(net: &Network, tx: EncryptedMessageWriter, recorder: Option<PeerMessageRecorder>) {
                 net.rx_run.store(true, Ordering::Release);
+                *net.recorder.lock().await = recorder;
                 *net.tx.lock().await = Some(tx);
             }
 
             let peer_address = msg.address;
             debug!(system.log(), "Bootstrapping"; "ip" => &peer_address, "peer" => myself.name(), "peer_uri" => myself.uri().to_string());
+            let message_recorder = info.message_recorder.clone();
             match bootstrap(msg, info, &system.log()).await {
-                Ok(BootstrapOutput(rx, tx, peer_public_key_hash, peer_id_marker, peer_metadata)) => {
+                Ok(BootstrapOutput(mut rx, mut tx, peer_public_key_hash, peer_id_marker, peer_metadata)) => {
                     // prepare PeerId
                     let peer_id = PeerId::new(myself.clone(), peer_public_key_hash, peer_id_marker, peer_address);
                     let log = {
@@ -269,8 +330,13 @@ impl Receive<Bootstrap> for Peer {
                     };
                     debug!(log, "Bootstrap successful"; "peer_metadata" => format!("{:?}", &peer_metadata));
 
-                    // setup encryption writer
-                    setup_net(&net, tx).await;
+                    // apply bandwidth limits
+                    rx.set_bandwidth(net.bandwidth.clone());
+                    tx.set_bandwidth(net.bandwidth.clone());
+
+                    // setup encryption writer (and message recorder)
+                    let recorder = message_recorder.map(|recorder| PeerMessageRecorder::new(recorder, peer_id.peer_id_marker.clone(), peer_address));
+                    setup_net(&net, tx, recorder).await;
 
                     // notify that peer was bootstrapped successfully
                     network_channel.tell(Publish {
@@ -284,6 +350,11 @@ impl Receive<Bootstrap> for Peer {
                     // connection to peer was closed, stop this actor
                     system.stop(myself);
                 }
+                Err(PeerError::HandshakeError { error: HandshakeError::TooManyConnections }) => {
+                    // rejected by us, it is not a failure of the remote peer
+                    debug!(system.log(), "Peer rejected because of too many connections"; "ip" => &peer_address, "peer" => myself.name(), "peer_uri" => myself.uri().to_string());
+                    system.stop(myself);
+                }
                 Err(err) => {
                     warn!(system.log(), "Connection to peer failed"; "reason" => format!("{}", &err), "ip" => &peer_address, "peer" => myself.name(), "peer_uri" => myself.uri().to_string());
 
@@ -317,10 +388,25 @@ impl Receive<SendMessage> for Peer {
         let system = ctx.system.clone();
         let myself = ctx.myself();
         let tx = self.net.tx.clone();
+        let recorder = self.net.recorder.clone();
+        let bandwidth = self.net.bandwidth.clone();
+        if let Some(bandwidth) = bandwidth.as_ref() {
+            if !bandwidth.try_enqueue() {
+                debug!(system.log(), "Outgoing message queue is full, message is dropped"; "peer" => myself.name());
+                return;
+            }
+        }
         self.tokio_executor.spawn(async move {
             let mut tx_lock = tx.lock().await;
             if let Some(tx) = tx_lock.as_mut() {
-                let write_result = timeout(IO_TIMEOUT, tx.write_message(msg.message.as_ref())).await;
+                // message is encoded just once, the same bytes are sent and recorded
+                let message_bytes = msg.message.as_bytes();
+                let write_result = match (message_bytes.as_ref(), bandwidth.as_ref()) {
+                    (Err(e), _) => Ok(Err(StreamError::from(e.clone()))),
+                    // throttled message can take long to send, so timeout applies to the chunks
+                    (Ok(message_bytes), Some(_)) => Ok(tx.write_message_bytes(message_bytes, Some(IO_TIMEOUT)).await),
+                    (Ok(message_bytes), None) => timeout(IO_TIMEOUT, tx.write_message_bytes(message_bytes, None)).await,
+                };
                 // release mutex as soon as possible
                 drop(tx_lock);
 
@@ -329,6 +415,8 @@ impl Receive<SendMessage> for Peer {
                         if let Err(e) = write_result {
                             warn!(system.log(), "Failed to send message"; "reason" => e);
                             system.stop(myself);
+                        } else if let (Some(recorder), Ok(message_bytes)) = (recorder.lock().await.as_ref(), message_bytes) {
+                            recorder.record(MessageDirection::Outgoing, message_bytes, &system.log());
                         }
                     }
                     Err(_) => {
@@ -337,6 +425,9 @@ impl Receive<SendMessage> for Peer {
                     }
                 }
             }
+            if let Some(bandwidth) = bandwidth {
+                bandwidth.dequeue();
+            }
         });
     }
 }
@@ -355,126 +446,88 @@ pub async fn bootstrap(
         msg_reader.split()
     };
 
-    let supported_protocol_version = &info.version;
-
-    // send connection message
-    let connection_message = ConnectionMessage::new(
-        info.listener_port,
-        &info.identity.public_key,
-        &info.identity.proof_of_work_stamp,
+    let (mut handshake, connection_message_sent) = Handshake::new(
+        HandshakeConfig {
+            listener_port: info.listener_port,
+            identity: info.identity.clone(),
+            version: info.version.clone(),
+            disable_mempool: msg.disable_mempool,
+            private_node: msg.private_node,
+            expected_pow: msg.expected_pow,
+            incoming: msg.incoming,
+            too_many_connections: msg.too_many_connections,
+            potential_peers: msg.potential_peers.iter().map(SocketAddr::to_string).collect(),
+        },
         Nonce::random(),
-        vec![supported_protocol_version.as_ref().clone()])?;
-    let connection_message_sent = {
-        let connection_message_bytes = BinaryChunk::from_content(&connection_message.as_bytes()?)?;
-        match timeout(IO_TIMEOUT, msg_tx.write_message(&connection_message_bytes)).await? {
-            Ok(_) => connection_message_bytes,
-            Err(e) => return Err(PeerError::NetworkError { error: e.into(), message: "Failed to transfer connection message" })
-        }
-    };
+    )?;
 
-    // receive connection message
-    let received_connection_message_bytes = match timeout(IO_TIMEOUT, msg_rx.read_message()).await? {
-        Ok(msg) => msg,
-        Err(e) => return Err(PeerError::NetworkError { error: e.into(), message: "No response to connection message was received" })
-    };
-
-    let connection_message = ConnectionMessage::from_bytes(received_connection_message_bytes.content())?;
-
-    // generate local and remote nonce
-    let NoncePair { local: nonce_local, remote: nonce_remote } = generate_nonces(&connection_message_sent, &received_connection_message_bytes, msg.incoming);
-
-    // create PublicKey from received bytes from remote peer
-    let peer_public_key = PublicKey::from_bytes(connection_message.public_key())?;
-
-    // pre-compute encryption key
-    let precomputed_key = PrecomputedKey::precompute(&peer_public_key, &info.identity.secret_key);
-
-    // generate public key hash for PublicKey, which will be used as a peer_id
-    let peer_public_key_hash = peer_public_key.public_key_hash();
-    let peer_id_marker = HashType::CryptoboxPublicKeyHash.hash_to_b58check(&peer_public_key_hash);
-    let log = log.new(o!("peer_id" => peer_id_marker.clone()));
-
-    // from now on all messages will be encrypted
-    let mut msg_tx = EncryptedMessageWriter::new(
-        msg_tx,
-        precomputed_key.clone(),
-        nonce_local,
-        log.clone(),
-    );
-    let mut msg_rx = EncryptedMessageReader::new(
-        msg_rx,
-        precomputed_key,
-        nonce_remote,
-        log.clone(),
-    );
-
-    let connecting_to_self = peer_public_key == info.identity.public_key;
-    if connecting_to_self {
-        debug!(log, "Detected self connection");
-        // treat as if nack was received
-        return Err(PeerError::NackWithMotiveReceived { nack_info: NackInfo::new(NackMotive::AlreadyConnected, &[]) });
+    // send connection message
+    if let Err(e) = timeout(IO_TIMEOUT, msg_tx.write_message(&connection_message_sent)).await? {
+        return Err(PeerError::NetworkError { error: e.into(), message: "Failed to transfer connection message" });
     }
 
-    // send metadata
-    let metadata = MetadataMessage::new(msg.disable_mempool, msg.private_node);
-    timeout(IO_TIMEOUT, msg_tx.write_message(&metadata)).await??;
+    loop {
+        let received = match timeout(IO_TIMEOUT, msg_rx.read_message()).await? {
+            Ok(chunk) => chunk,
+            Err(e) => return Err(PeerError::NetworkError { error: e.into(), message: "No response to handshake message was received" })
+        };
 
-    // receive metadata
-    let metadata_received = timeout(IO_TIMEOUT, msg_rx.read_message::<MetadataMessage>()).await??;
-    debug!(log, "Received remote peer metadata"; "disable_mempool" => metadata_received.disable_mempool(), "private_node" => metadata_received.private_node());
-
-    let protocol_not_supported = !connection_message.versions().iter().any(|version| supported_protocol_version.supports(version));
-    if protocol_not_supported {
-        // send nack
-        timeout(IO_TIMEOUT, msg_tx.write_message(&AckMessage::NackV0)).await??;
-
-        return Err(
-            PeerError::UnsupportedProtocol {
-                supported_version: format!("{:?}", &supported_protocol_version),
-                incompatible_versions: format!("{:?}", &connection_message.versions()),
+        match handshake.receive(received) {
+            Ok(HandshakeAction::Send(chunks)) => {
+                for chunk in chunks {
+                    timeout(IO_TIMEOUT, msg_tx.write_message(&chunk)).await??;
+                }
+            }
+            Ok(HandshakeAction::Reject(chunks, error)) => {
+                for chunk in chunks {
+                    timeout(IO_TIMEOUT, msg_tx.write_message(&chunk)).await??;
+                }
+                return Err(error.into());
+            }
+            Ok(HandshakeAction::Done(result)) => {
+                let log = log.new(o!("peer_id" => result.peer_id_marker.clone()));
+                debug!(log, "Received ACK"; "disable_mempool" => result.peer_metadata.disable_mempool(), "private_node" => result.peer_metadata.private_node());
+
+                // from now on all messages will be encrypted
+                let msg_tx = EncryptedMessageWriter::new(
+                    msg_tx,
+                    result.precomputed_key.clone(),
+                    result.nonce_local,
+                    log.clone(),
+                );
+                let msg_rx = EncryptedMessageReader::new(
+                    msg_rx,
+                    result.precomputed_key,
+                    result.nonce_remote,
+                    log,
+                );
+                return Ok(BootstrapOutput(msg_rx, msg_tx, result.peer_public_key_hash, result.peer_id_marker, result.peer_metadata));
+            }
+            Err(HandshakeError::ConnectingToSelf) => {
+                debug!(log, "Detected self connection");
+                // treat as if nack was received
+                return Err(PeerError::NackWithMotiveReceived { nack_info: NackInfo::new(NackMotive::AlreadyConnected, &[]) });
+            }
+            Err(e) => {
+                debug!(log, "Handshake failed"; "reason" => format!("{}", e));
+                return Err(e.into());
             }
-        );
-    }
-
-    // send ack
-    timeout(IO_TIMEOUT, msg_tx.write_message(&AckMessage::Ack)).await??;
-
-    // receive ack
-    let ack_received = timeout(IO_TIMEOUT, msg_rx.read_message()).await??;
-
-    match ack_received {
-        AckMessage::Ack => {
-            debug!(log, "Received ACK");
-            Ok(BootstrapOutput(msg_rx, msg_tx, peer_public_key_hash, peer_id_marker, metadata_received))
-        }
-        AckMessage::NackV0 => {
-            debug!(log, "Received NACK");
-            Err(PeerError::NackReceived)
-        }
-        AckMessage::Nack(nack_info) => {
-            debug!(log, "Received NACK with info: {:?}", nack_info);
-            Err(PeerError::NackWithMotiveReceived { nack_info })
         }
     }
 }
 
-
-/// Generate nonces (sent and recv encoding must be with length bytes also)
-///
-/// local_nonce is used for writing crypto messages to other peers
-/// remote_nonce is used for reading crypto messages from other peers
-fn generate_nonces(sent_msg: &BinaryChunk, recv_msg: &BinaryChunk, incoming: bool) -> NoncePair {
-    nonce::generate_nonces(sent_msg.raw(), recv_msg.raw(), incoming)
-}
-
 /// Start to process incoming data
 async fn begin_process_incoming(mut rx: EncryptedMessageReader, net: Network, myself: PeerRef, event_channel: NetworkChannelRef, log: Logger) {
     info!(log, "Starting to accept messages");
+    let recorder = net.recorder.lock().await.clone();
 
     while net.rx_run.load(Ordering::Acquire) {
-        match timeout(READ_TIMEOUT_LONG, rx.read_message::<PeerMessageResponse>()).await {
+        match timeout(READ_TIMEOUT_LONG, rx.read_message_with_bytes::<PeerMessageResponse>()).await {
             Ok(res) => match res {
-                Ok(msg) => {
+                Ok((msg, msg_bytes)) => {
+                    if let Some(recorder) = recorder.as_ref() {
+                        recorder.record(MessageDirection::Incoming, msg_bytes, &log);
+                    }
                     let should_broadcast_message = net.rx_run.load(Ordering::Acquire);
                     if should_broadcast_message {
                         trace!(log, "Message parsed successfully"; "msg" => format!("{:?}", &msg));
@@ -0,0 +1,500 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Optional recorder of all (decrypted) p2p messages exchanged with peers, used to debug synchronization problems.
+//!
+//! Messages are queued by the peers and written by a dedicated writer thread to rotating files in the configured directory.
+//! Every file is a sequence of records,
+//! every record is framed similar to [`BinaryChunk`](tezos_messages::p2p::binary_message::BinaryChunk), just with 4-byte size:
+//!
+//! ```text
+//! record  = size: u32 | content
+//! content = direction: u8 | timestamp_millis: u64 | peer_id_size: u16 | peer_id (b58check) | address_size: u16 | address | message
+//! ```
+//!
+//! where `message` contains bytes of the [`PeerMessageResponse`], exactly the bytes received from (sent to) the decrypted stream.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use slog::{warn, Logger};
+
+use tezos_encoding::binary_reader::BinaryReaderError;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+
+/// Recording files are named `<RECORDING_FILE_PREFIX><index>.<RECORDING_FILE_EXTENSION>`
+const RECORDING_FILE_PREFIX: &str = "p2p-messages-";
+const RECORDING_FILE_EXTENSION: &str = "rec";
+
+/// Records bigger than this are considered as corrupted
+const MAX_RECORD_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Max count of messages waiting for the writer thread, messages over this limit are dropped
+const MAX_QUEUED_MESSAGES: usize = 16 * 1024;
+
+/// Direction of the recorded message
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageDirection {
+    /// Received from peer
+    Incoming,
+    /// Sent to peer
+    Outgoing,
+}
+
+impl MessageDirection {
+    fn as_u8(self) -> u8 {
+        match self {
+            MessageDirection::Incoming => 0,
+            MessageDirection::Outgoing => 1,
+        }
+    }
+
+    fn from_u8(value: u8) -> io::Result<Self> {
+        match value {
+            0 => Ok(MessageDirection::Incoming),
+            1 => Ok(MessageDirection::Outgoing),
+            _ => Err(invalid_data(format!("invalid message direction: {}", value))),
+        }
+    }
+}
+
+/// One recorded message
+#[derive(Clone, Debug)]
+pub struct RecordedMessage {
+    pub direction: MessageDirection,
+    /// Unix timestamp in millis
+    pub timestamp_millis: u64,
+    /// Peer id (b58check encoded public key hash)
+    pub peer_id_marker: String,
+    pub peer_address: SocketAddr,
+    /// Encoded [`PeerMessageResponse`]
+    pub message_bytes: Vec<u8>,
+}
+
+impl RecordedMessage {
+    /// Decodes the recorded message
+    pub fn message(&self) -> Result<PeerMessageResponse, BinaryReaderError> {
+        PeerMessageResponse::from_bytes(self.message_bytes.clone())
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let peer_id_marker = self.peer_id_marker.as_bytes();
+        let peer_address = self.peer_address.to_string();
+
+        let mut content = Vec::with_capacity(
+            1 + 8 + 2 + peer_id_marker.len() + 2 + peer_address.len() + self.message_bytes.len(),
+        );
+        content.push(self.direction.as_u8());
+        content.extend_from_slice(&self.timestamp_millis.to_be_bytes());
+        write_u16_prefixed(&mut content, peer_id_marker)?;
+        write_u16_prefixed(&mut content, peer_address.as_bytes())?;
+        content.extend_from_slice(&self.message_bytes);
+
+        let size = u32::try_from(content.len())
+            .ok()
+            .filter(|size| *size <= MAX_RECORD_SIZE)
+            .ok_or_else(|| invalid_data(format!("record is too big: {}", content.len())))?;
+        writer.write_all(&size.to_be_bytes())?;
+        writer.write_all(&content)
+    }
+
+    /// Reads next record, returns None at the end of the stream
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut size = [0u8; 4];
+        match reader.read_exact(&mut size) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let size = u32::from_be_bytes(size);
+        if size > MAX_RECORD_SIZE {
+            return Err(invalid_data(format!("record is too big: {}", size)));
+        }
+        let mut content = vec![0u8; size as usize];
+        reader.read_exact(&mut content)?;
+
+        let mut content = content.as_slice();
+        let direction = MessageDirection::from_u8(take(&mut content, 1)?[0])?;
+        let mut timestamp_millis = [0u8; 8];
+        timestamp_millis.copy_from_slice(take(&mut content, 8)?);
+        let peer_id_marker = read_u16_prefixed(&mut content)?;
+        let peer_address = read_u16_prefixed(&mut content)?;
+
+        Ok(Some(RecordedMessage {
+            direction,
+            timestamp_millis: u64::from_be_bytes(timestamp_millis),
+            peer_id_marker: String::from_utf8(peer_id_marker.to_vec())
+                .map_err(|e| invalid_data(format!("invalid peer id: {}", e)))?,
+            peer_address: String::from_utf8_lossy(peer_address)
+                .parse()
+                .map_err(|e| invalid_data(format!("invalid peer address: {}", e)))?,
+            message_bytes: content.to_vec(),
+        }))
+    }
+}
+
+/// Recorder configuration
+#[derive(Clone, Debug)]
+pub struct MessageRecorderConfig {
+    /// Directory for recording files
+    pub dir: PathBuf,
+    /// When the current file exceeds this size, new file is started
+    pub max_file_size: u64,
+    /// Max count of kept files, the oldest files are removed
+    pub max_files: usize,
+}
+
+/// Handle to the recorder's writer thread shared by all peers, the thread stops, when all handles are dropped
+#[derive(Clone)]
+pub struct MessageRecorderRef {
+    queue: SyncSender<RecordedMessage>,
+}
+
+impl MessageRecorderRef {
+    /// Queues message for the writer thread, does not wait for the write
+    fn record(&self, message: RecordedMessage) -> io::Result<()> {
+        self.queue.try_send(message).map_err(|e| match e {
+            TrySendError::Full(_) => io::Error::new(ErrorKind::WouldBlock, "recorder queue is full, message is dropped"),
+            TrySendError::Disconnected(_) => io::Error::new(ErrorKind::BrokenPipe, "recorder writer thread is stopped"),
+        })
+    }
+}
+
+/// Writes recorded messages to rotating files
+pub struct MessageRecorder {
+    config: MessageRecorderConfig,
+    /// Indexes of the recording files, the last one is the current file
+    files: VecDeque<u64>,
+    current: Option<BufWriter<File>>,
+    current_size: u64,
+}
+
+impl MessageRecorder {
+    /// Creates recorder, the new recording file continues after files already present in directory
+    pub fn open(config: MessageRecorderConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let files = recording_files(&config.dir)?
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut recorder = MessageRecorder {
+            config,
+            files,
+            current: None,
+            current_size: 0,
+        };
+        recorder.rotate()?;
+        Ok(recorder)
+    }
+
+    /// Moves recorder to a dedicated writer thread, so peers do not wait for the file writes
+    pub fn spawn(self, log: Logger) -> io::Result<(MessageRecorderRef, JoinHandle<()>)> {
+        let (queue, queued) = sync_channel(MAX_QUEUED_MESSAGES);
+        let writer = thread::Builder::new()
+            .name("p2p-recorder".to_string())
+            .spawn(move || self.run(queued, log))?;
+        Ok((MessageRecorderRef { queue }, writer))
+    }
+
+    /// Writes queued messages until all handles are dropped
+    fn run(mut self, queued: Receiver<RecordedMessage>, log: Logger) {
+        while let Ok(message) = queued.recv() {
+            let mut result = self.record(&message);
+            // write all waiting messages at once
+            while result.is_ok() {
+                match queued.try_recv() {
+                    Ok(message) => result = self.record(&message),
+                    Err(_) => break,
+                }
+            }
+            // flush when the queue is empty, so the recording is usable also after crash
+            if let Err(e) = result.and_then(|_| self.flush()) {
+                warn!(log, "Failed to record p2p message"; "reason" => format!("{}", e));
+            }
+        }
+    }
+
+    pub fn record(&mut self, message: &RecordedMessage) -> io::Result<()> {
+        if self.current_size >= self.config.max_file_size {
+            self.rotate()?;
+        }
+
+        let mut record = Vec::new();
+        message.write_to(&mut record)?;
+        if let Some(current) = self.current.as_mut() {
+            current.write_all(&record)?;
+            self.current_size += record.len() as u64;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts new file and removes the oldest files over the limit
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut current) = self.current.take() {
+            current.flush()?;
+        }
+
+        let index = self.files.back().map(|index| index + 1).unwrap_or(0);
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(recording_file_path(&self.config.dir, index))?;
+        self.files.push_back(index);
+        self.current = Some(BufWriter::new(file));
+        self.current_size = 0;
+
+        while self.files.len() > self.config.max_files.max(1) {
+            if let Some(oldest) = self.files.pop_front() {
+                fs::remove_file(recording_file_path(&self.config.dir, oldest))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records messages of one bootstrapped peer
+#[derive(Clone)]
+pub struct PeerMessageRecorder {
+    recorder: MessageRecorderRef,
+    peer_id_marker: String,
+    peer_address: SocketAddr,
+}
+
+impl PeerMessageRecorder {
+    pub fn new(recorder: MessageRecorderRef, peer_id_marker: String, peer_address: SocketAddr) -> Self {
+        PeerMessageRecorder {
+            recorder,
+            peer_id_marker,
+            peer_address,
+        }
+    }
+
+    /// Records bytes of the message (as received from or sent to the decrypted stream),
+    /// failure is just logged, so it does not affect the communication with peer
+    pub fn record(&self, direction: MessageDirection, message_bytes: Vec<u8>, log: &Logger) {
+        let recorded = RecordedMessage {
+            direction,
+            timestamp_millis: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|timestamp| timestamp.as_millis() as u64)
+                .unwrap_or(0),
+            peer_id_marker: self.peer_id_marker.clone(),
+            peer_address: self.peer_address,
+            message_bytes,
+        };
+
+        if let Err(e) = self.recorder.record(recorded) {
+            warn!(log, "Failed to record p2p message"; "reason" => format!("{}", e));
+        }
+    }
+}
+
+/// Reads recorded messages from the recording file or from all recording files in directory (in the order of recording)
+pub struct RecordingReader {
+    files: VecDeque<PathBuf>,
+    current: Option<BufReader<File>>,
+}
+
+impl RecordingReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let files = if path.is_dir() {
+            recording_files(path)?
+                .into_iter()
+                .map(|(_, path)| path)
+                .collect()
+        } else {
+            vec![path.to_path_buf()].into_iter().collect()
+        };
+        Ok(RecordingReader {
+            files,
+            current: None,
+        })
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = io::Result<RecordedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.current.is_none() {
+                let file = self.files.pop_front()?;
+                match File::open(&file) {
+                    Ok(file) => self.current = Some(BufReader::new(file)),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            match RecordedMessage::read_from(self.current.as_mut()?) {
+                Ok(Some(message)) => return Some(Ok(message)),
+                // continue with the next file
+                Ok(None) => self.current = None,
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+fn recording_file_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{}{:08}.{}", RECORDING_FILE_PREFIX, index, RECORDING_FILE_EXTENSION))
+}
+
+/// Returns recording files in directory sorted by index
+fn recording_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let index = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(RECORDING_FILE_PREFIX))
+            .and_then(|name| name.strip_suffix(&format!(".{}", RECORDING_FILE_EXTENSION)))
+            .and_then(|index| index.parse::<u64>().ok());
+        if let Some(index) = index {
+            files.push((index, path));
+        }
+    }
+    files.sort_by_key(|(index, _)| *index);
+    Ok(files)
+}
+
+fn write_u16_prefixed(buf: &mut Vec<u8>, data: &[u8]) -> io::Result<()> {
+    let size = u16::try_from(data.len()).map_err(|_| invalid_data(format!("value is too long: {}", data.len())))?;
+    buf.extend_from_slice(&size.to_be_bytes());
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+fn read_u16_prefixed<'a>(content: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let mut size = [0u8; 2];
+    size.copy_from_slice(take(content, 2)?);
+    take(content, u16::from_be_bytes(size) as usize)
+}
+
+fn take<'a>(content: &mut &'a [u8], count: usize) -> io::Result<&'a [u8]> {
+    if content.len() < count {
+        return Err(invalid_data("record is truncated".to_string()));
+    }
+    let (taken, rest) = content.split_at(count);
+    *content = rest;
+    Ok(taken)
+}
+
+fn invalid_data(reason: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use slog::{Discard, Logger};
+
+    use tezos_messages::p2p::encoding::peer::PeerMessage;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tezedge-recorder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(dir: &Path, max_file_size: u64, max_files: usize) -> MessageRecorderConfig {
+        MessageRecorderConfig {
+            dir: dir.to_path_buf(),
+            max_file_size,
+            max_files,
+        }
+    }
+
+    fn recorded(index: u8) -> RecordedMessage {
+        RecordedMessage {
+            direction: MessageDirection::Incoming,
+            timestamp_millis: u64::from(index),
+            peer_id_marker: "idrdoT9g6YwELhUQyshCcHwAzBS9zA".to_string(),
+            peer_address: "127.0.0.1:9732".parse().unwrap(),
+            message_bytes: vec![index; 16],
+        }
+    }
+
+    #[test]
+    fn test_record_and_read_raw_bytes() {
+        let dir = test_dir("roundtrip");
+        let log = Logger::root(Discard, slog::o!());
+        let (recorder, writer) = MessageRecorder::open(config(&dir, 1024 * 1024, 10)).unwrap().spawn(log.clone()).unwrap();
+        let peer_recorder = PeerMessageRecorder::new(recorder, "idrdoT9g6YwELhUQyshCcHwAzBS9zA".to_string(), "127.0.0.1:9732".parse().unwrap());
+
+        let bootstrap = PeerMessageResponse::from(PeerMessage::Bootstrap).as_bytes().unwrap();
+        // bytes are recorded as they are, they are not re-encoded
+        let raw = vec![0, 0, 0, 3, 0xff, 0xfe];
+        peer_recorder.record(MessageDirection::Incoming, bootstrap.clone(), &log);
+        peer_recorder.record(MessageDirection::Outgoing, raw.clone(), &log);
+
+        // writer thread finishes all queued messages, when the last handle is dropped
+        drop(peer_recorder);
+        writer.join().unwrap();
+
+        let messages = RecordingReader::open(&dir).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].direction, MessageDirection::Incoming);
+        assert_eq!(messages[0].message_bytes, bootstrap);
+        assert!(matches!(messages[0].message().unwrap().messages()[0], PeerMessage::Bootstrap));
+        assert_eq!(messages[1].direction, MessageDirection::Outgoing);
+        assert_eq!(messages[1].message_bytes, raw);
+        assert_eq!(messages[1].peer_id_marker, "idrdoT9g6YwELhUQyshCcHwAzBS9zA");
+        assert_eq!(messages[1].peer_address, "127.0.0.1:9732".parse().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = test_dir("rotation");
+        // every record fills the file
+        let mut recorder = MessageRecorder::open(config(&dir, 1, 2)).unwrap();
+        for index in 1..=5 {
+            recorder.record(&recorded(index)).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        // only the last 2 files are kept
+        let files = recording_files(&dir).unwrap().into_iter().map(|(index, _)| index).collect::<Vec<_>>();
+        assert_eq!(files, vec![3, 4]);
+        let replayed = RecordingReader::open(&dir).unwrap().map(|message| message.unwrap().message_bytes).collect::<Vec<_>>();
+        assert_eq!(replayed, vec![vec![4; 16], vec![5; 16]]);
+
+        // single file can be read too
+        let last = RecordingReader::open(&recording_file_path(&dir, 4)).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].timestamp_millis, 5);
+
+        // reopened recorder continues with the next file
+        drop(recorder);
+        let recorder = MessageRecorder::open(config(&dir, 1, 2)).unwrap();
+        let files = recording_files(&dir).unwrap().into_iter().map(|(index, _)| index).collect::<Vec<_>>();
+        assert_eq!(files, vec![4, 5]);
+
+        drop(recorder);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
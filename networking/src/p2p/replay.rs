@@ -0,0 +1,241 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Replays messages recorded by [`MessageRecorder`](super::recorder::MessageRecorder) without network access.
+//!
+//! For every recorded peer a peer actor without connection is created and announced as bootstrapped,
+//! then all incoming messages are published to the network channel the same way as if they were received from network.
+//! Messages sent by the node to such peers are silently dropped.
+//!
+//! Replay has to wait for the actors consuming the network events, [`ReplayStart`] is used for that.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::collections::HashSet;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+use riker::actors::*;
+use slog::{info, warn, Logger};
+use tokio::runtime::Handle;
+
+use crypto::hash::HashType;
+use tezos_identity::Identity;
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::PeerId;
+
+use super::network_channel::{NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerMessageReceived};
+use super::peer::{Peer, PeerRef};
+use super::recorder::{MessageDirection, RecordingReader};
+
+#[derive(Debug, Fail)]
+pub enum ReplayError {
+    #[fail(display = "Failed to read recording: {}, reason: {}", path, reason)]
+    IOError {
+        path: String,
+        reason: std::io::Error,
+    },
+    #[fail(display = "Invalid recorded peer id: {}, reason: {}", peer_id, reason)]
+    InvalidPeerId {
+        peer_id: String,
+        reason: String,
+    },
+    #[fail(display = "Failed to create replayed peer actor, reason: {}", reason)]
+    PeerCreateError {
+        reason: String,
+    },
+    #[fail(display = "Failed to create replay start watcher, reason: {}", reason)]
+    WatcherCreateError {
+        reason: String,
+    },
+    #[fail(display = "Actors were not started before the replay: {:?}", actors)]
+    StartTimeout {
+        actors: Vec<String>,
+    },
+}
+
+/// Synchronizes start of the replay with the actors, which have to be subscribed to the network channel before the first replayed message.
+///
+/// Actor publishes [`SystemEvent::ActorCreated`] after its `pre_start` (where the channels are subscribed),
+/// so the watcher has to be created before the awaited actors.
+pub struct ReplayStart {
+    sys: ActorSystem,
+    watcher: ActorRef<SystemEvent>,
+    created: Receiver<String>,
+}
+
+impl ReplayStart {
+    pub fn watch(sys: &ActorSystem) -> Result<Self, ReplayError> {
+        let (created_tx, created) = mpsc::channel();
+        let watcher = sys.actor_of_props::<ReplayStartWatcher>("replay-start-watcher", Props::new_args(Arc::new(Mutex::new(created_tx))))
+            .map_err(|e| ReplayError::WatcherCreateError { reason: format!("{:?}", e) })?;
+        // subscribe right now (not in pre_start of the watcher), so no event published after this call is missed
+        sys.sys_events().tell(
+            Subscribe {
+                topic: SysTopic::ActorCreated.into(),
+                actor: Box::new(watcher.clone()),
+            }, None);
+        Ok(ReplayStart { sys: sys.clone(), watcher, created })
+    }
+
+    /// Blocks until all actors with the names are started
+    pub fn wait_for(self, actor_names: &[&str], timeout: Duration) -> Result<(), ReplayError> {
+        let mut waiting: HashSet<&str> = actor_names.iter().cloned().collect();
+        let deadline = Instant::now() + timeout;
+        while !waiting.is_empty() {
+            let remaining = deadline.checked_duration_since(Instant::now()).unwrap_or_default();
+            match self.created.recv_timeout(remaining) {
+                Ok(created) => {
+                    waiting.remove(created.as_str());
+                }
+                Err(_) => return Err(ReplayError::StartTimeout { actors: waiting.iter().map(|name| name.to_string()).collect() }),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ReplayStart {
+    fn drop(&mut self) {
+        self.sys.stop(self.watcher.clone());
+    }
+}
+
+/// Forwards names of the created actors to the [`ReplayStart`]
+struct ReplayStartWatcher {
+    created: Arc<Mutex<mpsc::Sender<String>>>,
+}
+
+impl ActorFactoryArgs<Arc<Mutex<mpsc::Sender<String>>>> for ReplayStartWatcher {
+    fn create_args(created: Arc<Mutex<mpsc::Sender<String>>>) -> Self {
+        ReplayStartWatcher { created }
+    }
+}
+
+impl Actor for ReplayStartWatcher {
+    type Msg = SystemEvent;
+
+    fn recv(&mut self, _ctx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
+        if let SystemEvent::ActorCreated(evt) = msg {
+            if let Ok(created) = self.created.lock() {
+                // receiver is dropped after the replay start
+                let _ = created.send(evt.actor.name().to_string());
+            }
+        }
+    }
+}
+
+/// Replay configuration
+#[derive(Clone, Debug)]
+pub struct ReplayConfig {
+    /// Recording file or directory with recording files
+    pub path: PathBuf,
+    /// Multiplier of the replay speed against the recorded time, 0 means no delays between messages
+    pub speed: f64,
+}
+
+/// Summary of the finished replay
+#[derive(Clone, Debug, Default)]
+pub struct ReplayStats {
+    pub peers: usize,
+    pub replayed_messages: usize,
+    pub skipped_messages: usize,
+}
+
+/// Replays the recording, blocks until all messages are published
+pub fn replay(
+    sys: &ActorSystem,
+    network_channel: NetworkChannelRef,
+    tokio_executor: Handle,
+    identity: Arc<Identity>,
+    network_version: Arc<NetworkVersion>,
+    config: &ReplayConfig,
+    log: &Logger) -> Result<ReplayStats, ReplayError> {
+    let reader = RecordingReader::open(&config.path)
+        .map_err(|reason| ReplayError::IOError { path: config.path.display().to_string(), reason })?;
+    info!(log, "Replaying recorded p2p messages"; "path" => config.path.display().to_string(), "speed" => config.speed);
+
+    let mut stats = ReplayStats::default();
+    let mut peers: HashMap<String, PeerRef> = HashMap::new();
+    // (first recorded timestamp, replay start)
+    let mut started: Option<(u64, Instant)> = None;
+
+    for recorded in reader {
+        let recorded = recorded.map_err(|reason| ReplayError::IOError { path: config.path.display().to_string(), reason })?;
+        if recorded.direction != MessageDirection::Incoming {
+            continue;
+        }
+
+        let message = match recorded.message() {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(log, "Skipping invalid recorded message"; "peer_id" => &recorded.peer_id_marker, "reason" => format!("{}", e));
+                stats.skipped_messages += 1;
+                continue;
+            }
+        };
+
+        // keep recorded pace
+        if config.speed > 0.0 {
+            let (first_timestamp, start) = *started.get_or_insert((recorded.timestamp_millis, Instant::now()));
+            let recorded_elapsed = recorded.timestamp_millis.saturating_sub(first_timestamp);
+            let replay_elapsed = Duration::from_millis((recorded_elapsed as f64 / config.speed) as u64);
+            if let Some(delay) = replay_elapsed.checked_sub(start.elapsed()) {
+                thread::sleep(delay);
+            }
+        }
+
+        let peer = match peers.get(&recorded.peer_id_marker) {
+            Some(peer) => peer.clone(),
+            None => {
+                let peer = create_replayed_peer(sys, &network_channel, &tokio_executor, &identity, &network_version, &recorded.peer_id_marker, recorded.peer_address)?;
+                peers.insert(recorded.peer_id_marker.clone(), peer.clone());
+                peer
+            }
+        };
+
+        network_channel.tell(
+            Publish {
+                msg: PeerMessageReceived {
+                    peer: peer.clone(),
+                    message: Arc::new(message),
+                }.into(),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            }, Some(peer.into()));
+        stats.replayed_messages += 1;
+    }
+
+    stats.peers = peers.len();
+    info!(log, "Replay of recorded p2p messages finished"; "peers" => stats.peers, "replayed_messages" => stats.replayed_messages, "skipped_messages" => stats.skipped_messages);
+    Ok(stats)
+}
+
+/// Creates peer actor without connection and announces it as bootstrapped
+fn create_replayed_peer(
+    sys: &ActorSystem,
+    network_channel: &NetworkChannelRef,
+    tokio_executor: &Handle,
+    identity: &Arc<Identity>,
+    network_version: &Arc<NetworkVersion>,
+    peer_id_marker: &str,
+    peer_address: SocketAddr) -> Result<PeerRef, ReplayError> {
+    let peer_public_key_hash = HashType::CryptoboxPublicKeyHash.b58check_to_hash(peer_id_marker)
+        .map_err(|e| ReplayError::InvalidPeerId { peer_id: peer_id_marker.to_string(), reason: format!("{}", e) })?;
+    let peer = Peer::actor(sys, network_channel.clone(), 0, identity.clone(), network_version.clone(), tokio_executor.clone(), &peer_address, None, None)
+        .map_err(|e| ReplayError::PeerCreateError { reason: format!("{:?}", e) })?;
+
+    network_channel.tell(
+        Publish {
+            msg: NetworkChannelMsg::PeerBootstrapped(
+                Arc::new(PeerId::new(peer.clone(), peer_public_key_hash, peer_id_marker.to_string(), peer_address)),
+                Arc::new(MetadataMessage::new(false, false)),
+            ),
+            topic: NetworkChannelTopic::NetworkEvents.into(),
+        }, Some(peer.clone().into()));
+    Ok(peer)
+}
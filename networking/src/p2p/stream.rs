@@ -22,6 +22,8 @@ use crypto::nonce::Nonce;
 use tezos_encoding::binary_reader::BinaryReaderError;
 use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryMessage, CONTENT_LENGTH_FIELD_BYTES};
 
+use super::bandwidth::PeerBandwidth;
+
 /// Max allowed content length in bytes when taking into account extra data added by encryption
 pub const CONTENT_LENGTH_MAX: usize = tezos_messages::p2p::binary_message::CONTENT_LENGTH_MAX - crypto::crypto_box::BOX_ZERO_BYTES;
 
@@ -49,6 +51,11 @@ pub enum StreamError {
         message: &'static str,
         error: Error,
     },
+    #[fail(display = "Message is too big, size: {}, max_message_size: {}", size, max_message_size)]
+    MessageTooBig {
+        size: usize,
+        max_message_size: usize,
+    },
 }
 
 impl From<tezos_encoding::ser::Error> for StreamError {
@@ -174,17 +181,35 @@ pub struct EncryptedMessageWriter {
     nonce_local: Nonce,
     /// Outgoing message writer
     tx: MessageWriter,
+    /// Bandwidth limits
+    bandwidth: Option<PeerBandwidth>,
     /// Logger
     log: Logger,
 }
 
 impl EncryptedMessageWriter {
     pub fn new(tx: MessageWriter, precomputed_key: PrecomputedKey, nonce_local: Nonce, log: Logger) -> Self {
-        EncryptedMessageWriter { tx, precomputed_key, nonce_local, log }
+        EncryptedMessageWriter { tx, precomputed_key, nonce_local, bandwidth: None, log }
+    }
+
+    /// Set bandwidth limits for all subsequently written messages
+    pub fn set_bandwidth(&mut self, bandwidth: Option<PeerBandwidth>) {
+        self.bandwidth = bandwidth;
     }
 
     pub async fn write_message<'a>(&'a mut self, message: &'a impl BinaryMessage) -> Result<(), StreamError> {
+        self.write_message_with_chunk_timeout(message, None).await
+    }
+
+    /// Same as [`write_message`](Self::write_message), but every chunk must be written to the network stream within `chunk_timeout`.
+    /// Time spent waiting for the bandwidth limits is not included.
+    pub async fn write_message_with_chunk_timeout<'a>(&'a mut self, message: &'a impl BinaryMessage, chunk_timeout: Option<Duration>) -> Result<(), StreamError> {
         let message_bytes = message.as_bytes()?;
+        self.write_message_bytes(&message_bytes, chunk_timeout).await
+    }
+
+    /// Same as [`write_message_with_chunk_timeout`](Self::write_message_with_chunk_timeout), but for already encoded message.
+    pub async fn write_message_bytes<'a>(&'a mut self, message_bytes: &'a [u8], chunk_timeout: Option<Duration>) -> Result<(), StreamError> {
         trace!(self.log, "Writing message"; "message" => FnValue(|_| hex::encode(&message_bytes)));
 
         for chunk_content_bytes in message_bytes.chunks(CONTENT_LENGTH_MAX) {
@@ -197,7 +222,14 @@ impl EncryptedMessageWriter {
 
             // send
             let chunk = BinaryChunk::from_content(&message_bytes_encrypted)?;
-            self.tx.write_message(&chunk).await?;
+            if let Some(bandwidth) = self.bandwidth.as_ref() {
+                bandwidth.throttle_upload(chunk.raw().len()).await;
+            }
+            match chunk_timeout {
+                Some(chunk_timeout) => tokio::time::timeout(chunk_timeout, self.tx.write_message(&chunk)).await
+                    .map_err(|_| StreamError::from(io::Error::new(io::ErrorKind::TimedOut, "chunk write timed out")))??,
+                None => self.tx.write_message(&chunk).await?,
+            }
         }
 
         Ok(())
@@ -220,6 +252,8 @@ pub struct EncryptedMessageReader {
     nonce_remote: Nonce,
     /// Incoming message reader
     rx: MessageReader,
+    /// Bandwidth limits
+    bandwidth: Option<PeerBandwidth>,
     /// Logger
     log: Logger,
 }
@@ -227,13 +261,26 @@ pub struct EncryptedMessageReader {
 impl EncryptedMessageReader {
     /// Create new encrypted message from async reader and peer data
     pub fn new(rx: MessageReader, precomputed_key: PrecomputedKey, nonce_remote: Nonce, log: Logger) -> Self {
-        EncryptedMessageReader { rx, precomputed_key, nonce_remote, log }
+        EncryptedMessageReader { rx, precomputed_key, nonce_remote, bandwidth: None, log }
+    }
+
+    /// Set bandwidth limits for all subsequently read messages
+    pub fn set_bandwidth(&mut self, bandwidth: Option<PeerBandwidth>) {
+        self.bandwidth = bandwidth;
     }
 
     /// Consume content of inner message reader into specific message
     pub async fn read_message<M>(&mut self) -> Result<M, StreamError>
         where
             M: BinaryMessage
+    {
+        self.read_message_with_bytes().await.map(|(message, _)| message)
+    }
+
+    /// Same as [`read_message`](Self::read_message), but also returns the decrypted bytes of the message
+    pub async fn read_message_with_bytes<M>(&mut self) -> Result<(M, Vec<u8>), StreamError>
+        where
+            M: BinaryMessage
     {
         let mut input_remaining = 0;
         let mut input_data = vec![];
@@ -241,6 +288,9 @@ impl EncryptedMessageReader {
         loop {
             // read
             let message_encrypted = self.rx.read_message().await?;
+            if let Some(bandwidth) = self.bandwidth.as_ref() {
+                bandwidth.throttle_download(message_encrypted.raw().len()).await;
+            }
 
             // decrypt
             let nonce = self.nonce_fetch_increment();
@@ -257,11 +307,19 @@ impl EncryptedMessageReader {
 
                     if input_remaining == 0 {
                         match M::from_bytes(&input_data) {
-                            Ok(message) => break Ok(message),
+                            Ok(message) => break Ok((message, input_data)),
                             Err(BinaryReaderError::Underflow { bytes }) => input_remaining += bytes,
                             Err(e) => break Err(e.into()),
                         }
                     }
+
+                    // do not wait for the rest of the message, which is over the limit
+                    if let Some(bandwidth) = self.bandwidth.as_ref() {
+                        let size = input_data.len() + input_remaining;
+                        if !bandwidth.check_message_size(size) {
+                            break Err(StreamError::MessageTooBig { size, max_message_size: bandwidth.max_message_size().unwrap_or_default() });
+                        }
+                    }
                 }
                 Err(error) => {
                     break Err(StreamError::FailedToDecryptMessage { error });
@@ -10,13 +10,17 @@ use riker::actor::ActorReference;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crypto::hash::{chain_id_to_b58_string, BlockHash, ChainId, ContextHash, HashType};
+use crypto::hash::{
+    chain_id_to_b58_string, BlockHash, ChainId, ContextHash, HashType, OperationHash,
+};
 use shell::mempool::mempool_prevalidator::MempoolPrevalidator;
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::context_action_storage::ContextActionType;
+use storage::merkle_storage::{ContextValue, ContextValueDiff, MerkleProof};
+use storage::operation_receipts_storage::{OperationContentReceipt, OperationReceipt};
 use storage::{
     BlockHeaderWithHash, BlockJsonData, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
-    BlockStorageReader, ChainMetaStorage,
+    BlockStorageReader, ChainMetaStorage, OperationLocation,
 };
 use tezos_api::ffi::{RpcMethod, RpcRequest};
 use tezos_messages::p2p::encoding::block_header::Level;
@@ -24,7 +28,9 @@ use tezos_messages::p2p::encoding::prelude::*;
 use tezos_messages::ts_to_rfc3339;
 
 use crate::encoding::base_types::UniString;
-use crate::server::{HasSingleValue, Query, RpcServiceEnvironment};
+use crate::server::{
+    HasSingleValue, Query, RpcLimiterStats, RpcResponseCacheStats, RpcServiceEnvironment,
+};
 
 #[macro_export]
 macro_rules! merge_slices {
@@ -310,6 +316,118 @@ impl Protocols {
     }
 }
 
+/// Context value (hex, null if missing) with proof of its presence or absence
+/// in the context of the block
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextValueWithProof {
+    context_hash: String,
+    value: Option<String>,
+    proof: MerkleProof,
+}
+
+impl ContextValueWithProof {
+    pub fn new(context_hash: &ContextHash, value: Option<Vec<u8>>, proof: MerkleProof) -> Self {
+        Self {
+            context_hash: HashType::ContextHash.hash_to_b58check(context_hash),
+            value: value.map(hex::encode),
+            proof,
+        }
+    }
+}
+
+/// Context value changed between two blocks (values in hex, null if missing)
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextValueDiffJson {
+    key: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+impl From<ContextValueDiff> for ContextValueDiffJson {
+    fn from(diff: ContextValueDiff) -> Self {
+        Self {
+            key: diff.key.join("/"),
+            old_value: diff.old_value.map(hex::encode),
+            new_value: diff.new_value.map(hex::encode),
+        }
+    }
+}
+
+/// Value of the context key (in hex, null if missing) in the context of the block
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextKeyHistoryJson {
+    level: Level,
+    block_hash: String,
+    value: Option<String>,
+}
+
+impl ContextKeyHistoryJson {
+    pub fn new(level: Level, block_hash: &BlockHash, value: Option<ContextValue>) -> Self {
+        Self {
+            level,
+            block_hash: HashType::BlockHash.hash_to_b58check(block_hash),
+            value: value.map(hex::encode),
+        }
+    }
+}
+
+/// Receipt of the applied operation
+#[derive(Serialize, Debug, Clone)]
+pub struct OperationReceiptJson {
+    hash: String,
+    block_hash: String,
+    level: Level,
+    validation_pass: u8,
+    operation_index: u32,
+    contents: Vec<OperationContentReceipt>,
+}
+
+impl OperationReceiptJson {
+    pub fn new(operation_hash: &OperationHash, receipt: OperationReceipt) -> Self {
+        Self {
+            hash: HashType::OperationHash.hash_to_b58check(operation_hash),
+            block_hash: HashType::BlockHash.hash_to_b58check(&receipt.block_hash),
+            level: receipt.level,
+            validation_pass: receipt.validation_pass,
+            operation_index: receipt.operation_index,
+            contents: receipt.contents,
+        }
+    }
+}
+
+/// Location of the operation in the block
+#[derive(Serialize, Debug, Clone)]
+pub struct OperationLocationJson {
+    hash: String,
+    block_hash: String,
+    level: Option<Level>,
+    validation_pass: u8,
+    operation_index: u32,
+}
+
+impl OperationLocationJson {
+    pub fn new(
+        operation_hash: &OperationHash,
+        location: OperationLocation,
+        level: Option<Level>,
+    ) -> Self {
+        Self {
+            hash: HashType::OperationHash.hash_to_b58check(operation_hash),
+            block_hash: HashType::BlockHash.hash_to_b58check(&location.block_hash),
+            level,
+            validation_pass: location.validation_pass,
+            operation_index: location.operation_index,
+        }
+    }
+}
+
+/// Statistics of RPC server limits and response cache
+#[derive(Serialize, Debug)]
+pub struct RpcStats {
+    pub limiter: RpcLimiterStats,
+    pub response_cache: RpcResponseCacheStats,
+}
+
 // ---------------------------------------------------------------------
 #[derive(Serialize, Debug, Clone)]
 pub struct NodeVersion {
@@ -2,9 +2,14 @@
 // SPDX-License-Identifier: MIT
 #![forbid(unsafe_code)]
 
-use hyper::{Body, Response, StatusCode};
+use hyper::{Body, Request, Response, StatusCode};
 use slog::{error, Logger};
 
+pub use server::{
+    RpcAccessPolicy, RpcAcl, RpcAclError, RpcAddressRange, RpcLimiter, RpcLimiterStats,
+    RpcLimitsConfiguration, RpcRateLimit, RpcResponseCache, RpcResponseCacheStats,
+    RpcServerConfiguration, RpcTlsConfiguration, RpcTlsError,
+};
 pub use services::mempool_services::MempoolOperations;
 
 pub mod encoding;
@@ -45,6 +50,29 @@ pub(crate) fn make_json_response<T: serde::Serialize>(content: &T) -> ServiceRes
         .body(Body::from(serde_json::to_string(content)?))?)
 }
 
+/// Function to generate binary response (`application/octet-stream`)
+pub(crate) fn make_binary_response(content: Vec<u8>) -> ServiceResult {
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "content-type")
+        .header(
+            hyper::header::ACCESS_CONTROL_ALLOW_METHODS,
+            "GET, POST, OPTIONS, PUT",
+        )
+        .body(Body::from(content))?)
+}
+
+/// Check if client prefers binary response (`Accept: application/octet-stream`)
+pub(crate) fn accepts_binary<T>(req: &Request<T>) -> bool {
+    req.headers()
+        .get(hyper::header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/octet-stream"))
+        .unwrap_or(false)
+}
+
 /// Function to generate JSON response from a stream
 pub(crate) fn make_json_stream_response<
     T: futures::Stream<Item = Result<String, failure::Error>> + Send + 'static,
@@ -118,6 +146,22 @@ pub(crate) fn empty() -> ServiceResult {
         .body(Body::empty())?)
 }
 
+/// Generate 403 response
+pub(crate) fn forbidden() -> ServiceResult {
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(403)?)
+        .body(Body::from("forbidden"))?)
+}
+
+/// Generate 429 response with `Retry-After` header (in seconds)
+pub(crate) fn too_many_requests(retry_after: std::time::Duration) -> ServiceResult {
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(429)?)
+        .header(hyper::header::RETRY_AFTER, retry_after_secs.max(1))
+        .body(Body::from("too many requests"))?)
+}
+
 /// Generate 404 response
 pub(crate) fn not_found() -> ServiceResult {
     Ok(Response::builder()
@@ -1,12 +1,11 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 
 use getset::{CopyGetters, Getters, Setters};
 use riker::actors::*;
-use slog::{warn, Logger};
+use slog::{error, info, warn, Logger};
 use tokio::runtime::Handle;
 
 use crypto::hash::ChainId;
@@ -20,7 +19,9 @@ use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_messages::p2p::encoding::version::NetworkVersion;
 use tezos_wrapper::TezosApiConnectionPool;
 
-use crate::server::{spawn_server, RpcServiceEnvironment};
+use crate::server::{
+    spawn_server, RpcLimiter, RpcResponseCache, RpcServerConfiguration, RpcServiceEnvironment,
+};
 
 pub type RpcServerRef = ActorRef<RpcServerMsg>;
 
@@ -53,7 +54,7 @@ impl RpcServer {
     pub fn actor(
         sys: &ActorSystem,
         shell_channel: ShellChannelRef,
-        rpc_listen_address: SocketAddr,
+        rpc_server_configuration: RpcServerConfiguration,
         tokio_executor: &Handle,
         persistent_storage: &PersistentStorage,
         current_mempool_state_storage: CurrentMempoolStateStorageRef,
@@ -96,15 +97,45 @@ impl RpcServer {
                 init_storage_data.chain_id.clone(),
                 init_storage_data.genesis_block_header_hash.clone(),
                 shared_state,
+                Arc::new(RpcLimiter::new(rpc_server_configuration.limits())),
+                Arc::new(RpcResponseCache::new(
+                    rpc_server_configuration
+                        .limits()
+                        .response_cache_max_entries(),
+                )),
                 &sys.log(),
             );
-            let inner_log = sys.log();
-
-            tokio_executor.spawn(async move {
-                if let Err(e) = spawn_server(&rpc_listen_address, env).await {
-                    warn!(inner_log, "HTTP Server encountered failure"; "error" => format!("{}", e));
-                }
-            });
+            let log = sys.log();
+
+            let tls_acceptor = match rpc_server_configuration.tls() {
+                Some(tls) => match tls.create_acceptor() {
+                    Ok(tls_acceptor) => Some(tls_acceptor),
+                    Err(e) => {
+                        // never fallback to plain HTTP, when TLS was requested
+                        error!(log, "Failed to initialize RPC TLS, RPC server is not started"; "reason" => format!("{}", e));
+                        return Ok(actor_ref);
+                    }
+                },
+                None => None,
+            };
+
+            for listen_address in rpc_server_configuration.listen_addresses() {
+                let listen_address = *listen_address;
+                let access_policy = rpc_server_configuration.acl().policy_for(&listen_address);
+                info!(log, "Starting RPC server";
+                           "listen_address" => listen_address.to_string(),
+                           "tls" => tls_acceptor.is_some(),
+                           "access_policy" => format!("{:?}", access_policy));
+
+                let env = env.clone();
+                let tls_acceptor = tls_acceptor.clone();
+                let inner_log = log.clone();
+                tokio_executor.spawn(async move {
+                    if let Err(e) = spawn_server(&listen_address, access_policy, tls_acceptor, env).await {
+                        warn!(inner_log, "HTTP Server encountered failure"; "listen_address" => listen_address.to_string(), "error" => format!("{}", e));
+                    }
+                });
+            }
         }
 
         Ok(actor_ref)
@@ -0,0 +1,447 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Access control for RPC endpoints.
+//!
+//! Mirrors the octez node semantics: access rules are bound to the address the RPC server
+//! listens on. Listening on a loopback address allows every endpoint, any other address falls
+//! back to a secure default blacklist, unless an explicit ACL entry (or `allow all`) is configured.
+
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+use failure::Fail;
+use hyper::Method;
+use serde::Deserialize;
+
+/// Endpoints not reachable on non-local addresses without explicit ACL configuration
+const SECURE_DEFAULT_BLACKLIST: &[&str] = &[
+    "POST /injection/**",
+    "POST /chains/*/mempool/request_operations",
+    "/dev/**",
+    "/stats/**",
+    "/workers/**",
+    "/network/**",
+    "/private/**",
+];
+
+#[derive(Debug, Fail)]
+pub enum RpcAclError {
+    #[fail(display = "Invalid RPC path pattern: {}, reason: {}", pattern, reason)]
+    InvalidPattern { pattern: String, reason: String },
+    #[fail(display = "Invalid RPC ACL address: {}", address)]
+    InvalidAddress { address: String },
+    #[fail(
+        display = "Invalid RPC ACL entry for address: {}, reason: {}",
+        address, reason
+    )]
+    InvalidEntry { address: String, reason: String },
+    #[fail(display = "Failed to read RPC ACL file, reason: {}", reason)]
+    IOError { reason: std::io::Error },
+    #[fail(display = "Failed to parse RPC ACL file, reason: {}", reason)]
+    JsonError { reason: serde_json::Error },
+}
+
+impl From<std::io::Error> for RpcAclError {
+    fn from(reason: std::io::Error) -> Self {
+        RpcAclError::IOError { reason }
+    }
+}
+
+impl From<serde_json::Error> for RpcAclError {
+    fn from(reason: serde_json::Error) -> Self {
+        RpcAclError::JsonError { reason }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PatternSegment {
+    /// Segment has to be equal
+    Exact(String),
+    /// `*` - matches any single segment
+    Any,
+    /// `**` - matches any (also empty) rest of the path
+    AnySuffix,
+}
+
+/// RPC path pattern in octez format: `[METHOD] /chains/*/blocks/**`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcPathPattern {
+    method: Option<Method>,
+    segments: Vec<PatternSegment>,
+}
+
+impl RpcPathPattern {
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        if let Some(expected) = &self.method {
+            if expected != method {
+                return false;
+            }
+        }
+
+        let mut path = path.split('/').filter(|s| !s.is_empty());
+        for segment in &self.segments {
+            match segment {
+                PatternSegment::AnySuffix => return true,
+                PatternSegment::Any => {
+                    if path.next().is_none() {
+                        return false;
+                    }
+                }
+                PatternSegment::Exact(expected) => match path.next() {
+                    Some(s) if s == expected => (),
+                    _ => return false,
+                },
+            }
+        }
+        path.next().is_none()
+    }
+}
+
+impl FromStr for RpcPathPattern {
+    type Err = RpcAclError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| RpcAclError::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let mut parts = pattern.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(path), None, None) => (None, path),
+            (Some(method), Some(path), None) => (
+                Some(
+                    Method::from_str(&method.to_ascii_uppercase())
+                        .map_err(|_| invalid("invalid method"))?,
+                ),
+                path,
+            ),
+            _ => return Err(invalid("expected format is `[METHOD] /path`")),
+        };
+        if !path.starts_with('/') {
+            return Err(invalid("path has to start with `/`"));
+        }
+
+        let segments: Vec<PatternSegment> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s {
+                "*" => PatternSegment::Any,
+                "**" => PatternSegment::AnySuffix,
+                s => PatternSegment::Exact(s.to_string()),
+            })
+            .collect();
+        if segments
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|s| *s == PatternSegment::AnySuffix)
+        {
+            return Err(invalid("`**` is allowed only as the last segment"));
+        }
+
+        Ok(Self { method, segments })
+    }
+}
+
+/// Which endpoints are reachable
+#[derive(Clone, Debug)]
+pub enum RpcAccessPolicy {
+    AllowAll,
+    /// Only matching endpoints are allowed
+    Whitelist(Vec<RpcPathPattern>),
+    /// Matching endpoints are denied
+    Blacklist(Vec<RpcPathPattern>),
+}
+
+impl RpcAccessPolicy {
+    pub fn secure_default() -> Self {
+        RpcAccessPolicy::Blacklist(
+            SECURE_DEFAULT_BLACKLIST
+                .iter()
+                .map(|pattern| pattern.parse().expect("Invalid default RPC ACL pattern"))
+                .collect(),
+        )
+    }
+
+    pub fn is_allowed(&self, method: &Method, path: &str) -> bool {
+        match self {
+            RpcAccessPolicy::AllowAll => true,
+            RpcAccessPolicy::Whitelist(patterns) => {
+                patterns.iter().any(|p| p.matches(method, path))
+            }
+            RpcAccessPolicy::Blacklist(patterns) => {
+                !patterns.iter().any(|p| p.matches(method, path))
+            }
+        }
+    }
+}
+
+/// Range of listening addresses: `IP[/PREFIX_LEN][:PORT]`, IPv6 with port as `[IP[/PREFIX_LEN]]:PORT`.
+/// Missing port matches any port.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcAddressRange {
+    ip: IpAddr,
+    prefix_len: u8,
+    port: Option<u16>,
+}
+
+impl RpcAddressRange {
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        if let Some(port) = self.port {
+            if port != address.port() {
+                return false;
+            }
+        }
+        match (self.ip, address.ip()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(range: &[u8], ip: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = (prefix_len / 8) as usize;
+    let rest_bits = prefix_len % 8;
+    if range[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest_bits);
+    range[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+impl FromStr for RpcAddressRange {
+    type Err = RpcAclError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let invalid = || RpcAclError::InvalidAddress {
+            address: address.to_string(),
+        };
+
+        // split port
+        let (range, port) = if address.starts_with('[') {
+            let end = address.find(']').ok_or_else(invalid)?;
+            let port = match &address[end + 1..] {
+                "" => None,
+                port if port.starts_with(':') => Some(port[1..].parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            };
+            (&address[1..end], port)
+        } else if address.matches(':').count() == 1 {
+            let idx = address.rfind(':').ok_or_else(invalid)?;
+            (
+                &address[..idx],
+                Some(address[idx + 1..].parse().map_err(|_| invalid())?),
+            )
+        } else {
+            (address, None)
+        };
+
+        // split prefix length
+        let (ip, prefix_len) = match range.find('/') {
+            Some(idx) => (
+                range[..idx].parse::<IpAddr>().map_err(|_| invalid())?,
+                Some(range[idx + 1..].parse::<u8>().map_err(|_| invalid())?),
+            ),
+            None => (range.parse::<IpAddr>().map_err(|_| invalid())?, None),
+        };
+        let max_prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_prefix_len);
+        if prefix_len > max_prefix_len {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            ip,
+            prefix_len,
+            port,
+        })
+    }
+}
+
+/// ACL entry as stored in ACL json file
+#[derive(Deserialize)]
+struct RpcAclEntryJson {
+    address: String,
+    whitelist: Option<Vec<String>>,
+    blacklist: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcAclEntry {
+    address: RpcAddressRange,
+    policy: RpcAccessPolicy,
+}
+
+impl RpcAclEntry {
+    pub fn new(address: RpcAddressRange, policy: RpcAccessPolicy) -> Self {
+        Self { address, policy }
+    }
+}
+
+/// Access control list, maps listening address ranges to access policies.
+/// The first entry matching the listening address wins.
+#[derive(Clone, Debug, Default)]
+pub struct RpcAcl {
+    entries: Vec<RpcAclEntry>,
+}
+
+impl RpcAcl {
+    pub fn new(entries: Vec<RpcAclEntry>) -> Self {
+        Self { entries }
+    }
+
+    /// Load ACL from json file in format:
+    /// `[{"address": "0.0.0.0:8732", "blacklist": ["POST /injection/**"]}, {"address": "10.0.0.0/8", "whitelist": ["GET /chains/**"]}]`
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self, RpcAclError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, RpcAclError> {
+        let entries: Vec<RpcAclEntryJson> = serde_json::from_str(json)?;
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let parse_patterns = |patterns: Vec<String>| {
+                    patterns
+                        .iter()
+                        .map(|pattern| pattern.parse())
+                        .collect::<Result<Vec<RpcPathPattern>, RpcAclError>>()
+                };
+                let policy = match (entry.whitelist, entry.blacklist) {
+                    (Some(whitelist), None) => {
+                        RpcAccessPolicy::Whitelist(parse_patterns(whitelist)?)
+                    }
+                    (None, Some(blacklist)) => {
+                        RpcAccessPolicy::Blacklist(parse_patterns(blacklist)?)
+                    }
+                    _ => {
+                        return Err(RpcAclError::InvalidEntry {
+                            address: entry.address,
+                            reason: "exactly one of `whitelist` or `blacklist` is expected"
+                                .to_string(),
+                        })
+                    }
+                };
+                Ok(RpcAclEntry::new(entry.address.parse()?, policy))
+            })
+            .collect::<Result<Vec<_>, RpcAclError>>()?;
+        Ok(Self::new(entries))
+    }
+
+    /// Allow every endpoint on matching listening addresses (octez `--allow-all-rpc`).
+    /// Takes precedence over all previously configured entries.
+    pub fn allow_all(mut self, address: RpcAddressRange) -> Self {
+        self.entries
+            .insert(0, RpcAclEntry::new(address, RpcAccessPolicy::AllowAll));
+        self
+    }
+
+    /// Resolve access policy for the listening address
+    pub fn policy_for(&self, listen_address: &SocketAddr) -> RpcAccessPolicy {
+        match self
+            .entries
+            .iter()
+            .find(|entry| entry.address.contains(listen_address))
+        {
+            Some(entry) => entry.policy.clone(),
+            None if listen_address.ip().is_loopback() => RpcAccessPolicy::AllowAll,
+            None => RpcAccessPolicy::secure_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_pattern_matches() -> Result<(), failure::Error> {
+        let pattern: RpcPathPattern = "POST /injection/**".parse()?;
+        assert!(pattern.matches(&Method::POST, "/injection/operation"));
+        assert!(pattern.matches(&Method::POST, "/injection"));
+        assert!(!pattern.matches(&Method::GET, "/injection/operation"));
+        assert!(!pattern.matches(&Method::POST, "/chains/main/blocks"));
+
+        let pattern: RpcPathPattern = "/chains/*/blocks/*/header".parse()?;
+        assert!(pattern.matches(&Method::GET, "/chains/main/blocks/head/header"));
+        assert!(pattern.matches(&Method::GET, "/chains/main/blocks/head/header/"));
+        assert!(!pattern.matches(&Method::GET, "/chains/main/blocks/head"));
+        assert!(!pattern.matches(&Method::GET, "/chains/main/blocks/head/header/shell"));
+
+        assert!("/chains/**/header".parse::<RpcPathPattern>().is_err());
+        assert!("chains".parse::<RpcPathPattern>().is_err());
+        assert!("GET POST /chains".parse::<RpcPathPattern>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_range_contains() -> Result<(), failure::Error> {
+        let range: RpcAddressRange = "10.0.0.0/8".parse()?;
+        assert!(range.contains(&"10.1.2.3:8732".parse()?));
+        assert!(!range.contains(&"11.1.2.3:8732".parse()?));
+
+        let range: RpcAddressRange = "192.168.1.0/23:8732".parse()?;
+        assert!(range.contains(&"192.168.0.5:8732".parse()?));
+        assert!(!range.contains(&"192.168.2.5:8732".parse()?));
+        assert!(!range.contains(&"192.168.0.5:8733".parse()?));
+
+        let range: RpcAddressRange = "[::1]:8732".parse()?;
+        assert!(range.contains(&"[::1]:8732".parse()?));
+        assert!(!range.contains(&"127.0.0.1:8732".parse()?));
+
+        let range: RpcAddressRange = "fe80::/10".parse()?;
+        assert!(range.contains(&"[fe80::1]:1".parse()?));
+
+        assert!("10.0.0.0/33".parse::<RpcAddressRange>().is_err());
+        assert!("localhost:8732".parse::<RpcAddressRange>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_acl_policy_for() -> Result<(), failure::Error> {
+        let acl = RpcAcl::from_json(
+            r#"[
+                {"address": "10.0.0.0/8", "whitelist": ["GET /chains/**"]},
+                {"address": "0.0.0.0:18732", "blacklist": ["/dev/**"]}
+            ]"#,
+        )?;
+
+        // loopback without explicit entry allows everything
+        let policy = acl.policy_for(&"127.0.0.1:18732".parse()?);
+        assert!(policy.is_allowed(&Method::POST, "/injection/operation"));
+
+        // public address without explicit entry uses secure default
+        let policy = acl.policy_for(&"1.2.3.4:18732".parse()?);
+        assert!(!policy.is_allowed(&Method::POST, "/injection/operation"));
+        assert!(!policy.is_allowed(&Method::GET, "/stats/memory"));
+        assert!(policy.is_allowed(&Method::GET, "/chains/main/blocks/head"));
+
+        let policy = acl.policy_for(&"10.0.0.1:18732".parse()?);
+        assert!(policy.is_allowed(&Method::GET, "/chains/main/blocks/head"));
+        assert!(!policy.is_allowed(&Method::GET, "/version"));
+
+        let policy = acl.policy_for(&"0.0.0.0:18732".parse()?);
+        assert!(policy.is_allowed(&Method::POST, "/injection/operation"));
+        assert!(!policy.is_allowed(&Method::GET, "/dev/chains/main/blocks"));
+
+        // allow all takes precedence
+        let acl = acl.allow_all("0.0.0.0".parse()?);
+        let policy = acl.policy_for(&"0.0.0.0:18732".parse()?);
+        assert!(policy.is_allowed(&Method::GET, "/dev/chains/main/blocks"));
+
+        assert!(RpcAcl::from_json(r#"[{"address": "0.0.0.0"}]"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Cache of responses, which cannot change anymore - responses about blocks (identified by block hash) older than current head.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use hyper::header::HeaderMap;
+use hyper::{Body, Method, Response, StatusCode};
+use serde::Serialize;
+
+use crypto::hash::HashType;
+use storage::BlockMetaStorage;
+
+use crate::server::acl::RpcPathPattern;
+use crate::server::RpcServiceEnvironment;
+
+/// Routes with immutable responses, `:block_id` is always the fourth path segment
+const IMMUTABLE_BLOCK_ROUTES: &[&str] = &[
+    "GET /chains/*/blocks/*",
+    "GET /chains/*/blocks/*/header",
+    "GET /chains/*/blocks/*/header/shell",
+    "GET /chains/*/blocks/*/hash",
+    "GET /chains/*/blocks/*/protocols",
+    "GET /chains/*/blocks/*/metadata",
+    "GET /chains/*/blocks/*/operations/**",
+    "GET /chains/*/blocks/*/operation_hashes/**",
+    "GET /chains/*/blocks/*/context/constants",
+    "GET /chains/*/blocks/*/helpers/baking_rights",
+    "GET /chains/*/blocks/*/helpers/endorsing_rights",
+];
+
+/// Bigger responses are not cached
+const MAX_CACHED_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Clone)]
+pub(crate) struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    fn to_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    responses: HashMap<String, CachedResponse>,
+    /// Insertion order, the oldest entries are evicted first
+    keys: VecDeque<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RpcResponseCacheStats {
+    hits: u64,
+    misses: u64,
+    entries: usize,
+    max_entries: usize,
+}
+
+/// Bounded cache of immutable responses
+pub struct RpcResponseCache {
+    max_entries: usize,
+    immutable_routes: Vec<RpcPathPattern>,
+    entries: Mutex<CacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RpcResponseCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            immutable_routes: IMMUTABLE_BLOCK_ROUTES
+                .iter()
+                .map(|pattern| {
+                    pattern
+                        .parse()
+                        .expect("Invalid immutable RPC route pattern")
+                })
+                .collect(),
+            entries: Mutex::new(CacheEntries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns cache key, if response for the request cannot change anymore
+    pub(crate) fn cache_key(
+        &self,
+        method: &Method,
+        path: &str,
+        query: Option<&str>,
+        env: &RpcServiceEnvironment,
+    ) -> Option<String> {
+        if self.max_entries == 0
+            || !self
+                .immutable_routes
+                .iter()
+                .any(|pattern| pattern.matches(method, path))
+        {
+            return None;
+        }
+
+        // only block hash identifies block forever (not head, level, head~1, ...)
+        let block_hash = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .nth(3)
+            .and_then(|block_id| HashType::BlockHash.b58check_to_hash(block_id).ok())?;
+
+        // current head can still be changed (e.g. metadata are not stored yet)
+        let is_current_head = env
+            .state()
+            .read()
+            .unwrap()
+            .current_head()
+            .as_ref()
+            .map(|current_head| current_head.hash == block_hash)
+            .unwrap_or(true);
+        if is_current_head {
+            return None;
+        }
+        let is_applied = BlockMetaStorage::new(env.persistent_storage())
+            .get(&block_hash)
+            .ok()
+            .flatten()
+            .map(|meta| meta.is_applied())
+            .unwrap_or(false);
+        if !is_applied {
+            return None;
+        }
+
+        Some(match query {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_string(),
+        })
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Response<Body>> {
+        let cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .responses
+            .get(key)
+            .map(CachedResponse::to_response);
+        match cached {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        cached
+    }
+
+    /// Stores successful response and returns it back to the caller
+    pub(crate) async fn store(
+        &self,
+        key: String,
+        response: Response<Body>,
+    ) -> Result<Response<Body>, hyper::Error> {
+        if response.status() != StatusCode::OK {
+            return Ok(response);
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        if body.len() <= MAX_CACHED_RESPONSE_SIZE {
+            self.put(
+                key,
+                CachedResponse {
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                },
+            );
+        }
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    fn put(&self, key: String, response: CachedResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.responses.insert(key.clone(), response).is_none() {
+            entries.keys.push_back(key);
+        }
+        while entries.keys.len() > self.max_entries {
+            if let Some(evicted) = entries.keys.pop_front() {
+                entries.responses.remove(&evicted);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> RpcResponseCacheStats {
+        RpcResponseCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().responses.len(),
+            max_entries: self.max_entries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached(body: &'static str) -> CachedResponse {
+        CachedResponse {
+            headers: HeaderMap::new(),
+            body: Bytes::from(body),
+        }
+    }
+
+    #[test]
+    fn test_cache_hits_misses_and_eviction() {
+        let cache = RpcResponseCache::new(2);
+
+        assert!(cache.get("a").is_none());
+        cache.put("a".to_string(), cached("a"));
+        cache.put("b".to_string(), cached("b"));
+        assert!(cache.get("a").is_some());
+
+        // the oldest entry is evicted
+        cache.put("c".to_string(), cached("c"));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+
+        let stats = cache.stats();
+        assert_eq!(3, stats.hits);
+        assert_eq!(2, stats.misses);
+        assert_eq!(2, stats.entries);
+    }
+}
@@ -1,13 +1,17 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use hyper::{Body, Request};
+use failure::format_err;
+use hyper::{Body, Method, Request};
 use slog::warn;
 
 use crate::helpers::{parse_block_hash, parse_chain_id, MAIN_CHAIN_ID};
 use crate::server::{HasSingleValue, Params, Query, RpcServiceEnvironment};
-use crate::services::{base_services, dev_services};
-use crate::{empty, make_json_response, required_param, result_to_json_response, ServiceResult};
+use crate::services::{base_services, dev_services, stream_services};
+use crate::{
+    empty, make_json_response, make_json_stream_response, required_param, result_to_json_response,
+    ServiceResult,
+};
 
 pub async fn dev_blocks(
     _: Request<Body>,
@@ -157,6 +161,153 @@ pub async fn dev_stats_memory(
     }
 }
 
+pub async fn rpc_stats(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    make_json_response(&dev_services::get_rpc_stats(&env))
+}
+
+pub async fn dev_context_diff(
+    _: Request<Body>,
+    _: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    // TODO: TE-221 - add optional chain_id to params mapping
+    let chain_id_param = MAIN_CHAIN_ID;
+    let chain_id = parse_chain_id(chain_id_param, &env)?;
+    let from_block_hash = parse_block_hash(&chain_id, required_param!(query, "from")?, &env)?;
+    let to_block_hash = parse_block_hash(&chain_id, required_param!(query, "to")?, &env)?;
+
+    result_to_json_response(
+        dev_services::get_context_diff(
+            &from_block_hash,
+            &to_block_hash,
+            query.get_str("prefix"),
+            query.get_usize("limit"),
+            &env,
+        ),
+        env.log(),
+    )
+}
+
+pub async fn dev_context_key_history(
+    _: Request<Body>,
+    _: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    // TODO: TE-221 - add optional chain_id to params mapping
+    let chain_id_param = MAIN_CHAIN_ID;
+    let chain_id = parse_chain_id(chain_id_param, &env)?;
+    let key = required_param!(query, "key")?;
+    let from_block_hash = parse_block_hash(&chain_id, required_param!(query, "from")?, &env)?;
+    let to_block_hash = parse_block_hash(&chain_id, required_param!(query, "to")?, &env)?;
+
+    result_to_json_response(
+        dev_services::get_context_key_history(&from_block_hash, &to_block_hash, key, &env),
+        env.log(),
+    )
+}
+
+/// Streams changes of the context key between blocks `from` and `to` (default is head), newest first,
+/// only every `step`-th level is checked
+pub async fn dev_context_history(
+    _: Request<Body>,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    // TODO: TE-221 - add optional chain_id to params mapping
+    let chain_id_param = MAIN_CHAIN_ID;
+    let chain_id = parse_chain_id(chain_id_param, &env)?;
+    let key = dev_services::parse_context_key(params.get_str("any").unwrap_or(""))?;
+    let from_block_hash = parse_block_hash(&chain_id, required_param!(query, "from")?, &env)?;
+    let to_block_hash = parse_block_hash(&chain_id, query.get_str("to").unwrap_or("head"), &env)?;
+    let step = match query.get_str("step") {
+        Some(step) => match step.parse::<usize>() {
+            Ok(step) if step > 0 => step,
+            _ => return Err(format_err!("Invalid step argument: {}", step).into()),
+        },
+        None => 1,
+    };
+
+    let cursor = dev_services::get_context_key_history_cursor(
+        key,
+        &from_block_hash,
+        &to_block_hash,
+        step,
+        env.persistent_storage(),
+    )?;
+    make_json_stream_response(stream_services::ContextKeyHistoryStream::new(
+        cursor,
+        env.persistent_storage(),
+    )?)
+}
+
+/// Receipts of operations affecting the account, ordered by level (from `from_level`)
+pub async fn dev_account_operations(
+    _: Request<Body>,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let account = required_param!(params, "pkh")?;
+    let from_level = match query.get_str("from_level") {
+        Some(from_level) => match from_level.parse::<i32>() {
+            Ok(from_level) => Some(from_level),
+            Err(_) => return Err(format_err!("Invalid from_level argument: {}", from_level).into()),
+        },
+        None => None,
+    };
+
+    result_to_json_response(
+        dev_services::get_account_operations(
+            account,
+            from_level,
+            query.get_usize("limit"),
+            env.main_chain_id(),
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
+pub async fn dev_operation_receipt(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(
+        dev_services::get_operation_receipt(
+            required_param!(params, "operation_hash")?,
+            env.main_chain_id(),
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
+pub async fn dev_operation_location(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(
+        dev_services::get_operation_location(
+            required_param!(params, "operation_hash")?,
+            env.main_chain_id(),
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
 pub async fn context_stats(
     _: Request<Body>,
     _: Params,
@@ -168,3 +319,20 @@ pub async fn context_stats(
         env.log(),
     )
 }
+
+/// Returns (GET) or changes (POST) the sandbox node clock
+pub async fn dev_sandbox_clock(
+    req: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    if req.method() == Method::GET {
+        return make_json_response(&dev_services::get_sandbox_clock());
+    }
+
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let body = String::from_utf8(body.to_vec())?;
+
+    result_to_json_response(dev_services::update_sandbox_clock(&body), env.log())
+}
@@ -0,0 +1,325 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Protection of RPC server against overloading.
+//!
+//! Every client (IP address) has its own token bucket, every request takes tokens according to the cost of the route.
+//! Expensive routes are additionally limited by the number of concurrently processed requests.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use getset::CopyGetters;
+use hyper::Method;
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::server::acl::RpcPathPattern;
+
+/// Cost of requests not matching [ROUTE_COSTS]
+const DEFAULT_ROUTE_COST: u32 = 1;
+
+/// (pattern, cost, is expensive) - expensive routes are limited by max concurrency, first match wins
+const ROUTE_COSTS: &[(&str, u32, bool)] = &[
+    ("/chains/*/blocks/*/helpers/baking_rights", 10, true),
+    ("/chains/*/blocks/*/helpers/endorsing_rights", 10, true),
+    ("POST /chains/*/blocks/*/helpers/preapply/**", 10, true),
+    ("/chains/*/blocks/*/context/raw/bytes/**", 5, true),
+    ("/chains/*/blocks/*/context/raw/json/**", 5, true),
+    ("/dev/accounts/*/operations", 10, true),
+    ("/dev/chains/main/actions/**", 20, true),
+    ("/dev/chains/main/blocks", 10, true),
+    ("/dev/chains/main/context/history/**", 20, true),
+    ("/dev/context/diff", 20, true),
+    ("/dev/context/key_history", 20, true),
+];
+
+/// Buckets of clients are dropped after this count is reached, if they are full (client is idle)
+const MAX_TRACKED_CLIENTS: usize = 16 * 1024;
+
+/// Token bucket parameters, every client starts with full bucket
+#[derive(Clone, Copy, Debug, CopyGetters)]
+pub struct RpcRateLimit {
+    /// Refill rate of the bucket
+    #[get_copy = "pub"]
+    tokens_per_second: u32,
+    /// Capacity of the bucket
+    #[get_copy = "pub"]
+    burst: u32,
+}
+
+impl RpcRateLimit {
+    pub fn new(tokens_per_second: u32, burst: u32) -> Self {
+        Self {
+            tokens_per_second: tokens_per_second.max(1),
+            burst: burst.max(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CopyGetters)]
+pub struct RpcLimitsConfiguration {
+    /// Per client rate limit, if not set, requests are not rate limited
+    #[get_copy = "pub"]
+    rate_limit: Option<RpcRateLimit>,
+    /// Max count of concurrently processed expensive requests, other requests wait
+    #[get_copy = "pub"]
+    max_concurrent_expensive_requests: usize,
+    /// Max count of cached immutable responses, 0 disables cache
+    #[get_copy = "pub"]
+    response_cache_max_entries: usize,
+}
+
+impl RpcLimitsConfiguration {
+    pub fn new(
+        rate_limit: Option<RpcRateLimit>,
+        max_concurrent_expensive_requests: usize,
+        response_cache_max_entries: usize,
+    ) -> Self {
+        Self {
+            rate_limit,
+            max_concurrent_expensive_requests: max_concurrent_expensive_requests.max(1),
+            response_cache_max_entries,
+        }
+    }
+}
+
+impl Default for RpcLimitsConfiguration {
+    fn default() -> Self {
+        Self::new(None, 8, 10_000)
+    }
+}
+
+/// Cost of request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct RouteCost {
+    pub(crate) cost: u32,
+    pub(crate) expensive: bool,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(rate_limit: &RpcRateLimit, now: Instant) -> Self {
+        Self {
+            tokens: rate_limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, rate_limit: &RpcRateLimit, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate_limit.tokens_per_second as f64)
+            .min(rate_limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes tokens, or returns how long client has to wait for them
+    fn try_take(
+        &mut self,
+        cost: u32,
+        rate_limit: &RpcRateLimit,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.refill(rate_limit, now);
+
+        // request more expensive than the whole bucket has to be still possible
+        let cost = cost.min(rate_limit.burst) as f64;
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (cost - self.tokens) / rate_limit.tokens_per_second as f64,
+            ))
+        }
+    }
+
+    fn is_full(&self, rate_limit: &RpcRateLimit) -> bool {
+        self.tokens >= rate_limit.burst as f64
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RpcLimiterStats {
+    rate_limited_requests: u64,
+    tracked_clients: usize,
+    expensive_requests_in_progress: usize,
+    max_concurrent_expensive_requests: usize,
+}
+
+/// Shared by all listening addresses, so every client has just one token bucket
+pub struct RpcLimiter {
+    rate_limit: Option<RpcRateLimit>,
+    route_costs: Vec<(RpcPathPattern, RouteCost)>,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    expensive_requests: Semaphore,
+    max_concurrent_expensive_requests: usize,
+    rate_limited_requests: AtomicU64,
+}
+
+impl RpcLimiter {
+    pub fn new(cfg: &RpcLimitsConfiguration) -> Self {
+        Self {
+            rate_limit: cfg.rate_limit(),
+            route_costs: ROUTE_COSTS
+                .iter()
+                .map(|(pattern, cost, expensive)| {
+                    (
+                        pattern.parse().expect("Invalid RPC route cost pattern"),
+                        RouteCost {
+                            cost: *cost,
+                            expensive: *expensive,
+                        },
+                    )
+                })
+                .collect(),
+            buckets: Mutex::new(HashMap::new()),
+            expensive_requests: Semaphore::new(cfg.max_concurrent_expensive_requests()),
+            max_concurrent_expensive_requests: cfg.max_concurrent_expensive_requests(),
+            rate_limited_requests: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn route_cost(&self, method: &Method, path: &str) -> RouteCost {
+        self.route_costs
+            .iter()
+            .find(|(pattern, _)| pattern.matches(method, path))
+            .map(|(_, cost)| *cost)
+            .unwrap_or(RouteCost {
+                cost: DEFAULT_ROUTE_COST,
+                expensive: false,
+            })
+    }
+
+    /// Takes tokens from client's bucket, or returns how long client has to wait for them
+    pub(crate) fn try_take_tokens(&self, client: IpAddr, cost: u32) -> Result<(), Duration> {
+        let rate_limit = match &self.rate_limit {
+            Some(rate_limit) => rate_limit,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| {
+                bucket.refill(rate_limit, now);
+                !bucket.is_full(rate_limit)
+            });
+        }
+
+        let result = buckets
+            .entry(client)
+            .or_insert_with(|| TokenBucket::full(rate_limit, now))
+            .try_take(cost, rate_limit, now);
+        if result.is_err() {
+            self.rate_limited_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Waits until expensive request can be processed, permit has to be held during processing
+    pub(crate) async fn acquire_expensive(&self) -> SemaphorePermit<'_> {
+        self.expensive_requests.acquire().await
+    }
+
+    pub fn stats(&self) -> RpcLimiterStats {
+        RpcLimiterStats {
+            rate_limited_requests: self.rate_limited_requests.load(Ordering::Relaxed),
+            tracked_clients: self.buckets.lock().unwrap().len(),
+            expensive_requests_in_progress: self.max_concurrent_expensive_requests
+                - self.expensive_requests.available_permits(),
+            max_concurrent_expensive_requests: self.max_concurrent_expensive_requests,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let rate_limit = RpcRateLimit::new(10, 20);
+        let now = Instant::now();
+        let mut bucket = TokenBucket::full(&rate_limit, now);
+
+        assert!(bucket.try_take(15, &rate_limit, now).is_ok());
+        assert!(bucket.try_take(5, &rate_limit, now).is_ok());
+        assert_eq!(
+            Err(Duration::from_secs_f64(0.1)),
+            bucket.try_take(1, &rate_limit, now)
+        );
+
+        // refilled after 1s
+        let now = now + Duration::from_secs(1);
+        assert!(bucket.try_take(10, &rate_limit, now).is_ok());
+        assert!(bucket.try_take(1, &rate_limit, now).is_err());
+
+        // never refilled over burst and more expensive request than burst is possible
+        let now = now + Duration::from_secs(100);
+        assert!(bucket.try_take(100, &rate_limit, now).is_ok());
+        assert!(bucket.try_take(1, &rate_limit, now).is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_per_client() {
+        let limiter = RpcLimiter::new(&RpcLimitsConfiguration::new(
+            Some(RpcRateLimit::new(1, 10)),
+            1,
+            0,
+        ));
+        let client_1: IpAddr = "10.0.0.1".parse().unwrap();
+        let client_2: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.try_take_tokens(client_1, 10).is_ok());
+        assert!(limiter.try_take_tokens(client_1, 1).is_err());
+        assert!(limiter.try_take_tokens(client_2, 10).is_ok());
+        assert_eq!(1, limiter.stats().rate_limited_requests);
+
+        // without rate limit, everything is allowed
+        let limiter = RpcLimiter::new(&RpcLimitsConfiguration::default());
+        assert!((0..100).all(|_| limiter.try_take_tokens(client_1, 10).is_ok()));
+    }
+
+    #[test]
+    fn test_route_cost() {
+        let limiter = RpcLimiter::new(&RpcLimitsConfiguration::default());
+
+        assert_eq!(
+            RouteCost {
+                cost: 10,
+                expensive: true
+            },
+            limiter.route_cost(
+                &Method::GET,
+                "/chains/main/blocks/head/helpers/baking_rights"
+            )
+        );
+        assert_eq!(
+            RouteCost {
+                cost: 5,
+                expensive: true
+            },
+            limiter.route_cost(
+                &Method::GET,
+                "/chains/main/blocks/head/context/raw/bytes/contracts"
+            )
+        );
+        assert_eq!(
+            RouteCost {
+                cost: DEFAULT_ROUTE_COST,
+                expensive: false
+            },
+            limiter.route_cost(&Method::GET, "/chains/main/blocks/head/header")
+        );
+    }
+}
@@ -3,15 +3,23 @@
 
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::sync::Arc;
 
+use futures::future::ready;
+use futures::stream::{self, StreamExt};
 use getset::Getters;
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::{AddrIncoming, AddrStream};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response};
+use path_tree::PathTree;
 use riker::actors::ActorSystem;
-use slog::{error, Logger};
+use slog::{debug, error, Logger};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
 
 use crypto::hash::{BlockHash, ChainId};
 use shell::mempool::CurrentMempoolStateStorageRef;
@@ -23,12 +31,57 @@ use tezos_messages::p2p::encoding::version::NetworkVersion;
 use tezos_wrapper::TezosApiConnectionPool;
 
 use crate::rpc_actor::{RpcCollectedStateRef, RpcServerRef};
-use crate::{error_with_message, not_found, options};
+use crate::{error_with_message, forbidden, not_found, options, too_many_requests};
 
+pub use self::acl::{RpcAccessPolicy, RpcAcl, RpcAclError, RpcAddressRange};
+pub use self::cache::{RpcResponseCache, RpcResponseCacheStats};
+pub use self::limits::{RpcLimiter, RpcLimiterStats, RpcLimitsConfiguration, RpcRateLimit};
+pub use self::tls::{RpcTlsConfiguration, RpcTlsError};
+
+mod acl;
+mod cache;
 mod dev_handler;
+mod limits;
 mod protocol_handler;
 mod router;
 mod shell_handler;
+mod tls;
+
+/// Limits number of TLS handshakes processed concurrently on one listening address
+const MAX_PENDING_TLS_HANDSHAKES: usize = 64;
+
+/// Where and how RPC server listens
+#[derive(Getters, Clone, Debug)]
+pub struct RpcServerConfiguration {
+    /// RPC server is started on every address
+    #[get = "pub"]
+    listen_addresses: Vec<SocketAddr>,
+    /// Access policy is resolved per listening address
+    #[get = "pub"]
+    acl: RpcAcl,
+    /// If set, RPC is served only over HTTPS
+    #[get = "pub"]
+    tls: Option<RpcTlsConfiguration>,
+    /// Rate limiting, concurrency limits and caching
+    #[get = "pub"]
+    limits: RpcLimitsConfiguration,
+}
+
+impl RpcServerConfiguration {
+    pub fn new(
+        listen_addresses: Vec<SocketAddr>,
+        acl: RpcAcl,
+        tls: Option<RpcTlsConfiguration>,
+        limits: RpcLimitsConfiguration,
+    ) -> Self {
+        Self {
+            listen_addresses,
+            acl,
+            tls,
+            limits,
+        }
+    }
+}
 
 /// Server environment parameters
 #[derive(Getters, Clone)]
@@ -65,6 +118,11 @@ pub struct RpcServiceEnvironment {
     tezos_readonly_prevalidation_api: Arc<TezosApiConnectionPool>,
     #[get = "pub(crate)"]
     tezos_without_context_api: Arc<TezosApiConnectionPool>,
+
+    #[get = "pub(crate)"]
+    limiter: Arc<RpcLimiter>,
+    #[get = "pub(crate)"]
+    response_cache: Arc<RpcResponseCache>,
 }
 
 impl RpcServiceEnvironment {
@@ -83,6 +141,8 @@ impl RpcServiceEnvironment {
         main_chain_id: ChainId,
         main_chain_genesis_hash: BlockHash,
         state: RpcCollectedStateRef,
+        limiter: Arc<RpcLimiter>,
+        response_cache: Arc<RpcResponseCache>,
         log: &Logger,
     ) -> Self {
         Self {
@@ -101,6 +161,8 @@ impl RpcServiceEnvironment {
             tezos_readonly_api,
             tezos_readonly_prevalidation_api,
             tezos_without_context_api,
+            limiter,
+            response_cache,
         }
     }
 }
@@ -136,28 +198,97 @@ impl MethodHandler {
     }
 }
 
-/// Spawn new HTTP server on given address interacting with specific actor system
-pub fn spawn_server(
+/// Spawn new HTTP(S) server on given address interacting with specific actor system,
+/// requests not allowed by `access_policy` are rejected with 403
+pub async fn spawn_server(
     bind_address: &SocketAddr,
+    access_policy: RpcAccessPolicy,
+    tls_acceptor: Option<TlsAcceptor>,
     env: RpcServiceEnvironment,
-) -> impl Future<Output = Result<(), hyper::Error>> {
+) -> Result<(), failure::Error> {
     let routes = Arc::new(router::create_routes(
         env.state().read().unwrap().is_sandbox(),
     ));
+    let access_policy = Arc::new(access_policy);
+    let mut incoming = AddrIncoming::bind(bind_address)?;
+
+    match tls_acceptor {
+        Some(tls_acceptor) => {
+            let log = env.log().clone();
+            let incoming = stream::poll_fn(move |cx| Pin::new(&mut incoming).poll_accept(cx))
+                .map(move |connection| {
+                    let tls_acceptor = tls_acceptor.clone();
+                    async move { tls_acceptor.accept(connection?).await }
+                })
+                .buffer_unordered(MAX_PENDING_TLS_HANDSHAKES)
+                .filter_map(move |connection| {
+                    ready(match connection {
+                        Ok(connection) => Some(Ok::<_, std::io::Error>(connection)),
+                        Err(e) => {
+                            debug!(log, "RPC TLS handshake failed"; "reason" => format!("{}", e));
+                            None
+                        }
+                    })
+                });
+            serve(accept::from_stream(incoming), routes, access_policy, env).await?
+        }
+        None => serve(incoming, routes, access_policy, env).await?,
+    }
+
+    Ok(())
+}
+
+/// Connection with known client address
+trait RemoteAddress {
+    fn remote_address(&self) -> SocketAddr;
+}
+
+impl RemoteAddress for AddrStream {
+    fn remote_address(&self) -> SocketAddr {
+        self.remote_addr()
+    }
+}
 
-    hyper::Server::bind(bind_address)
-        .serve(make_service_fn(move |_| {
+impl RemoteAddress for TlsStream<AddrStream> {
+    fn remote_address(&self) -> SocketAddr {
+        self.get_ref().0.remote_addr()
+    }
+}
+
+async fn serve<I>(
+    incoming: I,
+    routes: Arc<PathTree<MethodHandler>>,
+    access_policy: Arc<RpcAccessPolicy>,
+    env: RpcServiceEnvironment,
+) -> Result<(), hyper::Error>
+where
+    I: Accept + Send,
+    I::Conn: RemoteAddress + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    hyper::Server::builder(incoming)
+        .serve(make_service_fn(move |connection: &I::Conn| {
+            let client = connection.remote_address().ip();
             let env = env.clone();
             let routes = routes.clone();
+            let access_policy = access_policy.clone();
 
             async move {
                 let env = env.clone();
                 let routes = routes.clone();
+                let access_policy = access_policy.clone();
                 Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
                     let env = env.clone();
                     let routes = routes.clone();
+                    let access_policy = access_policy.clone();
                     async move {
-                        if let Some((method_and_handler, params)) = routes.find(req.uri().path().to_string().trim_end_matches('/')) {
+                        let path = req.uri().path().to_string();
+                        let path = path.trim_end_matches('/');
+                        if !access_policy.is_allowed(req.method(), path) {
+                            return forbidden();
+                        }
+
+                        if let Some((method_and_handler, params)) = routes.find(path) {
                             let MethodHandler {
                                 allowed_methods,
                                 handler,
@@ -176,15 +307,7 @@ pub fn spawn_server(
                                         let params: Params = params.into_iter().map(|(param, value)| (param.to_string(), value.to_string())).collect();
                                         let query: Query = req.uri().query().map(parse_query_string).unwrap_or_else(HashMap::new);
 
-                                        let handler = handler.clone();
-                                        let fut = handler(req, params, query, env);
-                                        match Pin::from(fut).await {
-                                            Ok(response) => Ok(response),
-                                            Err(e) => {
-                                                error!(log, "Failed to execute RPC function - unhandled error"; "reason" => format!("{:?}", &e));
-                                                error_with_message(format!("{:?}", e))
-                                            }
-                                        }
+                                        handle_limited(client, req, handler.clone(), params, query, env).await
                                     } else {
                                         let error_message = format!("Failed to execute RPC function - Method {} not registered for this RPC function", request_method);
                                         error!(log, "{}", error_message);
@@ -199,6 +322,51 @@ pub fn spawn_server(
                 }))
             }
         }))
+        .await
+}
+
+/// Executes handler with respect to rate limits and concurrency limits, immutable responses are cached
+async fn handle_limited(
+    client: IpAddr,
+    req: Request<Body>,
+    handler: Handler,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> HResult {
+    let limiter = env.limiter().clone();
+    let response_cache = env.response_cache().clone();
+    let log = env.log().clone();
+    let path = req.uri().path().trim_end_matches('/').to_string();
+
+    let cache_key = response_cache.cache_key(req.method(), &path, req.uri().query(), &env);
+    if let Some(response) = cache_key.as_ref().and_then(|key| response_cache.get(key)) {
+        return match limiter.try_take_tokens(client, 1) {
+            Ok(()) => Ok(response),
+            Err(retry_after) => too_many_requests(retry_after),
+        };
+    }
+
+    let route_cost = limiter.route_cost(req.method(), &path);
+    if let Err(retry_after) = limiter.try_take_tokens(client, route_cost.cost) {
+        return too_many_requests(retry_after);
+    }
+    let _permit = if route_cost.expensive {
+        Some(limiter.acquire_expensive().await)
+    } else {
+        None
+    };
+
+    match Pin::from(handler(req, params, query, env)).await {
+        Ok(response) => match cache_key {
+            Some(cache_key) => Ok(response_cache.store(cache_key, response).await?),
+            None => Ok(response),
+        },
+        Err(e) => {
+            error!(log, "Failed to execute RPC function - unhandled error"; "reason" => format!("{:?}", &e));
+            error_with_message(format!("{:?}", e))
+        }
+    }
 }
 
 /// Helper for parsing URI queries.
@@ -122,6 +122,31 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<MethodHandler> {
         "/chains/:chain_id/blocks/:block_id/operation_hashes",
         shell_handler::get_block_operation_hashes,
     );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/operation_hashes/:validation_pass_index",
+        shell_handler::get_block_operation_hashes_by_validation_pass,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/operation_hashes/:validation_pass_index/:operation_index",
+        shell_handler::get_block_operation_hash,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/operations",
+        shell_handler::get_block_operations,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/operations/:validation_pass_index",
+        shell_handler::get_block_operations_by_validation_pass,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/operations/:validation_pass_index/:operation_index",
+        shell_handler::get_block_operation,
+    );
     routes.handle(
         hash_set![Method::GET],
         "/chains/:chain_id/blocks/:block_id/context/raw/bytes",
@@ -132,6 +157,16 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<MethodHandler> {
         "/chains/:chain_id/blocks/:block_id/context/raw/bytes/*any",
         shell_handler::context_raw_bytes,
     );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/context/raw/json",
+        shell_handler::context_raw_json,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/blocks/:block_id/context/raw/json/*any",
+        shell_handler::context_raw_json,
+    );
     routes.handle(
         hash_set![Method::GET],
         "/chains/:chain_id/blocks/:block_id/metadata",
@@ -164,6 +199,11 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<MethodHandler> {
             "/injection/block",
             shell_handler::inject_block,
         );
+        routes.handle(
+            hash_set![Method::GET, Method::POST],
+            "/dev/sandbox/clock",
+            dev_handler::dev_sandbox_clock,
+        );
     }
 
     // Shell rpcs - routed through ffi calls
@@ -223,6 +263,36 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<MethodHandler> {
         "/dev/chains/main/actions/contracts/:contract_address",
         dev_handler::dev_action_cursor,
     );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/context/diff",
+        dev_handler::dev_context_diff,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/context/key_history",
+        dev_handler::dev_context_key_history,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/chains/main/context/history/*any",
+        dev_handler::dev_context_history,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/accounts/:pkh/operations",
+        dev_handler::dev_account_operations,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/operations/:operation_hash",
+        dev_handler::dev_operation_receipt,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/chains/main/operations/:operation_hash",
+        dev_handler::dev_operation_location,
+    );
     routes.handle(
         hash_set![Method::GET],
         "/stats/memory",
@@ -233,6 +303,7 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<MethodHandler> {
         "/stats/context",
         dev_handler::context_stats,
     );
+    routes.handle(hash_set![Method::GET], "/stats/rpc", dev_handler::rpc_stats);
     //routes.handle(hash_set![Method::GET], "/stats/storage", dev_handler::dev_stats_storage);
 
     // DEPRECATED in ocaml but still used by python tests
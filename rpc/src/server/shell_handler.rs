@@ -20,12 +20,13 @@ use crate::helpers::{
 use crate::server::{HResult, HasSingleValue, Params, Query, RpcServiceEnvironment};
 use crate::services::{base_services, stream_services};
 use crate::{
-    empty,
+    accepts_binary, empty,
     encoding::{base_types::*, monitor::BootstrapInfo},
-    helpers, make_json_response, make_json_stream_response, required_param,
-    result_option_to_json_response, result_to_empty_json_response, result_to_json_response,
-    services, ServiceResult,
+    helpers, make_binary_response, make_json_response, make_json_stream_response, not_found,
+    required_param, result_option_to_json_response, result_to_empty_json_response,
+    result_to_json_response, services, ServiceResult,
 };
+use storage::merkle_storage::StringTreeEntry;
 use storage::BlockHeaderWithHash;
 
 #[derive(Serialize)]
@@ -71,9 +72,9 @@ pub async fn active_chains(
     _: Request<Body>,
     _: Params,
     _: Query,
-    _: RpcServiceEnvironment,
+    env: RpcServiceEnvironment,
 ) -> HResult {
-    empty()
+    result_to_json_response(base_services::get_active_chains(&env), env.log())
 }
 
 pub async fn protocols(_: Request<Body>, _: Params, _: Query, _: RpcServiceEnvironment) -> HResult {
@@ -250,7 +251,7 @@ pub async fn chains_block_id_metadata(
 }
 
 pub async fn context_raw_bytes(
-    _: Request<Body>,
+    req: Request<Body>,
     params: Params,
     query: Query,
     env: RpcServiceEnvironment,
@@ -258,14 +259,79 @@ pub async fn context_raw_bytes(
     let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
     let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
     let prefix = params.get_str("any");
-    let depth = query.get_usize("depth");
+    let depth = parse_depth(&query)?;
+
+    // value with proof of its presence (or absence) in the block's context
+    if query.contains_key("proof") {
+        return result_to_json_response(
+            base_services::get_context_raw_bytes_proof(&block_hash, prefix, &env),
+            env.log(),
+        );
+    }
+
+    let raw_context = base_services::get_context_raw_bytes(&block_hash, prefix, depth, &env);
+
+    // values can be requested in binary form
+    if accepts_binary(&req) {
+        if let Ok(Some(StringTreeEntry::Blob(value))) = &raw_context {
+            return make_binary_response(hex::decode(value)?);
+        }
+    }
+
+    result_option_to_json_response(raw_context, env.log())
+}
+
+pub async fn context_raw_json(
+    req: Request<Body>,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id_param = required_param!(params, "chain_id")?;
+    let chain_id = parse_chain_id(chain_id_param, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let key = params.get_str("any");
+    // depth is applied by the protocol, but invalid values are rejected here
+    let _ = parse_depth(&query)?;
+
+    // value with proof of its presence (or absence) in the block's context
+    if query.contains_key("proof") {
+        return result_to_json_response(
+            base_services::get_context_raw_bytes_proof(&block_hash, key, &env),
+            env.log(),
+        );
+    }
+
+    if !base_services::context_raw_key_exists(&block_hash, key, &env)? {
+        return not_found();
+    }
 
+    // values are decoded to json by the storage descriptions of the block's protocol
+    let json_request = create_rpc_request(req).await?;
     result_to_json_response(
-        base_services::get_context_raw_bytes(&block_hash, prefix, depth, &env),
+        services::protocol::call_protocol_rpc(
+            chain_id_param,
+            chain_id,
+            block_hash,
+            json_request,
+            &env,
+        ),
         env.log(),
     )
 }
 
+/// Parse optional `depth` query argument of the context RPCs
+fn parse_depth(query: &Query) -> Result<Option<usize>, failure::Error> {
+    match query.get_str("depth") {
+        Some(depth) => {
+            Ok(Some(depth.parse::<usize>().map_err(|_| {
+                format_err!("Invalid depth argument: {}", depth)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
 pub async fn mempool_pending_operations(
     _: Request<Body>,
     params: Params,
@@ -395,12 +461,115 @@ pub async fn get_block_operation_hashes(
     let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
     let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
 
-    result_to_json_response(
+    result_option_to_json_response(
         base_services::get_block_operation_hashes(&chain_id, &block_hash, env.persistent_storage()),
         env.log(),
     )
 }
 
+pub async fn get_block_operation_hashes_by_validation_pass(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let validation_pass: usize = required_param!(params, "validation_pass_index")?.parse()?;
+
+    result_option_to_json_response(
+        base_services::get_block_operation_hashes_by_validation_pass(
+            &chain_id,
+            &block_hash,
+            validation_pass,
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
+pub async fn get_block_operation_hash(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let validation_pass: usize = required_param!(params, "validation_pass_index")?.parse()?;
+    let operation_index: usize = required_param!(params, "operation_index")?.parse()?;
+
+    result_option_to_json_response(
+        base_services::get_block_operation_hash(
+            &chain_id,
+            &block_hash,
+            validation_pass,
+            operation_index,
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
+pub async fn get_block_operations(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+
+    result_option_to_json_response(
+        base_services::get_block_operations(&chain_id, &block_hash, env.persistent_storage()),
+        env.log(),
+    )
+}
+
+pub async fn get_block_operations_by_validation_pass(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let validation_pass: usize = required_param!(params, "validation_pass_index")?.parse()?;
+
+    result_option_to_json_response(
+        base_services::get_block_operations_by_validation_pass(
+            &chain_id,
+            &block_hash,
+            validation_pass,
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
+pub async fn get_block_operation(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let validation_pass: usize = required_param!(params, "validation_pass_index")?.parse()?;
+    let operation_index: usize = required_param!(params, "operation_index")?.parse()?;
+
+    result_option_to_json_response(
+        base_services::get_block_operation(
+            &chain_id,
+            &block_hash,
+            validation_pass,
+            operation_index,
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
 pub async fn live_blocks(
     _: Request<Body>,
     params: Params,
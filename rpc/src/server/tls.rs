@@ -0,0 +1,93 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Optional TLS support for RPC server.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::Fail;
+use getset::Getters;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig, TLSError};
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Debug, Fail)]
+pub enum RpcTlsError {
+    #[fail(display = "Failed to read TLS file: {:?}, reason: {}", path, reason)]
+    IOError {
+        path: PathBuf,
+        reason: std::io::Error,
+    },
+    #[fail(display = "Invalid TLS certificate file: {:?}", path)]
+    InvalidCertificate { path: PathBuf },
+    #[fail(display = "Invalid or missing TLS private key in file: {:?}", path)]
+    InvalidPrivateKey { path: PathBuf },
+    #[fail(display = "Invalid TLS configuration, reason: {}", reason)]
+    ConfigurationError { reason: TLSError },
+}
+
+/// Certificate chain and private key (both PEM encoded) used to serve RPC over HTTPS
+#[derive(Clone, Debug, Getters)]
+pub struct RpcTlsConfiguration {
+    #[get = "pub"]
+    cert_path: PathBuf,
+    #[get = "pub"]
+    key_path: PathBuf,
+}
+
+impl RpcTlsConfiguration {
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> Self {
+        Self {
+            cert_path,
+            key_path,
+        }
+    }
+
+    /// Load certificates and key and create TLS acceptor
+    pub(crate) fn create_acceptor(&self) -> Result<TlsAcceptor, RpcTlsError> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = load_private_key(&self.key_path)?;
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(certs, key)
+            .map_err(|reason| RpcTlsError::ConfigurationError { reason })?;
+        config.set_protocols(&[b"http/1.1".to_vec()]);
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, RpcTlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|reason| RpcTlsError::IOError {
+            path: path.to_path_buf(),
+            reason,
+        })
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, RpcTlsError> {
+    match certs(&mut open(path)?) {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        _ => Err(RpcTlsError::InvalidCertificate {
+            path: path.to_path_buf(),
+        }),
+    }
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey, RpcTlsError> {
+    let invalid_key = || RpcTlsError::InvalidPrivateKey {
+        path: path.to_path_buf(),
+    };
+
+    // try PKCS8 first, fallback to RSA
+    let mut keys = pkcs8_private_keys(&mut open(path)?).map_err(|_| invalid_key())?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(path)?).map_err(|_| invalid_key())?;
+    }
+    keys.into_iter().next().ok_or_else(invalid_key)
+}
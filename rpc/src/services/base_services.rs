@@ -1,26 +1,32 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::HashMap;
+
 use failure::bail;
+use serde_json::Value;
 
 use crypto::hash::{BlockHash, ChainId, HashType};
 use storage::block_storage::BlockJsonData;
 use storage::context::ContextApi;
-use storage::merkle_storage::StringTreeEntry;
+use storage::merkle_storage::{ContextKey, MerkleError, StringTreeEntry};
 use storage::persistent::PersistentStorage;
 use storage::{
     context_key, BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
-    BlockStorageReader,
+    BlockStorageReader, ChainMetaStorage,
 };
 use tezos_messages::p2p::encoding::version::NetworkVersion;
 
+use crate::encoding::base_types::TimeStamp;
+use crate::encoding::monitor::{ActiveChains, ChainStatus};
 use crate::helpers::{
-    get_context_hash, BlockHeaderInfo, BlockHeaderShellInfo, BlockMetadata, FullBlockInfo,
-    NodeVersion, Protocols,
+    get_context_hash, BlockHeaderInfo, BlockHeaderShellInfo, BlockMetadata, ContextValueWithProof,
+    FullBlockInfo, NodeVersion, Protocols,
 };
 use crate::server::RpcServiceEnvironment;
 
 pub type BlockOperations = Vec<String>;
+pub type BlockOperation = HashMap<String, Value>;
 
 /// Retrieve blocks from database.
 pub(crate) fn get_blocks(
@@ -113,25 +119,66 @@ pub(crate) fn live_blocks(
     Ok(live_blocks)
 }
 
+/// Get context value or subtree under the prefix (relative to "/data"),
+/// returns None if there is nothing under the prefix
 pub(crate) fn get_context_raw_bytes(
     block_hash: &BlockHash,
     prefix: Option<&str>,
     depth: Option<usize>,
     env: &RpcServiceEnvironment,
-) -> Result<StringTreeEntry, failure::Error> {
+) -> Result<Option<StringTreeEntry>, failure::Error> {
+    let key_prefix = context_raw_key(prefix);
+
+    let ctx_hash = get_context_hash(block_hash, env)?;
+    match env
+        .tezedge_context()
+        .get_context_tree_by_prefix(&ctx_hash, &key_prefix, depth)
+    {
+        Ok(tree) => Ok(Some(tree)),
+        Err(MerkleError::ValueNotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Check if there is a value or a subtree under the key (relative to "/data") in the block's context
+pub(crate) fn context_raw_key_exists(
+    block_hash: &BlockHash,
+    key: Option<&str>,
+    env: &RpcServiceEnvironment,
+) -> Result<bool, failure::Error> {
+    Ok(get_context_raw_bytes(block_hash, key, Some(0), env)?.is_some())
+}
+
+/// Get context value under the key (relative to "/data"), if any, together with the proof
+/// of its presence or absence in the block's context
+pub(crate) fn get_context_raw_bytes_proof(
+    block_hash: &BlockHash,
+    key: Option<&str>,
+    env: &RpcServiceEnvironment,
+) -> Result<ContextValueWithProof, failure::Error> {
+    let key = context_raw_key(key);
+
+    let ctx_hash = get_context_hash(block_hash, env)?;
+    let (value, proof) = env.tezedge_context().get_merkle_proof(&ctx_hash, &key)?;
+    Ok(ContextValueWithProof::new(&ctx_hash, value, proof))
+}
+
+/// Context key under "/data" for the path (prefix) of the context RPCs
+pub(crate) fn context_raw_key(prefix: Option<&str>) -> ContextKey {
     // we assume that root is at "/data"
-    let mut key_prefix = context_key!("data");
+    let mut key = context_key!("data");
 
     // clients may pass in a prefix (without /data) with elements containing slashes (expecting us to split)
     // we need to join with '/' and split again
     if let Some(prefix) = prefix {
-        key_prefix.extend(prefix.split('/').map(|s| s.to_string()));
+        key.extend(
+            prefix
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        );
     };
-
-    let ctx_hash = get_context_hash(block_hash, env)?;
-    Ok(env
-        .tezedge_context()
-        .get_context_tree_by_prefix(&ctx_hash, &key_prefix, depth)?)
+    key
 }
 
 /// Extract the current_protocol and the next_protocol from the block metadata
@@ -157,36 +204,126 @@ pub(crate) fn get_block_protocols(
     }
 }
 
-/// Returns the chain id for the requested chain
+/// Returns the operation hashes of the block (grouped by validation pass), if the block is found
 pub(crate) fn get_block_operation_hashes(
     chain_id: &ChainId,
     block_hash: &BlockHash,
     persistent_storage: &PersistentStorage,
-) -> Result<Vec<BlockOperations>, failure::Error> {
-    if let Some(block_info) = get_block(chain_id, block_hash, persistent_storage)? {
-        let operations = block_info
-            .operations
-            .into_iter()
-            .map(|op_group| {
-                op_group
-                    .into_iter()
-                    .map(|op| op["hash"].to_string().replace("\"", ""))
-                    .collect()
-            })
-            .collect();
-        Ok(operations)
-    } else {
-        bail!(
-            "Cannot retrieve operation hashes from block, block_hash {} not found!",
-            HashType::BlockHash.hash_to_b58check(block_hash)
-        )
-    }
+) -> Result<Option<Vec<BlockOperations>>, failure::Error> {
+    Ok(
+        get_block(chain_id, block_hash, persistent_storage)?.map(|block_info| {
+            block_info
+                .operations
+                .into_iter()
+                .map(|op_group| {
+                    op_group
+                        .into_iter()
+                        .map(|op| op["hash"].to_string().replace("\"", ""))
+                        .collect()
+                })
+                .collect()
+        }),
+    )
+}
+
+/// Returns the operation hashes of the requested validation pass
+pub(crate) fn get_block_operation_hashes_by_validation_pass(
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    validation_pass: usize,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<BlockOperations>, failure::Error> {
+    Ok(
+        get_block_operation_hashes(chain_id, block_hash, persistent_storage)?
+            .and_then(|operations| operations.into_iter().nth(validation_pass)),
+    )
+}
+
+/// Returns the hash of the operation at the requested validation pass and index
+pub(crate) fn get_block_operation_hash(
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    validation_pass: usize,
+    operation_index: usize,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<String>, failure::Error> {
+    Ok(get_block_operation_hashes_by_validation_pass(
+        chain_id,
+        block_hash,
+        validation_pass,
+        persistent_storage,
+    )?
+    .and_then(|operations| operations.into_iter().nth(operation_index)))
+}
+
+/// Extract all the operations (with receipts) included in the provided block
+pub(crate) fn get_block_operations(
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<Vec<Vec<BlockOperation>>>, failure::Error> {
+    Ok(
+        get_block(chain_id, block_hash, persistent_storage)?
+            .map(|block_info| block_info.operations),
+    )
+}
+
+/// Extract the operations (with receipts) of the requested validation pass
+pub(crate) fn get_block_operations_by_validation_pass(
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    validation_pass: usize,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<Vec<BlockOperation>>, failure::Error> {
+    Ok(
+        get_block_operations(chain_id, block_hash, persistent_storage)?
+            .and_then(|operations| operations.into_iter().nth(validation_pass)),
+    )
+}
+
+/// Extract the operation (with receipts) at the requested validation pass and index
+pub(crate) fn get_block_operation(
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    validation_pass: usize,
+    operation_index: usize,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<BlockOperation>, failure::Error> {
+    Ok(get_block_operations_by_validation_pass(
+        chain_id,
+        block_hash,
+        validation_pass,
+        persistent_storage,
+    )?
+    .and_then(|operations| operations.into_iter().nth(operation_index)))
 }
 
 pub(crate) fn get_node_version(network_version: &NetworkVersion) -> NodeVersion {
     NodeVersion::new(network_version)
 }
 
+/// Retrieve main chain and its active test chain (if any)
+pub(crate) fn get_active_chains(
+    env: &RpcServiceEnvironment,
+) -> Result<ActiveChains, failure::Error> {
+    let mut active_chains = vec![ChainStatus::basic(
+        HashType::ChainId.hash_to_b58check(env.main_chain_id()),
+    )];
+
+    let chain_meta_storage = ChainMetaStorage::new(env.persistent_storage());
+    if let Some(test_chain_id) = chain_meta_storage.get_test_chain_id(env.main_chain_id())? {
+        if let Some(data) = chain_meta_storage.get_test_chain_data(&test_chain_id)? {
+            active_chains.push(ChainStatus::detailed(
+                HashType::ChainId.hash_to_b58check(&test_chain_id),
+                HashType::ProtocolHash.hash_to_b58check(&data.protocol_hash),
+                TimeStamp::Integral(data.expiration),
+            ));
+        }
+    }
+
+    Ok(active_chains)
+}
+
 pub(crate) fn get_block(
     chain_id: &ChainId,
     block_hash: &BlockHash,
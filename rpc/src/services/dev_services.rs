@@ -1,24 +1,43 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryInto;
+
+use failure::bail;
 use slog::Logger;
 
-use crypto::hash::{BlockHash, HashType};
+use crypto::hash::{BlockHash, ChainId, HashType};
+use shell::sandbox_clock::{self, SandboxClockState, SandboxClockUpdate};
 use shell::stats::memory::{Memory, MemoryData, MemoryStatsResult};
+use storage::chain_meta_storage::{ChainMetaStorage, ChainMetaStorageReader};
 use storage::context::{ContextApi, TezedgeContext};
 use storage::context_action_storage::{
     contract_id_to_contract_address_for_index, ContextActionFilters, ContextActionJson,
 };
-use storage::merkle_storage::MerkleStorageStats;
+use storage::merkle_storage::{ContextKey, KeyHistoryCursor, MerkleStorageStats};
 use storage::persistent::PersistentStorage;
-use storage::{ContextActionRecordValue, ContextActionStorage};
+use storage::{
+    BlockHeaderWithHash, BlockStorage, BlockStorageReader, ContextActionRecordValue,
+    ContextActionStorage, OperationReceiptsStorage, OperationsStorage, OperationsStorageReader,
+};
 use tezos_context::channel::ContextAction;
 use tezos_messages::base::rpc_support::UniversalValue;
 
-use crate::helpers::{get_action_types, PagedResult};
+use crate::helpers::{
+    get_action_types, get_context_hash, ContextKeyHistoryJson, ContextValueDiffJson,
+    OperationLocationJson, OperationReceiptJson, PagedResult, RpcStats,
+};
 use crate::server::RpcServiceEnvironment;
+use crate::services::base_services;
 use crate::services::protocol::get_context_protocol_params;
 
+/// Max count of levels returned by [get_context_key_history]
+const KEY_HISTORY_MAX_LEVELS: i32 = 1000;
+
+/// Default and max count of operations returned by [get_account_operations]
+const ACCOUNT_OPERATIONS_DEFAULT_LIMIT: usize = 100;
+const ACCOUNT_OPERATIONS_MAX_LIMIT: usize = 1000;
+
 /// Get actions for a specific block in ascending order.
 #[allow(dead_code)]
 pub(crate) fn get_block_actions(
@@ -113,6 +132,220 @@ pub(crate) fn get_context_stats(
     Ok(context.get_merkle_stats()?)
 }
 
+pub(crate) fn get_rpc_stats(env: &RpcServiceEnvironment) -> RpcStats {
+    RpcStats {
+        limiter: env.limiter().stats(),
+        response_cache: env.response_cache().stats(),
+    }
+}
+
+/// Get (at most limit) context values which differ between two blocks under the key prefix (relative to "/data")
+pub(crate) fn get_context_diff(
+    from_block_hash: &BlockHash,
+    to_block_hash: &BlockHash,
+    prefix: Option<&str>,
+    limit: Option<usize>,
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<ContextValueDiffJson>, failure::Error> {
+    let from_context_hash = get_context_hash(from_block_hash, env)?;
+    let to_context_hash = get_context_hash(to_block_hash, env)?;
+    let prefix = base_services::context_raw_key(prefix);
+
+    Ok(env
+        .tezedge_context()
+        .get_context_diff(&from_context_hash, &to_context_hash, &prefix, limit)?
+        .into_iter()
+        .map(|mut diff| {
+            // keys are relative to "/data" like the prefix
+            diff.key.remove(0);
+            ContextValueDiffJson::from(diff)
+        })
+        .collect())
+}
+
+/// Get values of the context key in the blocks from `from_block_hash` up to its descendant `to_block_hash`
+/// Parses context key from the path separated by '/'
+pub(crate) fn parse_context_key(key: &str) -> Result<ContextKey, failure::Error> {
+    let key: ContextKey = key
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    if key.is_empty() {
+        bail!("Context key cannot be empty");
+    }
+    Ok(key)
+}
+
+/// Get receipts of operations affecting the account (requires `--index-operation-receipts`) in ascending order by level,
+/// only operations of the blocks of the current main chain are returned.
+pub(crate) fn get_account_operations(
+    account: &str,
+    from_level: Option<i32>,
+    limit: Option<usize>,
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Vec<OperationReceiptJson>, failure::Error> {
+    let limit = limit
+        .unwrap_or(ACCOUNT_OPERATIONS_DEFAULT_LIMIT)
+        .min(ACCOUNT_OPERATIONS_MAX_LIMIT);
+    let head = match ChainMetaStorage::new(persistent_storage).get_current_head(chain_id)? {
+        Some(head) => head,
+        None => return Ok(vec![]),
+    };
+    let receipts = OperationReceiptsStorage::new(persistent_storage)
+        .get_by_account(account, from_level, limit, &head)?
+        .into_iter()
+        .map(|(operation_hash, receipt)| OperationReceiptJson::new(&operation_hash, receipt))
+        .collect();
+    Ok(receipts)
+}
+
+/// Get receipt of the operation applied by the block of the current main chain (requires `--index-operation-receipts`).
+pub(crate) fn get_operation_receipt(
+    operation_hash: &str,
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<OperationReceiptJson>, failure::Error> {
+    let operation_hash = HashType::OperationHash.b58check_to_hash(operation_hash)?;
+    let head = match ChainMetaStorage::new(persistent_storage).get_current_head(chain_id)? {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    Ok(OperationReceiptsStorage::new(persistent_storage)
+        .get(&operation_hash, &head)?
+        .map(|receipt| OperationReceiptJson::new(&operation_hash, receipt)))
+}
+
+/// Get location of the operation in the block of the current main chain.
+pub(crate) fn get_operation_location(
+    operation_hash: &str,
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<OperationLocationJson>, failure::Error> {
+    let operation_hash = HashType::OperationHash.b58check_to_hash(operation_hash)?;
+    let head = match ChainMetaStorage::new(persistent_storage).get_current_head(chain_id)? {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    match OperationsStorage::new(persistent_storage)
+        .get_operation_location(&operation_hash, &head)?
+    {
+        Some(location) => {
+            let level = BlockStorage::new(persistent_storage)
+                .get(&location.block_hash)?
+                .map(|block| block.header.level());
+            Ok(Some(OperationLocationJson::new(
+                &operation_hash,
+                location,
+                level,
+            )))
+        }
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn get_context_key_history(
+    from_block_hash: &BlockHash,
+    to_block_hash: &BlockHash,
+    key: &str,
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<ContextKeyHistoryJson>, failure::Error> {
+    let key = parse_context_key(key)?;
+
+    let block_storage = BlockStorage::new(env.persistent_storage());
+    let get_header = |block_hash: &BlockHash| -> Result<BlockHeaderWithHash, failure::Error> {
+        match block_storage.get(block_hash)? {
+            Some(header) => Ok(header),
+            None => bail!(
+                "Block not found for block_hash: {}",
+                HashType::BlockHash.hash_to_b58check(block_hash)
+            ),
+        }
+    };
+
+    let from_level = get_header(from_block_hash)?.header.level();
+    let mut header = get_header(to_block_hash)?;
+    if header.header.level() < from_level {
+        bail!("Block `to` cannot be lower than block `from`");
+    }
+    if header.header.level() - from_level >= KEY_HISTORY_MAX_LEVELS {
+        bail!(
+            "Too many levels requested, max count is {}",
+            KEY_HISTORY_MAX_LEVELS
+        );
+    }
+
+    // walk predecessors from `to` down to `from`
+    let mut history = Vec::with_capacity((header.header.level() - from_level + 1) as usize);
+    loop {
+        let value = env
+            .tezedge_context()
+            .get_key_from_history(header.header.context(), &key)?;
+        history.push(ContextKeyHistoryJson::new(
+            header.header.level(),
+            &header.hash,
+            value,
+        ));
+
+        if header.header.level() <= from_level {
+            break;
+        }
+        header = get_header(header.header.predecessor())?;
+    }
+
+    if header.hash != *from_block_hash {
+        bail!("Block `from` is not an ancestor of block `to`");
+    }
+
+    history.reverse();
+    Ok(history)
+}
+
+/// Creates cursor for streaming the key history between blocks `from` and `to`.
+///
+/// Levels and ancestry are checked up front by walking predecessors of `to` down to the level of `from`,
+/// so the stream does not fail after walking the whole range of commits.
+pub(crate) fn get_context_key_history_cursor(
+    key: ContextKey,
+    from_block_hash: &BlockHash,
+    to_block_hash: &BlockHash,
+    step: usize,
+    persistent_storage: &PersistentStorage,
+) -> Result<KeyHistoryCursor, failure::Error> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let get_header = |block_hash: &BlockHash| -> Result<BlockHeaderWithHash, failure::Error> {
+        match block_storage.get(block_hash)? {
+            Some(header) => Ok(header),
+            None => bail!(
+                "Block not found for block_hash: {}",
+                HashType::BlockHash.hash_to_b58check(block_hash)
+            ),
+        }
+    };
+
+    let from = get_header(from_block_hash)?;
+    let to = get_header(to_block_hash)?;
+    if to.header.level() < from.header.level() {
+        bail!("Block `to` cannot be lower than block `from`");
+    }
+
+    let mut header = to.clone();
+    while header.header.level() > from.header.level() {
+        header = get_header(header.header.predecessor())?;
+    }
+    if header.hash != from.hash {
+        bail!("Block `from` is not an ancestor of block `to`");
+    }
+
+    Ok(KeyHistoryCursor::new(
+        key,
+        from.header.context().as_slice().try_into()?,
+        to.header.context().as_slice().try_into()?,
+        step,
+    ))
+}
+
 pub(crate) fn get_cycle_length_for_block(
     block_hash: &BlockHash,
     env: &RpcServiceEnvironment,
@@ -134,3 +367,17 @@ pub(crate) fn get_cycle_length_for_block(
         Ok(4096)
     }
 }
+
+pub(crate) fn get_sandbox_clock() -> SandboxClockState {
+    sandbox_clock::node_clock().state()
+}
+
+pub(crate) fn update_sandbox_clock(body: &str) -> Result<SandboxClockState, failure::Error> {
+    let update: SandboxClockUpdate = if body.trim().is_empty() {
+        SandboxClockUpdate::default()
+    } else {
+        serde_json::from_str(body)?
+    };
+
+    Ok(sandbox_clock::node_clock().update(&update))
+}
@@ -14,6 +14,7 @@ use slog::info;
 
 use crypto::hash::{ChainId, HashType, OperationHash, ProtocolHash};
 use shell::mempool::CurrentMempoolStateStorageRef;
+use shell::sandbox_clock;
 use shell::shell_channel::{
     InjectBlock, MempoolOperationReceived, RequestCurrentHead, ShellChannelMsg, ShellChannelRef,
     ShellChannelTopic,
@@ -225,7 +226,7 @@ pub fn inject_operation(
     // store operation in mempool storage
     let mut mempool_storage = MempoolStorage::new(persistent_storage);
     let operation_hash_b58check_string = HashType::OperationHash.hash_to_b58check(&operation_hash);
-    let ttl = SystemTime::now() + Duration::from_secs(60);
+    let ttl = sandbox_clock::system_now() + Duration::from_secs(60);
     mempool_storage.put(MempoolOperationType::Pending, operation.into(), ttl)?;
 
     // callback will wait all the asynchonous processing to finish, and then returns rpc response
@@ -307,6 +308,16 @@ pub fn inject_block(
           "is_async" => is_async,
     );
 
+    // sandbox block from the future (according to the moved node clock) would be just ignored by chain_manager, so we report it here
+    if env.state().read().unwrap().is_sandbox() && validation::is_future_block(&header.header)? {
+        return Err(format_err!(
+            "Block injection - block is in the future, block_hash: {}, timestamp: {}, node clock: {}!",
+            &block_hash_b58check_string,
+            header.header.timestamp(),
+            sandbox_clock::now().timestamp(),
+        ));
+    }
+
     // special case for block on level 1 - has 0 validation passes
     let validation_passes: Option<Vec<Vec<Operation>>> = if header.header.validation_pass() > 0 {
         Some(
@@ -1,6 +1,6 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::future::Future;
 use std::pin::Pin;
 
@@ -15,15 +15,19 @@ use tokio::time::{Duration, Instant};
 
 use crypto::hash::{BlockHash, ChainId, HashType, ProtocolHash};
 use shell::mempool::CurrentMempoolStateStorageRef;
+use storage::merkle_storage::{ContextKeyChange, KeyHistoryCursor, MerkleSnapshot};
 use storage::persistent::PersistentStorage;
 use storage::{BlockHeaderWithHash, BlockStorage, BlockStorageReader};
 
-use crate::helpers::{BlockHeaderInfo, FullBlockInfo};
+use crate::helpers::{BlockHeaderInfo, ContextKeyHistoryJson, FullBlockInfo};
 use crate::rpc_actor::RpcCollectedStateRef;
 use crate::services::mempool_services::get_pending_operations;
 
 pub const MONITOR_TIMER_MILIS: u64 = 100;
 
+/// Max count of commits walked by [ContextKeyHistoryStream] in one poll
+const KEY_HISTORY_COMMITS_PER_POLL: usize = 256;
+
 /// Object containing information to recreate the block header shell information
 #[derive(Serialize, Debug, Clone)]
 struct BlockHeaderMonitorInfo {
@@ -99,6 +103,68 @@ pub struct OperationMonitorStream {
     query: MempoolOperationsQuery,
 }
 
+/// Streams changes of the context key (newest first) by walking parent commits in the merkle storage,
+/// one json line (level, block hash, value) per changed value
+pub struct ContextKeyHistoryStream {
+    block_storage: BlockStorage,
+    /// committed contexts are read without locking the merkle storage
+    merkle: MerkleSnapshot,
+
+    cursor: KeyHistoryCursor,
+    /// json lines ready to be yielded
+    buffer: VecDeque<String>,
+}
+
+impl ContextKeyHistoryStream {
+    pub fn new(
+        cursor: KeyHistoryCursor,
+        persistent_storage: &PersistentStorage,
+    ) -> Result<Self, failure::Error> {
+        let merkle = persistent_storage
+            .merkle()
+            .read()
+            .expect("lock poisoning")
+            .reader()
+            .snapshot(cursor.to_commit_hash())?;
+        Ok(Self {
+            block_storage: BlockStorage::new(persistent_storage),
+            merkle,
+            cursor,
+            buffer: VecDeque::new(),
+        })
+    }
+
+    fn walk_history(&mut self) -> Result<(), failure::Error> {
+        let changes = self
+            .merkle
+            .walk_key_history(&mut self.cursor, KEY_HISTORY_COMMITS_PER_POLL)?;
+
+        for ContextKeyChange { commit_hash, value } in changes {
+            let block = match self
+                .block_storage
+                .get_by_context_hash(&commit_hash.to_vec())?
+            {
+                Some(block) => block,
+                None => {
+                    return Err(format_err!(
+                        "Missing block for context_hash: {}",
+                        HashType::ContextHash.hash_to_b58check(&commit_hash),
+                    ))
+                }
+            };
+
+            let mut change_string = serde_json::to_string(&ContextKeyHistoryJson::new(
+                block.header.level(),
+                &block.hash,
+                value,
+            ))?;
+            change_string.push('\n');
+            self.buffer.push_back(change_string);
+        }
+        Ok(())
+    }
+}
+
 impl OperationMonitorStream {
     pub fn new(
         chain_id: ChainId,
@@ -430,4 +496,193 @@ impl Stream for OperationMonitorStream {
     }
 }
 
-// TODO: add tests for both Streams!
+impl Stream for ContextKeyHistoryStream {
+    type Item = Result<String, failure::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<String, failure::Error>>> {
+        if let Some(change_string) = self.buffer.pop_front() {
+            return Poll::Ready(Some(Ok(change_string)));
+        }
+        if self.cursor.is_finished() {
+            return Poll::Ready(None);
+        }
+
+        if let Err(e) = self.walk_history() {
+            // end the stream after the error
+            self.cursor.finish();
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        match self.buffer.pop_front() {
+            Some(change_string) => Poll::Ready(Some(Ok(change_string))),
+            None => {
+                // no change in the walked commits, continue with the next batch
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use storage::context::{ContextApi, TezedgeContext};
+    use storage::context_key;
+    use storage::tests_common::TmpStorage;
+    use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+    use crate::services::dev_services::get_context_key_history_cursor;
+
+    use super::*;
+
+    fn block(
+        hash: BlockHash,
+        level: i32,
+        predecessor: &BlockHash,
+        context_hash: Vec<u8>,
+    ) -> Result<BlockHeaderWithHash, failure::Error> {
+        Ok(BlockHeaderWithHash {
+            hash,
+            header: Arc::new(
+                BlockHeaderBuilder::default()
+                    .level(level)
+                    .proto(0)
+                    .predecessor(predecessor.clone())
+                    .timestamp(5_635_634)
+                    .validation_pass(0)
+                    .operations_hash(HashType::OperationListListHash.b58check_to_hash(
+                        "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc",
+                    )?)
+                    .fitness(vec![])
+                    .context(context_hash)
+                    .protocol_data(vec![])
+                    .build()
+                    .unwrap(),
+            ),
+        })
+    }
+
+    #[test]
+    fn test_context_key_history_stream() -> Result<(), failure::Error> {
+        let tmp_storage =
+            TmpStorage::create_to_out_dir("__stream_services:test_context_key_history_stream")?;
+        let persistent_storage = tmp_storage.storage();
+        let block_storage = BlockStorage::new(persistent_storage);
+        let mut context = TezedgeContext::new(
+            BlockStorage::new(persistent_storage),
+            persistent_storage.merkle(),
+        );
+
+        // data/a is changed at levels 0, 2 and 4, data/x at every level
+        let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+        for level in 0..=5u8 {
+            if level % 2 == 0 {
+                context.set(&None, &context_key!("data/a"), &vec![level])?;
+            }
+            context.set(&None, &context_key!("data/x"), &vec![level])?;
+            let block_hash = vec![level; 32];
+            let context_hash = context.commit(
+                &block_hash,
+                &None,
+                "Tezos".to_string(),
+                format!("Block {}", level),
+                i64::from(level),
+            )?;
+            let predecessor = match blocks.last() {
+                Some(predecessor) => predecessor.hash.clone(),
+                None => block_hash.clone(),
+            };
+            let block = block(
+                block_hash,
+                i32::from(level),
+                &predecessor,
+                context_hash.clone(),
+            )?;
+            block_storage.put_block_header(&block)?;
+            block_storage.assign_to_context(&block.hash, &context_hash)?;
+            blocks.push(block);
+        }
+
+        let stream = |key: &str,
+                      from: usize,
+                      to: usize,
+                      step: usize|
+         -> Result<Vec<String>, failure::Error> {
+            let cursor = get_context_key_history_cursor(
+                context_key!(key),
+                &blocks[from].hash,
+                &blocks[to].hash,
+                step,
+                persistent_storage,
+            )?;
+            block_on(ContextKeyHistoryStream::new(cursor, persistent_storage)?.collect::<Vec<_>>())
+                .into_iter()
+                .collect()
+        };
+        let json_line = |level: usize, value: Option<u8>| {
+            serde_json::to_string(&ContextKeyHistoryJson::new(
+                level as i32,
+                &blocks[level].hash,
+                value.map(|value| vec![value]),
+            ))
+            .unwrap()
+                + "\n"
+        };
+
+        // only changes are streamed (newest first), together with the value at the `from` level
+        assert_eq!(
+            stream("data/a", 0, 5, 1)?,
+            vec![
+                json_line(4, Some(4)),
+                json_line(2, Some(2)),
+                json_line(0, Some(0))
+            ]
+        );
+        assert_eq!(
+            stream("data/a", 3, 5, 1)?,
+            vec![json_line(4, Some(4)), json_line(3, Some(2))]
+        );
+        assert_eq!(stream("data/b", 0, 5, 1)?, vec![json_line(0, None)]);
+
+        // only every 2nd level (counted from `to`) is checked
+        assert_eq!(
+            stream("data/x", 0, 5, 2)?,
+            vec![
+                json_line(5, Some(5)),
+                json_line(3, Some(3)),
+                json_line(1, Some(1)),
+                json_line(0, Some(0))
+            ]
+        );
+
+        // `to` lower than `from` is rejected up front
+        assert!(stream("data/a", 5, 0, 1).is_err());
+
+        // block, which is not an ancestor of `to`, is rejected up front
+        let fork = block(
+            vec![33; 32],
+            3,
+            &blocks[2].hash,
+            blocks[3].header.context().clone(),
+        )?;
+        block_storage.put_block_header(&fork)?;
+        let cursor = get_context_key_history_cursor(
+            context_key!("data/a"),
+            &fork.hash,
+            &blocks[5].hash,
+            1,
+            persistent_storage,
+        );
+        assert!(cursor.is_err());
+
+        Ok(())
+    }
+}
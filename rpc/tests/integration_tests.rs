@@ -74,6 +74,26 @@ async fn integration_tests_rpc(from_block: i64, to_block: i64) {
             "chains/main/blocks", level, "operation_hashes"
         ))
         .await;
+        test_rpc_compare_json(&format!(
+            "{}/{}/{}",
+            "chains/main/blocks", level, "operation_hashes/0"
+        ))
+        .await;
+        test_rpc_compare_json(&format!(
+            "{}/{}/{}",
+            "chains/main/blocks", level, "operations"
+        ))
+        .await;
+        test_rpc_compare_json(&format!(
+            "{}/{}/{}",
+            "chains/main/blocks", level, "operations/0"
+        ))
+        .await;
+        test_rpc_compare_json(&format!(
+            "{}/{}/{}",
+            "chains/main/blocks", level, "operations/3"
+        ))
+        .await;
         test_rpc_compare_json(&format!(
             "{}/{}/{}",
             "chains/main/blocks", level, "context/raw/bytes/cycle"
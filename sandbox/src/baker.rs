@@ -0,0 +1,988 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Built-in baker, which bakes blocks only through the sandbox node's RPCs (no tezos-client is needed):
+//! - waits for the minimal valid timestamp according to the node clock (frozen clock is moved forward instead),
+//! - selects applied operations from the mempool and reveals seed nonces committed by its blocks in the previous cycle,
+//! - preapplies the block (`helpers/preapply/block`),
+//! - finds proof of work nonce and signs the block header with the wallet's secret key,
+//! - injects the block (`/injection/block`).
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::DateTime;
+use failure::Fail;
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use slog::{info, warn, Logger};
+use tokio::sync::oneshot;
+use warp::reject;
+
+use crypto::blake2b;
+use crypto::ed25519::{SigningKey, SIGNATURE_SIZE};
+use crypto::hash::HashType;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::block_header::BlockHeaderBuilder;
+
+use crate::clock::{self, ClockError, SandboxClockRequest};
+use crate::node_runner::NodeRpcIpPort;
+use crate::tezos_client_runner::Wallet;
+
+/// Max priority, which is checked for baking rights of the delegate
+const MAX_PRIORITY: u16 = 64;
+
+/// Tezos protocols have 4 validation passes: endorsements, votes, anonymous operations, manager operations
+const VALIDATION_PASSES: usize = 4;
+
+/// Watermark of block header signatures, which is followed by chain_id
+const BLOCK_HEADER_WATERMARK: u8 = 0x01;
+
+/// Validation pass of anonymous operations, which includes seed nonce revelations
+const ANONYMOUS_VALIDATION_PASS: usize = 2;
+
+/// Max count of tried proof of work nonces, before baking fails (sandbox threshold is usually met by the first one)
+const MAX_PROOF_OF_WORK_ATTEMPTS: u64 = 1 << 24;
+
+#[derive(Debug, Fail)]
+pub enum BakerError {
+    /// Node rpc call failed
+    #[fail(
+        display = "Sandbox node rpc call failed, url: {}, reason: {}",
+        url, reason
+    )]
+    RpcCallError { url: String, reason: String },
+
+    /// Unexpected node rpc response
+    #[fail(
+        display = "Invalid sandbox node rpc response, url: {}, reason: {}",
+        url, reason
+    )]
+    InvalidRpcResponse { url: String, reason: String },
+
+    /// Protocol refused the block
+    #[fail(display = "Block preapplication failed, reason: {}", reason)]
+    PreapplyError { reason: String },
+
+    /// Failed to forge block header
+    #[fail(display = "Failed to forge block header, reason: {}", reason)]
+    ForgeError { reason: String },
+
+    /// No nonce satisfies the proof of work threshold
+    #[fail(
+        display = "Proof of work nonce not found in {} attempts, threshold: {}",
+        attempts, threshold
+    )]
+    ProofOfWorkNotFound { attempts: u64, threshold: u64 },
+
+    /// Wallet cannot be used for signing
+    #[fail(
+        display = "Invalid secret key of the wallet ({}), reason: {}",
+        alias, reason
+    )]
+    InvalidWalletKey { alias: String, reason: String },
+
+    /// Delegate cannot bake the next block
+    #[fail(
+        display = "Delegate ({}) has no baking rights for the next level up to priority {}",
+        delegate, max_priority
+    )]
+    NoBakingRights { delegate: String, max_priority: u16 },
+
+    /// Auto baking is already running
+    #[fail(display = "Auto baking is already running, node_ref: {}", node_ref)]
+    AutoBakeAlreadyRunning { node_ref: NodeRpcIpPort },
+
+    /// Auto baking is not running
+    #[fail(display = "Auto baking is not running, node_ref: {}", node_ref)]
+    AutoBakeNotRunning { node_ref: NodeRpcIpPort },
+}
+
+impl From<BakerError> for reject::Rejection {
+    fn from(err: BakerError) -> reject::Rejection {
+        reject::custom(err)
+    }
+}
+
+impl reject::Reject for BakerError {}
+
+impl From<ClockError> for BakerError {
+    fn from(err: ClockError) -> BakerError {
+        match err {
+            ClockError::RpcCallError { url, reason } => BakerError::RpcCallError { url, reason },
+            ClockError::InvalidRpcResponse { url, reason } => {
+                BakerError::InvalidRpcResponse { url, reason }
+            }
+        }
+    }
+}
+
+/// The json body incoming with the bake request containing the alias for the wallet to bake with
+#[derive(Clone, Debug, Deserialize)]
+pub struct BakeRequest {
+    pub alias: String,
+}
+
+/// The json body incoming with the auto bake request, if alias is not set, an arbitrary wallet is used
+#[derive(Clone, Debug, Deserialize)]
+pub struct AutoBakeRequest {
+    pub alias: Option<String>,
+    pub interval_secs: u64,
+}
+
+/// Info about successfully injected block
+#[derive(Clone, Debug, Serialize)]
+pub struct BakedBlock {
+    block_hash: String,
+    level: i32,
+    priority: u16,
+    timestamp: String,
+    operations: usize,
+}
+
+#[derive(Deserialize)]
+struct HeadHeader {
+    hash: String,
+    level: i32,
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct BlockProtocols {
+    next_protocol: String,
+}
+
+#[derive(Deserialize)]
+struct BakingRight {
+    level: i32,
+    priority: u16,
+}
+
+#[derive(Deserialize)]
+struct NextLevel {
+    cycle: i32,
+    expected_commitment: bool,
+}
+
+#[derive(Deserialize)]
+struct PendingOperations {
+    applied: Vec<serde_json::Value>,
+}
+
+#[derive(Clone, Deserialize)]
+struct ShellHeader {
+    level: i32,
+    proto: u8,
+    predecessor: String,
+    timestamp: String,
+    validation_pass: u8,
+    operations_hash: String,
+    fitness: Vec<String>,
+    context: String,
+}
+
+#[derive(Deserialize)]
+struct PreappliedOperations {
+    applied: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct PreappliedBlock {
+    shell_header: ShellHeader,
+    operations: Vec<PreappliedOperations>,
+}
+
+/// Seed nonce committed by a baked block, it is revealed in the next cycle
+#[derive(Clone, Debug, PartialEq)]
+struct SeedNonce {
+    chain_id: String,
+    level: i32,
+    cycle: i32,
+    nonce: [u8; 32],
+}
+
+/// Seed nonces of the baked blocks, which are not revealed yet
+#[derive(Default)]
+pub struct SeedNonces {
+    nonces: Vec<SeedNonce>,
+}
+
+/// Thread-safe reference to the seed nonces, shared by all bakers
+pub type SeedNoncesRef = Arc<Mutex<SeedNonces>>;
+
+impl SeedNonces {
+    fn insert(&mut self, nonce: SeedNonce) {
+        self.nonces.push(nonce);
+    }
+
+    /// Nonces committed in the previous cycle, nonces which cannot be revealed in the cycle anymore are dropped
+    fn revealable(&mut self, chain_id: &str, cycle: i32) -> Vec<SeedNonce> {
+        self.nonces.retain(|nonce| {
+            nonce.chain_id != chain_id || (nonce.cycle == cycle - 1 || nonce.cycle == cycle)
+        });
+        self.nonces
+            .iter()
+            .filter(|nonce| nonce.chain_id == chain_id && nonce.cycle == cycle - 1)
+            .cloned()
+            .collect()
+    }
+
+    fn remove(&mut self, nonces: &[SeedNonce]) {
+        self.nonces.retain(|nonce| !nonces.contains(nonce));
+    }
+}
+
+/// Bakes blocks on one sandbox node
+pub struct Baker {
+    node_ref: NodeRpcIpPort,
+    /// Other nodes of the sandbox network, whose frozen clocks are moved together with the baking node's clock
+    network: Vec<NodeRpcIpPort>,
+    seed_nonces: SeedNoncesRef,
+    client: Client<HttpConnector>,
+}
+
+impl Baker {
+    pub fn new(
+        node_ref: NodeRpcIpPort,
+        network: Vec<NodeRpcIpPort>,
+        seed_nonces: SeedNoncesRef,
+    ) -> Self {
+        let network = network
+            .into_iter()
+            .filter(|peer| peer != &node_ref)
+            .collect();
+        Self {
+            node_ref,
+            network,
+            seed_nonces,
+            client: Client::new(),
+        }
+    }
+
+    /// Bakes and injects the next block with the wallet's key, waits for the minimal valid timestamp if needed
+    pub async fn bake(&self, wallet: &Wallet, log: &Logger) -> Result<BakedBlock, BakerError> {
+        let key = SigningKey::from_b58check(&wallet.secret_key).map_err(|e| {
+            BakerError::InvalidWalletKey {
+                alias: wallet.alias.clone(),
+                reason: format!("{}", e),
+            }
+        })?;
+        let delegate = &wallet.public_key_hash;
+
+        let head: HeadHeader = self.get("/chains/main/blocks/head/header").await?;
+        let chain_id: String = self.get("/chains/main/chain_id").await?;
+        let protocols: BlockProtocols = self.get("/chains/main/blocks/head/protocols").await?;
+        let constants: serde_json::Value = self
+            .get("/chains/main/blocks/head/context/constants")
+            .await?;
+
+        // find our best priority for the next level
+        let rights: Vec<BakingRight> = self
+            .get(&format!(
+                "/chains/main/blocks/head/helpers/baking_rights?delegate={}&max_priority={}",
+                delegate, MAX_PRIORITY
+            ))
+            .await?;
+        let priority = rights
+            .iter()
+            .filter(|right| right.level == head.level + 1)
+            .map(|right| right.priority)
+            .min()
+            .ok_or_else(|| BakerError::NoBakingRights {
+                delegate: delegate.clone(),
+                max_priority: MAX_PRIORITY,
+            })?;
+
+        // wait for the minimal valid timestamp of our priority (according to the node clock)
+        let timestamp = self.minimal_timestamp(&head, &constants, priority)?;
+        let node_clock = clock::get_clock(&self.node_ref).await?;
+        if timestamp > node_clock.timestamp {
+            if node_clock.frozen {
+                // time travel - no need to wait, just move the frozen clock
+                self.move_frozen_clock(timestamp, log).await?;
+            } else {
+                tokio::time::delay_for(Duration::from_secs(
+                    (timestamp - node_clock.timestamp) as u64,
+                ))
+                .await;
+            }
+        }
+        let timestamp = timestamp.max(node_clock.timestamp);
+
+        // nonce is stored after injection and revealed by the blocks baked in the next cycle
+        let next_level: NextLevel = self
+            .get("/chains/main/blocks/head/helpers/current_level?offset=1")
+            .await?;
+        let seed_nonce = if next_level.expected_commitment {
+            Some(rand::thread_rng().gen::<[u8; 32]>())
+        } else {
+            None
+        };
+        let seed_nonce_hash = seed_nonce.as_ref().map(|nonce| blake2b::digest_256(nonce));
+
+        // select operations from mempool and reveal nonces committed in the previous cycle
+        let mempool: PendingOperations =
+            self.get("/chains/main/mempool/pending_operations").await?;
+        let mut operations = select_operations(mempool.applied, &protocols.next_protocol);
+        let revelations = self
+            .seed_nonces
+            .lock()
+            .unwrap()
+            .revealable(&chain_id, next_level.cycle);
+        operations[ANONYMOUS_VALIDATION_PASS].extend(revelations.iter().map(|seed_nonce| {
+            seed_nonce_revelation(seed_nonce, &head.hash, &protocols.next_protocol)
+        }));
+
+        // preapply block - protocol validates the operations and calculates the shell header
+        let mut protocol_data = json!({
+            "protocol": protocols.next_protocol,
+            "priority": priority,
+            "proof_of_work_nonce": hex::encode([0u8; 8]),
+            "signature": HashType::SignatureEd25519.hash_to_b58check(&[0u8; SIGNATURE_SIZE]),
+        });
+        if let Some(seed_nonce_hash) = &seed_nonce_hash {
+            protocol_data["seed_nonce_hash"] =
+                HashType::NonceHash.hash_to_b58check(seed_nonce_hash).into();
+        }
+        let preapplied: PreappliedBlock = match self
+            .post(
+                &format!(
+                    "/chains/main/blocks/head/helpers/preapply/block?sort=true&timestamp={}",
+                    timestamp
+                ),
+                json!({
+                    "protocol_data": protocol_data,
+                    "operations": operations,
+                }),
+            )
+            .await
+        {
+            Ok(preapplied) => preapplied,
+            Err(e) => {
+                // refused revelations would fail every following block, so they are not retried
+                if !revelations.is_empty() {
+                    self.seed_nonces.lock().unwrap().remove(&revelations);
+                }
+                return Err(e);
+            }
+        };
+
+        // forge and sign block header, proof of work is computed outside of the async runtime threads
+        let threshold = parse_i64(&constants["proof_of_work_threshold"]).unwrap_or(-1) as u64;
+        let proof_of_work_nonce = {
+            let shell_header = preapplied.shell_header.clone();
+            let seed_nonce_hash = seed_nonce_hash.clone();
+            tokio::task::spawn_blocking(move || {
+                find_proof_of_work_nonce(
+                    &shell_header,
+                    priority,
+                    &seed_nonce_hash,
+                    threshold,
+                    MAX_PROOF_OF_WORK_ATTEMPTS,
+                )
+            })
+            .await
+            .map_err(|e| BakerError::ForgeError {
+                reason: format!("proof of work task failed: {}", e),
+            })??
+        };
+        let mut header = forge_block_header(
+            &preapplied.shell_header,
+            protocol_data_contents(priority, &proof_of_work_nonce, &seed_nonce_hash),
+        )?;
+        let mut watermark = vec![BLOCK_HEADER_WATERMARK];
+        watermark.extend(HashType::ChainId.b58check_to_hash(&chain_id).map_err(|e| {
+            BakerError::ForgeError {
+                reason: format!("invalid chain_id: {}, reason: {:?}", chain_id, e),
+            }
+        })?);
+        let signature = key.sign(&watermark, &header);
+        header.extend(signature);
+
+        // inject block with preapplied operations
+        let operations = preapplied
+            .operations
+            .iter()
+            .map(|validation_pass| {
+                validation_pass
+                    .applied
+                    .iter()
+                    .map(|op| json!({"branch": op["branch"], "data": op["data"]}))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let operations_count = operations.iter().map(Vec::len).sum();
+        let block_hash: String = self
+            .post(
+                "/injection/block?chain_id=main",
+                json!({
+                    "data": hex::encode(&header),
+                    "operations": operations,
+                }),
+            )
+            .await?;
+
+        {
+            let mut seed_nonces = self.seed_nonces.lock().unwrap();
+            seed_nonces.remove(&revelations);
+            if let Some(nonce) = seed_nonce {
+                seed_nonces.insert(SeedNonce {
+                    chain_id,
+                    level: preapplied.shell_header.level,
+                    cycle: next_level.cycle,
+                    nonce,
+                });
+            }
+        }
+
+        info!(log, "Block baked";
+                   "node_ref" => self.node_ref.to_string(),
+                   "block_hash" => block_hash.clone(),
+                   "level" => preapplied.shell_header.level,
+                   "priority" => priority,
+                   "operations" => operations_count,
+                   "revealed_nonces" => revelations.len(),
+                   "predecessor" => head.hash);
+
+        Ok(BakedBlock {
+            block_hash,
+            level: preapplied.shell_header.level,
+            priority,
+            timestamp: preapplied.shell_header.timestamp,
+            operations: operations_count,
+        })
+    }
+
+    /// Freezes the clock of the baking node (and the rest of the network) at `timestamp`
+    async fn move_frozen_clock(&self, timestamp: i64, log: &Logger) -> Result<(), BakerError> {
+        let request = SandboxClockRequest {
+            frozen_at: Some(timestamp),
+            ..Default::default()
+        };
+        clock::update_clock(&self.node_ref, &request).await?;
+        for peer in &self.network {
+            if let Err(e) = clock::update_clock(peer, &request).await {
+                warn!(log, "Failed to move frozen clock of the sandbox node"; "node_ref" => peer.to_string(), "reason" => format!("{}", e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Minimal timestamp is predecessor's timestamp + delay of the priority
+    fn minimal_timestamp(
+        &self,
+        head: &HeadHeader,
+        constants: &serde_json::Value,
+        priority: u16,
+    ) -> Result<i64, BakerError> {
+        let invalid_response = |reason: String| BakerError::InvalidRpcResponse {
+            url: self.url("/chains/main/blocks/head"),
+            reason,
+        };
+
+        let head_timestamp = parse_timestamp(&head.timestamp).map_err(invalid_response)?;
+        let time_between_blocks = constants["time_between_blocks"]
+            .as_array()
+            .map(|delays| delays.iter().filter_map(parse_i64).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let (first_delay, delay_per_priority) = match time_between_blocks.as_slice() {
+            [] => {
+                return Err(invalid_response(
+                    "missing constant time_between_blocks".to_string(),
+                ))
+            }
+            [delay] => (*delay, *delay),
+            [first_delay, delay_per_priority, ..] => (*first_delay, *delay_per_priority),
+        };
+
+        Ok(head_timestamp + first_delay + i64::from(priority) * delay_per_priority)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}:{}{}", self.node_ref.ip, self.node_ref.port, path)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, BakerError> {
+        self.call(Method::GET, path, None).await
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+    ) -> Result<T, BakerError> {
+        self.call(Method::POST, path, Some(body)).await
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, BakerError> {
+        let url = self.url(path);
+        let rpc_error = |reason: String| BakerError::RpcCallError {
+            url: url.clone(),
+            reason,
+        };
+
+        let request = Request::builder()
+            .method(method)
+            .uri(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(match body {
+                Some(body) => Body::from(body.to_string()),
+                None => Body::empty(),
+            })
+            .map_err(|e| rpc_error(format!("{}", e)))?;
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| rpc_error(format!("{}", e)))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| rpc_error(format!("{}", e)))?;
+        if !status.is_success() {
+            return Err(rpc_error(format!(
+                "status: {}, body: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        let value: serde_json::Value =
+            serde_json::from_slice(&body).map_err(|e| BakerError::InvalidRpcResponse {
+                url: url.clone(),
+                reason: format!("{}", e),
+            })?;
+
+        // protocol errors are returned as json with error_type "ocaml"
+        if let Some("ocaml") = value.get("error_type").and_then(|t| t.as_str()) {
+            return Err(BakerError::PreapplyError {
+                reason: value["message"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+
+        serde_json::from_value(value).map_err(|e| BakerError::InvalidRpcResponse {
+            url,
+            reason: format!("{}", e),
+        })
+    }
+}
+
+/// Sorts applied mempool operations into validation passes, in the format expected by preapply
+fn select_operations(
+    applied: Vec<serde_json::Value>,
+    protocol: &str,
+) -> Vec<Vec<serde_json::Value>> {
+    let mut operations = vec![vec![]; VALIDATION_PASSES];
+    for operation in applied {
+        let kind = operation["contents"][0]["kind"]
+            .as_str()
+            .unwrap_or_default();
+        let validation_pass = match kind {
+            "endorsement" | "endorsement_with_slot" => 0,
+            "proposals" | "ballot" => 1,
+            "seed_nonce_revelation"
+            | "double_endorsement_evidence"
+            | "double_baking_evidence"
+            | "activate_account" => 2,
+            _ => 3,
+        };
+        operations[validation_pass].push(json!({
+            "protocol": protocol,
+            "branch": operation["branch"],
+            "contents": operation["contents"],
+            "signature": operation["signature"],
+        }));
+    }
+    operations
+}
+
+/// Anonymous operation revealing the seed nonce, in the format expected by preapply
+fn seed_nonce_revelation(
+    seed_nonce: &SeedNonce,
+    branch: &str,
+    protocol: &str,
+) -> serde_json::Value {
+    json!({
+        "protocol": protocol,
+        "branch": branch,
+        "contents": [{
+            "kind": "seed_nonce_revelation",
+            "level": seed_nonce.level,
+            "nonce": hex::encode(&seed_nonce.nonce),
+        }],
+    })
+}
+
+/// Binary protocol data without signature: priority, proof_of_work_nonce, optional seed_nonce_hash
+fn protocol_data_contents(
+    priority: u16,
+    proof_of_work_nonce: &[u8],
+    seed_nonce_hash: &Option<Vec<u8>>,
+) -> Vec<u8> {
+    let mut contents = priority.to_be_bytes().to_vec();
+    contents.extend_from_slice(proof_of_work_nonce);
+    match seed_nonce_hash {
+        Some(seed_nonce_hash) => {
+            contents.push(0xff);
+            contents.extend_from_slice(seed_nonce_hash);
+        }
+        None => contents.push(0x00),
+    }
+    contents
+}
+
+/// Binary block header with the shell header calculated by preapply
+fn forge_block_header(shell: &ShellHeader, protocol_data: Vec<u8>) -> Result<Vec<u8>, BakerError> {
+    let forge_error = |reason: String| BakerError::ForgeError { reason };
+    let decode_hash = |hash_type: HashType, hash: &str| {
+        hash_type
+            .b58check_to_hash(hash)
+            .map_err(|e| forge_error(format!("invalid hash: {}, reason: {:?}", hash, e)))
+    };
+
+    let fitness = shell
+        .fitness
+        .iter()
+        .map(hex::decode)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| forge_error(format!("invalid fitness, reason: {}", e)))?;
+
+    BlockHeaderBuilder::default()
+        .level(shell.level)
+        .proto(shell.proto)
+        .predecessor(decode_hash(HashType::BlockHash, &shell.predecessor)?)
+        .timestamp(parse_timestamp(&shell.timestamp).map_err(forge_error)?)
+        .validation_pass(shell.validation_pass)
+        .operations_hash(decode_hash(
+            HashType::OperationListListHash,
+            &shell.operations_hash,
+        )?)
+        .fitness(fitness)
+        .context(decode_hash(HashType::ContextHash, &shell.context)?)
+        .protocol_data(protocol_data)
+        .build()
+        .map_err(forge_error)?
+        .as_bytes()
+        .map_err(|e| forge_error(format!("{}", e)))
+}
+
+/// Proof of work stamp is the first 8 bytes of the block hash (with zero signature), it has to be <= threshold
+fn find_proof_of_work_nonce(
+    shell: &ShellHeader,
+    priority: u16,
+    seed_nonce_hash: &Option<Vec<u8>>,
+    threshold: u64,
+    max_attempts: u64,
+) -> Result<Vec<u8>, BakerError> {
+    for nonce in 0..max_attempts {
+        let proof_of_work_nonce = nonce.to_be_bytes();
+        let mut protocol_data =
+            protocol_data_contents(priority, &proof_of_work_nonce, seed_nonce_hash);
+        protocol_data.extend_from_slice(&[0u8; SIGNATURE_SIZE]);
+
+        let hash = blake2b::digest_256(&forge_block_header(shell, protocol_data)?);
+        let stamp = u64::from_be_bytes(hash[0..8].try_into().unwrap());
+        if stamp <= threshold {
+            return Ok(proof_of_work_nonce.to_vec());
+        }
+    }
+    Err(BakerError::ProofOfWorkNotFound {
+        attempts: max_attempts,
+        threshold,
+    })
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<i64, String> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.timestamp())
+        .map_err(|e| format!("invalid timestamp: {}, reason: {}", timestamp, e))
+}
+
+/// Protocol constants are encoded as strings (int64, mutez) or numbers
+fn parse_i64(value: &serde_json::Value) -> Option<i64> {
+    match value {
+        serde_json::Value::String(value) => value.parse().ok(),
+        value => value.as_i64(),
+    }
+}
+
+/// Running auto bakers, one per node
+#[derive(Default)]
+pub struct AutoBakers {
+    bakers: HashMap<NodeRpcIpPort, oneshot::Sender<()>>,
+}
+
+/// Thread-safe reference to the auto bakers
+pub type AutoBakersRef = Arc<Mutex<AutoBakers>>;
+
+impl AutoBakers {
+    /// Starts baking a block every `interval` with the wallet's key, failures are just logged
+    pub fn start(
+        &mut self,
+        node_ref: NodeRpcIpPort,
+        network: Vec<NodeRpcIpPort>,
+        seed_nonces: SeedNoncesRef,
+        wallet: Wallet,
+        interval: Duration,
+        log: Logger,
+    ) -> Result<(), BakerError> {
+        if self.bakers.contains_key(&node_ref) {
+            return Err(BakerError::AutoBakeAlreadyRunning { node_ref });
+        }
+
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        self.bakers.insert(node_ref.clone(), stop_tx);
+
+        info!(log, "Auto baking started"; "node_ref" => node_ref.to_string(), "alias" => wallet.alias.clone(), "interval" => format!("{:?}", interval));
+        tokio::spawn(async move {
+            let baker = Baker::new(node_ref.clone(), network, seed_nonces);
+            let mut interval = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            loop {
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    _ = interval.tick() => {
+                        if let Err(e) = baker.bake(&wallet, &log).await {
+                            warn!(log, "Auto baking failed"; "node_ref" => node_ref.to_string(), "reason" => format!("{}", e));
+                        }
+                    }
+                }
+            }
+            info!(log, "Auto baking stopped"; "node_ref" => node_ref.to_string());
+        });
+
+        Ok(())
+    }
+
+    /// Stops auto baking, the block which is being baked is finished
+    pub fn stop(&mut self, node_ref: &NodeRpcIpPort) -> Result<(), BakerError> {
+        match self.bakers.remove(node_ref) {
+            Some(stop_tx) => {
+                let _ = stop_tx.send(());
+                Ok(())
+            }
+            None => Err(BakerError::AutoBakeNotRunning {
+                node_ref: node_ref.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_messages::p2p::encoding::block_header::BlockHeader;
+
+    use super::*;
+
+    fn shell_header(fitness: Vec<String>) -> ShellHeader {
+        ShellHeader {
+            level: 2,
+            proto: 1,
+            predecessor: "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".to_string(),
+            timestamp: "2020-10-01T12:00:00Z".to_string(),
+            validation_pass: 4,
+            operations_hash: "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".to_string(),
+            fitness,
+            context: "CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd".to_string(),
+        }
+    }
+
+    fn baker() -> Baker {
+        Baker::new(
+            NodeRpcIpPort {
+                ip: "127.0.0.1".to_string(),
+                port: 18732,
+            },
+            vec![],
+            SeedNoncesRef::default(),
+        )
+    }
+
+    fn head(timestamp: &str) -> HeadHeader {
+        HeadHeader {
+            hash: "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".to_string(),
+            level: 1,
+            timestamp: timestamp.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_protocol_data_contents() {
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            protocol_data_contents(3, &nonce, &None),
+            vec![0, 3, 1, 2, 3, 4, 5, 6, 7, 8, 0x00]
+        );
+
+        let seed_nonce_hash = vec![9; 32];
+        let contents = protocol_data_contents(258, &nonce, &Some(seed_nonce_hash.clone()));
+        assert_eq!(contents.len(), 2 + 8 + 1 + 32);
+        assert_eq!(&contents[0..2], &[1, 2]);
+        assert_eq!(&contents[2..10], &nonce);
+        assert_eq!(contents[10], 0xff);
+        assert_eq!(&contents[11..], seed_nonce_hash.as_slice());
+    }
+
+    #[test]
+    fn test_select_operations() {
+        let operation = |kind: &str| {
+            json!({
+                "hash": "oo",
+                "branch": "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe",
+                "contents": [{"kind": kind}],
+                "signature": "sig",
+            })
+        };
+        let operations = select_operations(
+            vec![
+                operation("transaction"),
+                operation("endorsement"),
+                operation("ballot"),
+                operation("activate_account"),
+                operation("reveal"),
+            ],
+            "PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb",
+        );
+
+        assert_eq!(operations.len(), VALIDATION_PASSES);
+        let kinds = operations
+            .iter()
+            .map(|pass| {
+                pass.iter()
+                    .map(|op| op["contents"][0]["kind"].as_str().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                vec!["endorsement"],
+                vec!["ballot"],
+                vec!["activate_account"],
+                vec!["transaction", "reveal"],
+            ]
+        );
+        let selected = &operations[3][0];
+        assert_eq!(
+            selected["protocol"],
+            "PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb"
+        );
+        assert_eq!(selected["signature"], "sig");
+        assert!(selected.get("hash").is_none());
+    }
+
+    #[test]
+    fn test_forge_block_header() {
+        let shell = shell_header(vec!["01".to_string(), "000000000000000a".to_string()]);
+        let header = forge_block_header(&shell, vec![0, 1, 2]).unwrap();
+        let header = BlockHeader::from_bytes(header).unwrap();
+
+        assert_eq!(header.level(), 2);
+        assert_eq!(header.proto(), 1);
+        assert_eq!(header.validation_pass(), 4);
+        assert_eq!(
+            header.timestamp(),
+            parse_timestamp(&shell.timestamp).unwrap()
+        );
+        assert_eq!(
+            header.fitness(),
+            &vec![vec![0x01], vec![0, 0, 0, 0, 0, 0, 0, 0x0a]]
+        );
+        assert_eq!(
+            HashType::ContextHash.hash_to_b58check(header.context()),
+            shell.context
+        );
+        assert_eq!(header.protocol_data(), &vec![0, 1, 2]);
+
+        let invalid_fitness = shell_header(vec!["x1".to_string()]);
+        assert!(matches!(
+            forge_block_header(&invalid_fitness, vec![]),
+            Err(BakerError::ForgeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_find_proof_of_work_nonce() {
+        let shell = shell_header(vec!["01".to_string()]);
+        assert_eq!(
+            find_proof_of_work_nonce(&shell, 0, &None, u64::MAX, 1).unwrap(),
+            vec![0; 8]
+        );
+        assert!(matches!(
+            find_proof_of_work_nonce(&shell, 0, &None, 0, 10),
+            Err(BakerError::ProofOfWorkNotFound { attempts: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn test_minimal_timestamp() {
+        let baker = baker();
+        let head = head("2020-10-01T12:00:00Z");
+        let head_timestamp = parse_timestamp(&head.timestamp).unwrap();
+
+        let constants = json!({ "time_between_blocks": ["60", "40"] });
+        assert_eq!(
+            baker.minimal_timestamp(&head, &constants, 0).unwrap(),
+            head_timestamp + 60
+        );
+        assert_eq!(
+            baker.minimal_timestamp(&head, &constants, 2).unwrap(),
+            head_timestamp + 60 + 2 * 40
+        );
+
+        let constants = json!({ "time_between_blocks": [1] });
+        assert_eq!(
+            baker.minimal_timestamp(&head, &constants, 3).unwrap(),
+            head_timestamp + 1 + 3
+        );
+
+        assert!(matches!(
+            baker.minimal_timestamp(&head, &json!({}), 0),
+            Err(BakerError::InvalidRpcResponse { .. })
+        ));
+        assert!(matches!(
+            baker.minimal_timestamp(&self::head("invalid"), &constants, 0),
+            Err(BakerError::InvalidRpcResponse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_seed_nonces_revealable_in_next_cycle() {
+        let seed_nonce = |chain_id: &str, level: i32, cycle: i32| SeedNonce {
+            chain_id: chain_id.to_string(),
+            level,
+            cycle,
+            nonce: [level as u8; 32],
+        };
+        let mut seed_nonces = SeedNonces::default();
+        seed_nonces.insert(seed_nonce("main", 1, 0));
+        seed_nonces.insert(seed_nonce("main", 9, 1));
+        seed_nonces.insert(seed_nonce("main", 17, 2));
+        seed_nonces.insert(seed_nonce("other", 1, 0));
+
+        assert_eq!(
+            seed_nonces.revealable("main", 2),
+            vec![seed_nonce("main", 9, 1)]
+        );
+        // nonce of the cycle 0 cannot be revealed anymore
+        assert_eq!(seed_nonces.nonces.len(), 3);
+
+        seed_nonces.remove(&[seed_nonce("main", 9, 1)]);
+        assert!(seed_nonces.revealable("main", 2).is_empty());
+        assert_eq!(
+            seed_nonces.revealable("other", 1),
+            vec![seed_nonce("other", 1, 0)]
+        );
+    }
+}
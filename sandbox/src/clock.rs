@@ -0,0 +1,131 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Controls the adjustable clock of the sandbox nodes through the node rpc `/dev/sandbox/clock`.
+//! The node clock is used by block validation, mempool and block injection instead of the wall clock.
+
+use failure::Fail;
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use warp::reject;
+
+use crate::node_runner::NodeRpcIpPort;
+
+const CLOCK_RPC_PATH: &str = "/dev/sandbox/clock";
+
+#[derive(Debug, Fail)]
+pub enum ClockError {
+    /// Node rpc call failed
+    #[fail(
+        display = "Sandbox node clock rpc call failed, url: {}, reason: {}",
+        url, reason
+    )]
+    RpcCallError { url: String, reason: String },
+
+    /// Unexpected node rpc response
+    #[fail(
+        display = "Invalid sandbox node clock rpc response, url: {}, reason: {}",
+        url, reason
+    )]
+    InvalidRpcResponse { url: String, reason: String },
+}
+
+impl From<ClockError> for reject::Rejection {
+    fn from(err: ClockError) -> reject::Rejection {
+        reject::custom(err)
+    }
+}
+
+impl reject::Reject for ClockError {}
+
+/// The json body incoming with the clock request, fields are applied in the declared order
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SandboxClockRequest {
+    /// Returns clock back to the wall-clock time
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reset: bool,
+    /// Sets offset against the wall clock (secs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset_secs: Option<i64>,
+    /// Moves clock by (secs), can be negative
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub advance_secs: Option<i64>,
+    /// Stops (true) or lets run (false) the clock
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freeze: Option<bool>,
+    /// Stops the clock at unix timestamp (secs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frozen_at: Option<i64>,
+}
+
+/// Current state of the node clock
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeClock {
+    pub now: String,
+    pub timestamp: i64,
+    pub offset_secs: i64,
+    pub frozen: bool,
+}
+
+/// Returns current state of the node clock
+pub async fn get_clock(node_ref: &NodeRpcIpPort) -> Result<NodeClock, ClockError> {
+    call(node_ref, Method::GET, Body::empty()).await
+}
+
+/// Changes the node clock and returns its new state
+pub async fn update_clock(
+    node_ref: &NodeRpcIpPort,
+    request: &SandboxClockRequest,
+) -> Result<NodeClock, ClockError> {
+    let body = serde_json::to_string(request).map_err(|e| ClockError::RpcCallError {
+        url: url(node_ref),
+        reason: format!("{}", e),
+    })?;
+    call(node_ref, Method::POST, Body::from(body)).await
+}
+
+fn url(node_ref: &NodeRpcIpPort) -> String {
+    format!("http://{}:{}{}", node_ref.ip, node_ref.port, CLOCK_RPC_PATH)
+}
+
+async fn call<T: DeserializeOwned>(
+    node_ref: &NodeRpcIpPort,
+    method: Method,
+    body: Body,
+) -> Result<T, ClockError> {
+    let url = url(node_ref);
+    let rpc_error = |reason: String| ClockError::RpcCallError {
+        url: url.clone(),
+        reason,
+    };
+
+    let request = Request::builder()
+        .method(method)
+        .uri(&url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .map_err(|e| rpc_error(format!("{}", e)))?;
+    let response = Client::<HttpConnector>::new()
+        .request(request)
+        .await
+        .map_err(|e| rpc_error(format!("{}", e)))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| rpc_error(format!("{}", e)))?;
+    if !status.is_success() {
+        return Err(rpc_error(format!(
+            "status: {}, body: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        )));
+    }
+
+    serde_json::from_slice(&body).map_err(|e| ClockError::InvalidRpcResponse {
+        url,
+        reason: format!("{}", e),
+    })
+}
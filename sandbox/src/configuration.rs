@@ -1,6 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::env;
 use std::path::{Path, PathBuf};
 
 use clap::{App, Arg};
@@ -11,6 +12,7 @@ pub struct LauncherEnvironment {
     pub log_level: slog::Level,
     pub sandbox_rpc_port: u16,
     pub tezos_client_path: PathBuf,
+    pub sandbox_states_dir: PathBuf,
 }
 
 macro_rules! parse_validator_fn {
@@ -94,6 +96,13 @@ fn sandbox_app() -> App<'static, 'static> {
                     u16,
                     "Value must be a valid port number"
                 )),
+        )
+        .arg(
+            Arg::with_name("sandbox-states-dir")
+                .long("sandbox-states-dir")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Directory for saved states of the sandbox nodes (default: <temp_dir>/tezedge-sandbox-states)"),
         );
     app
 }
@@ -129,6 +138,13 @@ impl LauncherEnvironment {
                 .unwrap_or("")
                 .parse::<PathBuf>()
                 .expect("Provided value cannot be converted to path"),
+            sandbox_states_dir: args
+                .value_of("sandbox-states-dir")
+                .map(|path| {
+                    path.parse::<PathBuf>()
+                        .expect("Provided value cannot be converted to path")
+                })
+                .unwrap_or_else(|| env::temp_dir().join("tezedge-sandbox-states")),
         }
     }
 }
@@ -7,14 +7,22 @@ use std::sync::{Arc, Mutex};
 use slog::Logger;
 use warp::Filter;
 
+use crate::baker::{AutoBakeRequest, AutoBakersRef, BakeRequest, SeedNoncesRef};
+use crate::clock::SandboxClockRequest;
 use crate::handlers::{
-    activate_protocol, bake_block_with_client, bake_block_with_client_arbitrary, get_wallets,
-    handle_rejection, init_client_data, list_nodes, resolve_node_from_request,
-    start_node_with_config, stop_node,
+    activate_protocol, bake_block, bake_block_arbitrary, delete_state, get_clock, get_wallets,
+    handle_rejection, init_client_data, list_nodes, list_states, partition_network,
+    resolve_node_from_request, restore_state, save_state, start_auto_bake, start_network,
+    start_node_with_config, stop_auto_bake, stop_network, stop_node, update_clock, NodeQuery,
+};
+use crate::node_runner::{
+    LightNodeRunnerRef, NodeRpcIpPort, SandboxNetworkRequest, SandboxPartitionRequest,
+};
+use crate::saved_states::{
+    DeleteStateRequest, RestoreStateRequest, SaveStateRequest, SavedStatesRef,
 };
-use crate::node_runner::{LightNodeRunnerRef, NodeRpcIpPort};
 use crate::tezos_client_runner::{
-    BakeRequest, SandboxWallets, TezosClientRunnerRef, TezosProtcolActivationParameters,
+    SandboxWallets, TezosClientRunnerRef, TezosProtcolActivationParameters,
 };
 
 pub fn sandbox(
@@ -22,6 +30,9 @@ pub fn sandbox(
     runner: LightNodeRunnerRef,
     client_runner: TezosClientRunnerRef,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
+    seed_nonces: SeedNoncesRef,
+    saved_states: SavedStatesRef,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     // Allow cors from any origin
     let cors = warp::cors()
@@ -37,10 +48,25 @@ pub fn sandbox(
     )
     .or(stop(
         log.clone(),
-        runner,
+        runner.clone(),
+        client_runner.clone(),
+        peers.clone(),
+        auto_bakers.clone(),
+    ))
+    .or(start_network_nodes(
+        log.clone(),
+        runner.clone(),
         client_runner.clone(),
         peers.clone(),
     ))
+    .or(stop_network_nodes(
+        log.clone(),
+        runner.clone(),
+        client_runner.clone(),
+        peers.clone(),
+        auto_bakers.clone(),
+    ))
+    .or(partition(log.clone(), runner.clone()))
     .or(list(log.clone(), peers.clone()))
     .or(init_client(
         log.clone(),
@@ -49,8 +75,49 @@ pub fn sandbox(
     ))
     .or(wallets(log.clone(), client_runner.clone(), peers.clone()))
     .or(activate(log.clone(), client_runner.clone(), peers.clone()))
-    .or(bake(log.clone(), client_runner.clone(), peers.clone()))
-    .or(bake_random(log.clone(), client_runner, peers))
+    .or(bake(
+        log.clone(),
+        client_runner.clone(),
+        seed_nonces.clone(),
+        peers.clone(),
+    ))
+    .or(bake_random(
+        log.clone(),
+        client_runner.clone(),
+        seed_nonces.clone(),
+        peers.clone(),
+    ))
+    .or(auto_bake(
+        log.clone(),
+        client_runner.clone(),
+        auto_bakers.clone(),
+        seed_nonces,
+        peers.clone(),
+    ))
+    .or(auto_bake_stop(
+        log.clone(),
+        auto_bakers.clone(),
+        peers.clone(),
+    ))
+    .or(clock(log.clone(), peers.clone()))
+    .or(clock_update(log.clone(), peers.clone()))
+    .or(save(
+        log.clone(),
+        runner.clone(),
+        client_runner.clone(),
+        saved_states.clone(),
+        peers.clone(),
+        auto_bakers,
+    ))
+    .or(restore(
+        log.clone(),
+        runner,
+        client_runner,
+        saved_states.clone(),
+        peers,
+    ))
+    .or(states(log.clone(), saved_states.clone()))
+    .or(delete_saved_state(log.clone(), saved_states))
     .recover(move |rejection| handle_rejection(rejection, log.clone()))
     .with(cors)
 }
@@ -76,6 +143,7 @@ pub fn stop(
     runner: LightNodeRunnerRef,
     client_runner: TezosClientRunnerRef,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("stop")
         .and(warp::get())
@@ -83,10 +151,56 @@ pub fn stop(
         .and(with_runner(runner))
         .and(with_client_runner(client_runner))
         .and(with_peers(peers.clone()))
+        .and(with_auto_bakers(auto_bakers))
         .and(with_peer(peers))
         .and_then(stop_node)
 }
 
+pub fn start_network_nodes(
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("start_network")
+        .and(warp::post())
+        .and(network_json_body())
+        .and(with_log(log))
+        .and(with_runner(runner))
+        .and(with_client_runner(client_runner))
+        .and(with_peers(peers))
+        .and_then(start_network)
+}
+
+pub fn stop_network_nodes(
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("stop_network")
+        .and(warp::get())
+        .and(with_log(log))
+        .and(with_runner(runner))
+        .and(with_client_runner(client_runner))
+        .and(with_peers(peers))
+        .and(with_auto_bakers(auto_bakers))
+        .and_then(stop_network)
+}
+
+pub fn partition(
+    log: Logger,
+    runner: LightNodeRunnerRef,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("partition")
+        .and(warp::post())
+        .and(partition_json_body())
+        .and(with_log(log))
+        .and(with_runner(runner))
+        .and_then(partition_network)
+}
+
 pub fn list(
     log: Logger,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
@@ -142,6 +256,7 @@ pub fn activate(
 pub fn bake(
     log: Logger,
     client_runner: TezosClientRunnerRef,
+    seed_nonces: SeedNoncesRef,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("bake")
@@ -149,21 +264,144 @@ pub fn bake(
         .and(bake_json_body())
         .and(with_log(log))
         .and(with_client_runner(client_runner))
+        .and(with_seed_nonces(seed_nonces))
+        .and(with_peers(peers.clone()))
         .and(with_peer(peers))
-        .and_then(bake_block_with_client)
+        .and_then(bake_block)
 }
 
 pub fn bake_random(
     log: Logger,
     client_runner: TezosClientRunnerRef,
+    seed_nonces: SeedNoncesRef,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("bake")
         .and(warp::get())
         .and(with_log(log))
         .and(with_client_runner(client_runner))
+        .and(with_seed_nonces(seed_nonces))
+        .and(with_peers(peers.clone()))
+        .and(with_peer(peers))
+        .and_then(bake_block_arbitrary)
+}
+
+pub fn auto_bake(
+    log: Logger,
+    client_runner: TezosClientRunnerRef,
+    auto_bakers: AutoBakersRef,
+    seed_nonces: SeedNoncesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("start_auto_bake")
+        .and(warp::post())
+        .and(auto_bake_json_body())
+        .and(with_log(log))
+        .and(with_client_runner(client_runner))
+        .and(with_auto_bakers(auto_bakers))
+        .and(with_seed_nonces(seed_nonces))
+        .and(with_peers(peers.clone()))
+        .and(with_peer(peers))
+        .and_then(start_auto_bake)
+}
+
+pub fn auto_bake_stop(
+    log: Logger,
+    auto_bakers: AutoBakersRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("stop_auto_bake")
+        .and(warp::get())
+        .and(with_log(log))
+        .and(with_auto_bakers(auto_bakers))
+        .and(with_peer(peers))
+        .and_then(stop_auto_bake)
+}
+
+pub fn clock(
+    log: Logger,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("clock")
+        .and(warp::get())
+        .and(with_log(log))
+        .and(with_peer(peers))
+        .and_then(get_clock)
+}
+
+pub fn clock_update(
+    log: Logger,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("clock")
+        .and(warp::post())
+        .and(clock_json_body())
+        .and(with_log(log))
+        .and(with_peers(peers))
+        .and(with_node_query())
+        .and_then(update_clock)
+}
+
+pub fn save(
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    saved_states: SavedStatesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("save_state")
+        .and(warp::post())
+        .and(save_state_json_body())
+        .and(with_log(log))
+        .and(with_runner(runner))
+        .and(with_client_runner(client_runner))
+        .and(with_saved_states(saved_states))
+        .and(with_peers(peers.clone()))
+        .and(with_auto_bakers(auto_bakers))
         .and(with_peer(peers))
-        .and_then(bake_block_with_client_arbitrary)
+        .and_then(save_state)
+}
+
+pub fn restore(
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    saved_states: SavedStatesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("restore_state")
+        .and(warp::post())
+        .and(restore_state_json_body())
+        .and(with_log(log))
+        .and(with_runner(runner))
+        .and(with_client_runner(client_runner))
+        .and(with_saved_states(saved_states))
+        .and(with_peers(peers))
+        .and_then(restore_state)
+}
+
+pub fn states(
+    log: Logger,
+    saved_states: SavedStatesRef,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("states")
+        .and(warp::get())
+        .and(with_log(log))
+        .and(with_saved_states(saved_states))
+        .and_then(list_states)
+}
+
+pub fn delete_saved_state(
+    log: Logger,
+    saved_states: SavedStatesRef,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("delete_state")
+        .and(warp::post())
+        .and(delete_state_json_body())
+        .and(with_log(log))
+        .and(with_saved_states(saved_states))
+        .and_then(delete_state)
 }
 
 fn json_body() -> impl Filter<Extract = (serde_json::Value,), Error = warp::Rejection> + Clone {
@@ -172,6 +410,20 @@ fn json_body() -> impl Filter<Extract = (serde_json::Value,), Error = warp::Reje
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
 
+fn network_json_body(
+) -> impl Filter<Extract = (SandboxNetworkRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized SandboxNetworkRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+fn partition_json_body(
+) -> impl Filter<Extract = (SandboxPartitionRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized SandboxPartitionRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
 fn init_client_json_body(
 ) -> impl Filter<Extract = (SandboxWallets,), Error = warp::Rejection> + Clone {
     // When accepting a body, we want a JSON body
@@ -192,6 +444,41 @@ fn bake_json_body() -> impl Filter<Extract = (BakeRequest,), Error = warp::Rejec
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
 
+fn auto_bake_json_body(
+) -> impl Filter<Extract = (AutoBakeRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized AutoBakeRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+fn clock_json_body(
+) -> impl Filter<Extract = (SandboxClockRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized SandboxClockRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+fn save_state_json_body(
+) -> impl Filter<Extract = (SaveStateRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized SaveStateRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+fn restore_state_json_body(
+) -> impl Filter<Extract = (RestoreStateRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized RestoreStateRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+fn delete_state_json_body(
+) -> impl Filter<Extract = (DeleteStateRequest,), Error = warp::Rejection> + Clone {
+    // When accepting a body, we want a JSON body with the deserialized DeleteStateRequest
+    // (and to reject huge payloads)...
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
 fn with_log(
     log: Logger,
 ) -> impl Filter<Extract = (Logger,), Error = std::convert::Infallible> + Clone {
@@ -210,6 +497,24 @@ fn with_client_runner(
     warp::any().map(move || client_runner.clone())
 }
 
+fn with_auto_bakers(
+    auto_bakers: AutoBakersRef,
+) -> impl Filter<Extract = (AutoBakersRef,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || auto_bakers.clone())
+}
+
+fn with_seed_nonces(
+    seed_nonces: SeedNoncesRef,
+) -> impl Filter<Extract = (SeedNoncesRef,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || seed_nonces.clone())
+}
+
+fn with_saved_states(
+    saved_states: SavedStatesRef,
+) -> impl Filter<Extract = (SavedStatesRef,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || saved_states.clone())
+}
+
 fn with_peers(
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
 ) -> impl Filter<Extract = (Arc<Mutex<HashSet<NodeRpcIpPort>>>,), Error = std::convert::Infallible> + Clone
@@ -217,9 +522,17 @@ fn with_peers(
     warp::any().map(move || peers.clone())
 }
 
-// TODO: this should resolve peer from request somehow (header, param,...)
+/// Resolves peer from the query param `?node=<rpc_port>`
 fn with_peer(
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
 ) -> impl Filter<Extract = (Option<NodeRpcIpPort>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || resolve_node_from_request(peers.clone()))
+    with_node_query().map(move |query| resolve_node_from_request(peers.clone(), query))
+}
+
+/// Optional query param `?node=<rpc_port>`
+fn with_node_query() -> impl Filter<Extract = (NodeQuery,), Error = std::convert::Infallible> + Clone
+{
+    warp::query::<NodeQuery>()
+        .or(warp::any().map(NodeQuery::default))
+        .unify()
 }
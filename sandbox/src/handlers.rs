@@ -1,21 +1,31 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::fmt;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use itertools::Itertools;
-use serde::Serialize;
-use slog::{error, info, Logger};
+use serde::{Deserialize, Serialize};
+use slog::{error, info, warn, Logger};
 use warp::http::StatusCode;
 use warp::{reject, Rejection, Reply};
 
-use crate::node_runner::{LightNodeRunnerError, LightNodeRunnerRef, NodeRpcIpPort};
+use crate::baker::{AutoBakeRequest, AutoBakersRef, BakeRequest, Baker, BakerError, SeedNoncesRef};
+use crate::clock::{self, ClockError, SandboxClockRequest};
+use crate::create_temp_dir;
+use crate::node_runner::{
+    LightNodeRunnerError, LightNodeRunnerRef, NodeRpcIpPort, SandboxNetworkRequest,
+    SandboxPartitionRequest,
+};
+use crate::saved_states::{
+    DeleteStateRequest, RestoreStateRequest, SaveStateRequest, SavedStateError, SavedStatesRef,
+};
 use crate::tezos_client_runner::{
-    reply_with_client_output, BakeRequest, SandboxWallets, TezosClientRunnerError,
-    TezosClientRunnerRef, TezosProtcolActivationParameters,
+    reply_with_client_output, SandboxWallets, TezosClientRunnerError, TezosClientRunnerRef,
+    TezosProtcolActivationParameters,
 };
 
 #[derive(Debug, Serialize, Clone)]
@@ -60,6 +70,12 @@ impl fmt::Display for ErrorMessage {
     }
 }
 
+/// Selects sandbox node by rpc port (`?node=18732`), if not set, node with the lowest port is used
+#[derive(Debug, Default, Deserialize)]
+pub struct NodeQuery {
+    node: Option<u16>,
+}
+
 /// Handler for start endpoint
 pub async fn start_node_with_config(
     cfg: serde_json::Value,
@@ -68,7 +84,7 @@ pub async fn start_node_with_config(
     client_runner: TezosClientRunnerRef,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
 ) -> Result<impl warp::Reply, reject::Rejection> {
-    info!(log, "Received request to start the light node"; "config" => format!("{:?})", cfg));
+    info!(log, "Received request to start the light node"; "config" => format!("{:?}", cfg));
 
     // aquire a write lock to the runner
     let mut runner = runner.write().unwrap();
@@ -97,11 +113,15 @@ pub async fn stop_node(
     runner: LightNodeRunnerRef,
     client_runner: TezosClientRunnerRef,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
     node_ref: Option<NodeRpcIpPort>,
 ) -> Result<impl warp::Reply, reject::Rejection> {
     info!(log, "Received request to stop the sandbox launcher...");
 
     let node_ref = ensure_node(node_ref)?;
+    // stop auto baking, if running
+    let _ = auto_bakers.lock().unwrap().stop(&node_ref);
+
     let mut runner = runner.write().unwrap();
     let mut client_runner = client_runner.write().unwrap();
     let mut errors = vec![];
@@ -136,6 +156,271 @@ pub async fn stop_node(
     }
 }
 
+/// Handler for start_network endpoint
+pub async fn start_network(
+    request: SandboxNetworkRequest,
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to start the sandbox network"; "nodes" => request.nodes, "config" => format!("{:?}", request.config));
+
+    // aquire a write lock to the runner
+    let mut runner = runner.write().unwrap();
+
+    // spawn the nodes
+    let nodes = runner.spawn_network(request, &log)?;
+
+    // initialize data for tezos client
+    let mut client_runner = client_runner.write().unwrap();
+    let mut peers = peers.lock().unwrap();
+    let mut node_refs = Vec::with_capacity(nodes.len());
+    for (node_ref, data_dir) in nodes {
+        info!(log, "Initializing tezos-client data  for light-node"; "node_ref" => format!("{}", &node_ref));
+        client_runner.init_sandbox_data(node_ref.clone(), data_dir);
+        peers.insert(node_ref.clone());
+        node_refs.push(node_ref);
+    }
+
+    info!(log, "Sandbox network started successfully!"; "nodes" => node_refs.len());
+    Ok(warp::reply::with_status(
+        warp::reply::json(&node_refs),
+        StatusCode::OK,
+    ))
+}
+
+/// Handler for stop_network endpoint, stops all running nodes
+pub async fn stop_network(
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to stop the sandbox network...");
+
+    let mut runner = runner.write().unwrap();
+    let mut client_runner = client_runner.write().unwrap();
+    let mut peers = peers.lock().unwrap();
+    let mut errors = vec![];
+
+    let node_refs = peers
+        .iter()
+        .cloned()
+        .chain(runner.nodes())
+        .unique()
+        .collect_vec();
+    for node_ref in node_refs {
+        let _ = auto_bakers.lock().unwrap().stop(&node_ref);
+        if let Err(e) = runner.shutdown(&node_ref) {
+            errors.push(format!("{:?}", e));
+        }
+        if let Err(e) = client_runner.cleanup(&node_ref) {
+            errors.push(format!("{:?}", e));
+        }
+        peers.remove(&node_ref);
+    }
+
+    if errors.is_empty() {
+        info!(log, "Sandbox network stopped!");
+    } else {
+        error!(log, "Sandbox network stopped!"; "errors" => errors.join(", "));
+    }
+    Ok(warp::reply::with_status(
+        warp::reply::json(&errors),
+        StatusCode::OK,
+    ))
+}
+
+/// Handler for partition endpoint
+pub async fn partition_network(
+    request: SandboxPartitionRequest,
+    log: Logger,
+    runner: LightNodeRunnerRef,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to partition the sandbox network"; "groups" => format!("{:?}", request.groups));
+
+    let mut runner = runner.write().unwrap();
+    runner.partition(request, &log)?;
+
+    info!(log, "Sandbox network partitioned!");
+    Ok(warp::reply::with_status(
+        warp::reply::json(&""),
+        StatusCode::OK,
+    ))
+}
+
+/// Handler for save_state endpoint, stops the node and saves its data as named state
+#[allow(clippy::too_many_arguments)]
+pub async fn save_state(
+    request: SaveStateRequest,
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    saved_states: SavedStatesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    auto_bakers: AutoBakersRef,
+    node_ref: Option<NodeRpcIpPort>,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to save the sandbox node state"; "name" => request.name.clone());
+
+    let node_ref = ensure_node(node_ref)?;
+    // fail fast, before the node is stopped
+    if saved_states.get(&request.name).is_ok() {
+        return Err(SavedStateError::StateAlreadyExists { name: request.name }.into());
+    }
+    // stop auto baking, if running
+    let _ = auto_bakers.lock().unwrap().stop(&node_ref);
+
+    // node has to be stopped, so the databases are consistent
+    let (cfg, sandbox_data) = {
+        let mut runner = runner.write().unwrap();
+        let sandbox_data = client_runner
+            .read()
+            .unwrap()
+            .sandbox_data(&node_ref)?
+            .clone();
+        (runner.stop(&node_ref)?, sandbox_data)
+    };
+
+    // data are copied without holding the runner locks
+    let saved_state = {
+        let name = request.name.clone();
+        let rpc_port = node_ref.port;
+        let cfg = cfg.clone();
+        let saved_states = saved_states.clone();
+        tokio::task::spawn_blocking(move || {
+            saved_states.save(
+                &name,
+                rpc_port,
+                &sandbox_data.data_dir_path,
+                cfg,
+                sandbox_data.wallets.values().cloned().collect(),
+            )
+        })
+        .await
+        .map_err(|e| SavedStateError::IOError {
+            message: format!("Failed to save state '{}'", request.name),
+            reason: std::io::Error::new(std::io::ErrorKind::Other, e),
+        })
+        .and_then(|result| result)
+    };
+    let saved_state = match saved_state {
+        Ok(saved_state) => saved_state,
+        Err(e) => {
+            // node continues with its data
+            if let Err(e) = runner.write().unwrap().spawn_restored(cfg, &log) {
+                error!(log, "Failed to restart sandbox node after failed save"; "node_ref" => node_ref.to_string(), "reason" => format!("{}", e));
+            }
+            return Err(e.into());
+        }
+    };
+
+    // the same as stop
+    if let Err(e) = client_runner.write().unwrap().cleanup(&node_ref) {
+        warn!(log, "Failed to cleanup sandbox node data"; "node_ref" => node_ref.to_string(), "reason" => format!("{}", e));
+    }
+    peers.lock().unwrap().remove(&node_ref);
+
+    info!(log, "Sandbox node state saved!"; "name" => saved_state.name.clone(), "node_ref" => node_ref.to_string());
+    Ok(warp::reply::with_status(
+        warp::reply::json(&saved_state),
+        StatusCode::OK,
+    ))
+}
+
+/// Handler for restore_state endpoint, starts a new node from the saved state
+pub async fn restore_state(
+    request: RestoreStateRequest,
+    log: Logger,
+    runner: LightNodeRunnerRef,
+    client_runner: TezosClientRunnerRef,
+    saved_states: SavedStatesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to restore the sandbox node state"; "name" => request.name.clone(), "config" => format!("{:?}", request.config));
+
+    // the same as spawn, every node has its own temp folder for data
+    let data_dir = create_temp_dir("sandbox-node").map_err(|reason| SavedStateError::IOError {
+        message: "Failed to create temp data dir for sandbox node".to_string(),
+        reason,
+    })?;
+
+    // data are copied without holding the runner lock
+    let restored = {
+        let name = request.name.clone();
+        let data_dir = data_dir.clone();
+        let saved_states = saved_states.clone();
+        tokio::task::spawn_blocking(move || saved_states.restore(&name, &data_dir))
+            .await
+            .map_err(|e| SavedStateError::IOError {
+                message: format!("Failed to restore state '{}'", request.name),
+                reason: std::io::Error::new(std::io::ErrorKind::Other, e),
+            })
+            .and_then(|result| result)
+    };
+    let node_ref = restored
+        .map_err(reject::Rejection::from)
+        .and_then(|restored| {
+            let mut cfg = restored.config;
+            if let (Some(cfg), Some(overrides)) = (
+                cfg.as_object_mut(),
+                request.config.as_ref().and_then(|c| c.as_object()),
+            ) {
+                cfg.extend(overrides.clone());
+            }
+            let node_ref = runner.write().unwrap().spawn_restored(cfg, &log)?;
+            Ok((node_ref, restored.wallets))
+        });
+    let (node_ref, wallets) = match node_ref {
+        Ok(restored) => restored,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&data_dir);
+            return Err(e);
+        }
+    };
+
+    client_runner
+        .write()
+        .unwrap()
+        .restore_sandbox_data(node_ref.clone(), data_dir, wallets);
+    peers.lock().unwrap().insert(node_ref.clone());
+
+    info!(log, "Sandbox node restored successfully!"; "name" => request.name, "node_ref" => node_ref.to_string());
+    Ok(warp::reply::with_status(
+        warp::reply::json(&node_ref),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn list_states(
+    log: Logger,
+    saved_states: SavedStatesRef,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to list saved sandbox states");
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&saved_states.list()?),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn delete_state(
+    request: DeleteStateRequest,
+    log: Logger,
+    saved_states: SavedStatesRef,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to delete saved sandbox state"; "name" => request.name.clone());
+
+    saved_states.delete(&request.name)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&""),
+        StatusCode::OK,
+    ))
+}
+
 pub async fn list_nodes(
     log: Logger,
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
@@ -202,33 +487,141 @@ pub async fn activate_protocol(
     reply_with_client_output(client_output, &log).map_err(|e| e.into())
 }
 
-pub async fn bake_block_with_client(
+pub async fn bake_block(
     request: BakeRequest,
     log: Logger,
     client_runner: TezosClientRunnerRef,
+    seed_nonces: SeedNoncesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
     node_ref: Option<NodeRpcIpPort>,
 ) -> Result<impl warp::Reply, reject::Rejection> {
-    info!(log, "Received request to bake a block");
+    info!(log, "Received request to bake a block"; "alias" => request.alias.clone());
 
     let node_ref = ensure_node(node_ref)?;
-    let client_runner = client_runner.read().unwrap();
-    let client_output = client_runner.bake_block(Some(request), &node_ref)?;
+    let wallet = client_runner
+        .read()
+        .unwrap()
+        .wallet(&node_ref, Some(&request.alias))?;
+    let baked_block = Baker::new(node_ref, running_nodes(&peers), seed_nonces)
+        .bake(&wallet, &log)
+        .await?;
 
-    reply_with_client_output(client_output, &log).map_err(|e| e.into())
+    Ok(warp::reply::with_status(
+        warp::reply::json(&baked_block),
+        StatusCode::OK,
+    ))
 }
 
-pub async fn bake_block_with_client_arbitrary(
+pub async fn bake_block_arbitrary(
     log: Logger,
     client_runner: TezosClientRunnerRef,
+    seed_nonces: SeedNoncesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
     node_ref: Option<NodeRpcIpPort>,
 ) -> Result<impl warp::Reply, reject::Rejection> {
     info!(log, "Received request to bake a block");
 
     let node_ref = ensure_node(node_ref)?;
-    let client_runner = client_runner.read().unwrap();
-    let client_output = client_runner.bake_block(None, &node_ref)?;
+    let wallet = client_runner.read().unwrap().wallet(&node_ref, None)?;
+    let baked_block = Baker::new(node_ref, running_nodes(&peers), seed_nonces)
+        .bake(&wallet, &log)
+        .await?;
 
-    reply_with_client_output(client_output, &log).map_err(|e| e.into())
+    Ok(warp::reply::with_status(
+        warp::reply::json(&baked_block),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn start_auto_bake(
+    request: AutoBakeRequest,
+    log: Logger,
+    client_runner: TezosClientRunnerRef,
+    auto_bakers: AutoBakersRef,
+    seed_nonces: SeedNoncesRef,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    node_ref: Option<NodeRpcIpPort>,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to start auto baking"; "alias" => format!("{:?}", request.alias), "interval_secs" => request.interval_secs);
+
+    let node_ref = ensure_node(node_ref)?;
+    let wallet = client_runner
+        .read()
+        .unwrap()
+        .wallet(&node_ref, request.alias.as_deref())?;
+    auto_bakers.lock().unwrap().start(
+        node_ref,
+        running_nodes(&peers),
+        seed_nonces,
+        wallet,
+        Duration::from_secs(request.interval_secs),
+        log.clone(),
+    )?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&""),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn stop_auto_bake(
+    log: Logger,
+    auto_bakers: AutoBakersRef,
+    node_ref: Option<NodeRpcIpPort>,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to stop auto baking");
+
+    let node_ref = ensure_node(node_ref)?;
+    auto_bakers.lock().unwrap().stop(&node_ref)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&""),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_clock(
+    log: Logger,
+    node_ref: Option<NodeRpcIpPort>,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to get the node clock");
+
+    let node_ref = ensure_node(node_ref)?;
+    let node_clock = clock::get_clock(&node_ref).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&node_clock),
+        StatusCode::OK,
+    ))
+}
+
+/// Changes clock of the selected node (`?node=<rpc_port>`), or of all running nodes
+pub async fn update_clock(
+    request: SandboxClockRequest,
+    log: Logger,
+    peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    query: NodeQuery,
+) -> Result<impl warp::Reply, reject::Rejection> {
+    info!(log, "Received request to change the node clock"; "request" => format!("{:?}", request));
+
+    let nodes = match query.node {
+        Some(_) => vec![ensure_node(resolve_node_from_request(peers, query))?],
+        None => running_nodes(&peers),
+    };
+    if nodes.is_empty() {
+        return Err(TezosClientRunnerError::UnavailableSandboxNodeError.into());
+    }
+
+    let mut clocks = HashMap::new();
+    for node_ref in nodes.into_iter().sorted_by_key(|node_ref| node_ref.port) {
+        let node_clock = clock::update_clock(&node_ref, &request).await?;
+        clocks.insert(node_ref.port.to_string(), node_clock);
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&clocks),
+        StatusCode::OK,
+    ))
 }
 
 pub async fn handle_rejection(err: Rejection, log: Logger) -> Result<impl Reply, Infallible> {
@@ -277,13 +670,52 @@ pub async fn handle_rejection(err: Rejection, log: Logger) -> Result<impl Reply,
                     )
                 }
             }
+        } else if let Some(be) = err.find::<BakerError>() {
+            // Baker errors
+            let message = format!("{}", be);
+            error!(log, "Rpc handle error (baker)"; "message" => message.clone());
+            let code = match be {
+                BakerError::PreapplyError { .. }
+                | BakerError::InvalidWalletKey { .. }
+                | BakerError::NoBakingRights { .. }
+                | BakerError::AutoBakeAlreadyRunning { .. }
+                | BakerError::AutoBakeNotRunning { .. } => StatusCode::BAD_REQUEST,
+                BakerError::RpcCallError { .. }
+                | BakerError::InvalidRpcResponse { .. }
+                | BakerError::ForgeError { .. }
+                | BakerError::ProofOfWorkNotFound { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (code, ErrorMessage::generic(code, &message, "".to_string()))
+        } else if let Some(sse) = err.find::<SavedStateError>() {
+            // Saved state errors
+            let message = format!("{}", sse);
+            error!(log, "Rpc handle error (saved state)"; "message" => message.clone());
+            let code = match sse {
+                SavedStateError::InvalidName { .. }
+                | SavedStateError::StateNotFound { .. }
+                | SavedStateError::StateAlreadyExists { .. } => StatusCode::BAD_REQUEST,
+                SavedStateError::IOError { .. } | SavedStateError::InvalidMetadata { .. } => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            (code, ErrorMessage::generic(code, &message, "".to_string()))
+        } else if let Some(ce) = err.find::<ClockError>() {
+            // Node clock errors
+            let message = format!("{}", ce);
+            error!(log, "Rpc handle error (clock)"; "message" => message.clone());
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorMessage::generic(StatusCode::INTERNAL_SERVER_ERROR, &message, "".to_string()),
+            )
         } else if let Some(lnre) = err.find::<LightNodeRunnerError>() {
             // Light-node errors
             match lnre {
                 LightNodeRunnerError::JsonParsingError { .. }
                 | LightNodeRunnerError::IOError { .. }
                 | LightNodeRunnerError::ConfigurationMissingValidRpcPort { .. }
-                | LightNodeRunnerError::NodeAlreadyRunning
+                | LightNodeRunnerError::ConfigurationInvalidNetworkValue { .. }
+                | LightNodeRunnerError::InvalidPartition { .. }
+                | LightNodeRunnerError::NodeAlreadyRunning { .. }
                 | LightNodeRunnerError::NodeNotRunning { .. } => {
                     let message = format!("{}", lnre);
                     error!(log, "Rpc handle error (light-node)"; "message" => message.clone());
@@ -359,9 +791,17 @@ fn extract_field_name(message: &str) -> Option<String> {
 /// Resolves which peer we want to call
 pub fn resolve_node_from_request(
     peers: Arc<Mutex<HashSet<NodeRpcIpPort>>>,
+    query: NodeQuery,
 ) -> Option<NodeRpcIpPort> {
-    // TODO: resolve some peer from request
-    peers.lock().unwrap().iter().next().map(|p| p.clone())
+    let peers = peers.lock().unwrap();
+    match query.node {
+        Some(port) => peers.iter().find(|p| p.port == port).cloned(),
+        None => peers.iter().min_by_key(|p| p.port).cloned(),
+    }
+}
+
+fn running_nodes(peers: &Arc<Mutex<HashSet<NodeRpcIpPort>>>) -> Vec<NodeRpcIpPort> {
+    peers.lock().unwrap().iter().cloned().collect()
 }
 
 fn ensure_node(node_ref: Option<NodeRpcIpPort>) -> Result<NodeRpcIpPort, TezosClientRunnerError> {
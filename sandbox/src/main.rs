@@ -11,10 +11,13 @@ use rand::distributions::Alphanumeric;
 use rand::Rng;
 use slog::{info, Drain, Level, Logger};
 
+mod baker;
+mod clock;
 mod configuration;
 mod filters;
 mod handlers;
 mod node_runner;
+mod saved_states;
 mod tezos_client_runner;
 
 #[tokio::main]
@@ -41,11 +44,28 @@ async fn main() {
         env.tezos_client_path,
     )));
 
+    // running auto bakers
+    let auto_bakers = Arc::new(Mutex::new(baker::AutoBakers::default()));
+
+    // seed nonces of the baked blocks waiting for revelation
+    let seed_nonces = Arc::new(Mutex::new(baker::SeedNonces::default()));
+
+    // saved states of the sandbox nodes
+    let saved_states = Arc::new(saved_states::SavedStates::new(env.sandbox_states_dir));
+
     // the port to open the rpc server on
     let rpc_port = env.sandbox_rpc_port;
 
     // combined warp filter
-    let api = filters::sandbox(log.clone(), runner, client_runner, peers);
+    let api = filters::sandbox(
+        log.clone(),
+        runner,
+        client_runner,
+        peers,
+        auto_bakers,
+        seed_nonces,
+        saved_states,
+    );
 
     info!(log, "Start to serving Sandbox RPCs");
 
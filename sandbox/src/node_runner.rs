@@ -1,10 +1,12 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs;
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::sync::{Arc, RwLock};
 use std::thread;
@@ -24,8 +26,11 @@ use crate::{create_temp_dir, rand_chars};
 #[derive(Debug, Fail)]
 pub enum LightNodeRunnerError {
     /// Already running error.
-    #[fail(display = "Sandbox light-node is already running")]
-    NodeAlreadyRunning,
+    #[fail(
+        display = "Sandbox light-node is already running, node_ref: {}",
+        node_ref
+    )]
+    NodeAlreadyRunning { node_ref: NodeRpcIpPort },
 
     /// Already running error.
     #[fail(display = "Sandbox light-node is not running, node_ref: {}", node_ref)]
@@ -52,6 +57,20 @@ pub enum LightNodeRunnerError {
         value
     )]
     ConfigurationMissingValidRpcPort { value: Option<String> },
+
+    /// Network node configuration error
+    #[fail(
+        display = "Failed to configure sandbox network - invalid `{}` in configuration, value: {:?}",
+        property, value
+    )]
+    ConfigurationInvalidNetworkValue {
+        property: String,
+        value: Option<String>,
+    },
+
+    /// Invalid partition request
+    #[fail(display = "Invalid sandbox network partition, reason: {}", reason)]
+    InvalidPartition { reason: String },
 }
 
 impl From<LightNodeRunnerError> for reject::Rejection {
@@ -67,11 +86,18 @@ pub type LightNodeRunnerRef = Arc<RwLock<LightNodeRunner>>;
 
 const SANDBOX_NODE_IP: &str = "localhost";
 const NODE_CONFIG_RPC_PORT: &str = "rpc_port";
+const NODE_CONFIG_P2P_PORT: &str = "p2p_port";
+const NODE_CONFIG_WEBSOCKET_ADDRESS: &str = "websocket_address";
+const NODE_CONFIG_PEERS: &str = "peers";
+const NODE_CONFIG_PRIVATE_NODE: &str = "private_node";
+const NODE_CONFIG_DISABLE_BOOTSTRAP_LOOKUP: &str = "disable_bootstrap_lookup";
+const NODE_CONFIG_BOOTSTRAP_LOOKUP_ADDRESS: &str = "bootstrap_lookup_address";
 const NODE_CONFIG_TEZOS_DATA_DIR: &str = "tezos_data_dir";
 const NODE_CONFIG_TEZEDGE_DATA_DIR: &str = "bootstrap_db_path";
 const NODE_CONFIG_IDENTITY_FILE: &str = "identity_file";
 const NODE_CONFIG_PATCH_CONTEXT_JSON_FILE_PATH: &str = "sandbox_patch_context_json_file";
 const NODE_CONFIG_PROTOCOL_RUNNER: &str = "protocol_runner";
+const NODE_CONFIG_RPC_ALLOW_ALL: &str = "rpc_allow_all";
 
 /// RPC ip/port, where is light node listening for rpc requests
 #[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
@@ -108,6 +134,13 @@ impl NodeRpcIpPort {
             port,
         })
     }
+
+    pub fn with_port(port: u16) -> Self {
+        Self {
+            ip: SANDBOX_NODE_IP.to_string(),
+            port,
+        }
+    }
 }
 
 impl fmt::Display for NodeRpcIpPort {
@@ -116,19 +149,42 @@ impl fmt::Display for NodeRpcIpPort {
     }
 }
 
-/// Struct that holds info about the running child process
+/// Running light-node child process
+struct SandboxNode {
+    process: Child,
+    /// Final (sandboxed) configuration, used to restart the node
+    cfg: serde_json::Value,
+    /// P2P port, if configured
+    p2p_port: Option<u16>,
+}
+
+/// Request to start multiple interconnected nodes, `config` is used for the first node,
+/// next nodes have `rpc_port`, `p2p_port` and `websocket_address` port incremented by the node index
+#[derive(Clone, Debug, Deserialize)]
+pub struct SandboxNetworkRequest {
+    pub nodes: u16,
+    pub config: serde_json::Value,
+}
+
+/// Groups of nodes (identified by rpc port), which can communicate only with each other,
+/// running nodes not mentioned in any group form one additional group, so empty `groups` heals the network
+#[derive(Clone, Debug, Deserialize)]
+pub struct SandboxPartitionRequest {
+    pub groups: Vec<Vec<u16>>,
+}
+
+/// Struct that holds info about the running child processes
 pub struct LightNodeRunner {
     executable_path: PathBuf,
     protocol_runner_executable_path: PathBuf,
     _name: String,
 
-    // TODO: TE-213 Launch multiple nodes + wrap process with rpc ip/port
-    // TODO: in main we have peers
-    process: Option<Child>,
+    nodes: HashMap<NodeRpcIpPort, SandboxNode>,
 }
 
 impl LightNodeRunner {
     const PROCESS_WAIT_TIMEOUT: Duration = Duration::from_secs(4);
+    const PROCESS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
     pub fn new(
         name: &str,
@@ -139,7 +195,7 @@ impl LightNodeRunner {
             executable_path,
             protocol_runner_executable_path,
             _name: name.to_string(),
-            process: None,
+            nodes: HashMap::new(),
         }
     }
 
@@ -154,11 +210,13 @@ impl LightNodeRunner {
         cfg: serde_json::Value,
         log: &Logger,
     ) -> Result<(NodeRpcIpPort, PathBuf), LightNodeRunnerError> {
-        if self.is_running() {
-            Err(LightNodeRunnerError::NodeAlreadyRunning)
+        // parse rpc settings
+        let node = NodeRpcIpPort::new(&cfg)?;
+
+        if self.is_running(&node) {
+            Err(LightNodeRunnerError::NodeAlreadyRunning { node_ref: node })
         } else {
-            // parse rpc settings
-            let node = NodeRpcIpPort::new(&cfg)?;
+            let p2p_port = parse_port(&cfg, NODE_CONFIG_P2P_PORT).ok();
 
             // one node will have its own temp folder for data (identity, dbs, ...)
             let data_dir =
@@ -216,9 +274,7 @@ impl LightNodeRunner {
                 _ => {
                     // somehow process was not finished, so we need to kill him
                     match Self::send_sigint(process.id()) {
-                        Ok(()) => {
-                            self.process = None;
-                        }
+                        Ok(()) => (),
                         // if for some reason, the SIGINT fails to end the process, kill it with SIGKILL
                         Err(e) => {
                             let error_msg = handle_stderr(&mut process);
@@ -267,32 +323,277 @@ impl LightNodeRunner {
 
             // the process started up OK, but we restart it to enable normal logging (stderr won't be piped)
             // start the process again without piped stdout/stderr, e.g. to have ability log to syslog in docker to tezos-debugger
-            let process = Command::new(&self.executable_path)
-                .args(Self::construct_args(cfg, false)?)
-                .spawn()
-                .map_err(|err| LightNodeRunnerError::IOError {
-                    message: "Failed to start light-node".to_string(),
-                    reason: err,
-                })?;
-
-            self.process = Some(process);
+            let process = Self::start_process(&self.executable_path, &cfg)?;
+
+            self.nodes.insert(
+                node.clone(),
+                SandboxNode {
+                    process,
+                    cfg,
+                    p2p_port,
+                },
+            );
             Ok((node, data_dir))
         }
     }
 
+    /// Spawn `request.nodes` light-nodes, every node has all other nodes as trusted peers (private mode)
+    ///
+    /// If any node fails to start, already started nodes are shut down
+    pub fn spawn_network(
+        &mut self,
+        request: SandboxNetworkRequest,
+        log: &Logger,
+    ) -> Result<Vec<(NodeRpcIpPort, PathBuf)>, LightNodeRunnerError> {
+        let rpc_port = NodeRpcIpPort::new(&request.config)?.port;
+        let p2p_port = parse_port(&request.config, NODE_CONFIG_P2P_PORT)?;
+        let websocket_address = match request.config.get(NODE_CONFIG_WEBSOCKET_ADDRESS) {
+            Some(value) => Some(
+                value
+                    .as_str()
+                    .and_then(|address| address.parse::<SocketAddr>().ok())
+                    .ok_or_else(|| LightNodeRunnerError::ConfigurationInvalidNetworkValue {
+                        property: NODE_CONFIG_WEBSOCKET_ADDRESS.to_string(),
+                        value: Some(value.to_string()),
+                    })?,
+            ),
+            None => None,
+        };
+
+        let nodes_count = request.nodes.max(1);
+        let max_port = rpc_port
+            .max(p2p_port)
+            .max(websocket_address.map(|address| address.port()).unwrap_or(0));
+        if u32::from(max_port) + u32::from(nodes_count) > u32::from(u16::MAX) {
+            return Err(LightNodeRunnerError::ConfigurationInvalidNetworkValue {
+                property: "nodes".to_string(),
+                value: Some(nodes_count.to_string()),
+            });
+        }
+        let p2p_ports = (0..nodes_count).map(|i| p2p_port + i).collect::<Vec<_>>();
+
+        let mut started = Vec::with_capacity(nodes_count as usize);
+        for i in 0..nodes_count {
+            let mut cfg = request.config.clone();
+            if let Some(map) = cfg.as_object_mut() {
+                map.insert(NODE_CONFIG_RPC_PORT.to_string(), (rpc_port + i).into());
+                map.insert(NODE_CONFIG_P2P_PORT.to_string(), (p2p_port + i).into());
+                if let Some(mut websocket_address) = websocket_address {
+                    websocket_address.set_port(websocket_address.port() + i);
+                    map.insert(
+                        NODE_CONFIG_WEBSOCKET_ADDRESS.to_string(),
+                        websocket_address.to_string().into(),
+                    );
+                }
+                let peers = p2p_ports
+                    .iter()
+                    .filter(|port| **port != p2p_port + i)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                set_peers(map, &peers);
+            }
+
+            info!(log, "Starting sandbox network node"; "index" => i, "rpc_port" => rpc_port + i, "p2p_port" => p2p_port + i);
+            match self.spawn(cfg, log) {
+                Ok(node) => started.push(node),
+                Err(e) => {
+                    for (node_ref, _) in &started {
+                        if let Err(e) = self.shutdown(node_ref) {
+                            warn!(log, "Failed to shutdown sandbox network node"; "node_ref" => node_ref.to_string(), "reason" => format!("{}", e));
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(started)
+    }
+
+    /// Restarts nodes, so that they are connected only to the nodes from the same group.
+    /// Data of the nodes are preserved.
+    pub fn partition(
+        &mut self,
+        request: SandboxPartitionRequest,
+        log: &Logger,
+    ) -> Result<(), LightNodeRunnerError> {
+        let mut groups = request
+            .groups
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(NodeRpcIpPort::with_port)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // validate groups
+        let mut grouped = HashSet::new();
+        for node_ref in groups.iter().flatten() {
+            if !self.nodes.contains_key(node_ref) {
+                return Err(LightNodeRunnerError::NodeNotRunning {
+                    node_ref: node_ref.clone(),
+                });
+            }
+            if !grouped.insert(node_ref.clone()) {
+                return Err(LightNodeRunnerError::InvalidPartition {
+                    reason: format!("node is in more than one group, node_ref: {}", node_ref),
+                });
+            }
+        }
+        let rest = self
+            .nodes
+            .keys()
+            .filter(|node_ref| !grouped.contains(*node_ref))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !rest.is_empty() {
+            groups.push(rest);
+        }
+
+        // resolve p2p ports
+        let mut p2p_ports = HashMap::new();
+        for node_ref in groups.iter().flatten() {
+            match self.nodes[node_ref].p2p_port {
+                Some(p2p_port) => p2p_ports.insert(node_ref.clone(), p2p_port),
+                None => {
+                    return Err(LightNodeRunnerError::ConfigurationInvalidNetworkValue {
+                        property: NODE_CONFIG_P2P_PORT.to_string(),
+                        value: None,
+                    })
+                }
+            };
+        }
+
+        // configurations before the partition, restored if any node fails to restart
+        let previous_cfgs = self
+            .nodes
+            .iter()
+            .map(|(node_ref, node)| (node_ref.clone(), node.cfg.clone()))
+            .collect::<HashMap<_, _>>();
+
+        // stop all nodes first, so no node reconnects to the old peers
+        for node in self.nodes.values_mut() {
+            Self::stop_process(&mut node.process);
+        }
+
+        for group in &groups {
+            for node_ref in group {
+                let peers = group
+                    .iter()
+                    .filter(|peer| *peer != node_ref)
+                    .map(|peer| p2p_ports[peer])
+                    .collect::<Vec<_>>();
+
+                info!(log, "Restarting sandbox node for partition"; "node_ref" => node_ref.to_string(), "peers" => format!("{:?}", peers));
+                let node = self.nodes.get_mut(node_ref).unwrap();
+                if let Some(map) = node.cfg.as_object_mut() {
+                    set_peers(map, &peers);
+                }
+                match Self::start_process(&self.executable_path, &node.cfg) {
+                    Ok(process) => node.process = process,
+                    Err(e) => {
+                        warn!(log, "Failed to restart sandbox node for partition, restoring previous peers"; "node_ref" => node_ref.to_string(), "reason" => format!("{}", e));
+                        self.restart_with_cfgs(previous_cfgs, log);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops all running nodes and starts them again with the configurations
+    fn restart_with_cfgs(&mut self, cfgs: HashMap<NodeRpcIpPort, serde_json::Value>, log: &Logger) {
+        for (node_ref, cfg) in cfgs {
+            let node = match self.nodes.get_mut(&node_ref) {
+                Some(node) => node,
+                None => continue,
+            };
+            // process of the node may not be started yet (or already stopped)
+            if let Ok(None) = node.process.try_wait() {
+                Self::stop_process(&mut node.process);
+            }
+            node.cfg = cfg;
+            match Self::start_process(&self.executable_path, &node.cfg) {
+                Ok(process) => node.process = process,
+                Err(e) => {
+                    error!(log, "Failed to restart sandbox node"; "node_ref" => node_ref.to_string(), "reason" => format!("{}", e))
+                }
+            }
+        }
+    }
+
+    /// Starts light-node with already sandboxed configuration (e.g. restored from saved state),
+    /// identity and data dirs are expected to exist
+    pub fn spawn_restored(
+        &mut self,
+        cfg: serde_json::Value,
+        log: &Logger,
+    ) -> Result<NodeRpcIpPort, LightNodeRunnerError> {
+        let node = NodeRpcIpPort::new(&cfg)?;
+        if self.is_running(&node) {
+            return Err(LightNodeRunnerError::NodeAlreadyRunning { node_ref: node });
+        }
+        let p2p_port = parse_port(&cfg, NODE_CONFIG_P2P_PORT).ok();
+
+        let mut process = Self::start_process(&self.executable_path, &cfg)?;
+
+        // the same as in spawn, we only care if it fails to start up
+        thread::sleep(Duration::from_secs(1));
+        if let Ok(Some(exit_status)) = process.try_wait() {
+            error!(log, "Failed to start restored light-node"; "node_ref" => node.to_string(), "exit_status" => exit_status.to_string());
+            return Err(LightNodeRunnerError::NodeStartupError {
+                reason: format!("light-node exited with {}", exit_status),
+            });
+        }
+
+        self.nodes.insert(
+            node.clone(),
+            SandboxNode {
+                process,
+                cfg,
+                p2p_port,
+            },
+        );
+        Ok(node)
+    }
+
+    /// Stops the light-node and waits until it finishes, so its data can be copied.
+    ///
+    /// Returns the node configuration
+    pub fn stop(
+        &mut self,
+        node_ref: &NodeRpcIpPort,
+    ) -> Result<serde_json::Value, LightNodeRunnerError> {
+        match self.nodes.remove(node_ref) {
+            Some(mut node) => {
+                Self::stop_process(&mut node.process);
+                Ok(node.cfg)
+            }
+            None => Err(LightNodeRunnerError::NodeNotRunning {
+                node_ref: node_ref.clone(),
+            }),
+        }
+    }
+
+    /// Returns all running nodes
+    pub fn nodes(&self) -> Vec<NodeRpcIpPort> {
+        self.nodes.keys().cloned().collect()
+    }
+
     /// Shut down the light-node
     pub fn shutdown(&mut self, node_ref: &NodeRpcIpPort) -> Result<(), LightNodeRunnerError> {
-        if self.is_running() {
-            let process = self.process.as_mut().unwrap();
+        if self.is_running(node_ref) {
+            let mut node = self.nodes.remove(node_ref).unwrap();
             // kill with SIGINT (ctr-c)
-            match Self::send_sigint(process.id()) {
-                Ok(()) => {
-                    self.process = None;
-                    Ok(())
-                }
+            match Self::send_sigint(node.process.id()) {
+                Ok(()) => Ok(()),
                 // if for some reason, the SIGINT fails to end the process, kill it with SIGKILL
                 Err(_) => {
-                    Self::terminate_ref(process);
+                    Self::terminate_ref(&mut node.process);
                     Ok(())
                 }
             }
@@ -303,6 +604,30 @@ impl LightNodeRunner {
         }
     }
 
+    fn start_process(
+        executable_path: &Path,
+        cfg: &serde_json::Value,
+    ) -> Result<Child, LightNodeRunnerError> {
+        Command::new(executable_path)
+            .args(Self::construct_args(cfg.clone(), false)?)
+            .spawn()
+            .map_err(|err| LightNodeRunnerError::IOError {
+                message: "Failed to start light-node".to_string(),
+                reason: err,
+            })
+    }
+
+    /// Stops process and waits until it finishes (releases db locks and ports)
+    fn stop_process(process: &mut Child) {
+        if Self::send_sigint(process.id()).is_ok() {
+            if let Ok(Some(_)) = process.wait_timeout(Self::PROCESS_SHUTDOWN_TIMEOUT) {
+                return;
+            }
+        }
+        let _ = process.kill();
+        let _ = process.wait();
+    }
+
     fn terminate_ref(process: &mut Child) {
         match process.wait_timeout(Self::PROCESS_WAIT_TIMEOUT).unwrap() {
             Some(_) => (),
@@ -313,9 +638,9 @@ impl LightNodeRunner {
         };
     }
 
-    fn is_running(&mut self) -> bool {
-        if let Some(process) = &mut self.process {
-            match process.try_wait() {
+    fn is_running(&mut self, node_ref: &NodeRpcIpPort) -> bool {
+        if let Some(node) = self.nodes.get_mut(node_ref) {
+            match node.process.try_wait() {
                 Ok(None) => true,
                 _ => false,
             }
@@ -354,6 +679,7 @@ impl LightNodeRunner {
     /// 1. replaces [NODE_CONFIG_TEZOS_DATA_DIR][NODE_CONFIG_TEZEDGE_DATA_DIR][NODE_CONFIG_IDENTITY_FILE] with custom names prefixed [sandbox_data_dir]
     /// 2. replaces [NODE_CONFIG_PROTOCOL_RUNNER] with own settings
     /// 3. stores [sandbox_patch_context_json] to tempfile and sets it as [NODE_CONFIG_PATCH_CONTEXT_JSON_FILE_PATH] (if present)
+    /// 4. sets [NODE_CONFIG_RPC_ALLOW_ALL] (if not present), so tezos-client can inject operations and blocks
     fn ensure_sandbox_cfg(
         &self,
         mut cfg: serde_json::Value,
@@ -443,6 +769,14 @@ impl LightNodeRunner {
                                "new_value" => sandbox_patch_context_json_file.as_path().display().to_string());
                 }
             }
+
+            // 4.
+            if !map.contains_key(NODE_CONFIG_RPC_ALLOW_ALL) {
+                map.insert(
+                    NODE_CONFIG_RPC_ALLOW_ALL.to_string(),
+                    serde_json::Value::String("0.0.0.0".to_string()),
+                );
+            }
         }
         Ok(cfg)
     }
@@ -454,6 +788,45 @@ impl LightNodeRunner {
     }
 }
 
+/// Sets (localhost) p2p ports of trusted peers, node without peers is isolated
+fn set_peers(cfg: &mut serde_json::Map<String, serde_json::Value>, peers: &[u16]) {
+    cfg.remove(NODE_CONFIG_BOOTSTRAP_LOOKUP_ADDRESS);
+    cfg.insert(
+        NODE_CONFIG_DISABLE_BOOTSTRAP_LOOKUP.to_string(),
+        serde_json::Value::String("".to_string()),
+    );
+    if peers.is_empty() {
+        cfg.remove(NODE_CONFIG_PEERS);
+        cfg.remove(NODE_CONFIG_PRIVATE_NODE);
+    } else {
+        cfg.insert(
+            NODE_CONFIG_PEERS.to_string(),
+            peers
+                .iter()
+                .map(|port| format!("127.0.0.1:{}", port))
+                .join(",")
+                .into(),
+        );
+        cfg.insert(NODE_CONFIG_PRIVATE_NODE.to_string(), true.into());
+    }
+}
+
+fn parse_port(cfg: &serde_json::Value, property: &str) -> Result<u16, LightNodeRunnerError> {
+    match cfg.get(property) {
+        Some(value) => match value.as_u64() {
+            Some(port) if port <= u16::MAX as u64 => Ok(port as u16),
+            _ => Err(LightNodeRunnerError::ConfigurationInvalidNetworkValue {
+                property: property.to_string(),
+                value: Some(value.to_string()),
+            }),
+        },
+        None => Err(LightNodeRunnerError::ConfigurationInvalidNetworkValue {
+            property: property.to_string(),
+            value: None,
+        }),
+    }
+}
+
 /// extract data as String form the piped stderr
 fn handle_stderr(process: &mut Child) -> String {
     if let Some(stderr) = process.stderr.take() {
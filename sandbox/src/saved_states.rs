@@ -0,0 +1,386 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Named saved states of sandbox nodes, so test scenarios don't need to activate protocol and bake up to the desired state from scratch.
+//!
+//! Saved state is a copy of the node's sandbox data dir (tezedge db, tezos db, identity, tezos-client base dir)
+//! together with [`SAVED_STATE_METADATA_FILE`], which holds the node configuration and wallets.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::Utc;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use warp::reject;
+
+use crate::tezos_client_runner::Wallet;
+
+/// Metadata of the saved state, stored next to the copied data
+const SAVED_STATE_METADATA_FILE: &str = "sandbox_state.json";
+
+/// Copied sandbox data dir of the node
+const SAVED_STATE_DATA_DIR: &str = "data";
+
+#[derive(Debug, Fail)]
+pub enum SavedStateError {
+    /// IO Error.
+    #[fail(display = "IOError - {}, reason: {}", message, reason)]
+    IOError {
+        message: String,
+        reason: std::io::Error,
+    },
+
+    /// Invalid name of the saved state
+    #[fail(
+        display = "Invalid saved state name: '{}', only alphanumeric characters, '-' and '_' are allowed",
+        name
+    )]
+    InvalidName { name: String },
+
+    /// Saved state does not exists
+    #[fail(display = "Saved state '{}' does not exists", name)]
+    StateNotFound { name: String },
+
+    /// Saved state already exists
+    #[fail(display = "Saved state '{}' already exists", name)]
+    StateAlreadyExists { name: String },
+
+    /// Metadata of the saved state cannot be read/written
+    #[fail(
+        display = "Invalid saved state '{}' metadata, reason: {}",
+        name, reason
+    )]
+    InvalidMetadata { name: String, reason: String },
+}
+
+impl From<SavedStateError> for reject::Rejection {
+    fn from(err: SavedStateError) -> reject::Rejection {
+        reject::custom(err)
+    }
+}
+
+impl reject::Reject for SavedStateError {}
+
+/// Thread-safe reference to the saved states
+pub type SavedStatesRef = Arc<SavedStates>;
+
+/// The json body incoming with the save state request
+#[derive(Clone, Debug, Deserialize)]
+pub struct SaveStateRequest {
+    pub name: String,
+}
+
+/// The json body incoming with the restore state request,
+/// `config` overrides the saved node configuration (e.g. `rpc_port`, `p2p_port`)
+#[derive(Clone, Debug, Deserialize)]
+pub struct RestoreStateRequest {
+    pub name: String,
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+}
+
+/// The json body incoming with the delete state request
+#[derive(Clone, Debug, Deserialize)]
+pub struct DeleteStateRequest {
+    pub name: String,
+}
+
+/// Metadata of the saved state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedState {
+    pub name: String,
+    pub saved_at: String,
+    /// Rpc port of the node, which was saved
+    pub rpc_port: u16,
+    /// Original data dir of the node, paths in `config` are relative to this dir
+    data_dir: String,
+    config: serde_json::Value,
+    wallets: Vec<Wallet>,
+}
+
+/// State prepared for the restored node
+pub struct RestoredState {
+    pub config: serde_json::Value,
+    pub wallets: Vec<Wallet>,
+}
+
+/// Saved states stored in one directory, every state in its own subdirectory
+pub struct SavedStates {
+    states_dir: PathBuf,
+}
+
+impl SavedStates {
+    pub fn new(states_dir: PathBuf) -> Self {
+        Self { states_dir }
+    }
+
+    /// Copies the (stopped) node data dir as a new saved state
+    pub fn save(
+        &self,
+        name: &str,
+        rpc_port: u16,
+        data_dir: &Path,
+        config: serde_json::Value,
+        wallets: Vec<Wallet>,
+    ) -> Result<SavedState, SavedStateError> {
+        let state_dir = self.state_dir(name)?;
+        if state_dir.exists() {
+            return Err(SavedStateError::StateAlreadyExists {
+                name: name.to_string(),
+            });
+        }
+
+        let state = SavedState {
+            name: name.to_string(),
+            saved_at: Utc::now().to_rfc3339(),
+            rpc_port,
+            data_dir: data_dir.display().to_string(),
+            config,
+            wallets,
+        };
+
+        let result = copy_dir_all(data_dir, &state_dir.join(SAVED_STATE_DATA_DIR)).and_then(|_| {
+            let metadata = serde_json::to_vec_pretty(&state)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            fs::write(state_dir.join(SAVED_STATE_METADATA_FILE), metadata)
+        });
+        if let Err(reason) = result {
+            // do not leave incomplete state
+            let _ = fs::remove_dir_all(&state_dir);
+            return Err(SavedStateError::IOError {
+                message: format!("Failed to save state '{}'", name),
+                reason,
+            });
+        }
+
+        Ok(state)
+    }
+
+    /// Copies saved state to the new node data dir and returns configuration with the paths moved to this dir
+    pub fn restore(&self, name: &str, data_dir: &Path) -> Result<RestoredState, SavedStateError> {
+        let state = self.get(name)?;
+        let state_dir = self.state_dir(name)?;
+
+        copy_dir_all(&state_dir.join(SAVED_STATE_DATA_DIR), data_dir).map_err(|reason| {
+            SavedStateError::IOError {
+                message: format!("Failed to restore state '{}'", name),
+                reason,
+            }
+        })?;
+
+        Ok(RestoredState {
+            config: relocate_paths(
+                state.config,
+                &state.data_dir,
+                &data_dir.display().to_string(),
+            ),
+            wallets: state.wallets,
+        })
+    }
+
+    /// Returns all saved states sorted by name, states with invalid metadata are skipped
+    pub fn list(&self) -> Result<Vec<SavedState>, SavedStateError> {
+        if !self.states_dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let entries =
+            fs::read_dir(&self.states_dir).map_err(|reason| SavedStateError::IOError {
+                message: format!(
+                    "Failed to list saved states in {}",
+                    self.states_dir.display()
+                ),
+                reason,
+            })?;
+        let mut states = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter_map(|name| self.get(&name).ok())
+            .collect::<Vec<_>>();
+        states.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(states)
+    }
+
+    pub fn get(&self, name: &str) -> Result<SavedState, SavedStateError> {
+        let metadata_file = self.state_dir(name)?.join(SAVED_STATE_METADATA_FILE);
+        if !metadata_file.exists() {
+            return Err(SavedStateError::StateNotFound {
+                name: name.to_string(),
+            });
+        }
+
+        let metadata = fs::read(&metadata_file).map_err(|reason| SavedStateError::IOError {
+            message: format!("Failed to read {}", metadata_file.display()),
+            reason,
+        })?;
+        serde_json::from_slice(&metadata).map_err(|e| SavedStateError::InvalidMetadata {
+            name: name.to_string(),
+            reason: format!("{}", e),
+        })
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), SavedStateError> {
+        let state_dir = self.state_dir(name)?;
+        if !state_dir.exists() {
+            return Err(SavedStateError::StateNotFound {
+                name: name.to_string(),
+            });
+        }
+
+        fs::remove_dir_all(&state_dir).map_err(|reason| SavedStateError::IOError {
+            message: format!("Failed to delete state '{}'", name),
+            reason,
+        })
+    }
+
+    fn state_dir(&self, name: &str) -> Result<PathBuf, SavedStateError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(SavedStateError::InvalidName {
+                name: name.to_string(),
+            });
+        }
+        Ok(self.states_dir.join(name))
+    }
+}
+
+/// Replaces `from` prefix of all string values in configuration (also nested in arrays and objects) with `to`
+fn relocate_paths(config: serde_json::Value, from: &str, to: &str) -> serde_json::Value {
+    match config {
+        serde_json::Value::String(path) => match path.strip_prefix(from) {
+            Some(rest) => serde_json::Value::String(format!("{}{}", to, rest)),
+            None => serde_json::Value::String(path),
+        },
+        serde_json::Value::Array(values) => serde_json::Value::Array(
+            values
+                .into_iter()
+                .map(|value| relocate_paths(value, from, to))
+                .collect(),
+        ),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, relocate_paths(value, from, to)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Recursively copies directory
+fn copy_dir_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn wallet(alias: &str) -> Wallet {
+        Wallet {
+            alias: alias.to_string(),
+            public_key_hash: "tz1".to_string(),
+            public_key: "edpk".to_string(),
+            secret_key: "edsk".to_string(),
+            initial_balance: "1000".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_relocate_paths() {
+        let config = json!({
+            "tezos_data_dir": "/tmp/node-a/tezos",
+            "identity_file": "/tmp/node-a/identity.json",
+            "peers": ["127.0.0.1:9732"],
+            "log": [{"file": "/tmp/node-a/log"}, {"file": "/var/log/node"}],
+            "nested": {"db": {"path": "/tmp/node-a/db"}},
+            "other": "/tmp/node-ab",
+            "rpc_port": 18732,
+        });
+
+        assert_eq!(
+            relocate_paths(config, "/tmp/node-a", "/tmp/node-b"),
+            json!({
+                "tezos_data_dir": "/tmp/node-b/tezos",
+                "identity_file": "/tmp/node-b/identity.json",
+                "peers": ["127.0.0.1:9732"],
+                "log": [{"file": "/tmp/node-b/log"}, {"file": "/var/log/node"}],
+                "nested": {"db": {"path": "/tmp/node-b/db"}},
+                "other": "/tmp/node-bb",
+                "rpc_port": 18732,
+            })
+        );
+    }
+
+    #[test]
+    fn test_save_restore_delete() -> Result<(), failure::Error> {
+        let root = crate::create_temp_dir("saved-states-test")?;
+        let saved_states = SavedStates::new(root.join("states"));
+        let data_dir = root.join("node-a");
+        fs::create_dir_all(data_dir.join("tezos/context"))?;
+        fs::write(data_dir.join("identity.json"), b"identity")?;
+        fs::write(data_dir.join("tezos/context/store"), b"context")?;
+        let config = json!({
+            "tezos_data_dir": data_dir.join("tezos").display().to_string(),
+            "rpc_port": 18732,
+        });
+
+        let saved = saved_states.save("state-1", 18732, &data_dir, config, vec![wallet("bob")])?;
+        assert_eq!(saved.name, "state-1");
+        assert!(matches!(
+            saved_states.save("state-1", 18732, &data_dir, json!({}), vec![]),
+            Err(SavedStateError::StateAlreadyExists { .. })
+        ));
+        assert!(matches!(
+            saved_states.save("../state", 18732, &data_dir, json!({}), vec![]),
+            Err(SavedStateError::InvalidName { .. })
+        ));
+        assert_eq!(
+            saved_states
+                .list()?
+                .into_iter()
+                .map(|state| state.name)
+                .collect::<Vec<_>>(),
+            vec!["state-1".to_string()]
+        );
+
+        let restored_dir = root.join("node-b");
+        let restored = saved_states.restore("state-1", &restored_dir)?;
+        assert_eq!(fs::read(restored_dir.join("identity.json"))?, b"identity");
+        assert_eq!(
+            fs::read(restored_dir.join("tezos/context/store"))?,
+            b"context"
+        );
+        assert_eq!(
+            restored.config["tezos_data_dir"],
+            restored_dir.join("tezos").display().to_string()
+        );
+        assert_eq!(restored.wallets[0].alias, "bob");
+
+        saved_states.delete("state-1")?;
+        assert!(matches!(
+            saved_states.restore("state-1", &root.join("node-c")),
+            Err(SavedStateError::StateNotFound { .. })
+        ));
+        assert!(saved_states.list()?.is_empty());
+
+        fs::remove_dir_all(root)?;
+        Ok(())
+    }
+}
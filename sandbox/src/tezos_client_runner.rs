@@ -85,17 +85,11 @@ pub type SandboxWallets = Vec<Wallet>;
 /// Structure holding data used by tezos client
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Wallet {
-    alias: String,
-    public_key_hash: String,
-    public_key: String,
-    secret_key: String,
-    initial_balance: String,
-}
-
-/// The json body incoming with the bake request containing the alias for the wallet to bake with
-#[derive(Clone, Debug, Deserialize)]
-pub struct BakeRequest {
-    alias: String,
+    pub alias: String,
+    pub public_key_hash: String,
+    pub public_key: String,
+    pub secret_key: String,
+    pub initial_balance: String,
 }
 
 #[derive(Serialize)]
@@ -169,6 +163,37 @@ impl TezosClientRunner {
         );
     }
 
+    /// Initializes sandbox data of the node restored from saved state, the data dir already contains tezos-client data
+    pub fn restore_sandbox_data(
+        &mut self,
+        node_ref: NodeRpcIpPort,
+        data_dir_path: PathBuf,
+        wallets: SandboxWallets,
+    ) {
+        self.sandbox_data.insert(
+            node_ref,
+            SandboxData {
+                data_dir_path,
+                wallets: wallets
+                    .into_iter()
+                    .map(|wallet| (wallet.alias.clone(), wallet))
+                    .collect(),
+            },
+        );
+    }
+
+    /// Returns sandbox data (data dir and wallets) of the node
+    pub fn sandbox_data(
+        &self,
+        node_ref: &NodeRpcIpPort,
+    ) -> Result<&SandboxData, TezosClientRunnerError> {
+        self.sandbox_data.get(node_ref).ok_or_else(|| {
+            TezosClientRunnerError::SandboxDataDirNotInitialized {
+                node_ref: node_ref.clone(),
+            }
+        })
+    }
+
     pub fn wallets(
         &self,
         node_ref: &NodeRpcIpPort,
@@ -273,39 +298,25 @@ impl TezosClientRunner {
     }
 
     /// Bake a block with the bootstrap1 account
-    pub fn bake_block(
+    /// Returns wallet used for baking
+    pub fn wallet(
         &self,
-        request: Option<BakeRequest>,
         node_ref: &NodeRpcIpPort,
-    ) -> Result<TezosClientReply, TezosClientRunnerError> {
-        let mut client_output: TezosClientReply = Default::default();
-
-        let alias = if let Some(request) = request {
-            if let Some(wallet) = self.wallets(node_ref)?.get(&request.alias) {
-                &wallet.alias
-            } else {
-                return Err(TezosClientRunnerError::NonexistantWallet {
-                    alias: request.alias,
-                });
-            }
+        alias: Option<&str>,
+    ) -> Result<Wallet, TezosClientRunnerError> {
+        let wallets = self.wallets(node_ref)?;
+        let wallet = if let Some(alias) = alias {
+            wallets.get(alias)
         } else {
-            // if there is no wallet provided in the request (GET) set the alias to be an arbitrary wallet
-            if let Some(wallet) = self.wallets(node_ref)?.values().next() {
-                &wallet.alias
-            } else {
-                return Err(TezosClientRunnerError::NonexistantWallet {
-                    alias: "-none-".to_string(),
-                });
-            }
+            // if there is no wallet provided in the request (GET) use an arbitrary wallet
+            wallets.values().min_by(|a, b| a.alias.cmp(&b.alias))
         };
 
-        self.run_client(
-            node_ref,
-            ["bake", "for", &alias].to_vec(),
-            &mut client_output,
-        )?;
-
-        Ok(client_output)
+        wallet
+            .cloned()
+            .ok_or_else(|| TezosClientRunnerError::NonexistantWallet {
+                alias: alias.unwrap_or("-none-").to_string(),
+            })
     }
 
     /// Initialize the accounts in the tezos-client
@@ -22,8 +22,8 @@ use storage::context::{ContextApi, TezedgeContext};
 use storage::persistent::PersistentStorage;
 use storage::{
     initialize_storage_with_genesis_block, store_applied_block_result, store_commit_genesis_result,
-    BlockMetaStorage, BlockStorage, ChainMetaStorage, OperationsMetaStorage, StorageError,
-    StorageInitInfo,
+    BlockMetaStorage, BlockStorage, ChainMetaStorage, ContextHistoryIndex,
+    OperationReceiptsStorage, OperationsMetaStorage, StorageError, StorageInitInfo,
 };
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::ApplyBlockRequest;
@@ -35,6 +35,7 @@ use crate::chain_manager::{ChainManagerMsg, ChainManagerRef, ProcessValidatedBlo
 use crate::shell_channel::{ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
 use crate::stats::BlockValidationTimer;
 use crate::subscription::subscribe_to_shell_shutdown;
+use crate::test_chain::{update_test_chain, TestChainEvent, TestChainStatus};
 use crate::utils::{dispatch_condvar_result, CondvarResult};
 
 type SharedJoinHandle = Arc<Mutex<Option<JoinHandle<Result<(), Error>>>>>;
@@ -118,6 +119,8 @@ impl ChainFeeder {
         tezos_env: &TezosEnvironmentConfiguration,
         ipc_server: IpcCmdServer,
         log: Logger,
+        index_operation_receipts: bool,
+        index_context_history: bool,
     ) -> Result<ChainFeederRef, CreateError> {
         // spawn thread which processes event
         let (block_applier_event_sender, mut block_applier_event_receiver) = channel();
@@ -138,6 +141,16 @@ impl ChainFeeder {
                     block_storage.clone(),
                     persistent_storage.merkle(),
                 ));
+                let context_history_index = if index_context_history {
+                    Some(ContextHistoryIndex::new(&persistent_storage))
+                } else {
+                    None
+                };
+                let operation_receipts_storage = if index_operation_receipts {
+                    Some(OperationReceiptsStorage::new(&persistent_storage))
+                } else {
+                    None
+                };
                 let mut ipc_server = ipc_server;
 
                 while apply_block_run.load(Ordering::Acquire) {
@@ -152,6 +165,8 @@ impl ChainFeeder {
                             &chain_meta_storage,
                             &operations_meta_storage,
                             &context,
+                            context_history_index.as_ref(),
+                            operation_receipts_storage.as_ref(),
                             protocol_controller,
                             &mut block_applier_event_receiver,
                             &log,
@@ -322,6 +337,8 @@ fn feed_chain_to_protocol(
     chain_meta_storage: &ChainMetaStorage,
     operations_meta_storage: &OperationsMetaStorage,
     context: &Box<dyn ContextApi>,
+    context_history_index: Option<&ContextHistoryIndex>,
+    operation_receipts_storage: Option<&OperationReceiptsStorage>,
     protocol_controller: ProtocolController,
     block_applier_event_receiver: &mut QueueReceiver<Event>,
     log: &Logger,
@@ -442,17 +459,34 @@ fn feed_chain_to_protocol(
                                            "context_wait_elapsed" => format!("{:?}", &context_wait_elapsed));
                             }
 
+                            // test chain status is reported only by the blocks of the main chain
+                            let test_chain_status = if request.chain_id == *chain_id {
+                                match TestChainStatus::from_block_metadata_json(
+                                    &apply_block_result.block_header_proto_metadata_json,
+                                ) {
+                                    Ok(status) => status.map(|status| {
+                                        (status, apply_block_result.forking_testchain_data.clone())
+                                    }),
+                                    Err(e) => {
+                                        warn!(log, "Failed to resolve test chain status"; "block" => HashType::BlockHash.hash_to_b58check(&block_hash), "reason" => format!("{}", e));
+                                        None
+                                    }
+                                }
+                            } else {
+                                None
+                            };
+
                             // Lets mark header as applied and store result
                             // store success result
                             let store_result_timer = Instant::now();
-                            match store_applied_block_result(
+                            let block_json_data = match store_applied_block_result(
                                 block_storage,
                                 block_meta_storage,
                                 &block_hash,
                                 apply_block_result,
                                 &mut current_head_meta,
                             ) {
-                                Ok(_) => {
+                                Ok((block_json_data, _)) => {
                                     // now everythings stored, we are done
 
                                     // notify condvar
@@ -461,6 +495,7 @@ fn feed_chain_to_protocol(
                                     {
                                         warn!(log, "Failed to dispatch result to condvar"; "reason" => format!("{}", e));
                                     }
+                                    block_json_data
                                 }
                                 Err(e) => {
                                     if let Err(e) = dispatch_condvar_result(
@@ -480,6 +515,62 @@ fn feed_chain_to_protocol(
                             };
                             let store_result_elapsed = store_result_timer.elapsed();
 
+                            // index context changes for historical queries (if enabled), failure does not affect applying of blocks
+                            if let Some(context_history_index) = context_history_index {
+                                let history_index_timer = Instant::now();
+                                match context_history_index
+                                    .index_block(&request.block_header, &request.pred_header)
+                                {
+                                    Ok(true) => {
+                                        trace!(log, "Context history indexed"; "block_header_hash" => block_hash_encoding.hash_to_b58check(&block_hash), "elapsed" => format!("{:?}", history_index_timer.elapsed()))
+                                    }
+                                    Ok(false) => (),
+                                    Err(e) => {
+                                        warn!(log, "Failed to index context history"; "block" => HashType::BlockHash.hash_to_b58check(&block_hash), "reason" => format!("{}", e))
+                                    }
+                                }
+                            }
+
+                            // index operation receipts (if enabled), failure does not affect applying of blocks
+                            if let Some(operation_receipts_storage) = operation_receipts_storage {
+                                if let Err(e) = operation_receipts_storage.index_block(
+                                    &block_hash,
+                                    request.block_header.level(),
+                                    block_json_data.operations_proto_metadata_json(),
+                                ) {
+                                    warn!(log, "Failed to index operation receipts"; "block" => HashType::BlockHash.hash_to_b58check(&block_hash), "reason" => format!("{}", e))
+                                }
+                            }
+
+                            if let Some((status, forking_testchain_data)) = test_chain_status {
+                                match update_test_chain(
+                                    chain_meta_storage,
+                                    block_storage,
+                                    chain_id,
+                                    &request.block_header,
+                                    status,
+                                    forking_testchain_data.as_ref(),
+                                ) {
+                                    Ok(Some(TestChainEvent::Activated {
+                                        test_chain_id,
+                                        data,
+                                    })) => {
+                                        info!(log, "Test chain activated";
+                                                   "test_chain_id" => HashType::ChainId.hash_to_b58check(&test_chain_id),
+                                                   "protocol" => HashType::ProtocolHash.hash_to_b58check(&data.protocol_hash),
+                                                   "genesis" => HashType::BlockHash.hash_to_b58check(&data.genesis),
+                                                   "expiration" => data.expiration);
+                                    }
+                                    Ok(Some(TestChainEvent::Stopped { test_chain_id })) => {
+                                        info!(log, "Test chain stopped"; "test_chain_id" => HashType::ChainId.hash_to_b58check(&test_chain_id));
+                                    }
+                                    Ok(None) => (),
+                                    Err(e) => {
+                                        warn!(log, "Failed to update test chain"; "block" => HashType::BlockHash.hash_to_b58check(&block_hash), "reason" => format!("{}", e));
+                                    }
+                                }
+                            }
+
                             // notify chain_manager, which sent request
                             if apply_block_run.load(Ordering::Acquire) {
                                 chain_manager.tell(
@@ -47,6 +47,7 @@ use tezos_wrapper::TezosApiConnectionPool;
 use crate::chain_feeder::{ApplyBlock, ChainFeederRef};
 use crate::mempool::mempool_state::MempoolState;
 use crate::mempool::CurrentMempoolStateStorageRef;
+use crate::sandbox_clock;
 use crate::shell_channel::{
     AllBlockOperationsReceived, BlockReceived, InjectBlock, MempoolOperationReceived,
     ShellChannelMsg, ShellChannelRef, ShellChannelTopic,
@@ -355,7 +356,7 @@ impl ChainManager {
 
     /// The `ChainManager` is intended to serve as a singleton actor so that's why
     /// we won't support multiple names per instance.
-    fn name() -> &'static str {
+    pub fn name() -> &'static str {
         "chain-manager"
     }
 
@@ -377,7 +378,7 @@ impl ChainManager {
                     .drain(0..num_opts_to_get)
                     .collect::<Vec<_>>();
 
-                let ttl = SystemTime::now() + MEMPOOL_OPERATION_TTL;
+                let ttl = sandbox_clock::system_now() + MEMPOOL_OPERATION_TTL;
                 ops_to_enqueue
                     .iter()
                     .cloned()
@@ -2419,6 +2420,8 @@ pub mod tests {
             Arc::new(NetworkVersion::new("testet".to_string(), 0, 0)),
             tokio_runtime.handle().clone(),
             &socket_address,
+            None,
+            None,
         )
         .unwrap();
 
@@ -11,8 +11,10 @@ pub mod chain_manager;
 pub mod context_listener;
 pub mod mempool;
 pub mod peer_manager;
+pub mod sandbox_clock;
 pub mod shell_channel;
 pub mod stats;
+pub mod test_chain;
 pub mod utils;
 pub mod validation;
 
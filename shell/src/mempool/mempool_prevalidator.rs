@@ -441,7 +441,7 @@ fn begin_construction(
     block_header: Arc<BlockHeader>,
     log: &Logger,
 ) -> Result<(Option<PrevalidatorWrapper>, Option<BlockHash>), PrevalidationError> {
-    // try to begin construction
+    // try to begin construction (protocol uses the wall clock of the protocol runner, not the sandbox node clock)
     let result = match api.begin_construction(BeginConstructionRequest {
         chain_id: chain_id.clone(),
         predecessor: (&*block_header).clone(),
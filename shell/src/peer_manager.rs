@@ -20,10 +20,13 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Handle;
 use tokio::time::timeout;
 
+use networking::p2p::bandwidth::{BandwidthConfig, BandwidthThrottleRef};
 use networking::p2p::network_channel::{
     NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapFailed, PeerCreated,
 };
 use networking::p2p::peer::{Bootstrap, Peer, PeerRef, SendMessage};
+use networking::p2p::recorder::{MessageRecorder, MessageRecorderConfig, MessageRecorderRef};
+use networking::p2p::replay::ReplayConfig;
 use networking::PeerId;
 use tezos_identity::Identity;
 use tezos_messages::p2p::encoding::prelude::*;
@@ -40,6 +43,10 @@ const WHITELIST_INTERVAL: Duration = Duration::from_secs(1_800);
 const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
 /// Limit how often we allow to trigger check of a peer count
 const CHECK_PEER_COUNT_LIMIT: Duration = Duration::from_secs(5);
+/// Max count of incoming connections over the peer limit, which are being rejected (with NACK) at the same time
+const MAX_REJECTED_PEERS: usize = 10;
+/// Max count of potential peers sent to the rejected peer
+const MAX_NACK_POTENTIAL_PEERS: usize = 50;
 
 /// Check peer threshold
 /// Received message instructs this actor to check whether number of connected peers is within desired bounds
@@ -69,6 +76,8 @@ pub struct P2p {
     pub listener_port: u16,
     pub disable_mempool: bool,
     pub private_node: bool,
+    /// Expected proof of work of the remote peer identity
+    pub expected_pow: f64,
 
     pub peer_threshold: PeerConnectionThreshold,
 
@@ -79,6 +88,13 @@ pub struct P2p {
 
     /// Peers (IP:port) which we try to connect all the time
     pub bootstrap_peers: Vec<SocketAddr>,
+
+    /// Records all p2p messages exchanged with peers, if set
+    pub message_recorder: Option<MessageRecorderConfig>,
+    /// Replays recorded p2p messages instead of connecting to network, if set
+    pub replay: Option<ReplayConfig>,
+    /// Bandwidth limits of the peer connections
+    pub bandwidth: BandwidthConfig,
 }
 
 impl P2p {
@@ -109,6 +125,8 @@ pub struct PeerManager {
     threshold: PeerConnectionThreshold,
     /// Map of all peers
     peers: HashMap<ActorUri, PeerState>,
+    /// Peers over the peer limit, which are being rejected
+    rejected_peers: HashSet<ActorUri>,
 
     /// Bootstrap peer, which we try to connect all the the, if no other peers presents
     bootstrap_addresses: HashSet<(String, u16)>,
@@ -117,6 +135,8 @@ pub struct PeerManager {
     disable_mempool: bool,
     /// Indicates that p2p is working in private mode
     private_node: bool,
+    /// Expected proof of work of the remote peer identity
+    expected_pow: f64,
     /// List of potential peers to connect to
     potential_peers: HashSet<SocketAddr>,
     /// Tokio runtime
@@ -138,12 +158,19 @@ pub struct PeerManager {
     check_peer_count_last: Option<Instant>,
     /// Indicates that system is shutting down
     shutting_down: bool,
+    /// Recorder configuration, recorder is opened on actor start
+    message_recorder_config: Option<MessageRecorderConfig>,
+    /// Recorder shared by all peers
+    message_recorder: Option<MessageRecorderRef>,
+    /// Bandwidth limits shared by all peers
+    bandwidth: BandwidthThrottleRef,
 }
 
 /// Reference to [peer manager](PeerManager) actor.
 pub type PeerManagerRef = ActorRef<PeerManagerMsg>;
 
 impl PeerManager {
+    #[allow(clippy::too_many_arguments)]
     pub fn actor(
         sys: &impl ActorRefFactory,
         network_channel: NetworkChannelRef,
@@ -152,6 +179,7 @@ impl PeerManager {
         identity: Arc<Identity>,
         network_version: Arc<NetworkVersion>,
         p2p_config: P2p,
+        bandwidth: BandwidthThrottleRef,
     ) -> Result<PeerManagerRef, CreateError> {
         sys.actor_of_props::<PeerManager>(
             PeerManager::name(),
@@ -162,6 +190,7 @@ impl PeerManager {
                 identity,
                 network_version,
                 p2p_config,
+                bandwidth,
             )),
         )
     }
@@ -223,23 +252,20 @@ impl PeerManager {
     }
 
     /// Create new peer actor
-    fn create_peer(&mut self, sys: &impl ActorRefFactory, socket_address: &SocketAddr) -> PeerRef {
-        let peer = Peer::actor(
-            sys,
-            self.network_channel.clone(),
-            self.listener_port,
-            self.identity.clone(),
-            self.network_version.clone(),
-            self.tokio_executor.clone(),
-            socket_address,
-        )
-        .unwrap();
+    fn create_peer(
+        &mut self,
+        sys: &impl ActorRefFactory,
+        socket_address: &SocketAddr,
+        incoming: bool,
+    ) -> PeerRef {
+        let peer = self.spawn_peer(sys, socket_address);
 
         self.peers.insert(
             peer.uri().clone(),
             PeerState {
                 peer_ref: peer.clone(),
                 address: *socket_address,
+                incoming,
             },
         );
 
@@ -257,6 +283,42 @@ impl PeerManager {
         peer
     }
 
+    /// Create new peer actor, which is not counted as connected peer
+    fn spawn_peer(&self, sys: &impl ActorRefFactory, socket_address: &SocketAddr) -> PeerRef {
+        Peer::actor(
+            sys,
+            self.network_channel.clone(),
+            self.listener_port,
+            self.identity.clone(),
+            self.network_version.clone(),
+            self.tokio_executor.clone(),
+            socket_address,
+            self.message_recorder.clone(),
+            Some(self.bandwidth.clone()),
+        )
+        .unwrap()
+    }
+
+    /// Random sample of connected and potential peers (IPv4 and IPv6), which can be sent to the rejected peer.
+    ///
+    /// Incoming peers are not included, their address has the ephemeral port of the remote, not its listening port.
+    fn potential_peers_sample(&self, rejected_address: &SocketAddr) -> Vec<SocketAddr> {
+        let mut addresses = self
+            .peers
+            .values()
+            .filter(|peer_state| !peer_state.incoming)
+            .map(|peer_state| peer_state.address)
+            .chain(self.potential_peers.iter().cloned())
+            .filter(|address| address.ip() != rejected_address.ip())
+            .filter(|address| !self.is_blacklisted(&address.ip()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        addresses.shuffle(&mut rand::thread_rng());
+        addresses.truncate(MAX_NACK_POTENTIAL_PEERS);
+        addresses
+    }
+
     /// Check if given ip address is blacklisted to connect to
     fn is_blacklisted(&self, ip_address: &IpAddr) -> bool {
         self.ip_blacklist.contains(ip_address)
@@ -350,16 +412,26 @@ impl
         Arc<Identity>,
         Arc<NetworkVersion>,
         P2p,
+        BandwidthThrottleRef,
     )> for PeerManager
 {
     fn create_args(
-        (network_channel, shell_channel, tokio_executor, identity, network_version, p2p_config): (
+        (
+            network_channel,
+            shell_channel,
+            tokio_executor,
+            identity,
+            network_version,
+            p2p_config,
+            bandwidth,
+        ): (
             NetworkChannelRef,
             ShellChannelRef,
             Handle,
             Arc<Identity>,
             Arc<NetworkVersion>,
             P2p,
+            BandwidthThrottleRef,
         ),
     ) -> Self {
         // resolve all bootstrap addresses
@@ -387,13 +459,18 @@ impl
             network_version,
             disable_mempool: p2p_config.disable_mempool,
             private_node: p2p_config.private_node,
+            expected_pow: p2p_config.expected_pow,
             rx_run: Arc::new(AtomicBool::new(true)),
             potential_peers: HashSet::new(),
             peers: HashMap::new(),
+            rejected_peers: HashSet::new(),
             ip_blacklist: HashSet::new(),
             discovery_last: None,
             check_peer_count_last: None,
             shutting_down: false,
+            message_recorder_config: p2p_config.message_recorder,
+            message_recorder: None,
+            bandwidth,
         }
     }
 }
@@ -407,6 +484,21 @@ impl Actor for PeerManager {
         subscribe_to_dead_letters(ctx.system.dead_letters(), ctx.myself());
         subscribe_to_network_commands(&self.network_channel, ctx.myself());
 
+        if let Some(config) = self.message_recorder_config.as_ref() {
+            // writer thread stops, when all peers and peer manager drop the recorder
+            match MessageRecorder::open(config.clone())
+                .and_then(|recorder| recorder.spawn(ctx.system.log()))
+            {
+                Ok((recorder, _)) => {
+                    info!(ctx.system.log(), "Recording p2p messages"; "dir" => config.dir.display().to_string());
+                    self.message_recorder = Some(recorder);
+                }
+                Err(e) => {
+                    warn!(ctx.system.log(), "Failed to open p2p message recorder, messages will not be recorded"; "dir" => config.dir.display().to_string(), "reason" => format!("{}", e))
+                }
+            }
+        }
+
         ctx.schedule::<Self::Msg, _>(
             Duration::from_secs(10),
             Duration::from_secs(15),
@@ -467,6 +559,7 @@ impl Receive<DeadLetter> for PeerManager {
         msg: DeadLetter,
         _sender: Option<BasicActorRef>,
     ) {
+        self.rejected_peers.remove(msg.recipient.uri());
         if self.peers.remove(msg.recipient.uri()).is_some() {
             if self.shutting_down {
                 return;
@@ -497,6 +590,7 @@ impl Receive<SystemEvent> for PeerManager {
         _sender: Option<BasicActorRef>,
     ) {
         if let SystemEvent::ActorTerminated(evt) = msg {
+            self.rejected_peers.remove(evt.actor.uri());
             if self.peers.remove(evt.actor.uri()).is_some() {
                 if self.shutting_down {
                     return;
@@ -590,17 +684,27 @@ impl Receive<ConnectToPeer> for PeerManager {
         if self.is_blacklisted(&msg.address.ip()) {
             debug!(ctx.system.log(), "Peer is blacklisted - will not connect"; "ip" => format!("{}", msg.address.ip()));
         } else {
-            let peer = self.create_peer(ctx, &msg.address);
+            let peer = self.create_peer(ctx, &msg.address, false);
             let system = ctx.system.clone();
             let disable_mempool = self.disable_mempool;
             let private_node = self.private_node;
+            let expected_pow = self.expected_pow;
 
             self.tokio_executor.spawn(async move {
                 debug!(system.log(), "Connecting to IP"; "ip" => msg.address, "peer" => peer.name(), "peer_uri" => peer.uri().to_string());
                 match timeout(CONNECT_TIMEOUT, TcpStream::connect(&msg.address)).await {
                     Ok(Ok(stream)) => {
                         info!(system.log(), "Connection successful"; "ip" => msg.address, "peer" => peer.name(), "peer_uri" => peer.uri().to_string());
-                        peer.tell(Bootstrap::outgoing(stream, msg.address, disable_mempool, private_node), None);
+                        peer.tell(
+                            Bootstrap::outgoing(
+                                stream,
+                                msg.address,
+                                disable_mempool,
+                                private_node,
+                                expected_pow,
+                            ),
+                            None,
+                        );
                     }
                     Ok(Err(e)) => {
                         info!(system.log(), "Connection failed"; "ip" => msg.address, "peer" => peer.name(), "peer_uri" => peer.uri().to_string(), "reason" => format!("{:?}", e));
@@ -624,13 +728,30 @@ impl Receive<AcceptPeer> for PeerManager {
             warn!(ctx.system.log(), "Peer is blacklisted - will not accept connection"; "ip" => format!("{}", msg.address.ip()));
         } else if self.peers.len() < self.threshold.high {
             debug!(ctx.system.log(), "Connection from"; "ip" => msg.address);
-            let peer = self.create_peer(ctx, &msg.address);
+            let peer = self.create_peer(ctx, &msg.address, true);
             peer.tell(
                 Bootstrap::incoming(
                     msg.stream,
                     msg.address,
                     self.disable_mempool,
                     self.private_node,
+                    self.expected_pow,
+                ),
+                None,
+            );
+        } else if self.rejected_peers.len() < MAX_REJECTED_PEERS {
+            debug!(ctx.system.log(), "Peer limit was reached, incoming peer connection will be rejected with potential peers"; "ip" => msg.address);
+            let potential_peers = self.potential_peers_sample(&msg.address);
+            let peer = self.spawn_peer(ctx, &msg.address);
+            self.rejected_peers.insert(peer.uri().clone());
+            peer.tell(
+                Bootstrap::incoming_rejected(
+                    msg.stream,
+                    msg.address,
+                    self.disable_mempool,
+                    self.private_node,
+                    self.expected_pow,
+                    potential_peers,
                 ),
                 None,
             );
@@ -731,4 +852,6 @@ struct PeerState {
     peer_ref: PeerRef,
     /// Peer IP address
     address: SocketAddr,
+    /// Connection was opened by the remote peer (address port is not its listening port)
+    incoming: bool,
 }
@@ -0,0 +1,233 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Adjustable node clock used by the shell instead of the wall clock.
+//!
+//! On a regular node the clock is never touched and always returns the wall-clock time.
+//! Sandbox nodes expose it through rpc (see `/dev/sandbox/clock`), so test suites can shift (offset)
+//! or stop (freeze) the time seen by block validation, mempool and block injection,
+//! e.g. to bake through whole cycles in seconds.
+//!
+//! The clock does not reach the protocol: prevalidator of the mempool is created by the protocol runner
+//! (`begin_construction`) with its own wall-clock timestamp, because the ffi request carries no timestamp.
+
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    /// Process-wide clock shared by all shell components
+    static ref NODE_CLOCK: SandboxClock = SandboxClock::default();
+}
+
+/// Returns current time according to the node clock
+pub fn now() -> DateTime<Utc> {
+    NODE_CLOCK.now()
+}
+
+/// Returns current time according to the node clock as [`SystemTime`]
+pub fn system_now() -> SystemTime {
+    NODE_CLOCK.system_now()
+}
+
+/// Returns the process-wide node clock
+pub fn node_clock() -> &'static SandboxClock {
+    &NODE_CLOCK
+}
+
+#[derive(Clone, Debug, Default)]
+struct ClockSettings {
+    /// Seconds added to the wall-clock time
+    offset_secs: i64,
+    /// Unix timestamp (secs), if clock is frozen
+    frozen_at: Option<i64>,
+}
+
+/// Current state of the clock, as reported by rpc
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SandboxClockState {
+    /// Current node time (RFC3339)
+    pub now: String,
+    /// Current node time as unix timestamp (secs)
+    pub timestamp: i64,
+    /// Offset against the wall clock (secs)
+    pub offset_secs: i64,
+    pub frozen: bool,
+}
+
+/// Requested change of the clock, fields are applied in the declared order
+#[derive(Deserialize, Debug, Default)]
+pub struct SandboxClockUpdate {
+    /// Returns clock back to the wall-clock time
+    #[serde(default)]
+    pub reset: bool,
+    /// Sets offset against the wall clock (secs)
+    pub offset_secs: Option<i64>,
+    /// Moves clock by (secs), can be negative
+    pub advance_secs: Option<i64>,
+    /// Stops (true) or lets run (false) the clock
+    pub freeze: Option<bool>,
+    /// Stops the clock at unix timestamp (secs)
+    pub frozen_at: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct SandboxClock {
+    settings: RwLock<ClockSettings>,
+}
+
+impl SandboxClock {
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc.timestamp(self.timestamp(), 0)
+    }
+
+    pub fn system_now(&self) -> SystemTime {
+        let timestamp = self.timestamp();
+        if timestamp >= 0 {
+            UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs((-timestamp) as u64)
+        }
+    }
+
+    /// Current unix timestamp (secs) according to the clock
+    pub fn timestamp(&self) -> i64 {
+        let settings = self.settings.read().unwrap();
+        match settings.frozen_at {
+            Some(frozen_at) => frozen_at,
+            None => Utc::now().timestamp() + settings.offset_secs,
+        }
+    }
+
+    pub fn state(&self) -> SandboxClockState {
+        let settings = self.settings.read().unwrap().clone();
+        let timestamp = match settings.frozen_at {
+            Some(frozen_at) => frozen_at,
+            None => Utc::now().timestamp() + settings.offset_secs,
+        };
+        SandboxClockState {
+            now: Utc.timestamp(timestamp, 0).to_rfc3339(),
+            timestamp,
+            offset_secs: match settings.frozen_at {
+                Some(frozen_at) => frozen_at - Utc::now().timestamp(),
+                None => settings.offset_secs,
+            },
+            frozen: settings.frozen_at.is_some(),
+        }
+    }
+
+    /// Sets offset against the wall clock, frozen clock is moved to the new time and stays frozen
+    pub fn set_offset(&self, offset_secs: i64) {
+        let mut settings = self.settings.write().unwrap();
+        settings.offset_secs = offset_secs;
+        if settings.frozen_at.is_some() {
+            settings.frozen_at = Some(Utc::now().timestamp() + offset_secs);
+        }
+    }
+
+    /// Moves the clock by `secs` (can be negative), works also for frozen clock
+    pub fn advance(&self, secs: i64) {
+        let mut settings = self.settings.write().unwrap();
+        settings.offset_secs += secs;
+        if let Some(frozen_at) = settings.frozen_at.as_mut() {
+            *frozen_at += secs;
+        }
+    }
+
+    /// Stops the clock at `timestamp` (secs), or at the current clock time if not set
+    pub fn freeze(&self, timestamp: Option<i64>) {
+        let mut settings = self.settings.write().unwrap();
+        let timestamp = timestamp.unwrap_or_else(|| match settings.frozen_at {
+            Some(frozen_at) => frozen_at,
+            None => Utc::now().timestamp() + settings.offset_secs,
+        });
+        settings.frozen_at = Some(timestamp);
+    }
+
+    /// Lets the clock run again, continuing from the frozen time
+    pub fn unfreeze(&self) {
+        let mut settings = self.settings.write().unwrap();
+        if let Some(frozen_at) = settings.frozen_at.take() {
+            settings.offset_secs = frozen_at - Utc::now().timestamp();
+        }
+    }
+
+    /// Returns clock back to the wall-clock time
+    pub fn reset(&self) {
+        *self.settings.write().unwrap() = ClockSettings::default();
+    }
+
+    /// Applies all requested changes, returns the new state of the clock
+    pub fn update(&self, update: &SandboxClockUpdate) -> SandboxClockState {
+        if update.reset {
+            self.reset();
+        }
+        if let Some(offset_secs) = update.offset_secs {
+            self.set_offset(offset_secs);
+        }
+        if let Some(advance_secs) = update.advance_secs {
+            self.advance(advance_secs);
+        }
+        match (update.freeze, update.frozen_at) {
+            (Some(false), _) => self.unfreeze(),
+            (_, Some(frozen_at)) => self.freeze(Some(frozen_at)),
+            (Some(true), None) => self.freeze(None),
+            (None, None) => (),
+        }
+        self.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_and_advance() {
+        let clock = SandboxClock::default();
+        assert!((clock.timestamp() - Utc::now().timestamp()).abs() <= 1);
+
+        clock.set_offset(3600);
+        assert!((clock.timestamp() - Utc::now().timestamp() - 3600).abs() <= 1);
+
+        clock.advance(-600);
+        assert!((clock.timestamp() - Utc::now().timestamp() - 3000).abs() <= 1);
+        assert_eq!(3000, clock.state().offset_secs);
+        assert!(!clock.state().frozen);
+
+        clock.reset();
+        assert!((clock.timestamp() - Utc::now().timestamp()).abs() <= 1);
+    }
+
+    #[test]
+    fn test_freeze_and_unfreeze() {
+        let clock = SandboxClock::default();
+        let timestamp = Utc::now().timestamp() + 86400;
+
+        clock.freeze(Some(timestamp));
+        assert_eq!(timestamp, clock.timestamp());
+        assert_eq!(Utc.timestamp(timestamp, 0), clock.now());
+        assert!(clock.state().frozen);
+
+        clock.advance(60);
+        assert_eq!(timestamp + 60, clock.timestamp());
+
+        // freeze without timestamp keeps the frozen time
+        clock.freeze(None);
+        assert_eq!(timestamp + 60, clock.timestamp());
+
+        // unfreeze continues from frozen time
+        clock.unfreeze();
+        assert!(!clock.state().frozen);
+        assert!((clock.timestamp() - timestamp - 60).abs() <= 1);
+
+        clock.freeze(Some(timestamp));
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(timestamp as u64),
+            clock.system_now()
+        );
+    }
+}
@@ -0,0 +1,534 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Test chain metadata tracking.
+//!
+//! The protocol reports the test chain status (`test_chain_status`) in the metadata of every applied block of the main chain.
+//! When the test chain is forked, its chain_id, protocol, expiration and genesis are stored in [`ChainMetaStorage`]
+//! (listed by `/monitor/active_chains`), when it is stopped (`not_running`) or expired, all its metadata are removed.
+//!
+//! This is not a test chain support - there is no chain state for the test chain, its blocks are neither synchronized
+//! nor applied and RPCs of the test chain are rejected.
+
+use chrono::DateTime;
+use failure::Fail;
+use serde::Deserialize;
+
+use crypto::blake2b;
+use crypto::hash::{BlockHash, ChainId, HashType, ProtocolHash};
+use storage::chain_meta_storage::{ChainMetaStorage, TestChainData};
+use storage::{BlockStorage, BlockStorageReader, StorageError};
+use tezos_api::ffi::ForkingTestchainData;
+use tezos_messages::p2p::encoding::block_header::BlockHeader;
+use tezos_messages::Head;
+
+/// Max count of the main chain blocks searched for the forking block of the already running test chain
+const MAX_FORKING_BLOCK_SEARCH: usize = 16_384;
+
+#[derive(Debug, Fail)]
+pub enum TestChainError {
+    #[fail(
+        display = "Invalid test chain status in block metadata, reason: {}",
+        reason
+    )]
+    InvalidStatus { reason: String },
+    #[fail(display = "Storage error, reason: {}", error)]
+    StorageError { error: StorageError },
+}
+
+impl From<StorageError> for TestChainError {
+    fn from(error: StorageError) -> Self {
+        TestChainError::StorageError { error }
+    }
+}
+
+/// Status of the test chain as reported in the block header metadata
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestChainStatus {
+    NotRunning,
+    Forking {
+        protocol_hash: ProtocolHash,
+        expiration: i64,
+    },
+    Running {
+        chain_id: ChainId,
+        genesis: BlockHash,
+        protocol_hash: ProtocolHash,
+        expiration: i64,
+    },
+}
+
+#[derive(Deserialize)]
+struct BlockMetadataJson {
+    test_chain_status: Option<TestChainStatusJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum TestChainStatusJson {
+    NotRunning,
+    Forking {
+        protocol: String,
+        expiration: String,
+    },
+    Running {
+        chain_id: String,
+        genesis: String,
+        protocol: String,
+        expiration: String,
+    },
+}
+
+impl TestChainStatus {
+    /// Parses `test_chain_status` from the block header metadata json, returns None if the metadata does not contain it
+    pub fn from_block_metadata_json(json: &str) -> Result<Option<Self>, TestChainError> {
+        let metadata: BlockMetadataJson =
+            serde_json::from_str(json).map_err(|e| TestChainError::InvalidStatus {
+                reason: format!("{}", e),
+            })?;
+
+        Ok(match metadata.test_chain_status {
+            None => None,
+            Some(TestChainStatusJson::NotRunning) => Some(TestChainStatus::NotRunning),
+            Some(TestChainStatusJson::Forking {
+                protocol,
+                expiration,
+            }) => Some(TestChainStatus::Forking {
+                protocol_hash: parse_hash(HashType::ProtocolHash, &protocol)?,
+                expiration: parse_timestamp(&expiration)?,
+            }),
+            Some(TestChainStatusJson::Running {
+                chain_id,
+                genesis,
+                protocol,
+                expiration,
+            }) => Some(TestChainStatus::Running {
+                chain_id: parse_hash(HashType::ChainId, &chain_id)?,
+                genesis: parse_hash(HashType::BlockHash, &genesis)?,
+                protocol_hash: parse_hash(HashType::ProtocolHash, &protocol)?,
+                expiration: parse_timestamp(&expiration)?,
+            }),
+        })
+    }
+}
+
+/// Change of the test chain caused by the applied block
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestChainEvent {
+    Activated {
+        test_chain_id: ChainId,
+        data: TestChainData,
+    },
+    Stopped {
+        test_chain_id: ChainId,
+    },
+}
+
+/// Genesis block of the test chain forked at `forking_block_hash` (the same as `Context.compute_testchain_genesis` in OCaml)
+pub fn compute_test_chain_genesis(forking_block_hash: &BlockHash) -> BlockHash {
+    blake2b::digest_256(forking_block_hash)
+}
+
+/// Updates test chain metadata of the main chain according to the status reported by the applied block
+pub fn update_test_chain(
+    chain_meta_storage: &ChainMetaStorage,
+    block_storage: &BlockStorage,
+    main_chain_id: &ChainId,
+    block_header: &BlockHeader,
+    status: TestChainStatus,
+    forking_testchain_data: Option<&ForkingTestchainData>,
+) -> Result<Option<TestChainEvent>, TestChainError> {
+    let current_test_chain_id = chain_meta_storage.get_test_chain_id(main_chain_id)?;
+
+    let (test_chain_id, data, genesis_level) = match status {
+        TestChainStatus::NotRunning => {
+            return match current_test_chain_id {
+                Some(test_chain_id) => {
+                    stop_test_chain(chain_meta_storage, main_chain_id, test_chain_id)
+                }
+                None => Ok(None),
+            };
+        }
+        TestChainStatus::Forking {
+            protocol_hash,
+            expiration,
+        } => match forking_testchain_data {
+            // test chain is forked from this block
+            Some(forking) => (
+                forking.test_chain_id.clone(),
+                TestChainData {
+                    protocol_hash,
+                    genesis: compute_test_chain_genesis(&forking.forking_block_hash),
+                    expiration,
+                },
+                Some(block_header.level()),
+            ),
+            None => return Ok(None),
+        },
+        TestChainStatus::Running {
+            chain_id,
+            genesis,
+            protocol_hash,
+            expiration,
+        } => {
+            // test chain was forked before (e.g. node started after the fork), genesis level is the level of the forking block
+            let genesis_level = if current_test_chain_id.as_ref() != Some(&chain_id)
+                && block_header.timestamp() < expiration
+            {
+                find_forking_block_level(block_storage, block_header, &genesis)?
+            } else {
+                None
+            };
+            (
+                chain_id,
+                TestChainData {
+                    protocol_hash,
+                    genesis,
+                    expiration,
+                },
+                genesis_level,
+            )
+        }
+    };
+
+    // expired test chain is stopped, even if the protocol still reports it
+    if block_header.timestamp() >= data.expiration {
+        return match current_test_chain_id {
+            Some(test_chain_id) => {
+                stop_test_chain(chain_meta_storage, main_chain_id, test_chain_id)
+            }
+            None => Ok(None),
+        };
+    }
+
+    match current_test_chain_id {
+        Some(current_test_chain_id) if current_test_chain_id == test_chain_id => Ok(None),
+        current_test_chain_id => {
+            // a new test chain replaces the old one
+            if let Some(current_test_chain_id) = current_test_chain_id {
+                chain_meta_storage.remove_test_chain(main_chain_id, &current_test_chain_id)?;
+            }
+
+            chain_meta_storage.set_test_chain_id(main_chain_id, &test_chain_id)?;
+            chain_meta_storage.set_test_chain_data(&test_chain_id, &data)?;
+            // genesis block is not stored, so it is not set as the current head
+            if let Some(genesis_level) = genesis_level {
+                chain_meta_storage.set_genesis(
+                    &test_chain_id,
+                    Head::new(data.genesis.clone(), genesis_level, vec![]),
+                )?;
+            }
+            Ok(Some(TestChainEvent::Activated {
+                test_chain_id,
+                data,
+            }))
+        }
+    }
+}
+
+/// Walks back the main chain from the block to the forking block, whose hash is the base of the test chain genesis.
+/// Returns None, if the forking block is not stored (or too far).
+fn find_forking_block_level(
+    block_storage: &BlockStorage,
+    block_header: &BlockHeader,
+    genesis: &BlockHash,
+) -> Result<Option<i32>, TestChainError> {
+    let mut block_hash = block_header.predecessor().clone();
+    for _ in 0..MAX_FORKING_BLOCK_SEARCH {
+        let block = match block_storage.get(&block_hash)? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        if &compute_test_chain_genesis(&block.hash) == genesis {
+            return Ok(Some(block.header.level()));
+        }
+        if block.header.level() <= 0 {
+            return Ok(None);
+        }
+        block_hash = block.header.predecessor().clone();
+    }
+    Ok(None)
+}
+
+fn stop_test_chain(
+    chain_meta_storage: &ChainMetaStorage,
+    main_chain_id: &ChainId,
+    test_chain_id: ChainId,
+) -> Result<Option<TestChainEvent>, TestChainError> {
+    chain_meta_storage.remove_test_chain(main_chain_id, &test_chain_id)?;
+    Ok(Some(TestChainEvent::Stopped { test_chain_id }))
+}
+
+fn parse_hash(hash_type: HashType, value: &str) -> Result<Vec<u8>, TestChainError> {
+    hash_type
+        .b58check_to_hash(value)
+        .map_err(|e| TestChainError::InvalidStatus {
+            reason: format!("invalid hash: {}, reason: {}", value, e),
+        })
+}
+
+fn parse_timestamp(value: &str) -> Result<i64, TestChainError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.timestamp())
+        .map_err(|e| TestChainError::InvalidStatus {
+            reason: format!("invalid timestamp: {}, reason: {}", value, e),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use storage::tests_common::TmpStorage;
+    use storage::BlockHeaderWithHash;
+    use tezos_messages::p2p::encoding::block_header::BlockHeaderBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_test_chain_status() -> Result<(), failure::Error> {
+        assert_eq!(
+            None,
+            TestChainStatus::from_block_metadata_json(
+                r#"{"protocol":"PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb"}"#
+            )?
+        );
+        assert_eq!(
+            Some(TestChainStatus::NotRunning),
+            TestChainStatus::from_block_metadata_json(
+                r#"{"test_chain_status":{"status":"not_running"},"max_operations_ttl":60}"#
+            )?
+        );
+        assert_eq!(
+            Some(TestChainStatus::Forking {
+                protocol_hash: HashType::ProtocolHash
+                    .b58check_to_hash("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?,
+                expiration: 1_577_836_800,
+            }),
+            TestChainStatus::from_block_metadata_json(
+                r#"{"test_chain_status":{"status":"forking","protocol":"PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb","expiration":"2020-01-01T00:00:00Z"}}"#
+            )?
+        );
+        assert_eq!(
+            Some(TestChainStatus::Running {
+                chain_id: HashType::ChainId.b58check_to_hash("NetXjD3HPJJjmcd")?,
+                genesis: HashType::BlockHash
+                    .b58check_to_hash("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+                protocol_hash: HashType::ProtocolHash
+                    .b58check_to_hash("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?,
+                expiration: 1_577_836_800,
+            }),
+            TestChainStatus::from_block_metadata_json(
+                r#"{"test_chain_status":{"status":"running","chain_id":"NetXjD3HPJJjmcd","genesis":"BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe","protocol":"PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb","expiration":"2020-01-01T00:00:00Z"}}"#
+            )?
+        );
+        assert!(TestChainStatus::from_block_metadata_json(
+            r#"{"test_chain_status":{"status":"running","chain_id":"invalid"}}"#
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_test_chain() -> Result<(), failure::Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__test_update_test_chain")?;
+        let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+
+        let main_chain_id = HashType::ChainId.b58check_to_hash("NetXgtSLGNJvNye")?;
+        let test_chain_id = HashType::ChainId.b58check_to_hash("NetXjD3HPJJjmcd")?;
+        let forking_block_hash = HashType::BlockHash
+            .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+        let protocol_hash = HashType::ProtocolHash
+            .b58check_to_hash("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?;
+        let forking = ForkingTestchainData {
+            forking_block_hash: forking_block_hash.clone(),
+            test_chain_id: test_chain_id.clone(),
+        };
+
+        // test chain is forked
+        let event = update_test_chain(
+            &chain_meta_storage,
+            &block_storage,
+            &main_chain_id,
+            &block_header(100, 1_000, forking_block_hash.clone())?,
+            TestChainStatus::Forking {
+                protocol_hash: protocol_hash.clone(),
+                expiration: 2_000,
+            },
+            Some(&forking),
+        )?;
+        let genesis = compute_test_chain_genesis(&forking_block_hash);
+        assert_eq!(
+            Some(TestChainEvent::Activated {
+                test_chain_id: test_chain_id.clone(),
+                data: TestChainData {
+                    protocol_hash: protocol_hash.clone(),
+                    genesis: genesis.clone(),
+                    expiration: 2_000,
+                },
+            }),
+            event
+        );
+        assert_eq!(
+            Some(test_chain_id.clone()),
+            chain_meta_storage.get_test_chain_id(&main_chain_id)?
+        );
+        let genesis_head = chain_meta_storage.get_genesis(&test_chain_id)?.unwrap();
+        assert_eq!(&genesis, genesis_head.block_hash());
+        assert_eq!(100, *genesis_head.level());
+        // only metadata are tracked, the test chain has no head
+        assert!(chain_meta_storage
+            .get_current_head(&test_chain_id)?
+            .is_none());
+
+        // test chain is running, nothing changes
+        let running = TestChainStatus::Running {
+            chain_id: test_chain_id.clone(),
+            genesis,
+            protocol_hash,
+            expiration: 2_000,
+        };
+        assert_eq!(
+            None,
+            update_test_chain(
+                &chain_meta_storage,
+                &block_storage,
+                &main_chain_id,
+                &block_header(101, 1_500, forking_block_hash.clone())?,
+                running.clone(),
+                None
+            )?
+        );
+
+        // test chain expired
+        assert_eq!(
+            Some(TestChainEvent::Stopped {
+                test_chain_id: test_chain_id.clone()
+            }),
+            update_test_chain(
+                &chain_meta_storage,
+                &block_storage,
+                &main_chain_id,
+                &block_header(102, 2_000, forking_block_hash.clone())?,
+                running,
+                None
+            )?
+        );
+        assert!(chain_meta_storage
+            .get_test_chain_id(&main_chain_id)?
+            .is_none());
+        assert!(chain_meta_storage
+            .get_test_chain_data(&test_chain_id)?
+            .is_none());
+        assert!(chain_meta_storage.get_genesis(&test_chain_id)?.is_none());
+
+        // no test chain to stop
+        assert_eq!(
+            None,
+            update_test_chain(
+                &chain_meta_storage,
+                &block_storage,
+                &main_chain_id,
+                &block_header(103, 2_500, forking_block_hash.clone())?,
+                TestChainStatus::NotRunning,
+                None
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_already_running_test_chain() -> Result<(), failure::Error> {
+        let tmp_storage =
+            TmpStorage::create_to_out_dir("__test_update_already_running_test_chain")?;
+        let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+
+        let main_chain_id = HashType::ChainId.b58check_to_hash("NetXgtSLGNJvNye")?;
+        let test_chain_id = HashType::ChainId.b58check_to_hash("NetXjD3HPJJjmcd")?;
+        let forking_block_hash = HashType::BlockHash
+            .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+        let next_block_hash = HashType::BlockHash
+            .b58check_to_hash("BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ")?;
+        let protocol_hash = HashType::ProtocolHash
+            .b58check_to_hash("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?;
+        let genesis = compute_test_chain_genesis(&forking_block_hash);
+        let running = TestChainStatus::Running {
+            chain_id: test_chain_id.clone(),
+            genesis: genesis.clone(),
+            protocol_hash,
+            expiration: 2_000,
+        };
+
+        // forking block is not stored, genesis level is unknown
+        assert!(matches!(
+            update_test_chain(
+                &chain_meta_storage,
+                &block_storage,
+                &main_chain_id,
+                &block_header(102, 1_200, next_block_hash.clone())?,
+                running.clone(),
+                None
+            )?,
+            Some(TestChainEvent::Activated { .. })
+        ));
+        assert!(chain_meta_storage.get_genesis(&test_chain_id)?.is_none());
+        chain_meta_storage.remove_test_chain(&main_chain_id, &test_chain_id)?;
+
+        // genesis level is found by the stored forking block
+        block_storage.put_block_header(&BlockHeaderWithHash {
+            hash: forking_block_hash.clone(),
+            header: Arc::new(block_header(
+                100,
+                1_000,
+                HashType::BlockHash
+                    .b58check_to_hash("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+            )?),
+        })?;
+        block_storage.put_block_header(&BlockHeaderWithHash {
+            hash: next_block_hash.clone(),
+            header: Arc::new(block_header(101, 1_100, forking_block_hash)?),
+        })?;
+        assert!(matches!(
+            update_test_chain(
+                &chain_meta_storage,
+                &block_storage,
+                &main_chain_id,
+                &block_header(102, 1_200, next_block_hash)?,
+                running,
+                None
+            )?,
+            Some(TestChainEvent::Activated { .. })
+        ));
+        let genesis_head = chain_meta_storage.get_genesis(&test_chain_id)?.unwrap();
+        assert_eq!(&genesis, genesis_head.block_hash());
+        assert_eq!(100, *genesis_head.level());
+        Ok(())
+    }
+
+    fn block_header(
+        level: i32,
+        timestamp: i64,
+        predecessor: BlockHash,
+    ) -> Result<BlockHeader, failure::Error> {
+        Ok(BlockHeaderBuilder::default()
+            .level(level)
+            .proto(1)
+            .predecessor(predecessor)
+            .timestamp(timestamp)
+            .validation_pass(4)
+            .operations_hash(
+                HashType::OperationListListHash
+                    .b58check_to_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc")?,
+            )
+            .fitness(vec![])
+            .context(
+                HashType::ContextHash
+                    .b58check_to_hash("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd")?,
+            )
+            .protocol_data(vec![])
+            .build()
+            .unwrap())
+    }
+}
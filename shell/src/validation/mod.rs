@@ -24,6 +24,7 @@ use tezos_messages::Head;
 use tezos_wrapper::service::{ProtocolController, ProtocolServiceError};
 
 use crate::mempool::CurrentMempoolStateStorageRef;
+use crate::sandbox_clock::{self, SandboxClock};
 use crate::validation::fitness_comparator::FitnessWrapper;
 
 /// Validates if new_head is stronger or at least equals to old_head - according to fitness
@@ -62,10 +63,17 @@ pub fn is_same_head(head: &Head, incoming_header: &BlockHeader) -> Result<bool,
     Ok(is_same)
 }
 
-/// Returns only true, if timestamp of header is not in the far future
+/// Returns only true, if timestamp of header is in the far future (according to the node clock)
 pub fn is_future_block(block_header: &BlockHeader) -> Result<bool, failure::Error> {
-    let future_margin =
-        chrono::offset::Utc::now() + chrono::Duration::from_std(Duration::from_secs(15))?;
+    is_future_block_by_clock(block_header, sandbox_clock::node_clock())
+}
+
+/// Returns only true, if timestamp of header is in the far future according to the clock
+pub fn is_future_block_by_clock(
+    block_header: &BlockHeader,
+    clock: &SandboxClock,
+) -> Result<bool, failure::Error> {
+    let future_margin = clock.now() + chrono::Duration::from_std(Duration::from_secs(15))?;
     let block_timestamp = chrono::Utc.from_utc_datetime(&chrono::NaiveDateTime::from_timestamp(
         block_header.timestamp(),
         0,
@@ -448,37 +456,72 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_is_future_block_by_clock_update() -> Result<(), failure::Error> {
+        let clock = SandboxClock::default();
+        let timestamp = chrono::Utc::now().timestamp() + 3600;
+        let header = block_header(fitness!([0]), timestamp)?;
+
+        // injected before the clock update
+        assert!(is_future_block_by_clock(&header, &clock)?);
+
+        // injected after the clock update
+        clock.update(&serde_json::from_str(r#"{"advance_secs": 3600}"#)?);
+        assert!(!is_future_block_by_clock(&header, &clock)?);
+
+        // reset is applied before the other changes of the same update
+        clock.update(&serde_json::from_str(
+            r#"{"advance_secs": 3600, "reset": true}"#,
+        )?);
+        assert!(!is_future_block_by_clock(&header, &clock)?);
+
+        // freezing is applied after the move, so the block stays in the future
+        clock.update(&serde_json::from_str(&format!(
+            r#"{{"frozen_at": {}, "advance_secs": 7200}}"#,
+            timestamp - 60
+        ))?);
+        assert_eq!(clock.timestamp(), timestamp - 60);
+        assert!(is_future_block_by_clock(&header, &clock)?);
+
+        // frozen clock is moved by advance
+        clock.update(&serde_json::from_str(r#"{"advance_secs": 60}"#)?);
+        assert!(!is_future_block_by_clock(&header, &clock)?);
+
+        Ok(())
+    }
+
     fn new_head(fitness: Fitness) -> Result<BlockHeaderWithHash, failure::Error> {
         Ok(BlockHeaderWithHash {
             hash: HashType::BlockHash
                 .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?,
-            header: Arc::new(
-                BlockHeaderBuilder::default()
-                    .level(34)
-                    .proto(1)
-                    .predecessor(
-                        HashType::BlockHash.b58check_to_hash(
-                            "BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET",
-                        )?,
-                    )
-                    .timestamp(5_635_634)
-                    .validation_pass(4)
-                    .operations_hash(HashType::OperationListListHash.b58check_to_hash(
-                        "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc",
-                    )?)
-                    .fitness(fitness)
-                    .context(
-                        HashType::ContextHash.b58check_to_hash(
-                            "CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd",
-                        )?,
-                    )
-                    .protocol_data(vec![0, 1, 2, 3, 4, 5, 6, 7, 8])
-                    .build()
-                    .unwrap(),
-            ),
+            header: Arc::new(block_header(fitness, 5_635_634)?),
         })
     }
 
+    fn block_header(fitness: Fitness, timestamp: i64) -> Result<BlockHeader, failure::Error> {
+        Ok(BlockHeaderBuilder::default()
+            .level(34)
+            .proto(1)
+            .predecessor(
+                HashType::BlockHash
+                    .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?,
+            )
+            .timestamp(timestamp)
+            .validation_pass(4)
+            .operations_hash(
+                HashType::OperationListListHash
+                    .b58check_to_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc")?,
+            )
+            .fitness(fitness)
+            .context(
+                HashType::ContextHash
+                    .b58check_to_hash("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd")?,
+            )
+            .protocol_data(vec![0, 1, 2, 3, 4, 5, 6, 7, 8])
+            .build()
+            .unwrap())
+    }
+
     fn current_head(fitness: Fitness) -> Result<Head, failure::Error> {
         Ok(Head::new(
             HashType::BlockHash
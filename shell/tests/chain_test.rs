@@ -40,8 +40,12 @@ lazy_static! {
             disable_bootstrap_lookup: true,
             disable_mempool: false,
             private_node: false,
+            expected_pow: 0.0,
             bootstrap_peers: vec![],
             peer_threshold: PeerConnectionThreshold::new(0, 10, Some(0)),
+            message_recorder: None,
+            replay: None,
+            bandwidth: Default::default(),
         },
         NETWORK_VERSION.clone(),
     );
@@ -1256,6 +1260,7 @@ mod test_node_peer {
                                 server_address,
                                 false,
                                 false,
+                                0.0,
                             );
 
                             match peer::bootstrap(bootstrap, local, &log).await {
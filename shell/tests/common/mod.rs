@@ -89,6 +89,7 @@ pub mod infra {
     use tokio::runtime::Runtime;
 
     use crypto::hash::{BlockHash, ContextHash, HashType, OperationHash};
+    use networking::p2p::bandwidth::BandwidthThrottle;
     use networking::p2p::network_channel::{NetworkChannel, NetworkChannelRef};
     use shell::chain_feeder::ChainFeeder;
     use shell::chain_manager::ChainManager;
@@ -260,6 +261,8 @@ pub mod infra {
                 &tezos_env,
                 apply_protocol_commands,
                 log.clone(),
+                false,
+                false,
             )
             .expect("Failed to create chain feeder");
             let _ = ChainManager::actor(
@@ -290,6 +293,7 @@ pub mod infra {
 
             // and than open p2p and others - if configured
             let peer_manager = if let Some((p2p_config, network_version)) = p2p {
+                let bandwidth = Arc::new(BandwidthThrottle::new(p2p_config.bandwidth.clone()));
                 let peer_manager = PeerManager::actor(
                     &actor_system,
                     network_channel.clone(),
@@ -298,6 +302,7 @@ pub mod infra {
                     identity,
                     Arc::new(network_version),
                     p2p_config,
+                    bandwidth,
                 )
                 .expect("Failed to create peer manager");
                 Some(peer_manager)
@@ -0,0 +1,126 @@
+use failure::Error;
+use rand::Rng;
+
+use crypto::hash::{BlockHash, ContextHash};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use storage::context::{ContextApi, TezedgeContext};
+use storage::merkle_storage::ContextKey;
+use storage::tests_common::TmpStorage;
+use storage::{BlockStorage, ContextHistoryIndex};
+
+const KEYS_COUNT: usize = 10_000;
+const COMMITS_COUNT: usize = 500;
+const CHANGES_PER_COMMIT: usize = 50;
+
+fn context_key(index: usize) -> ContextKey {
+    vec![
+        "data".to_string(),
+        "contracts".to_string(),
+        "index".to_string(),
+        format!("{:02x}", index % 256),
+        format!("{:02x}", (index / 256) % 256),
+        format!("{}", index),
+        "balance".to_string(),
+    ]
+}
+
+/// Create and return a storage with [COMMITS_COUNT] commits (all indexed) and their context hashes
+fn init_mocked_context() -> Result<(TmpStorage, Vec<ContextHash>), Error> {
+    let tmp_storage = TmpStorage::create("__mocked_context_history")?;
+    let persistent_storage = tmp_storage.storage();
+    let history_index = ContextHistoryIndex::new(persistent_storage);
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(persistent_storage),
+        persistent_storage.merkle(),
+    );
+    let block_hash: BlockHash = vec![0; 32];
+    let mut rng = rand::thread_rng();
+
+    // genesis with all keys
+    for index in 0..KEYS_COUNT {
+        context.set(&None, &context_key(index), &index.to_be_bytes().to_vec())?;
+    }
+    let mut context_hashes = vec![context.commit(
+        &block_hash,
+        &None,
+        "Tezos".to_string(),
+        "Genesis".to_string(),
+        0,
+    )?];
+    history_index.index_commit(&context_hashes[0])?;
+
+    // every commit changes random keys
+    for commit in 1..COMMITS_COUNT {
+        for _ in 0..CHANGES_PER_COMMIT {
+            let index = rng.gen_range(0, KEYS_COUNT);
+            context.set(&None, &context_key(index), &commit.to_be_bytes().to_vec())?;
+        }
+        let context_hash = context.commit(
+            &block_hash,
+            &None,
+            "Tezos".to_string(),
+            format!("Commit {}", commit),
+            commit as i64,
+        )?;
+        history_index.index_commit(&context_hash)?;
+        context_hashes.push(context_hash);
+    }
+
+    Ok((tmp_storage, context_hashes))
+}
+
+fn get_key_from_history_benchmark(c: &mut Criterion) {
+    let (tmp_storage, context_hashes) = init_mocked_context().unwrap();
+    let persistent_storage = tmp_storage.storage();
+    let context = TezedgeContext::new(
+        BlockStorage::new(persistent_storage),
+        persistent_storage.merkle(),
+    );
+    let indexed_context = TezedgeContext::new(
+        BlockStorage::new(persistent_storage),
+        persistent_storage.merkle(),
+    )
+    .with_history_index(ContextHistoryIndex::new(persistent_storage));
+
+    // just, check if impl. is correct
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+        let context_hash = &context_hashes[rng.gen_range(0, COMMITS_COUNT)];
+        let key = context_key(rng.gen_range(0, KEYS_COUNT));
+        assert_eq!(
+            context.get_key_from_history(context_hash, &key).unwrap(),
+            indexed_context
+                .get_key_from_history(context_hash, &key)
+                .unwrap()
+        );
+    }
+
+    // run bench - value of a key over all levels
+    let key = context_key(KEYS_COUNT / 2);
+    c.bench_function("get_key_from_history_merkle", |b| {
+        b.iter(|| {
+            for context_hash in &context_hashes {
+                context.get_key_from_history(context_hash, &key).unwrap();
+            }
+        })
+    });
+    c.bench_function("get_key_from_history_skip_list", |b| {
+        b.iter(|| {
+            for context_hash in &context_hashes {
+                indexed_context
+                    .get_key_from_history(context_hash, &key)
+                    .unwrap();
+            }
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = get_key_from_history_benchmark
+}
+
+criterion_main!(benches);
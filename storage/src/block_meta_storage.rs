@@ -4,17 +4,17 @@
 use std::sync::Arc;
 
 use getset::{CopyGetters, Getters, Setters};
-use rocksdb::{Cache, ColumnFamilyDescriptor, MergeOperands};
 use slog::{warn, Logger};
 
 use crypto::hash::{BlockHash, ChainId, HashType};
 use tezos_messages::p2p::encoding::block_header::Level;
+use tezos_messages::Head;
 
 use crate::num_from_slice;
 use crate::persistent::database::{IteratorMode, IteratorWithSchema};
 use crate::persistent::{
-    default_table_options, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema,
-    PersistentStorage, SchemaError,
+    Decoder, Encoder, KeyValueColumn, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage,
+    SchemaError,
 };
 use crate::predecessor_storage::{PredecessorKey, PredecessorStorage};
 use crate::{BlockHeaderWithHash, StorageError};
@@ -168,6 +168,21 @@ impl BlockMetaStorage {
         Ok(())
     }
 
+    /// Checks (by the predecessors index), if the block at the level is the block of the chain of the `head`
+    pub fn is_on_chain(
+        &self,
+        head: &Head,
+        block_hash: &BlockHash,
+        level: Level,
+    ) -> Result<bool, StorageError> {
+        if level > *head.level() {
+            return Ok(false);
+        }
+        let block_at_level =
+            self.find_block_at_distance(head.block_hash().clone(), head.level() - level)?;
+        Ok(block_at_level.as_ref() == Some(block_hash))
+    }
+
     #[inline]
     pub fn put(&self, block_hash: &BlockHash, meta: &Meta) -> Result<(), StorageError> {
         self.kv.merge(block_hash, meta).map_err(StorageError::from)
@@ -506,10 +521,8 @@ impl KeyValueSchema for BlockMetaStorage {
     type Key = BlockHash;
     type Value = Meta;
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_merge_operator("block_meta_storage_merge_operator", merge_meta_value, None);
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_merge_operator(merge_meta_value)
     }
 
     #[inline]
@@ -521,7 +534,7 @@ impl KeyValueSchema for BlockMetaStorage {
 fn merge_meta_value(
     _new_key: &[u8],
     existing_val: Option<&[u8]>,
-    operands: &mut MergeOperands,
+    operands: &mut dyn Iterator<Item = &[u8]>,
 ) -> Option<Vec<u8>> {
     let mut result = existing_val.map(|v| v.to_vec());
 
@@ -6,7 +6,7 @@ use std::sync::Arc;
 use rocksdb::{Cache, ColumnFamilyDescriptor};
 use serde::{Deserialize, Serialize};
 
-use crypto::hash::{ChainId, HashType};
+use crypto::hash::{BlockHash, ChainId, HashType, ProtocolHash};
 use tezos_messages::Head;
 
 use crate::persistent::{
@@ -122,6 +122,52 @@ impl ChainMetaStorage {
             .delete(&MetaKey::key_test_chain_id(chain_id.clone()))
             .map_err(StorageError::from)
     }
+
+    #[inline]
+    pub fn get_test_chain_data(
+        &self,
+        test_chain_id: &ChainId,
+    ) -> Result<Option<TestChainData>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_test_chain_data(test_chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::TestChainData(value)) => Some(value),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_test_chain_data(
+        &self,
+        test_chain_id: &ChainId,
+        data: &TestChainData,
+    ) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_test_chain_data(test_chain_id.clone()),
+                &MetadataValue::TestChainData(data.clone()),
+            )
+            .map_err(StorageError::from)
+    }
+
+    /// Removes test chain of the main chain together with all its metadata (data, genesis, current head)
+    pub fn remove_test_chain(
+        &self,
+        chain_id: &ChainId,
+        test_chain_id: &ChainId,
+    ) -> Result<(), StorageError> {
+        self.remove_test_chain_id(chain_id)?;
+        for key in &[
+            MetaKey::key_test_chain_data(test_chain_id.clone()),
+            MetaKey::key_genesis(test_chain_id.clone()),
+            MetaKey::key_current_head(test_chain_id.clone()),
+            MetaKey::key_caboose(test_chain_id.clone()),
+        ] {
+            self.kv.delete(key)?;
+        }
+        Ok(())
+    }
 }
 
 impl ChainMetaStorageReader for ChainMetaStorage {
@@ -189,6 +235,7 @@ impl MetaKey {
     const KEY_CABOOSE: &'static str = "cbs";
     const KEY_GENESIS: &'static str = "gns";
     const KEY_TEST_CHAIN_ID: &'static str = "tcid";
+    const KEY_TEST_CHAIN_DATA: &'static str = "tcdata";
 
     fn key_current_head(chain_id: ChainId) -> MetaKey {
         MetaKey {
@@ -217,6 +264,13 @@ impl MetaKey {
             key: Self::KEY_TEST_CHAIN_ID.to_string(),
         }
     }
+
+    fn key_test_chain_data(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_TEST_CHAIN_DATA.to_string(),
+        }
+    }
 }
 
 impl Encoder for MetaKey {
@@ -244,10 +298,22 @@ impl Decoder for MetaKey {
     }
 }
 
+/// Test chain forked from the main chain, stored under the test chain id
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TestChainData {
+    /// Protocol running on the test chain
+    pub protocol_hash: ProtocolHash,
+    /// Genesis block of the test chain
+    pub genesis: BlockHash,
+    /// Test chain is stopped after this time (unix timestamp in seconds)
+    pub expiration: i64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum MetadataValue {
     Head(Head),
     TestChainId(ChainId),
+    TestChainData(TestChainData),
 }
 
 impl BincodeEncoded for MetadataValue {}
@@ -493,4 +559,44 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_test_chain_data() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__test_test_chain_data")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let main_chain_id = HashType::ChainId.b58check_to_hash("NetXgtSLGNJvNye")?;
+        let test_chain_id = HashType::ChainId.b58check_to_hash("NetXjD3HPJJjmcd")?;
+        let genesis = Head::new(
+            HashType::BlockHash
+                .b58check_to_hash("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+            1,
+            vec![],
+        );
+        let data = TestChainData {
+            protocol_hash: HashType::ProtocolHash
+                .b58check_to_hash("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?,
+            genesis: genesis.block_hash().clone(),
+            expiration: 1_600_000_000,
+        };
+
+        assert!(index.get_test_chain_data(&test_chain_id)?.is_none());
+
+        // activate test chain
+        index.set_test_chain_id(&main_chain_id, &test_chain_id)?;
+        index.set_test_chain_data(&test_chain_id, &data)?;
+        index.set_genesis(&test_chain_id, genesis.clone())?;
+        index.set_current_head(&test_chain_id, genesis)?;
+        assert_eq!(index.get_test_chain_data(&test_chain_id)?, Some(data));
+        assert!(index.get_test_chain_data(&main_chain_id)?.is_none());
+
+        // remove test chain with all metadata
+        index.remove_test_chain(&main_chain_id, &test_chain_id)?;
+        assert!(index.get_test_chain_id(&main_chain_id)?.is_none());
+        assert!(index.get_test_chain_data(&test_chain_id)?.is_none());
+        assert!(index.get_genesis(&test_chain_id)?.is_none());
+        assert!(index.get_current_head(&test_chain_id)?.is_none());
+
+        Ok(())
+    }
 }
@@ -10,9 +10,10 @@ use failure::Fail;
 
 use crypto::hash::{BlockHash, ContextHash, HashType};
 
+use crate::context_history::ContextHistoryIndex;
 use crate::merkle_storage::{
-    ContextKey, ContextValue, EntryHash, MerkleError, MerkleStorage, MerkleStorageStats,
-    StringTreeEntry,
+    ContextKey, ContextValue, ContextValueDiff, EntryHash, MerkleError, MerkleProof, MerkleReader,
+    MerkleStorage, MerkleStorageStats, StringTreeEntry,
 };
 use crate::{BlockStorage, BlockStorageReader, StorageError};
 
@@ -79,6 +80,20 @@ pub trait ContextApi {
         prefix: &ContextKey,
         depth: Option<usize>,
     ) -> Result<StringTreeEntry, MerkleError>;
+    // get value for key (if any) together with proof of its presence or absence in context identified by context hash
+    fn get_merkle_proof(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<(Option<ContextValue>, MerkleProof), MerkleError>;
+    // get all (or at most limit) values under a certain key prefix which differ between two contexts
+    fn get_context_diff(
+        &self,
+        from_context_hash: &ContextHash,
+        to_context_hash: &ContextHash,
+        prefix: &ContextKey,
+        limit: Option<usize>,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError>;
 
     // get currently checked out hash
     fn get_last_commit_hash(&self) -> Option<Vec<u8>>;
@@ -212,9 +227,20 @@ impl ContextApi for TezedgeContext {
         context_hash: &ContextHash,
         key: &ContextKey,
     ) -> Result<Option<ContextValue>, ContextError> {
+        // values of indexed commits are read without traversing the merkle tree,
+        // commits not indexed (and index failures) are resolved by merkle storage as before
+        if let Some(history_index) = &self.history_index {
+            if let Ok(Some(value)) = history_index.get_key(context_hash, key) {
+                return Ok(value);
+            }
+        }
+
         let context_hash_arr: EntryHash = context_hash.as_slice().try_into()?;
-        let mut merkle = self.merkle.write().expect("lock poisoning");
-        match merkle.get_history(&context_hash_arr, key) {
+        match self
+            .reader
+            .snapshot(&context_hash_arr)
+            .and_then(|snapshot| snapshot.get(key))
+        {
             Err(MerkleError::ValueNotFound { key: _ }) => Ok(None),
             Err(MerkleError::EntryNotFound { hash: _ }) => {
                 Err(ContextError::UnknownContextHashError {
@@ -232,8 +258,9 @@ impl ContextApi for TezedgeContext {
         prefix: &ContextKey,
     ) -> Result<Option<Vec<(ContextKey, ContextValue)>>, MerkleError> {
         let context_hash_arr: EntryHash = context_hash.as_slice().try_into()?;
-        let mut merkle = self.merkle.write().expect("lock poisoning");
-        merkle.get_key_values_by_prefix(&context_hash_arr, prefix)
+        self.reader
+            .snapshot(&context_hash_arr)?
+            .get_key_values_by_prefix(prefix)
     }
 
     fn get_context_tree_by_prefix(
@@ -243,8 +270,34 @@ impl ContextApi for TezedgeContext {
         depth: Option<usize>,
     ) -> Result<StringTreeEntry, MerkleError> {
         let context_hash_arr: EntryHash = context_hash.as_slice().try_into()?;
-        let mut merkle = self.merkle.write().expect("lock poisoning");
-        merkle.get_context_tree_by_prefix(&context_hash_arr, prefix, depth)
+        self.reader
+            .snapshot(&context_hash_arr)?
+            .get_context_tree_by_prefix(prefix, depth)
+    }
+
+    fn get_merkle_proof(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<(Option<ContextValue>, MerkleProof), MerkleError> {
+        let context_hash_arr: EntryHash = context_hash.as_slice().try_into()?;
+        self.reader
+            .snapshot(&context_hash_arr)?
+            .get_merkle_proof(key)
+    }
+
+    fn get_context_diff(
+        &self,
+        from_context_hash: &ContextHash,
+        to_context_hash: &ContextHash,
+        prefix: &ContextKey,
+        limit: Option<usize>,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        let from_context_hash_arr: EntryHash = from_context_hash.as_slice().try_into()?;
+        let to_context_hash_arr: EntryHash = to_context_hash.as_slice().try_into()?;
+        self.reader
+            .snapshot(&to_context_hash_arr)?
+            .get_context_diff_from(&from_context_hash_arr, prefix, limit)
     }
 
     fn get_last_commit_hash(&self) -> Option<Vec<u8>> {
@@ -271,13 +324,27 @@ impl ContextApi for TezedgeContext {
 pub struct TezedgeContext {
     block_storage: BlockStorage,
     merkle: Arc<RwLock<MerkleStorage>>,
+    /// committed contexts are read without locking the merkle storage
+    reader: MerkleReader,
+    history_index: Option<ContextHistoryIndex>,
 }
 
 impl TezedgeContext {
     pub fn new(block_storage: BlockStorage, merkle: Arc<RwLock<MerkleStorage>>) -> Self {
+        let reader = merkle.read().expect("lock poisoning").reader();
         TezedgeContext {
             block_storage,
             merkle,
+            reader,
+            history_index: None,
+        }
+    }
+
+    /// Historical values are read from the index (if indexed)
+    pub fn with_history_index(self, history_index: ContextHistoryIndex) -> Self {
+        TezedgeContext {
+            history_index: Some(history_index),
+            ..self
         }
     }
 }
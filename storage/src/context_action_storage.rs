@@ -8,7 +8,6 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use failure::Fail;
-use rocksdb::{Cache, ColumnFamilyDescriptor, SliceTransform};
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::{BlockHash, HashType};
@@ -19,8 +18,8 @@ use crate::num_from_slice;
 use crate::persistent::codec::{range_from_idx_len, vec_from_slice};
 use crate::persistent::sequence::{SequenceGenerator, SequenceNumber};
 use crate::persistent::{
-    default_table_options, BincodeEncoded, Decoder, Encoder, KeyValueSchema,
-    KeyValueStoreWithSchema, PersistentStorage, SchemaError,
+    BincodeEncoded, Decoder, Encoder, KeyValueColumn, KeyValueSchema, KeyValueStoreWithSchema,
+    PersistentStorage, SchemaError,
 };
 use crate::StorageError;
 
@@ -178,6 +177,16 @@ impl ContextActionStorage {
             .and_then(|idx| self.load_indexes(idx.into_iter()))
     }
 
+    /// Returns true, if any action is stored for the block
+    #[inline]
+    pub fn contains_block_hash(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        Ok(self
+            .context_by_block_index
+            .get_by_block_hash_iterator(block_hash, None)?
+            .next()
+            .is_some())
+    }
+
     #[inline]
     pub fn get_by_contract_address(
         &self,
@@ -366,13 +375,9 @@ impl KeyValueSchema for ContextActionByBlockHashIndex {
     type Key = ContextActionByBlockHashKey;
     type Value = ();
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
-            ContextActionByBlockHashKey::LEN_BLOCK_HASH,
-        ));
-        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name())
+            .with_fixed_prefix(ContextActionByBlockHashKey::LEN_BLOCK_HASH)
     }
 
     fn name() -> &'static str {
@@ -500,14 +505,9 @@ impl KeyValueSchema for ContextActionByContractIndex {
     type Key = ContextActionByContractIndexKey;
     type Value = ();
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
-            ContextActionByContractIndexKey::LEN_CONTRACT_ADDRESS,
-        ));
-        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
-        // cf_opts.set_comparator("reverse_id", ContextActionByContractIndexKey::reverse_id_comparator);
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name())
+            .with_fixed_prefix(ContextActionByContractIndexKey::LEN_CONTRACT_ADDRESS)
     }
 
     fn name() -> &'static str {
@@ -726,14 +726,8 @@ impl KeyValueSchema for ContextActionByTypeIndex {
     type Key = ContextActionByTypeIndexKey;
     type Value = ();
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(mem::size_of::<
-            ContextActionType,
-        >()));
-        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
-        // cf_opts.set_comparator("reverse_id", ContextActionByTypeIndexKey::reverse_id_comparator);
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_fixed_prefix(mem::size_of::<ContextActionType>())
     }
 
     fn name() -> &'static str {
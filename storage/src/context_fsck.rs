@@ -0,0 +1,384 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Integrity check (fsck) of the merkle storage for a range of applied blocks.
+//!
+//! Contexts of the blocks are checked from the lowest level, every reachable entry (commit, tree, blob) is loaded
+//! from the database and re-hashed, entries already checked by lower blocks are skipped. So issues are reported
+//! by the block, which introduced the broken entry, if the range starts low enough. Only up to [MAX_CHECKED_ENTRIES]
+//! checked hashes are remembered, so a broken entry shared by many blocks can be reported more than once for long ranges.
+//!
+//! Broken contexts can be repaired by re-applying the blocks from the stored context actions
+//! (only if the node stored them, see `--store-context-actions`), which writes the entries of the block again.
+//! Actions of all broken blocks are checked before the first block is re-applied.
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use failure::Fail;
+use slog::{info, warn, Logger};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
+use tezos_context::channel::ContextAction;
+
+use crate::block_storage::BlockLevel;
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::context::{ContextApi, ContextError, TezedgeContext};
+use crate::merkle_storage::{EntryHash, EntryIssue, MerkleError};
+use crate::persistent::PersistentStorage;
+use crate::{
+    BlockHeaderWithHash, BlockStorage, BlockStorageReader, ChainMetaStorage, ContextActionStorage,
+    StorageError,
+};
+
+/// Count of checked blocks between progress logs
+const PROGRESS_LOG_BLOCKS: usize = 1000;
+
+/// Max count of remembered hashes of checked entries (~200MB), the set is cleared when exceeded
+const MAX_CHECKED_ENTRIES: usize = 4_000_000;
+
+/// Range of blocks (of the main chain) to be checked
+#[derive(Debug, Clone)]
+pub struct ContextFsckConfig {
+    pub from_level: BlockLevel,
+    /// Current head is used, if not set
+    pub to_level: Option<BlockLevel>,
+    /// Re-apply blocks with broken context from stored context actions
+    pub repair: bool,
+}
+
+/// Issues found in the context of the block
+#[derive(Debug, Clone)]
+pub struct BlockContextIssues {
+    pub level: BlockLevel,
+    pub block_hash: BlockHash,
+    pub context_hash: ContextHash,
+    pub issues: Vec<EntryIssue>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContextFsckReport {
+    pub checked_blocks: usize,
+    pub checked_entries: usize,
+    /// Blocks ordered by level
+    pub broken_blocks: Vec<BlockContextIssues>,
+}
+
+impl ContextFsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.broken_blocks.is_empty()
+    }
+
+    pub fn issues_count(&self) -> usize {
+        self.broken_blocks
+            .iter()
+            .map(|block| block.issues.len())
+            .sum()
+    }
+}
+
+/// Checks contexts of the main chain blocks in the range `from_level..=to_level`
+pub fn check_context(
+    chain_id: &ChainId,
+    from_level: BlockLevel,
+    to_level: Option<BlockLevel>,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) -> Result<ContextFsckReport, ContextFsckError> {
+    let blocks = load_blocks(chain_id, from_level, to_level, persistent_storage)?;
+    info!(log, "Checking context of blocks"; "from_level" => from_level, "count" => blocks.len());
+
+    let merkle = persistent_storage.merkle();
+    let merkle = merkle.read().expect("lock poisoning");
+    let mut checked = HashSet::new();
+    let mut report = ContextFsckReport::default();
+
+    for block in blocks {
+        let block = block?;
+        let context_hash: EntryHash = block.header.context().as_slice().try_into()?;
+        let (checked_entries, issues) = merkle.check_entries(&context_hash, &mut checked)?;
+        if checked.len() > MAX_CHECKED_ENTRIES {
+            // entries shared with the next blocks are checked again
+            checked.clear();
+        }
+
+        report.checked_blocks += 1;
+        report.checked_entries += checked_entries;
+        if !issues.is_empty() {
+            warn!(log, "Context of block is broken";
+                       "level" => block.header.level(),
+                       "block_hash" => HashType::BlockHash.hash_to_b58check(&block.hash),
+                       "issues" => issues.len());
+            report.broken_blocks.push(BlockContextIssues {
+                level: block.header.level(),
+                block_hash: block.hash.clone(),
+                context_hash: block.header.context().clone(),
+                issues,
+            });
+        }
+        if report.checked_blocks % PROGRESS_LOG_BLOCKS == 0 {
+            info!(log, "Context check progress";
+                       "level" => block.header.level(),
+                       "checked_blocks" => report.checked_blocks,
+                       "checked_entries" => report.checked_entries);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Re-applies broken blocks from the report (from the lowest level) from stored context actions,
+/// returns hashes of the re-applied blocks.
+///
+/// Run [check_context] again to verify the result, entries introduced by blocks lower than the checked range
+/// cannot be repaired this way.
+pub fn repair_context(
+    report: &ContextFsckReport,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) -> Result<Vec<BlockHash>, ContextFsckError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let context_action_storage = ContextActionStorage::new(persistent_storage);
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(persistent_storage),
+        persistent_storage.merkle(),
+    );
+
+    // do not re-apply any block, if some of them cannot be repaired
+    for broken_block in &report.broken_blocks {
+        if !context_action_storage.contains_block_hash(&broken_block.block_hash)? {
+            return Err(ContextFsckError::MissingContextActions {
+                block_hash: HashType::BlockHash.hash_to_b58check(&broken_block.block_hash),
+            });
+        }
+    }
+
+    let mut repaired = Vec::with_capacity(report.broken_blocks.len());
+    for broken_block in &report.broken_blocks {
+        let block = get_block(&block_storage, &broken_block.block_hash)?;
+        let predecessor = get_block(&block_storage, block.header.predecessor())?;
+
+        let mut actions = context_action_storage.get_by_block_hash(&block.hash)?;
+        actions.sort_by_key(|action| action.id());
+
+        context.checkout(predecessor.header.context())?;
+        let mut context_hash = None;
+        for action in actions {
+            if let Some(hash) = replay_context_action(&mut context, action.action())? {
+                context_hash = Some(hash);
+            }
+        }
+
+        match context_hash {
+            Some(context_hash) if &context_hash == block.header.context() => {
+                info!(log, "Block re-applied";
+                           "level" => block.header.level(),
+                           "block_hash" => HashType::BlockHash.hash_to_b58check(&block.hash));
+                repaired.push(block.hash);
+            }
+            context_hash => {
+                return Err(ContextFsckError::InvalidContextHash {
+                    block_hash: HashType::BlockHash.hash_to_b58check(&block.hash),
+                    expected: HashType::ContextHash.hash_to_b58check(block.header.context()),
+                    found: context_hash
+                        .map(|hash| HashType::ContextHash.hash_to_b58check(&hash))
+                        .unwrap_or_else(|| "-none-".to_string()),
+                })
+            }
+        }
+    }
+
+    Ok(repaired)
+}
+
+/// Applies context action to the context, returns hash of the commit for commit action.
+///
+/// Only actions which modify the context are applied (the same way as context actions received from the protocol runner).
+pub fn replay_context_action(
+    context: &mut dyn ContextApi,
+    action: &ContextAction,
+) -> Result<Option<ContextHash>, ContextError> {
+    match action {
+        ContextAction::Set {
+            key,
+            value,
+            context_hash,
+            ignored: false,
+            ..
+        } => context.set(context_hash, key, value)?,
+        ContextAction::Copy {
+            to_key,
+            from_key,
+            context_hash,
+            ignored: false,
+            ..
+        } => context.copy_to_diff(context_hash, from_key, to_key)?,
+        ContextAction::Delete {
+            key,
+            context_hash,
+            ignored: false,
+            ..
+        } => context.delete_to_diff(context_hash, key)?,
+        ContextAction::RemoveRecursively {
+            key,
+            context_hash,
+            ignored: false,
+            ..
+        } => context.remove_recursively_to_diff(context_hash, key)?,
+        ContextAction::Commit {
+            parent_context_hash,
+            block_hash: Some(block_hash),
+            author,
+            message,
+            date,
+            ..
+        } => {
+            return context
+                .commit(
+                    block_hash,
+                    parent_context_hash,
+                    author.to_string(),
+                    message.to_string(),
+                    *date,
+                )
+                .map(Some)
+        }
+        ContextAction::Checkout { context_hash, .. } => context.checkout(context_hash)?,
+        _ => (),
+    };
+    Ok(None)
+}
+
+/// Blocks of the main chain ordered by level, only hashes are kept in memory, headers are loaded when iterated
+pub struct MainChainBlocks {
+    block_storage: BlockStorage,
+    /// hashes of the remaining blocks, the next one is the last
+    block_hashes: Vec<BlockHash>,
+}
+
+impl MainChainBlocks {
+    /// Hash of the next (lowest) block
+    pub fn first_block_hash(&self) -> Option<&BlockHash> {
+        self.block_hashes.last()
+    }
+}
+
+impl Iterator for MainChainBlocks {
+    type Item = Result<BlockHeaderWithHash, ContextFsckError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.block_hashes
+            .pop()
+            .map(|block_hash| get_block(&self.block_storage, &block_hash))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.block_hashes.len(), Some(self.block_hashes.len()))
+    }
+}
+
+impl ExactSizeIterator for MainChainBlocks {}
+
+/// Load blocks of the main chain (ordered by level) by walking predecessors from the current head
+pub fn load_blocks(
+    chain_id: &ChainId,
+    from_level: BlockLevel,
+    to_level: Option<BlockLevel>,
+    persistent_storage: &PersistentStorage,
+) -> Result<MainChainBlocks, ContextFsckError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let current_head = match ChainMetaStorage::new(persistent_storage).get_current_head(chain_id)? {
+        Some(current_head) => current_head,
+        None => return Err(ContextFsckError::MissingCurrentHead),
+    };
+
+    let mut block_hashes = Vec::new();
+    let mut block = get_block(&block_storage, current_head.block_hash())?;
+    loop {
+        let level = block.header.level();
+        if level < from_level {
+            break;
+        }
+        if to_level.map(|to_level| level <= to_level).unwrap_or(true) {
+            block_hashes.push(block.hash.clone());
+        }
+        // genesis is its own predecessor
+        if level == 0 {
+            break;
+        }
+        block = get_block(&block_storage, block.header.predecessor())?;
+    }
+
+    Ok(MainChainBlocks {
+        block_storage,
+        block_hashes,
+    })
+}
+
+fn get_block(
+    block_storage: &BlockStorage,
+    block_hash: &BlockHash,
+) -> Result<BlockHeaderWithHash, ContextFsckError> {
+    block_storage
+        .get(block_hash)?
+        .ok_or_else(|| ContextFsckError::MissingBlock {
+            block_hash: HashType::BlockHash.hash_to_b58check(block_hash),
+        })
+}
+
+/// Possible errors for context check and repair
+#[derive(Debug, Fail)]
+pub enum ContextFsckError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Merkle storage error: {}", error)]
+    MerkleError { error: MerkleError },
+    #[fail(display = "Context error: {}", error)]
+    ContextError { error: ContextError },
+    #[fail(display = "Invalid context hash: {}", error)]
+    InvalidHash {
+        error: std::array::TryFromSliceError,
+    },
+    #[fail(display = "Current head is not stored")]
+    MissingCurrentHead,
+    #[fail(display = "Block not found: {}", block_hash)]
+    MissingBlock { block_hash: String },
+    #[fail(
+        display = "Context actions not stored for block: {} (store context actions to repair context)",
+        block_hash
+    )]
+    MissingContextActions { block_hash: String },
+    #[fail(
+        display = "Invalid context hash for re-applied block: {}, expected: {}, found: {}",
+        block_hash, expected, found
+    )]
+    InvalidContextHash {
+        block_hash: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl From<StorageError> for ContextFsckError {
+    fn from(error: StorageError) -> Self {
+        ContextFsckError::StorageError { error }
+    }
+}
+
+impl From<MerkleError> for ContextFsckError {
+    fn from(error: MerkleError) -> Self {
+        ContextFsckError::MerkleError { error }
+    }
+}
+
+impl From<ContextError> for ContextFsckError {
+    fn from(error: ContextError) -> Self {
+        ContextFsckError::ContextError { error }
+    }
+}
+
+impl From<std::array::TryFromSliceError> for ContextFsckError {
+    fn from(error: std::array::TryFromSliceError) -> Self {
+        ContextFsckError::InvalidHash { error }
+    }
+}
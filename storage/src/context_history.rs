@@ -0,0 +1,342 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Index of context values changed by applied blocks, backed by the [skip list](crate::skip_list).
+//!
+//! Every indexed commit is one node of the skip list, which holds values changed against the previously indexed commit,
+//! so the value of a key in a historical context is read from (aggregated) changes instead of traversing
+//! the merkle tree of the commit.
+//!
+//! Index is optional (`--index-context-history`). It is started by the first block applied after the genesis,
+//! commits of the blocks applied before (or while the index was disabled) are indexed by [ContextHistoryIndex::backfill].
+//! Contexts not indexed are still read from the merkle storage.
+//!
+//! Changes are computed from a [MerkleReader] snapshot, so indexing does not hold the lock of the merkle storage.
+
+use std::array::TryFromSliceError;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use failure::Fail;
+
+use crypto::hash::{BlockHash, ContextHash, HashType};
+use tezos_messages::p2p::encoding::block_header::BlockHeader;
+
+use crate::merkle_storage::{ContextKey, ContextValue, EntryHash, MerkleError, MerkleReader};
+use crate::persistent::backend::WriteBatch;
+use crate::persistent::sequence::SequenceGenerator;
+use crate::persistent::{
+    DBError, KeyValueSchema, KeyValueStore, KeyValueStoreWithSchema, PersistentStorage,
+};
+use crate::skip_list::{
+    Bucket, DatabaseBackedSkipList, SkipList, SkipListError, SkipListId, TypedSkipList,
+};
+use crate::{BlockStorage, BlockStorageReader, StorageError};
+
+/// Skip list (in the shared skip list columns) used for the context history
+const CONTEXT_HISTORY_LIST_ID: SkipListId = 1;
+const CONTEXT_HISTORY_SEQUENCE: &str = "context_history";
+
+/// Key of the context value stored in the skip list
+type HistoryKey = String;
+/// Value (or its removal) stored in the skip list
+type HistoryValue = Bucket<ContextValue>;
+
+pub type ContextHistoryIndexKV = dyn KeyValueStoreWithSchema<ContextHistoryIndex> + Sync + Send;
+
+/// Index of context values changed by indexed commits.
+///
+/// Index is written only by the thread, which applies blocks, but can be read concurrently.
+#[derive(Clone)]
+pub struct ContextHistoryIndex {
+    /// context_hash -> position in the skip list
+    kv: Arc<ContextHistoryIndexKV>,
+    /// position in the skip list -> context_hash
+    by_position_index: ContextHistoryByPositionIndex,
+    list_kv: Arc<KeyValueStore>,
+    sequence: Arc<SequenceGenerator>,
+    merkle: MerkleReader,
+}
+
+impl ContextHistoryIndex {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            kv: persistent_storage.kv(),
+            by_position_index: ContextHistoryByPositionIndex::new(persistent_storage.kv()),
+            list_kv: persistent_storage.kv(),
+            sequence: persistent_storage.seq().generator(CONTEXT_HISTORY_SEQUENCE),
+            merkle: persistent_storage
+                .merkle()
+                .read()
+                .expect("lock poisoning")
+                .reader(),
+        }
+    }
+
+    /// Skip list state is always loaded from database, so readers see the changes pushed by the writer
+    fn list(&self) -> Result<DatabaseBackedSkipList, ContextHistoryError> {
+        DatabaseBackedSkipList::new(
+            CONTEXT_HISTORY_LIST_ID,
+            self.list_kv.clone(),
+            self.sequence.clone(),
+        )
+        .map_err(ContextHistoryError::from)
+    }
+
+    /// Count of indexed commits
+    pub fn len(&self) -> Result<usize, ContextHistoryError> {
+        Ok(self.list()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, ContextHistoryError> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn contains(&self, context_hash: &ContextHash) -> Result<bool, ContextHistoryError> {
+        self.kv
+            .contains(context_hash)
+            .map_err(ContextHistoryError::from)
+    }
+
+    /// Indexes context of the applied block.
+    ///
+    /// Empty index is started only by the first block after the genesis (together with the genesis context),
+    /// returns false if the block was not indexed.
+    pub fn index_block(
+        &self,
+        block_header: &BlockHeader,
+        predecessor_header: &BlockHeader,
+    ) -> Result<bool, ContextHistoryError> {
+        if self.is_empty()? {
+            if block_header.level() != 1 {
+                return Ok(false);
+            }
+            self.index_commit(predecessor_header.context())?;
+        }
+        self.index_commit(block_header.context())
+    }
+
+    /// Indexes the commit with values changed against the last indexed commit (or all values for empty index).
+    ///
+    /// Changes are always resolved against the last indexed commit (not the parent commit), so the aggregated
+    /// changes of the whole list reconstruct exactly the indexed context, even after reorg.
+    /// Returns false, if the commit is already indexed.
+    pub fn index_commit(&self, context_hash: &ContextHash) -> Result<bool, ContextHistoryError> {
+        if self.contains(context_hash)? {
+            return Ok(false);
+        }
+
+        let mut list = self.list()?;
+        let len = list.len();
+        if len > 0 && self.by_position_index.get(len - 1)?.is_none() {
+            // last node was pushed, but commit was not stored (e.g. crash), so the node is removed and indexed again
+            list.pop()?;
+        }
+        let last_context_hash: Option<EntryHash> = match list.len() {
+            0 => None,
+            len => match self.by_position_index.get(len - 1)? {
+                Some(last_context_hash) => Some(last_context_hash.as_slice().try_into()?),
+                None => {
+                    return Err(ContextHistoryError::MissingCommit { position: len - 1 });
+                }
+            },
+        };
+        let context_hash_arr: EntryHash = context_hash.as_slice().try_into()?;
+
+        let changes: BTreeMap<HistoryKey, HistoryValue> = self
+            .merkle
+            .snapshot(&context_hash_arr)?
+            .get_context_changes_from(last_context_hash.as_ref())?
+            .into_iter()
+            .map(|diff| {
+                let value = match diff.new_value {
+                    Some(value) => Bucket::Exists(value),
+                    None => Bucket::Deleted,
+                };
+                (history_key(&diff.key), value)
+            })
+            .collect();
+
+        TypedSkipList::<HistoryKey, HistoryValue>::push(&mut list, &changes)?;
+
+        // commit is visible for readers, after it is completely stored in the skip list
+        let position = list.len() - 1;
+        let mut batch = WriteBatch::default();
+        self.by_position_index
+            .put_batch(&mut batch, position, context_hash)?;
+        self.kv.put_batch(&mut batch, context_hash, &position)?;
+        self.kv.write_batch(batch)?;
+        Ok(true)
+    }
+
+    /// Indexes contexts of the blocks from level 1 up to the block (usually the current head),
+    /// which are not indexed yet, e.g. blocks applied before the index was enabled.
+    ///
+    /// Blocks are walked back from the block until an already indexed context is found,
+    /// so only hashes of the walked blocks are held in memory. Returns count of indexed commits.
+    pub fn backfill(
+        &self,
+        block_storage: &BlockStorage,
+        block_hash: &BlockHash,
+    ) -> Result<usize, ContextHistoryError> {
+        let mut block_hashes = Vec::new();
+        let mut next_block_hash = block_hash.clone();
+        while let Some(block) = block_storage.get(&next_block_hash)? {
+            if block.header.level() <= 0 || self.contains(block.header.context())? {
+                break;
+            }
+            next_block_hash = block.header.predecessor().clone();
+            block_hashes.push(block.hash);
+        }
+
+        let mut indexed = 0;
+        for block_hash in block_hashes.iter().rev() {
+            let block = block_storage.get(block_hash)?.ok_or_else(|| {
+                ContextHistoryError::MissingBlock {
+                    block_hash: HashType::BlockHash.hash_to_b58check(block_hash),
+                }
+            })?;
+            let predecessor = block_storage
+                .get(block.header.predecessor())?
+                .ok_or_else(|| ContextHistoryError::MissingBlock {
+                    block_hash: HashType::BlockHash.hash_to_b58check(block.header.predecessor()),
+                })?;
+            if self.is_empty()? && block.header.level() != 1 {
+                // index can be started only by the first block after the genesis
+                return Ok(indexed);
+            }
+            if self.index_block(&block.header, &predecessor.header)? {
+                indexed += 1;
+            }
+        }
+        Ok(indexed)
+    }
+
+    /// Get value of the key in the indexed context.
+    ///
+    /// Returns None, if the context is not indexed, Some(None) if the value is not set in the context.
+    pub fn get_key(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<Option<Option<ContextValue>>, ContextHistoryError> {
+        let position = match self.kv.get(context_hash)? {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+
+        let value: Option<HistoryValue> = TypedSkipList::<HistoryKey, HistoryValue>::get_key(
+            &self.list()?,
+            position,
+            &history_key(key),
+        )?;
+        Ok(Some(match value {
+            Some(Bucket::Exists(value)) => Some(value),
+            Some(Bucket::Deleted) | None => None,
+        }))
+    }
+}
+
+impl KeyValueSchema for ContextHistoryIndex {
+    type Key = ContextHash;
+    type Value = usize;
+
+    #[inline]
+    fn name() -> &'static str {
+        "context_history_storage"
+    }
+}
+
+#[inline]
+fn history_key(key: &ContextKey) -> HistoryKey {
+    key.join("/")
+}
+
+#[derive(Clone)]
+pub struct ContextHistoryByPositionIndex {
+    kv: Arc<ContextHistoryByPositionIndexKV>,
+}
+
+pub type ContextHistoryByPositionIndexKV =
+    dyn KeyValueStoreWithSchema<ContextHistoryByPositionIndex> + Sync + Send;
+
+impl ContextHistoryByPositionIndex {
+    fn new(kv: Arc<ContextHistoryByPositionIndexKV>) -> Self {
+        Self { kv }
+    }
+
+    fn put_batch(
+        &self,
+        batch: &mut WriteBatch,
+        position: usize,
+        context_hash: &ContextHash,
+    ) -> Result<(), StorageError> {
+        self.kv
+            .put_batch(batch, &position, context_hash)
+            .map_err(StorageError::from)
+    }
+
+    fn get(&self, position: usize) -> Result<Option<ContextHash>, StorageError> {
+        self.kv.get(&position).map_err(StorageError::from)
+    }
+}
+
+impl KeyValueSchema for ContextHistoryByPositionIndex {
+    type Key = usize;
+    type Value = ContextHash;
+
+    #[inline]
+    fn name() -> &'static str {
+        "context_history_by_position_storage"
+    }
+}
+
+/// Possible errors for context history
+#[derive(Debug, Fail)]
+pub enum ContextHistoryError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Skip list error: {}", error)]
+    SkipListError { error: SkipListError },
+    #[fail(display = "Merkle storage error: {}", error)]
+    MerkleError { error: MerkleError },
+    #[fail(display = "Invalid context hash: {}", error)]
+    InvalidContextHash { error: TryFromSliceError },
+    #[fail(display = "Indexed commit is missing at position: {}", position)]
+    MissingCommit { position: usize },
+    #[fail(display = "Block is missing: {}", block_hash)]
+    MissingBlock { block_hash: String },
+}
+
+impl From<StorageError> for ContextHistoryError {
+    fn from(error: StorageError) -> Self {
+        ContextHistoryError::StorageError { error }
+    }
+}
+
+impl From<DBError> for ContextHistoryError {
+    fn from(error: DBError) -> Self {
+        ContextHistoryError::StorageError {
+            error: error.into(),
+        }
+    }
+}
+
+impl From<SkipListError> for ContextHistoryError {
+    fn from(error: SkipListError) -> Self {
+        ContextHistoryError::SkipListError { error }
+    }
+}
+
+impl From<MerkleError> for ContextHistoryError {
+    fn from(error: MerkleError) -> Self {
+        ContextHistoryError::MerkleError { error }
+    }
+}
+
+impl From<TryFromSliceError> for ContextHistoryError {
+    fn from(error: TryFromSliceError) -> Self {
+        ContextHistoryError::InvalidContextHash { error }
+    }
+}
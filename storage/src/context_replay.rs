@@ -0,0 +1,251 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Replay of stored context actions, used to benchmark context storage without the protocol runner.
+//!
+//! Actions of the blocks (stored only if the node runs with `--store-context-actions`) are applied to any [ContextApi]
+//! implementation in the recorded order, hashes of the commits are verified against the recorded ones
+//! and latencies of the replayed actions are measured.
+//!
+//! Context of the predecessor of the first replayed block has to be available in the replayed context,
+//! a fresh merkle storage can be prepared by [MerkleStorage::copy_context_from](crate::merkle_storage::MerkleStorage::copy_context_from).
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+use slog::{info, Logger};
+
+use crypto::hash::{BlockHash, HashType};
+use tezos_context::channel::ContextAction;
+
+use crate::context::{ContextApi, ContextError};
+use crate::context_action_storage::ContextActionType;
+use crate::context_fsck::{replay_context_action, ContextFsckError};
+use crate::{BlockHeaderWithHash, ContextActionStorage, StorageError};
+
+/// Count of replayed blocks between progress logs
+const PROGRESS_LOG_BLOCKS: usize = 1000;
+
+/// Latencies of the replayed actions of one type
+#[derive(Debug, Clone, Default)]
+pub struct ActionLatencies {
+    pub count: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl ActionLatencies {
+    fn add(&mut self, latency: Duration) {
+        if self.count == 0 || latency < self.min {
+            self.min = latency;
+        }
+        if latency > self.max {
+            self.max = latency;
+        }
+        self.count += 1;
+        self.total += latency;
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::default();
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContextReplayReport {
+    pub replayed_blocks: usize,
+    /// Count of reads (get, mem, dirmem) with result different from the recorded one
+    pub read_mismatches: usize,
+    /// Latencies by the type of action
+    pub latencies: BTreeMap<String, ActionLatencies>,
+}
+
+impl ContextReplayReport {
+    pub fn replayed_actions(&self) -> usize {
+        self.latencies
+            .values()
+            .map(|latencies| latencies.count)
+            .sum()
+    }
+
+    /// Time spent in all replayed actions
+    pub fn total_time(&self) -> Duration {
+        self.latencies
+            .values()
+            .map(|latencies| latencies.total)
+            .sum()
+    }
+
+    fn add_latency(&mut self, action_type: ContextActionType, latency: Duration) {
+        self.latencies
+            .entry(format!("{:?}", action_type))
+            .or_insert_with(ActionLatencies::default)
+            .add(latency);
+    }
+}
+
+/// Replays stored context actions of the blocks (ordered by level), every block is replayed from the context of its predecessor.
+///
+/// Replay stops on the first commit with hash different from the recorded one or on the first error of `blocks`.
+pub fn replay_blocks<I, E>(
+    blocks: I,
+    context_action_storage: &ContextActionStorage,
+    context: &mut dyn ContextApi,
+    log: &Logger,
+) -> Result<ContextReplayReport, ContextReplayError>
+where
+    I: IntoIterator<Item = Result<BlockHeaderWithHash, E>>,
+    ContextReplayError: From<E>,
+{
+    let mut report = ContextReplayReport::default();
+
+    for block in blocks {
+        let block = block?;
+        let mut actions = context_action_storage.get_by_block_hash(&block.hash)?;
+        if actions.is_empty() {
+            return Err(ContextReplayError::MissingContextActions {
+                block_hash: HashType::BlockHash.hash_to_b58check(&block.hash),
+            });
+        }
+        actions.sort_by_key(|action| action.id());
+
+        // checkout is not stored with the actions of the block, predecessor context is the parent of the commit
+        let parent_context_hash = actions
+            .iter()
+            .find_map(|action| match action.action() {
+                ContextAction::Commit {
+                    parent_context_hash,
+                    ..
+                } => Some(parent_context_hash.clone()),
+                _ => None,
+            })
+            .ok_or_else(|| ContextReplayError::MissingCommit {
+                block_hash: HashType::BlockHash.hash_to_b58check(&block.hash),
+            })?
+            .ok_or_else(|| ContextReplayError::MissingParentContext {
+                block_hash: HashType::BlockHash.hash_to_b58check(&block.hash),
+            })?;
+        let instant = Instant::now();
+        context.checkout(&parent_context_hash)?;
+        report.add_latency(ContextActionType::Checkout, instant.elapsed());
+
+        for action in &actions {
+            replay_action(context, &block.hash, action.action(), &mut report)?;
+        }
+
+        report.replayed_blocks += 1;
+        if report.replayed_blocks % PROGRESS_LOG_BLOCKS == 0 {
+            info!(log, "Context replay progress";
+                       "level" => block.header.level(),
+                       "replayed_blocks" => report.replayed_blocks,
+                       "replayed_actions" => report.replayed_actions());
+        }
+    }
+
+    Ok(report)
+}
+
+/// Replays the action of the block and measures its latency, actions not applied by the node are skipped
+fn replay_action(
+    context: &mut dyn ContextApi,
+    block_hash: &BlockHash,
+    action: &ContextAction,
+    report: &mut ContextReplayReport,
+) -> Result<(), ContextReplayError> {
+    let action_type = match action {
+        ContextAction::Set { ignored: true, .. }
+        | ContextAction::Delete { ignored: true, .. }
+        | ContextAction::RemoveRecursively { ignored: true, .. }
+        | ContextAction::Copy { ignored: true, .. }
+        | ContextAction::Fold { .. } => return Ok(()),
+        action => match ContextActionType::extract_type(action) {
+            Some(action_type) => action_type,
+            None => return Ok(()),
+        },
+    };
+
+    let instant = Instant::now();
+    let matches = match action {
+        ContextAction::Get { key, value, .. } => &context.get_key(key)? == value,
+        ContextAction::Mem { key, value, .. } => context.mem(key)? == *value,
+        ContextAction::DirMem { key, value, .. } => context.dirmem(key)? == *value,
+        action => {
+            let commit_hash = replay_context_action(context, action)?;
+            report.add_latency(action_type, instant.elapsed());
+            // every recorded commit is verified, commit without the block hash is not applied, so it does not match
+            if let ContextAction::Commit {
+                new_context_hash, ..
+            } = action
+            {
+                if commit_hash.as_ref() != Some(new_context_hash) {
+                    return Err(ContextReplayError::InvalidContextHash {
+                        block_hash: HashType::BlockHash.hash_to_b58check(block_hash),
+                        expected: HashType::ContextHash.hash_to_b58check(new_context_hash),
+                        found: commit_hash
+                            .map(|commit_hash| HashType::ContextHash.hash_to_b58check(&commit_hash))
+                            .unwrap_or_else(|| "<not committed>".to_string()),
+                    });
+                }
+            }
+            return Ok(());
+        }
+    };
+    report.add_latency(action_type, instant.elapsed());
+    if !matches {
+        report.read_mismatches += 1;
+    }
+
+    Ok(())
+}
+
+/// Possible errors for context replay
+#[derive(Debug, Fail)]
+pub enum ContextReplayError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Context error: {}", error)]
+    ContextError { error: ContextError },
+    #[fail(display = "Failed to load block: {}", error)]
+    BlockError { error: ContextFsckError },
+    #[fail(display = "Context actions not stored for block: {}", block_hash)]
+    MissingContextActions { block_hash: String },
+    #[fail(display = "Commit action not stored for block: {}", block_hash)]
+    MissingCommit { block_hash: String },
+    #[fail(
+        display = "Commit action of block: {} has no parent context (genesis cannot be replayed)",
+        block_hash
+    )]
+    MissingParentContext { block_hash: String },
+    #[fail(
+        display = "Invalid context hash for replayed block: {}, expected: {}, found: {}",
+        block_hash, expected, found
+    )]
+    InvalidContextHash {
+        block_hash: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl From<StorageError> for ContextReplayError {
+    fn from(error: StorageError) -> Self {
+        ContextReplayError::StorageError { error }
+    }
+}
+
+impl From<ContextError> for ContextReplayError {
+    fn from(error: ContextError) -> Self {
+        ContextReplayError::ContextError { error }
+    }
+}
+
+impl From<ContextFsckError> for ContextReplayError {
+    fn from(error: ContextFsckError) -> Self {
+        ContextReplayError::BlockError { error }
+    }
+}
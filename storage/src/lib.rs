@@ -30,15 +30,18 @@ pub use crate::chain_meta_storage::ChainMetaStorage;
 pub use crate::context_action_storage::{
     ContextActionByBlockHashKey, ContextActionRecordValue, ContextActionStorage,
 };
+pub use crate::context_history::ContextHistoryIndex;
 pub use crate::mempool_storage::{MempoolStorage, MempoolStorageKV};
 use crate::merkle_storage::MerkleStorage;
+pub use crate::operation_receipts_storage::OperationReceiptsStorage;
 pub use crate::operations_meta_storage::{OperationsMetaStorage, OperationsMetaStorageKV};
 pub use crate::operations_storage::{
-    OperationKey, OperationsStorage, OperationsStorageKV, OperationsStorageReader,
+    OperationKey, OperationLocation, OperationsStorage, OperationsStorageKV,
+    OperationsStorageReader,
 };
 pub use crate::persistent::database::{Direction, IteratorMode};
 use crate::persistent::sequence::SequenceError;
-use crate::persistent::{CommitLogError, DBError, Decoder, Encoder, SchemaError};
+use crate::persistent::{CommitLogError, DBError, Decoder, Encoder, KeyValueStore, SchemaError};
 pub use crate::predecessor_storage::PredecessorStorage;
 pub use crate::system_storage::SystemStorage;
 
@@ -47,8 +50,14 @@ pub mod block_storage;
 pub mod chain_meta_storage;
 pub mod context;
 pub mod context_action_storage;
+pub mod context_fsck;
+pub mod context_history;
+pub mod context_replay;
+pub mod lru_cache;
 pub mod mempool_storage;
+pub mod merkle_encoding;
 pub mod merkle_storage;
+pub mod operation_receipts_storage;
 pub mod operations_meta_storage;
 pub mod operations_storage;
 pub mod persistent;
@@ -357,7 +366,7 @@ pub fn initialize_storage_with_genesis_block(
 }
 
 pub fn check_database_compatibility(
-    db: Arc<rocksdb::DB>,
+    db: Arc<KeyValueStore>,
     expected_database_version: i64,
     tezos_env: &TezosEnvironmentConfiguration,
     log: &Logger,
@@ -421,7 +430,13 @@ pub mod tests_common {
 
     use crate::block_storage;
     use crate::chain_meta_storage::ChainMetaStorage;
+    use crate::context_action_storage::{
+        ContextActionByBlockHashIndex, ContextActionByContractIndex, ContextActionByTypeIndex,
+    };
+    use crate::context_history::ContextHistoryByPositionIndex;
     use crate::mempool_storage::MempoolStorage;
+    use crate::operation_receipts_storage::OperationReceiptsByAccountIndex;
+    use crate::operations_storage::OperationsByHashIndex;
     use crate::persistent::sequence::Sequences;
     use crate::persistent::*;
     use crate::skip_list::{DatabaseBackedSkipList, Lane, ListValue};
@@ -445,10 +460,36 @@ pub mod tests_common {
             Self::initialize(path, true, true)
         }
 
+        /// Create storage, which uses given key-value store backend instead of the default one
+        pub fn create_with_backend<P: AsRef<Path>>(
+            path: P,
+            backend: KeyValueStoreBackendType,
+        ) -> Result<Self, Error> {
+            let cfg = DbConfigurationBuilder::default()
+                .backend(backend)
+                .build()
+                .unwrap();
+            Self::initialize_with_cfg(path, true, true, &cfg)
+        }
+
         pub fn initialize<P: AsRef<Path>>(
             path: P,
             remove_if_exists: bool,
             remove_on_destroy: bool,
+        ) -> Result<Self, Error> {
+            Self::initialize_with_cfg(
+                path,
+                remove_if_exists,
+                remove_on_destroy,
+                &DbConfiguration::default(),
+            )
+        }
+
+        fn initialize_with_cfg<P: AsRef<Path>>(
+            path: P,
+            remove_if_exists: bool,
+            remove_on_destroy: bool,
+            cfg: &DbConfiguration,
         ) -> Result<Self, Error> {
             let path = path.as_ref().to_path_buf();
             // remove previous data if exists
@@ -456,35 +497,39 @@ pub mod tests_common {
                 fs::remove_dir_all(&path).unwrap();
             }
 
-            let cfg = DbConfiguration::default();
-
             // create common RocksDB block cache to be shared among column families
             let cache = Cache::new_lru_cache(128 * 1024 * 1024)?; // 128 MB
 
-            let kv = open_kv(
+            let kv = open_kv_store(
                 &path,
                 vec![
-                    block_storage::BlockPrimaryIndex::descriptor(&cache),
-                    block_storage::BlockByLevelIndex::descriptor(&cache),
-                    block_storage::BlockByContextHashIndex::descriptor(&cache),
-                    BlockMetaStorage::descriptor(&cache),
-                    OperationsStorage::descriptor(&cache),
-                    OperationsMetaStorage::descriptor(&cache),
-                    context_action_storage::ContextActionByBlockHashIndex::descriptor(&cache),
-                    context_action_storage::ContextActionByContractIndex::descriptor(&cache),
-                    context_action_storage::ContextActionByTypeIndex::descriptor(&cache),
-                    MerkleStorage::descriptor(&cache),
-                    SystemStorage::descriptor(&cache),
-                    Sequences::descriptor(&cache),
-                    DatabaseBackedSkipList::descriptor(&cache),
-                    Lane::descriptor(&cache),
-                    ListValue::descriptor(&cache),
-                    MempoolStorage::descriptor(&cache),
-                    ContextActionStorage::descriptor(&cache),
-                    ChainMetaStorage::descriptor(&cache),
-                    PredecessorStorage::descriptor(&cache),
+                    KeyValueStoreColumn::of::<block_storage::BlockPrimaryIndex>(),
+                    KeyValueStoreColumn::of::<block_storage::BlockByLevelIndex>(),
+                    KeyValueStoreColumn::of::<block_storage::BlockByContextHashIndex>(),
+                    KeyValueStoreColumn::of::<BlockMetaStorage>(),
+                    KeyValueStoreColumn::of::<OperationsStorage>(),
+                    KeyValueStoreColumn::of::<OperationsByHashIndex>(),
+                    KeyValueStoreColumn::of::<OperationsMetaStorage>(),
+                    KeyValueStoreColumn::of::<OperationReceiptsStorage>(),
+                    KeyValueStoreColumn::of::<OperationReceiptsByAccountIndex>(),
+                    KeyValueStoreColumn::of::<ContextActionByBlockHashIndex>(),
+                    KeyValueStoreColumn::of::<ContextActionByContractIndex>(),
+                    KeyValueStoreColumn::of::<ContextActionByTypeIndex>(),
+                    KeyValueStoreColumn::of::<MerkleStorage>(),
+                    KeyValueStoreColumn::of::<SystemStorage>(),
+                    KeyValueStoreColumn::of::<Sequences>(),
+                    KeyValueStoreColumn::of::<DatabaseBackedSkipList>(),
+                    KeyValueStoreColumn::of::<Lane>(),
+                    KeyValueStoreColumn::of::<ListValue>(),
+                    KeyValueStoreColumn::of::<ContextHistoryIndex>(),
+                    KeyValueStoreColumn::of::<ContextHistoryByPositionIndex>(),
+                    KeyValueStoreColumn::of::<MempoolStorage>(),
+                    KeyValueStoreColumn::of::<ContextActionStorage>(),
+                    KeyValueStoreColumn::of::<ChainMetaStorage>(),
+                    KeyValueStoreColumn::of::<PredecessorStorage>(),
                 ],
-                &cfg,
+                &cache,
+                cfg,
             )?;
             let clog = open_cl(&path, vec![BlockStorage::descriptor()])?;
 
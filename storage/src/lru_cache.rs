@@ -0,0 +1,154 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Size-aware LRU cache, the least recently used entries are evicted, when the sum of entry sizes exceeds the capacity.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use serde::Serialize;
+
+/// Counters of the cache
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct LruCacheStats {
+    pub hits: u64,
+    /// lookups of cacheable values not found in the cache, see [SizedLruCache::record_miss]
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    /// sum of sizes of cached entries (bytes)
+    pub size: usize,
+    /// max sum of sizes of cached entries (bytes)
+    pub capacity: usize,
+}
+
+struct CachedValue<V> {
+    value: V,
+    size: usize,
+    /// tick of the last access
+    last_used: u64,
+}
+
+pub(crate) struct SizedLruCache<K, V> {
+    values: HashMap<K, CachedValue<V>>,
+    /// last access tick -> key, the first one is the least recently used
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+    stats: LruCacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V> SizedLruCache<K, V> {
+    /// Cache with zero capacity does not store anything
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            values: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            stats: LruCacheStats {
+                capacity,
+                ..LruCacheStats::default()
+            },
+        }
+    }
+
+    /// Returns cached value and marks it as the most recently used.
+    ///
+    /// Misses are not counted here, because the caller may look up values, which are never cached.
+    pub(crate) fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.next_tick();
+        let cached = self.values.get_mut(key)?;
+        self.stats.hits += 1;
+        self.recency.remove(&cached.last_used);
+        self.recency.insert(tick, key.clone());
+        cached.last_used = tick;
+        Some(&cached.value)
+    }
+
+    /// Counts lookup of a cacheable value, which was not found in the cache
+    pub(crate) fn record_miss(&mut self) {
+        self.stats.misses += 1;
+    }
+
+    /// Stores the value (if it fits to the capacity) and evicts the least recently used values to fit the capacity
+    pub(crate) fn put(&mut self, key: K, value: V, size: usize) {
+        if size > self.stats.capacity {
+            return;
+        }
+        let tick = self.next_tick();
+        let replaced = self.values.insert(
+            key.clone(),
+            CachedValue {
+                value,
+                size,
+                last_used: tick,
+            },
+        );
+        if let Some(replaced) = replaced {
+            self.recency.remove(&replaced.last_used);
+            self.stats.size -= replaced.size;
+        }
+        self.recency.insert(tick, key);
+        self.stats.size += size;
+
+        while self.stats.size > self.stats.capacity {
+            let oldest_tick = match self.recency.keys().next() {
+                Some(oldest_tick) => *oldest_tick,
+                None => break,
+            };
+            if let Some(evicted_key) = self.recency.remove(&oldest_tick) {
+                if let Some(evicted) = self.values.remove(&evicted_key) {
+                    self.stats.size -= evicted.size;
+                    self.stats.evictions += 1;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> LruCacheStats {
+        LruCacheStats {
+            entries: self.values.len(),
+            ..self.stats.clone()
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = SizedLruCache::new(10);
+        cache.put(1, "a", 4);
+        cache.put(2, "b", 4);
+        assert_eq!(cache.get(&1), Some(&"a"));
+
+        // 2 is evicted, because 1 was used later
+        cache.put(3, "c", 4);
+        assert_eq!(cache.get(&2), None);
+        cache.record_miss();
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.get(&3), Some(&"c"));
+
+        // too big values are not stored
+        cache.put(4, "d", 11);
+        assert_eq!(cache.get(&4), None);
+
+        // replaced value is not counted twice
+        cache.put(3, "e", 7);
+        assert_eq!(cache.get(&3), Some(&"e"));
+        assert_eq!(cache.get(&1), None);
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.size, 7);
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 1);
+    }
+}
@@ -0,0 +1,483 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Compact versioned binary encoding of merkle storage entries.
+//!
+//! Encoded entry starts with the version tag followed by the kind of the entry:
+//!
+//! * tree - varint count of children, every child is encoded as varint length of the prefix shared with the key
+//!   of the previous child, varint `suffix_length << 1 | is_leaf`, suffix bytes and 32 bytes of the entry hash
+//!   (children are ordered by the key, so neighbouring keys often share the prefix)
+//! * blob - raw bytes of the value
+//! * compressed blob - varint length of the value and the value compressed by deflate
+//!   (only blobs of at least [BLOB_COMPRESSION_THRESHOLD] bytes, when compression saves space)
+//! * commit - flag of the parent, parent hash (if any), root hash, varint time, varint length prefixed author and message
+//!
+//! Entries written by older versions are bincode encoded `Entry` enums, which start with the (little endian u32)
+//! index of the variant, so the first byte is never the version tag and both formats can be read.
+//!
+//! Savings of the compact encoding are reported by the merkle storage stats (estimated from sampled entries, see [EncodingStats])
+//! and exactly for the entries re-encoded by `--context-migrate-encoding` (logged when the migration finishes).
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+use failure::Fail;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+use merkle_proof::HASH_LEN;
+
+use crate::merkle_storage::{Commit, Entry, EntryHash, Node, NodeKind, Tree};
+
+/// Tag of the current version of the compact encoding
+const COMPACT_V1: u8 = 0xc1;
+
+const KIND_TREE: u8 = 0;
+const KIND_BLOB: u8 = 1;
+const KIND_COMPRESSED_BLOB: u8 = 2;
+const KIND_COMMIT: u8 = 3;
+
+/// Blobs smaller than this (bytes) are never compressed
+pub const BLOB_COMPRESSION_THRESHOLD: usize = 256;
+
+/// Size in the legacy encoding is measured only for every n-th written entry
+pub const LEGACY_SIZE_SAMPLE_RATE: u64 = 64;
+
+/// Sizes of the written entries in the compact and the legacy (bincode) encoding
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct EncodingStats {
+    pub entries: u64,
+    pub compressed_blobs: u64,
+    /// bytes written in the compact encoding
+    pub compact_bytes: u64,
+    /// count of entries with measured size in the legacy encoding
+    pub sampled_entries: u64,
+    /// bytes of the sampled entries in the compact encoding
+    pub sampled_compact_bytes: u64,
+    /// bytes of the sampled entries in the legacy encoding
+    pub sampled_legacy_bytes: u64,
+}
+
+impl EncodingStats {
+    /// Bytes saved by the compact encoding, extrapolated from the sampled entries (exact, if all entries are sampled)
+    pub fn saved_bytes(&self) -> i64 {
+        if self.sampled_compact_bytes == 0 {
+            return 0;
+        }
+        let legacy_bytes = u128::from(self.compact_bytes) * u128::from(self.sampled_legacy_bytes)
+            / u128::from(self.sampled_compact_bytes);
+        legacy_bytes as i64 - self.compact_bytes as i64
+    }
+
+    /// Adds the written entry, its size in the legacy encoding is measured only for every [LEGACY_SIZE_SAMPLE_RATE]-th entry
+    pub(crate) fn add(&mut self, entry: &Entry, encoded: &[u8]) -> Result<(), EncodingError> {
+        let legacy_size = if self.entries % LEGACY_SIZE_SAMPLE_RATE == 0 {
+            Some(bincode::serialized_size(entry)?)
+        } else {
+            None
+        };
+        self.add_sized(encoded, legacy_size);
+        Ok(())
+    }
+
+    /// Adds the written entry with known size in the legacy encoding (if any), e.g. re-encoded legacy entry
+    pub(crate) fn add_sized(&mut self, encoded: &[u8], legacy_size: Option<u64>) {
+        self.entries += 1;
+        self.compact_bytes += encoded.len() as u64;
+        if let Some(legacy_size) = legacy_size {
+            self.sampled_entries += 1;
+            self.sampled_compact_bytes += encoded.len() as u64;
+            self.sampled_legacy_bytes += legacy_size;
+        }
+        if encoded.get(1) == Some(&KIND_COMPRESSED_BLOB) {
+            self.compressed_blobs += 1;
+        }
+    }
+}
+
+/// Returns true if the entry is encoded in the legacy (bincode) format
+pub fn is_legacy_encoded(bytes: &[u8]) -> bool {
+    bytes.first() != Some(&COMPACT_V1)
+}
+
+pub(crate) fn encode_entry(entry: &Entry) -> Result<Vec<u8>, EncodingError> {
+    let mut out = vec![COMPACT_V1];
+    match entry {
+        Entry::Tree(tree) => {
+            out.push(KIND_TREE);
+            encode_tree(tree, &mut out);
+        }
+        Entry::Blob(blob) => match compress(blob)? {
+            Some(compressed) => {
+                out.push(KIND_COMPRESSED_BLOB);
+                write_varint(blob.len() as u64, &mut out);
+                out.extend_from_slice(&compressed);
+            }
+            None => {
+                out.push(KIND_BLOB);
+                out.extend_from_slice(blob);
+            }
+        },
+        Entry::Commit(commit) => {
+            out.push(KIND_COMMIT);
+            encode_commit(commit, &mut out);
+        }
+    }
+    Ok(out)
+}
+
+/// Decodes entry in the compact or the legacy encoding
+pub(crate) fn decode_entry(bytes: &[u8]) -> Result<Entry, EncodingError> {
+    if is_legacy_encoded(bytes) {
+        return Ok(bincode::deserialize(bytes)?);
+    }
+
+    let mut reader = SliceReader { bytes, pos: 1 };
+    match reader.read_byte()? {
+        KIND_TREE => decode_tree(&mut reader).map(Entry::Tree),
+        KIND_BLOB => Ok(Entry::Blob(reader.rest().to_vec())),
+        KIND_COMPRESSED_BLOB => {
+            let len = reader.read_len()?;
+            let mut blob = Vec::new();
+            DeflateDecoder::new(reader.rest())
+                .take(len as u64 + 1)
+                .read_to_end(&mut blob)?;
+            if blob.len() != len {
+                return Err(EncodingError::InvalidLength);
+            }
+            Ok(Entry::Blob(blob))
+        }
+        KIND_COMMIT => decode_commit(&mut reader).map(Entry::Commit),
+        kind => Err(EncodingError::UnknownEntryKind { kind }),
+    }
+}
+
+fn encode_tree(tree: &Tree, out: &mut Vec<u8>) {
+    write_varint(tree.len() as u64, out);
+    let mut previous_key: &[u8] = &[];
+    for (key, node) in tree {
+        let key = key.as_bytes();
+        let shared = previous_key
+            .iter()
+            .zip(key)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = &key[shared..];
+        let is_leaf = match node.node_kind {
+            NodeKind::Leaf => 1,
+            NodeKind::NonLeaf => 0,
+        };
+        write_varint(shared as u64, out);
+        write_varint((suffix.len() as u64) << 1 | is_leaf, out);
+        out.extend_from_slice(suffix);
+        out.extend_from_slice(&node.entry_hash);
+        previous_key = key;
+    }
+}
+
+fn decode_tree(reader: &mut SliceReader) -> Result<Tree, EncodingError> {
+    let count = reader.read_len()?;
+    let mut tree = Tree::new();
+    let mut previous_key: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let shared = reader.read_len()?;
+        let suffix_len_and_kind = reader.read_varint()?;
+        let node_kind = if suffix_len_and_kind & 1 == 1 {
+            NodeKind::Leaf
+        } else {
+            NodeKind::NonLeaf
+        };
+        let suffix = reader.read_bytes((suffix_len_and_kind >> 1) as usize)?;
+        if shared > previous_key.len() {
+            return Err(EncodingError::InvalidLength);
+        }
+
+        let mut key = previous_key[..shared].to_vec();
+        key.extend_from_slice(suffix);
+        let entry_hash = reader.read_hash()?;
+        tree.insert(
+            String::from_utf8(key.clone())?,
+            Node {
+                node_kind,
+                entry_hash,
+            },
+        );
+        previous_key = key;
+    }
+    Ok(tree)
+}
+
+fn encode_commit(commit: &Commit, out: &mut Vec<u8>) {
+    match &commit.parent_commit_hash {
+        Some(parent_commit_hash) => {
+            out.push(1);
+            out.extend_from_slice(parent_commit_hash);
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&commit.root_hash);
+    write_varint(commit.time, out);
+    write_varint(commit.author.len() as u64, out);
+    out.extend_from_slice(commit.author.as_bytes());
+    write_varint(commit.message.len() as u64, out);
+    out.extend_from_slice(commit.message.as_bytes());
+}
+
+fn decode_commit(reader: &mut SliceReader) -> Result<Commit, EncodingError> {
+    let parent_commit_hash = match reader.read_byte()? {
+        0 => None,
+        _ => Some(reader.read_hash()?),
+    };
+    let root_hash = reader.read_hash()?;
+    let time = reader.read_varint()?;
+    let author = reader.read_string()?;
+    let message = reader.read_string()?;
+    Ok(Commit {
+        parent_commit_hash,
+        root_hash,
+        time,
+        author,
+        message,
+    })
+}
+
+/// Returns compressed blob, if the compression saves space
+fn compress(blob: &[u8]) -> Result<Option<Vec<u8>>, EncodingError> {
+    if blob.len() < BLOB_COMPRESSION_THRESHOLD {
+        return Ok(None);
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(blob)?;
+    let compressed = encoder.finish()?;
+    // compressed blob is prefixed by the length of the value
+    if compressed.len() + 4 < blob.len() {
+        Ok(Some(compressed))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Writes LEB128 encoded unsigned number
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct SliceReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    fn read_byte(&mut self) -> Result<u8, EncodingError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], EncodingError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(EncodingError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, EncodingError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(EncodingError::InvalidLength)
+    }
+
+    fn read_len(&mut self) -> Result<usize, EncodingError> {
+        self.read_varint()?
+            .try_into()
+            .map_err(|_| EncodingError::InvalidLength)
+    }
+
+    fn read_hash(&mut self) -> Result<EntryHash, EncodingError> {
+        let mut hash = [0; HASH_LEN];
+        hash.copy_from_slice(self.read_bytes(HASH_LEN)?);
+        Ok(hash)
+    }
+
+    fn read_string(&mut self) -> Result<String, EncodingError> {
+        let len = self.read_len()?;
+        Ok(String::from_utf8(self.read_bytes(len)?.to_vec())?)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        rest
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum EncodingError {
+    #[fail(display = "Unexpected end of encoded entry")]
+    UnexpectedEnd,
+    #[fail(display = "Invalid length in encoded entry")]
+    InvalidLength,
+    #[fail(display = "Unknown kind of encoded entry: {}", kind)]
+    UnknownEntryKind { kind: u8 },
+    #[fail(display = "Invalid UTF-8 string in encoded entry: {}", error)]
+    InvalidString { error: std::string::FromUtf8Error },
+    #[fail(display = "Blob compression error: {}", error)]
+    CompressionError { error: std::io::Error },
+    #[fail(display = "Legacy entry serialization error: {:?}", error)]
+    LegacyEncodingError { error: bincode::Error },
+}
+
+impl From<std::string::FromUtf8Error> for EncodingError {
+    fn from(error: std::string::FromUtf8Error) -> Self {
+        EncodingError::InvalidString { error }
+    }
+}
+
+impl From<std::io::Error> for EncodingError {
+    fn from(error: std::io::Error) -> Self {
+        EncodingError::CompressionError { error }
+    }
+}
+
+impl From<bincode::Error> for EncodingError {
+    fn from(error: bincode::Error) -> Self {
+        EncodingError::LegacyEncodingError { error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(node_kind: NodeKind, byte: u8) -> Node {
+        Node {
+            node_kind,
+            entry_hash: [byte; HASH_LEN],
+        }
+    }
+
+    fn assert_roundtrip(entry: Entry) -> Vec<u8> {
+        let encoded = encode_entry(&entry).unwrap();
+        assert!(!is_legacy_encoded(&encoded));
+        let decoded = decode_entry(&encoded).unwrap();
+        assert_eq!(
+            bincode::serialize(&decoded).unwrap(),
+            bincode::serialize(&entry).unwrap()
+        );
+        encoded
+    }
+
+    #[test]
+    fn test_tree_roundtrip() {
+        let mut tree = Tree::new();
+        tree.insert("contracts".to_string(), node(NodeKind::NonLeaf, 1));
+        tree.insert("contract".to_string(), node(NodeKind::Leaf, 2));
+        tree.insert("cycle".to_string(), node(NodeKind::NonLeaf, 3));
+        tree.insert("žluťoučký".to_string(), node(NodeKind::Leaf, 4));
+        let entry = Entry::Tree(tree);
+
+        let encoded = assert_roundtrip(entry.clone());
+        assert!(encoded.len() < bincode::serialize(&entry).unwrap().len());
+
+        assert_roundtrip(Entry::Tree(Tree::new()));
+    }
+
+    #[test]
+    fn test_blob_roundtrip() {
+        let encoded = assert_roundtrip(Entry::Blob(vec![1, 2, 3]));
+        assert_eq!(encoded[1], KIND_BLOB);
+
+        // big repetitive blob is compressed
+        let encoded = assert_roundtrip(Entry::Blob(vec![7; 4096]));
+        assert_eq!(encoded[1], KIND_COMPRESSED_BLOB);
+        assert!(encoded.len() < 100);
+
+        assert_roundtrip(Entry::Blob(vec![]));
+    }
+
+    #[test]
+    fn test_commit_roundtrip() {
+        for parent_commit_hash in vec![None, Some([5; HASH_LEN])] {
+            assert_roundtrip(Entry::Commit(Commit {
+                parent_commit_hash,
+                root_hash: [6; HASH_LEN],
+                time: 1_600_000_000,
+                author: "Tezos".to_string(),
+                message: "Genesis".to_string(),
+            }));
+        }
+    }
+
+    #[test]
+    fn test_encoding_stats_sampling() {
+        let entry = Entry::Blob(vec![7; 100]);
+        let encoded = encode_entry(&entry).unwrap();
+        let legacy_size = bincode::serialized_size(&entry).unwrap();
+
+        let mut stats = EncodingStats::default();
+        for _ in 0..2 * LEGACY_SIZE_SAMPLE_RATE {
+            stats.add(&entry, &encoded).unwrap();
+        }
+        assert_eq!(stats.entries, 2 * LEGACY_SIZE_SAMPLE_RATE);
+        assert_eq!(stats.sampled_entries, 2);
+        assert_eq!(stats.sampled_legacy_bytes, 2 * legacy_size);
+
+        // saved bytes are extrapolated to all entries
+        let saved_per_entry = legacy_size as i64 - encoded.len() as i64;
+        assert_eq!(
+            stats.saved_bytes(),
+            saved_per_entry * 2 * LEGACY_SIZE_SAMPLE_RATE as i64
+        );
+    }
+
+    #[test]
+    fn test_decode_legacy_entry() {
+        let entry = Entry::Blob(vec![1, 2, 3]);
+        let legacy = bincode::serialize(&entry).unwrap();
+        assert!(is_legacy_encoded(&legacy));
+        match decode_entry(&legacy).unwrap() {
+            Entry::Blob(blob) => assert_eq!(blob, vec![1, 2, 3]),
+            _ => panic!("blob expected"),
+        }
+    }
+
+    #[test]
+    fn test_decode_truncated_entry() {
+        let mut tree = Tree::new();
+        tree.insert("a".to_string(), node(NodeKind::Leaf, 1));
+        let encoded = encode_entry(&Entry::Tree(tree)).unwrap();
+        assert!(matches!(
+            decode_entry(&encoded[..encoded.len() - 1]),
+            Err(EncodingError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in vec![0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(value, &mut out);
+            let mut reader = SliceReader {
+                bytes: &out,
+                pos: 0,
+            };
+            assert_eq!(reader.read_varint().unwrap(), value);
+            assert!(reader.rest().is_empty());
+        }
+    }
+}
@@ -44,64 +44,96 @@
 //! Reference: https://git-scm.com/book/en/v2/Git-Internals-Git-Objects
 use std::array::TryFromSliceError;
 use std::collections::hash_map::Entry as MapEntry;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryInto;
+use std::fmt;
 use std::hash::Hash;
-use std::sync::Arc;
+use std::mem;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-use blake2::digest::{Update, VariableOutput};
-use blake2::VarBlake2b;
 use failure::Fail;
-use rocksdb::{Cache, ColumnFamilyDescriptor, WriteBatch};
+use rocksdb::{Cache, ColumnFamilyDescriptor};
 use serde::Deserialize;
 use serde::Serialize;
 
 use crypto::hash::HashType;
+pub use merkle_proof::{EntryHash, MerkleProof, MerkleProofEnd, MerkleProofNode, MerkleProofStep};
 
+use crate::lru_cache::{LruCacheStats, SizedLruCache};
+use crate::merkle_encoding::{self, EncodingError, EncodingStats};
 use crate::persistent;
-use crate::persistent::database::RocksDBStats;
+use crate::persistent::backend::WriteBatch;
+use crate::persistent::database::{IteratorMode, RocksDBStats};
 use crate::persistent::BincodeEncoded;
 use crate::persistent::{default_table_options, KeyValueSchema, KeyValueStoreWithSchema};
 
-const HASH_LEN: usize = 32;
-
 pub type ContextKey = Vec<String>;
 pub type ContextValue = Vec<u8>;
-pub type EntryHash = [u8; HASH_LEN];
+
+/// Default memory budget of the cache of decoded trees (bytes)
+pub const DEFAULT_TREE_CACHE_CAPACITY: usize = 256 * 1024 * 1024;
+
+/// Count of entries written to database in one batch by the encoding migration and by the copy of contexts
+const WRITE_BATCH_SIZE: usize = 10_000;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-enum NodeKind {
+pub(crate) enum NodeKind {
     NonLeaf,
     Leaf,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct Node {
-    node_kind: NodeKind,
-    entry_hash: EntryHash,
+pub(crate) struct Node {
+    pub(crate) node_kind: NodeKind,
+    pub(crate) entry_hash: EntryHash,
 }
 
 // Tree must be an ordered structure for consistent hash in hash_tree
 // Currently immutable OrdMap is used to allow cloning trees without too much overhead
-type Tree = BTreeMap<String, Node>;
+pub(crate) type Tree = BTreeMap<String, Node>;
 
 #[derive(Debug, Hash, Clone, Serialize, Deserialize)]
-struct Commit {
-    parent_commit_hash: Option<EntryHash>,
-    root_hash: EntryHash,
-    time: u64,
-    author: String,
-    message: String,
+pub(crate) struct Commit {
+    pub(crate) parent_commit_hash: Option<EntryHash>,
+    pub(crate) root_hash: EntryHash,
+    pub(crate) time: u64,
+    pub(crate) author: String,
+    pub(crate) message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-enum Entry {
+pub(crate) enum Entry {
     Tree(Tree),
     Blob(ContextValue),
     Commit(Commit),
 }
 
+/// Entry for reading only, trees are shared with the tree cache instead of being copied
+enum SharedEntry {
+    Tree(Arc<Tree>),
+    Blob(ContextValue),
+    Commit(Commit),
+}
+
+impl From<Entry> for SharedEntry {
+    fn from(entry: Entry) -> Self {
+        match entry {
+            Entry::Tree(tree) => SharedEntry::Tree(Arc::new(tree)),
+            Entry::Blob(blob) => SharedEntry::Blob(blob),
+            Entry::Commit(commit) => SharedEntry::Commit(commit),
+        }
+    }
+}
+
+/// Kind of the entry expected by its reference
+#[derive(Debug, Clone, Copy)]
+enum EntryKind {
+    Tree,
+    Blob,
+    Commit,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SetAction {
     key: ContextKey,
@@ -130,11 +162,15 @@ pub type MerkleStorageKV = dyn KeyValueStoreWithSchema<MerkleStorage> + Sync + S
 
 pub type RefCnt = usize;
 
+type TreeCache = Mutex<SizedLruCache<EntryHash, Arc<Tree>>>;
+
 pub struct MerkleStorage {
     /// tree with current staging area (currently checked out context)
-    current_stage_tree: Option<Tree>,
+    current_stage_tree: Option<Arc<Tree>>,
     current_stage_tree_hash: Option<EntryHash>,
     db: Arc<MerkleStorageKV>,
+    /// decoded trees read from (or written to) database
+    tree_cache: Arc<TreeCache>,
     /// all entries in current staging area
     staged: Vec<(EntryHash, RefCnt, Entry)>,
     /// HashMap for looking up entry index in self.staged by hash
@@ -155,6 +191,8 @@ pub enum MerkleError {
     },
     #[fail(display = "Serialization error: {:?}", error)]
     SerializationError { error: bincode::Error },
+    #[fail(display = "Entry encoding error: {}", error)]
+    EncodingError { error: EncodingError },
 
     /// Internal unrecoverable bugs that should never occur
     #[fail(display = "No root retrieved for this commit!")]
@@ -181,6 +219,11 @@ pub enum MerkleError {
     ValueNotFound { key: String },
     #[fail(display = "Cannot search for an empty key.")]
     KeyEmpty,
+    #[fail(
+        display = "Commit {} is not an ancestor of commit {}.",
+        ancestor, commit
+    )]
+    CommitNotAncestor { ancestor: String, commit: String },
     #[fail(display = "Failed to convert hash to array: {}", error)]
     HashConversionError { error: TryFromSliceError },
 }
@@ -197,6 +240,12 @@ impl From<bincode::Error> for MerkleError {
     }
 }
 
+impl From<EncodingError> for MerkleError {
+    fn from(error: EncodingError) -> Self {
+        MerkleError::EncodingError { error }
+    }
+}
+
 impl From<TryFromSliceError> for MerkleError {
     fn from(error: TryFromSliceError) -> Self {
         MerkleError::HashConversionError { error }
@@ -238,6 +287,19 @@ pub type PerPathOperationStats = HashMap<String, OperationLatencyStats>;
 pub struct MerklePerfStats {
     pub global: OperationLatencyStats,
     pub perpath: PerPathOperationStats,
+    /// counters of the cache of decoded trees, misses include all entries read from database
+    pub tree_cache: LruCacheStats,
+    /// sizes of entries written by commits
+    pub encoding: EncodingStats,
+}
+
+/// Result of the migration of stored entries to the compact encoding
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct EncodingMigrationStats {
+    /// count of all stored entries
+    pub checked_entries: u64,
+    /// sizes of the legacy entries re-encoded by the migration
+    pub migrated: EncodingStats,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -277,79 +339,201 @@ pub enum StringTreeEntry {
     Null,
 }
 
-fn encode_irmin_node_kind(kind: &NodeKind) -> [u8; 8] {
-    match kind {
-        NodeKind::NonLeaf => [0, 0, 0, 0, 0, 0, 0, 0],
-        NodeKind::Leaf => [255, 0, 0, 0, 0, 0, 0, 0],
+/// Value which differs between two commits: added (no old value), removed (no new value)
+/// or modified (both values)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextValueDiff {
+    pub key: ContextKey,
+    pub old_value: Option<ContextValue>,
+    pub new_value: Option<ContextValue>,
+}
+
+/// Value of the key set by the commit (None if the key has no value)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextKeyChange {
+    pub commit_hash: EntryHash,
+    pub value: Option<ContextValue>,
+}
+
+/// Inconsistency of the stored entry found by [MerkleStorage::check_entries]
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryIssue {
+    /// entry is referenced, but it is not stored
+    Missing { hash: EntryHash },
+    /// stored bytes cannot be decoded
+    Undecodable { hash: EntryHash },
+    /// hash of the stored entry differs from the hash it is stored (and referenced) under
+    HashMismatch {
+        hash: EntryHash,
+        actual_hash: EntryHash,
+    },
+    /// entry is referenced as other kind of entry (commit, tree, blob) than the stored one
+    UnexpectedKind { hash: EntryHash, expected: String },
+}
+
+impl EntryIssue {
+    pub fn hash(&self) -> &EntryHash {
+        match self {
+            EntryIssue::Missing { hash }
+            | EntryIssue::Undecodable { hash }
+            | EntryIssue::HashMismatch { hash, .. }
+            | EntryIssue::UnexpectedKind { hash, .. } => hash,
+        }
+    }
+}
+
+impl fmt::Display for EntryIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = HashType::ContextHash.hash_to_b58check(self.hash());
+        match self {
+            EntryIssue::Missing { .. } => write!(f, "missing entry: {}", hash),
+            EntryIssue::Undecodable { .. } => write!(f, "undecodable entry: {}", hash),
+            EntryIssue::HashMismatch { actual_hash, .. } => write!(
+                f,
+                "entry: {} has hash: {}",
+                hash,
+                HashType::ContextHash.hash_to_b58check(actual_hash)
+            ),
+            EntryIssue::UnexpectedKind { expected, .. } => {
+                write!(f, "entry: {} is not {}", hash, expected)
+            }
+        }
+    }
+}
+
+/// Hashes of the entries on the path to the key (root tree first, the key entry last),
+/// the path ends early if the key is missing
+type KeyPathHashes = Vec<EntryHash>;
+
+/// Position of walking the history of the key through parent commits, see [MerkleStorage::walk_key_history]
+#[derive(Debug, Clone)]
+pub struct KeyHistoryCursor {
+    key: ContextKey,
+    from_commit_hash: EntryHash,
+    to_commit_hash: EntryHash,
+    /// only every n-th commit (counted from `to_commit_hash`) is checked
+    step: usize,
+    /// next commit to visit, None when finished
+    next_commit_hash: Option<EntryHash>,
+    /// count of visited commits
+    visited: usize,
+    /// last checked commit, which is reported, if its value differs from the next checked (older) commit
+    pending: Option<(EntryHash, KeyPathHashes)>,
+}
+
+impl KeyHistoryCursor {
+    /// Walks from `to_commit_hash` back to its ancestor `from_commit_hash`
+    pub fn new(
+        key: ContextKey,
+        from_commit_hash: EntryHash,
+        to_commit_hash: EntryHash,
+        step: usize,
+    ) -> Self {
+        Self {
+            key,
+            from_commit_hash,
+            to_commit_hash,
+            step: step.max(1),
+            next_commit_hash: Some(to_commit_hash),
+            visited: 0,
+            pending: None,
+        }
+    }
+
+    pub fn to_commit_hash(&self) -> &EntryHash {
+        &self.to_commit_hash
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_commit_hash.is_none()
+    }
+
+    /// Stops walking, remaining commits are not visited
+    pub fn finish(&mut self) {
+        self.next_commit_hash = None;
+        self.pending = None;
+    }
+}
+
+impl MerkleProofStep {
+    fn new(key: &str, siblings: Tree) -> Self {
+        MerkleProofStep {
+            key: key.to_string(),
+            siblings: siblings
+                .into_iter()
+                .map(|(name, node)| (name, proof_node(node)))
+                .collect(),
+        }
+    }
+}
+
+fn proof_node(node: Node) -> MerkleProofNode {
+    MerkleProofNode {
+        leaf: matches!(node.node_kind, NodeKind::Leaf),
+        hash: node.entry_hash,
     }
 }
 
 // Calculates hash of tree
-// uses BLAKE2 binary 256 length hash function
-// hash is calculated as:
-// <number of child nodes (8 bytes)><CHILD NODE>
-// where:
-// - CHILD NODE - <NODE TYPE><length of string (1 byte)><string/path bytes><length of hash (8bytes)><hash bytes>
-// - NODE TYPE - leaf node(0xff0000000000000000) or internal node (0x0000000000000000)
+// see [merkle_proof::hash_tree_nodes] for the encoding
 fn hash_tree(tree: &Tree) -> Result<EntryHash, MerkleError> {
-    let mut hasher = VarBlake2b::new(HASH_LEN).unwrap();
-
-    hasher.update(&(tree.len() as u64).to_be_bytes());
-    tree.iter().for_each(|(k, v)| {
-        hasher.update(encode_irmin_node_kind(&v.node_kind));
-        hasher.update(&[k.len() as u8]);
-        hasher.update(&k.clone().into_bytes());
-        hasher.update(&(HASH_LEN as u64).to_be_bytes());
-        hasher.update(&v.entry_hash);
-    });
-
-    Ok(hasher.finalize_boxed().as_ref().try_into()?)
+    Ok(merkle_proof::hash_tree_nodes(
+        tree.len(),
+        tree.iter().map(|(k, v)| {
+            (
+                k.as_str(),
+                matches!(v.node_kind, NodeKind::Leaf),
+                &v.entry_hash,
+            )
+        }),
+    )?)
+}
+
+// Approximate memory used by the decoded tree (key, node and map overhead for every child)
+fn tree_size(tree: &Tree) -> usize {
+    const CHILD_OVERHEAD: usize = mem::size_of::<String>() + mem::size_of::<Node>() + 16;
+    mem::size_of::<Tree>()
+        + tree
+            .keys()
+            .map(|name| name.len() + CHILD_OVERHEAD)
+            .sum::<usize>()
 }
 
 // Calculates hash of BLOB
-// uses BLAKE2 binary 256 length hash function
-// hash is calculated as <length of data (8 bytes)><data>
+// see [merkle_proof::hash_blob] for the encoding
 fn hash_blob(blob: &ContextValue) -> Result<EntryHash, MerkleError> {
-    let mut hasher = VarBlake2b::new(HASH_LEN).unwrap();
-    hasher.update(&(blob.len() as u64).to_be_bytes());
-    hasher.update(blob);
-
-    Ok(hasher.finalize_boxed().as_ref().try_into()?)
+    Ok(merkle_proof::hash_blob(blob)?)
 }
 
 // Calculates hash of commit
-// uses BLAKE2 binary 256 length hash function
-// hash is calculated as:
-// <hash length (8 bytes)><tree hash bytes>
-// <length of parent hash (8bytes)><parent hash bytes>
-// <time in epoch format (8bytes)
-// <commit author name length (8bytes)><commit author name bytes>
-// <commit message length (8bytes)><commit message bytes>
+// see [merkle_proof::hash_commit] for the encoding
 fn hash_commit(commit: &Commit) -> Result<EntryHash, MerkleError> {
-    let mut hasher = VarBlake2b::new(HASH_LEN).unwrap();
-    hasher.update(&(HASH_LEN as u64).to_be_bytes());
-    hasher.update(&commit.root_hash);
-
-    if commit.parent_commit_hash.is_none() {
-        hasher.update(&(0_u64).to_be_bytes());
-    } else {
-        hasher.update(&(1_u64).to_be_bytes()); // # of parents; we support only 1
-        hasher.update(&(commit.parent_commit_hash.unwrap().len() as u64).to_be_bytes());
-        hasher.update(&commit.parent_commit_hash.unwrap());
-    }
-    hasher.update(&(commit.time as u64).to_be_bytes());
-    hasher.update(&(commit.author.len() as u64).to_be_bytes());
-    hasher.update(&commit.author.clone().into_bytes());
-    hasher.update(&(commit.message.len() as u64).to_be_bytes());
-    hasher.update(&commit.message.clone().into_bytes());
-
-    Ok(hasher.finalize_boxed().as_ref().try_into()?)
+    Ok(merkle_proof::hash_commit(
+        &commit.root_hash,
+        commit.parent_commit_hash.as_ref(),
+        commit.time,
+        &commit.author,
+        &commit.message,
+    )?)
 }
 
 impl MerkleStorage {
     pub fn new(db: Arc<MerkleStorageKV>) -> Self {
+        Self::with_tree_cache(db, DEFAULT_TREE_CACHE_CAPACITY)
+    }
+
+    /// Creates storage with cache of decoded trees limited to `tree_cache_capacity` bytes (0 disables the cache)
+    pub fn with_tree_cache(db: Arc<MerkleStorageKV>, tree_cache_capacity: usize) -> Self {
+        Self::with_shared_tree_cache(
+            db,
+            Arc::new(Mutex::new(SizedLruCache::new(tree_cache_capacity))),
+        )
+    }
+
+    fn with_shared_tree_cache(db: Arc<MerkleStorageKV>, tree_cache: Arc<TreeCache>) -> Self {
         MerkleStorage {
             db,
+            tree_cache,
             staged: Vec::new(),
             staged_indices: HashMap::new(),
             current_stage_tree: None,
@@ -358,11 +542,21 @@ impl MerkleStorage {
             perf_stats: MerklePerfStats {
                 global: HashMap::new(),
                 perpath: HashMap::new(),
+                tree_cache: LruCacheStats::default(),
+                encoding: EncodingStats::default(),
             },
             actions: Arc::new(Vec::new()),
         }
     }
 
+    /// Returns reader of committed contexts, which can be used without the lock of this storage
+    pub fn reader(&self) -> MerkleReader {
+        MerkleReader {
+            db: self.db.clone(),
+            tree_cache: self.tree_cache.clone(),
+        }
+    }
+
     /// Get value from current staged root
     pub fn get(&mut self, key: &ContextKey) -> Result<ContextValue, MerkleError> {
         let instant = Instant::now();
@@ -370,10 +564,8 @@ impl MerkleStorage {
         // note: this can be slow if there are a lot of actions
         self.apply_actions_to_staging_area()?;
 
-        let root = &self.get_staged_root()?;
-        let root_hash = hash_tree(&root)?;
-
-        let rv = self.get_from_tree(&root_hash, key);
+        let root = self.get_staged_root()?;
+        let rv = self.get_from_tree(root, key);
         self.update_execution_stats("Get".to_string(), Some(&key), &instant);
         if rv.is_err() {
             Ok(Vec::new())
@@ -389,10 +581,8 @@ impl MerkleStorage {
         // note: this can be slow if there are a lot of actions
         self.apply_actions_to_staging_area()?;
 
-        let root = &self.get_staged_root()?;
-        let root_hash = hash_tree(&root)?;
-
-        let rv = self.value_exists(&root_hash, key);
+        let root = self.get_staged_root()?;
+        let rv = self.value_exists(root, key);
         self.update_execution_stats("Mem".to_string(), Some(&key), &instant);
         rv
     }
@@ -404,10 +594,8 @@ impl MerkleStorage {
         // note: this can be slow if there are a lot of actions
         self.apply_actions_to_staging_area()?;
 
-        let root = &self.get_staged_root()?;
-        let root_hash = hash_tree(&root)?;
-
-        let rv = self.directory_exists(&root_hash, key);
+        let root = self.get_staged_root()?;
+        let rv = self.directory_exists(root, key);
         self.update_execution_stats("DirMem".to_string(), Some(&key), &instant);
         rv
     }
@@ -430,18 +618,19 @@ impl MerkleStorage {
         let instant = Instant::now();
         let commit = self.get_commit(commit_hash)?;
 
-        let rv = self.get_from_tree(&commit.root_hash, key);
+        let rv = self
+            .get_tree(&commit.root_hash)
+            .and_then(|root| self.get_from_tree(root, key));
         self.update_execution_stats("GetKeyFromHistory".to_string(), Some(&key), &instant);
         rv
     }
 
-    fn value_exists(&self, root_hash: &EntryHash, key: &ContextKey) -> Result<bool, MerkleError> {
+    fn value_exists(&self, root: Arc<Tree>, key: &ContextKey) -> Result<bool, MerkleError> {
         let mut full_path = key.clone();
         let file = full_path.pop().ok_or(MerkleError::KeyEmpty)?;
         let path = full_path;
         // find tree by path
-        let root = self.get_tree(root_hash)?;
-        let node = self.find_tree(&root, &path);
+        let node = self.find_tree(root, &path);
         if node.is_err() {
             return Ok(false);
         }
@@ -454,14 +643,9 @@ impl MerkleStorage {
         }
     }
 
-    fn directory_exists(
-        &self,
-        root_hash: &EntryHash,
-        key: &ContextKey,
-    ) -> Result<bool, MerkleError> {
+    fn directory_exists(&self, root: Arc<Tree>, key: &ContextKey) -> Result<bool, MerkleError> {
         // find tree by path
-        let root = self.get_tree(root_hash)?;
-        let node = self.find_tree(&root, &key);
+        let node = self.find_tree(root, &key);
         if node.is_err() || node?.is_empty() {
             Ok(false)
         } else {
@@ -471,15 +655,14 @@ impl MerkleStorage {
 
     fn get_from_tree(
         &self,
-        root_hash: &EntryHash,
+        root: Arc<Tree>,
         key: &ContextKey,
     ) -> Result<ContextValue, MerkleError> {
         let mut full_path = key.clone();
         let file = full_path.pop().ok_or(MerkleError::KeyEmpty)?;
         let path = full_path;
         // find tree by path
-        let root = self.get_tree(root_hash)?;
-        let node = self.find_tree(&root, &path)?;
+        let node = self.find_tree(root, &path)?;
 
         // get file node from tree
         let node = match node.get(&file) {
@@ -575,46 +758,528 @@ impl MerkleStorage {
 
     /// Get context tree under given prefix in string form (for JSON)
     /// depth - None returns full tree
+    ///
+    /// Follows octez `context/raw/bytes` semantics: a value under the prefix is returned as
+    /// hex string, subtrees beyond depth are cut (null) and `ValueNotFound` is returned
+    /// if there is neither a value nor a tree under the prefix.
     pub fn get_context_tree_by_prefix(
         &mut self,
         context_hash: &EntryHash,
         prefix: &ContextKey,
         depth: Option<usize>,
     ) -> Result<StringTreeEntry, MerkleError> {
-        if let Some(0) = depth {
-            return Ok(StringTreeEntry::Null);
-        }
-
         let instant = Instant::now();
-        let mut out = StringTreeMap::new();
+        let rv = self._get_context_tree_by_prefix(context_hash, prefix, depth);
+        self.update_execution_stats(
+            "GetContextTreeByPrefix".to_string(),
+            Some(&prefix),
+            &instant,
+        );
+        rv
+    }
+
+    fn _get_context_tree_by_prefix(
+        &self,
+        context_hash: &EntryHash,
+        prefix: &ContextKey,
+        depth: Option<usize>,
+    ) -> Result<StringTreeEntry, MerkleError> {
         let commit = self.get_commit(context_hash)?;
         let root_tree = self.get_tree(&commit.root_hash)?;
-        let prefixed_tree = self.find_tree(&root_tree, prefix)?;
+        let entry = match self.find_entry(&root_tree, prefix)? {
+            Some(entry) => entry,
+            None => {
+                return Err(MerkleError::ValueNotFound {
+                    key: self.key_to_string(prefix),
+                })
+            }
+        };
 
-        for (key, child_node) in prefixed_tree.iter() {
-            let entry = self.get_entry(&child_node.entry_hash)?;
-            let delimiter: &str;
-            if prefix.is_empty() {
-                delimiter = "";
-            } else {
-                delimiter = "/";
+        self.get_context_recursive(&self.key_to_string(prefix), &entry, depth)
+    }
+
+    /// Get value under the key (None if there is no value) together with the proof of its
+    /// presence or absence in the commit identified by context hash
+    pub fn get_merkle_proof(
+        &mut self,
+        context_hash: &EntryHash,
+        key: &ContextKey,
+    ) -> Result<(Option<ContextValue>, MerkleProof), MerkleError> {
+        let instant = Instant::now();
+        let rv = self._get_merkle_proof(context_hash, key);
+        self.update_execution_stats("GetMerkleProof".to_string(), Some(&key), &instant);
+        rv
+    }
+
+    fn _get_merkle_proof(
+        &self,
+        context_hash: &EntryHash,
+        key: &ContextKey,
+    ) -> Result<(Option<ContextValue>, MerkleProof), MerkleError> {
+        if key.is_empty() {
+            return Err(MerkleError::KeyEmpty);
+        }
+        let commit = self.get_commit(context_hash)?;
+        let mut tree = Tree::clone(&self.get_tree(&commit.root_hash)?);
+        let mut steps = Vec::with_capacity(key.len());
+        let mut value = None;
+        let mut end = MerkleProofEnd::Missing;
+
+        // walk down the path and collect siblings of every tree on the way
+        for (idx, chunk) in key.iter().enumerate() {
+            let child_node = tree.remove(chunk);
+            steps.push(MerkleProofStep::new(chunk, tree));
+            let child_node = match child_node {
+                Some(child_node) => child_node,
+                None => break,
+            };
+
+            let is_last = idx + 1 == key.len();
+            match self.get_entry(&child_node.entry_hash)? {
+                Entry::Tree(child) if !is_last => tree = child,
+                Entry::Blob(blob) if is_last => {
+                    value = Some(blob);
+                    end = MerkleProofEnd::Value;
+                    break;
+                }
+                Entry::Commit(_) => {
+                    return Err(MerkleError::FoundUnexpectedStructure {
+                        sought: "Tree/Blob".to_string(),
+                        found: "commit".to_string(),
+                    })
+                }
+                // value in the middle of the key or a tree under the whole key
+                _ => {
+                    end = MerkleProofEnd::Node(proof_node(child_node));
+                    break;
+                }
             }
+        }
 
-            // construct full path as Tree key is only one chunk of it
-            let fullpath = self.key_to_string(prefix) + delimiter + key;
-            let rdepth = depth.map(|d| d - 1);
-            out.insert(
-                key.to_owned(),
-                self.get_context_recursive(&fullpath, &entry, rdepth)?,
-            );
+        let proof = MerkleProof {
+            parent_commit_hash: commit.parent_commit_hash,
+            time: commit.time,
+            author: commit.author,
+            message: commit.message,
+            steps,
+            end,
+        };
+        Ok((value, proof))
+    }
+
+    /// Get all values under given prefix which differ between two commits (ordered by key).
+    /// Trees are walked in parallel and subtrees with equal hashes are skipped, so the cost
+    /// depends on the size of the change, not on the size of the context.
+    /// limit - at most this count of (first) changed values is returned, None returns all of them
+    pub fn get_context_diff(
+        &mut self,
+        from_context_hash: &EntryHash,
+        to_context_hash: &EntryHash,
+        prefix: &ContextKey,
+        limit: Option<usize>,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        let instant = Instant::now();
+        let rv = self._get_context_diff(from_context_hash, to_context_hash, prefix, limit);
+        self.update_execution_stats("GetContextDiff".to_string(), Some(&prefix), &instant);
+        rv
+    }
+
+    fn _get_context_diff(
+        &self,
+        from_context_hash: &EntryHash,
+        to_context_hash: &EntryHash,
+        prefix: &ContextKey,
+        limit: Option<usize>,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        let from_commit = self.get_commit(from_context_hash)?;
+        let to_commit = self.get_commit(to_context_hash)?;
+        let from_hash = self.find_entry_hash(&from_commit.root_hash, prefix)?;
+        let to_hash = self.find_entry_hash(&to_commit.root_hash, prefix)?;
+
+        let mut diff = Vec::new();
+        self.diff_recursively(
+            &mut prefix.clone(),
+            from_hash.as_ref(),
+            to_hash.as_ref(),
+            limit,
+            &mut diff,
+        )?;
+        if let Some(limit) = limit {
+            diff.truncate(limit);
+        }
+        Ok(diff)
+    }
+
+    /// Get all values which differ between the base commit (empty context if None) and the commit (ordered by key).
+    pub fn get_context_changes(
+        &mut self,
+        base_context_hash: Option<&EntryHash>,
+        context_hash: &EntryHash,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        let instant = Instant::now();
+        let rv = self._get_context_changes(base_context_hash, context_hash);
+        self.update_execution_stats("GetContextChanges".to_string(), None, &instant);
+        rv
+    }
+
+    fn _get_context_changes(
+        &self,
+        base_context_hash: Option<&EntryHash>,
+        context_hash: &EntryHash,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        let base_root_hash = match base_context_hash {
+            Some(base_context_hash) => Some(self.get_commit(base_context_hash)?.root_hash),
+            None => None,
+        };
+        let root_hash = self.get_commit(context_hash)?.root_hash;
+
+        let mut diff = Vec::new();
+        self.diff_recursively(
+            &mut Vec::new(),
+            base_root_hash.as_ref(),
+            Some(&root_hash),
+            None,
+            &mut diff,
+        )?;
+        Ok(diff)
+    }
+
+    // TODO: recursion is risky (stack overflow), but context depth is limited
+    fn diff_recursively(
+        &self,
+        path: &mut ContextKey,
+        old_hash: Option<&EntryHash>,
+        new_hash: Option<&EntryHash>,
+        limit: Option<usize>,
+        diff: &mut Vec<ContextValueDiff>,
+    ) -> Result<(), MerkleError> {
+        // identical (or both missing) subtrees have no changes
+        if old_hash == new_hash {
+            return Ok(());
+        }
+        // enough changes collected, the rest of the trees is not walked
+        if let Some(limit) = limit {
+            if diff.len() >= limit {
+                return Ok(());
+            }
         }
 
-        self.update_execution_stats(
-            "GetContextTreeByPrefix".to_string(),
-            Some(&prefix),
-            &instant,
-        );
-        Ok(StringTreeEntry::Tree(out))
+        let old = old_hash
+            .map(|hash| self.get_entry_shared(hash))
+            .transpose()?;
+        let new = new_hash
+            .map(|hash| self.get_entry_shared(hash))
+            .transpose()?;
+        if let (Some(SharedEntry::Commit(_)), _) | (_, Some(SharedEntry::Commit(_))) = (&old, &new)
+        {
+            return Err(MerkleError::FoundUnexpectedStructure {
+                sought: "Tree/Blob".to_string(),
+                found: "commit".to_string(),
+            });
+        }
+
+        match (old, new) {
+            (Some(SharedEntry::Blob(old_value)), Some(SharedEntry::Blob(new_value))) => {
+                diff.push(ContextValueDiff {
+                    key: path.clone(),
+                    old_value: Some(old_value),
+                    new_value: Some(new_value),
+                });
+            }
+            (old, new) => {
+                // value replaced by tree (or vice versa) or changed tree
+                let empty_tree = Arc::new(Tree::new());
+                let mut old_tree = empty_tree.clone();
+                let mut new_tree = empty_tree;
+                match old {
+                    Some(SharedEntry::Blob(old_value)) => diff.push(ContextValueDiff {
+                        key: path.clone(),
+                        old_value: Some(old_value),
+                        new_value: None,
+                    }),
+                    Some(SharedEntry::Tree(tree)) => old_tree = tree,
+                    _ => (),
+                }
+                match new {
+                    Some(SharedEntry::Blob(new_value)) => diff.push(ContextValueDiff {
+                        key: path.clone(),
+                        old_value: None,
+                        new_value: Some(new_value),
+                    }),
+                    Some(SharedEntry::Tree(tree)) => new_tree = tree,
+                    _ => (),
+                }
+
+                let names: BTreeSet<&String> = old_tree.keys().chain(new_tree.keys()).collect();
+                for name in names {
+                    path.push(name.clone());
+                    self.diff_recursively(
+                        path,
+                        old_tree.get(name).map(|node| &node.entry_hash),
+                        new_tree.get(name).map(|node| &node.entry_hash),
+                        limit,
+                        diff,
+                    )?;
+                    path.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks at most `max_commits` commits of the key history (through parent commits, newest first)
+    /// and returns commits, which changed value of the key against the previous (older) checked commit.
+    /// The oldest (`from`) commit is always returned with its value.
+    ///
+    /// Only the path to the key is traversed and it is reused, where a subtree on the path has the same hash
+    /// as in the previously checked commit.
+    pub fn walk_key_history(
+        &self,
+        cursor: &mut KeyHistoryCursor,
+        max_commits: usize,
+    ) -> Result<Vec<ContextKeyChange>, MerkleError> {
+        if cursor.key.is_empty() {
+            return Err(MerkleError::KeyEmpty);
+        }
+
+        let mut changes = Vec::new();
+        for _ in 0..max_commits {
+            let commit_hash = match cursor.next_commit_hash.take() {
+                Some(commit_hash) => commit_hash,
+                None => break,
+            };
+            let commit = self.get_commit(&commit_hash)?;
+            let is_from_commit = commit_hash == cursor.from_commit_hash;
+
+            if is_from_commit || cursor.visited % cursor.step == 0 {
+                let path_hashes = self.key_path_hashes(
+                    &commit.root_hash,
+                    &cursor.key,
+                    cursor.pending.as_ref().map(|(_, path_hashes)| path_hashes),
+                )?;
+                if let Some((pending_commit_hash, pending_path_hashes)) = cursor.pending.take() {
+                    if pending_path_hashes.get(cursor.key.len())
+                        != path_hashes.get(cursor.key.len())
+                    {
+                        changes.push(self.key_change(
+                            pending_commit_hash,
+                            &pending_path_hashes,
+                            cursor.key.len(),
+                        )?);
+                    }
+                }
+                cursor.pending = Some((commit_hash, path_hashes));
+            }
+
+            if is_from_commit {
+                if let Some((commit_hash, path_hashes)) = cursor.pending.take() {
+                    changes.push(self.key_change(commit_hash, &path_hashes, cursor.key.len())?);
+                }
+                break;
+            }
+
+            match commit.parent_commit_hash {
+                Some(parent_commit_hash) => cursor.next_commit_hash = Some(parent_commit_hash),
+                None => {
+                    return Err(MerkleError::CommitNotAncestor {
+                        ancestor: HashType::ContextHash.hash_to_b58check(&cursor.from_commit_hash),
+                        commit: HashType::ContextHash.hash_to_b58check(&cursor.to_commit_hash),
+                    })
+                }
+            }
+            cursor.visited += 1;
+        }
+
+        Ok(changes)
+    }
+
+    // Collect hashes on the path to the key, path is not traversed further, if subtree is the same as in previous path
+    fn key_path_hashes(
+        &self,
+        root_hash: &EntryHash,
+        key: &ContextKey,
+        previous_path_hashes: Option<&KeyPathHashes>,
+    ) -> Result<KeyPathHashes, MerkleError> {
+        let mut path_hashes = Vec::with_capacity(key.len() + 1);
+        let mut hash = *root_hash;
+        for (depth, name) in key.iter().enumerate() {
+            if let Some(previous_path_hashes) = previous_path_hashes {
+                if previous_path_hashes.get(depth) == Some(&hash) {
+                    // unchanged subtree
+                    path_hashes.extend_from_slice(&previous_path_hashes[depth..]);
+                    return Ok(path_hashes);
+                }
+            }
+            path_hashes.push(hash);
+
+            let tree = match self.get_entry_shared(&hash)? {
+                SharedEntry::Tree(tree) => tree,
+                _ => return Ok(path_hashes),
+            };
+            match tree.get(name) {
+                Some(node) => hash = node.entry_hash,
+                None => return Ok(path_hashes),
+            }
+        }
+        path_hashes.push(hash);
+        Ok(path_hashes)
+    }
+
+    fn key_change(
+        &self,
+        commit_hash: EntryHash,
+        path_hashes: &KeyPathHashes,
+        key_len: usize,
+    ) -> Result<ContextKeyChange, MerkleError> {
+        let value = match path_hashes.get(key_len) {
+            Some(hash) => match self.get_entry_shared(hash)? {
+                SharedEntry::Blob(value) => Some(value),
+                _ => None,
+            },
+            None => None,
+        };
+        Ok(ContextKeyChange { commit_hash, value })
+    }
+
+    /// Re-hashes the stored commit and all entries reachable from its root tree and returns found inconsistencies
+    /// together with the count of checked entries.
+    ///
+    /// Entries in `checked` are skipped (with their subtrees) and all checked entries are added to it,
+    /// so consecutive commits re-hash only the entries they introduced. Parent commits are not checked.
+    /// Only the database is checked, staging area is ignored.
+    pub fn check_entries(
+        &self,
+        commit_hash: &EntryHash,
+        checked: &mut HashSet<EntryHash>,
+    ) -> Result<(usize, Vec<EntryIssue>), MerkleError> {
+        let mut checked_count = 0;
+        let mut issues = Vec::new();
+        let mut to_check = vec![(*commit_hash, EntryKind::Commit)];
+
+        while let Some((hash, expected_kind)) = to_check.pop() {
+            if !checked.insert(hash) {
+                continue;
+            }
+            checked_count += 1;
+
+            let entry: Entry = match self.db.get(&hash)? {
+                Some(entry_bytes) => match merkle_encoding::decode_entry(&entry_bytes) {
+                    Ok(entry) => entry,
+                    Err(_) => {
+                        issues.push(EntryIssue::Undecodable { hash });
+                        continue;
+                    }
+                },
+                None => {
+                    issues.push(EntryIssue::Missing { hash });
+                    continue;
+                }
+            };
+
+            let actual_hash = self.hash_entry(&entry)?;
+            if actual_hash != hash {
+                issues.push(EntryIssue::HashMismatch { hash, actual_hash });
+                continue;
+            }
+
+            match (entry, expected_kind) {
+                (Entry::Commit(commit), EntryKind::Commit) => {
+                    to_check.push((commit.root_hash, EntryKind::Tree))
+                }
+                (Entry::Tree(tree), EntryKind::Tree) => {
+                    for node in tree.values() {
+                        let kind = match node.node_kind {
+                            NodeKind::Leaf => EntryKind::Blob,
+                            NodeKind::NonLeaf => EntryKind::Tree,
+                        };
+                        to_check.push((node.entry_hash, kind));
+                    }
+                }
+                (Entry::Blob(_), EntryKind::Blob) => (),
+                (_, expected_kind) => issues.push(EntryIssue::UnexpectedKind {
+                    hash,
+                    expected: format!("{:?}", expected_kind).to_lowercase(),
+                }),
+            }
+        }
+
+        Ok((checked_count, issues))
+    }
+
+    /// Re-encodes all entries stored in the legacy (bincode) encoding to the compact encoding,
+    /// see [merkle_encoding]. Hashes of entries are not changed, so the migration can be interrupted and run again.
+    pub fn migrate_entries_encoding(&self) -> Result<EncodingMigrationStats, MerkleError> {
+        let mut stats = EncodingMigrationStats::default();
+        let mut batch = WriteBatch::default();
+        let mut batch_size = 0;
+
+        for (hash, entry_bytes) in self.db.iterator(IteratorMode::Start)? {
+            let (hash, entry_bytes) = (hash?, entry_bytes?);
+            stats.checked_entries += 1;
+            if !merkle_encoding::is_legacy_encoded(&entry_bytes) {
+                continue;
+            }
+
+            let entry = merkle_encoding::decode_entry(&entry_bytes)?;
+            let encoded = merkle_encoding::encode_entry(&entry)?;
+            stats
+                .migrated
+                .add_sized(&encoded, Some(entry_bytes.len() as u64));
+            self.db.put_batch(&mut batch, &hash, &encoded)?;
+            batch_size += 1;
+            if batch_size >= WRITE_BATCH_SIZE {
+                self.db.write_batch(mem::take(&mut batch))?;
+                batch_size = 0;
+            }
+        }
+        self.db.write_batch(batch)?;
+
+        Ok(stats)
+    }
+
+    /// Copies all entries of the commit (the commit, its trees and blobs) from the other storage,
+    /// so the context can be checked out from this storage, returns count of copied entries.
+    ///
+    /// Entries are copied as stored (without re-encoding), parents of the commit are not copied.
+    pub fn copy_context_from(
+        &self,
+        source: &MerkleStorage,
+        commit_hash: &EntryHash,
+    ) -> Result<usize, MerkleError> {
+        let mut copied = HashSet::new();
+        let mut to_copy = vec![*commit_hash];
+        let mut batch = WriteBatch::default();
+        let mut batch_size = 0;
+
+        while let Some(hash) = to_copy.pop() {
+            if !copied.insert(hash) {
+                continue;
+            }
+            let entry_bytes = match source.db.get(&hash)? {
+                Some(entry_bytes) => entry_bytes,
+                None => {
+                    return Err(MerkleError::EntryNotFound {
+                        hash: HashType::ContextHash.hash_to_b58check(&hash),
+                    })
+                }
+            };
+
+            match merkle_encoding::decode_entry(&entry_bytes)? {
+                Entry::Commit(commit) => to_copy.push(commit.root_hash),
+                Entry::Tree(tree) => to_copy.extend(tree.values().map(|node| node.entry_hash)),
+                Entry::Blob(_) => (),
+            }
+
+            self.db.put_batch(&mut batch, &hash, &entry_bytes)?;
+            batch_size += 1;
+            if batch_size >= WRITE_BATCH_SIZE {
+                self.db.write_batch(mem::take(&mut batch))?;
+                batch_size = 0;
+            }
+        }
+        self.db.write_batch(batch)?;
+
+        Ok(copied.len())
     }
 
     /// Construct Vec of all context key-values under given prefix
@@ -633,10 +1298,10 @@ impl MerkleStorage {
 
     fn _get_key_values_by_prefix(
         &self,
-        root_tree: Tree,
+        root_tree: Arc<Tree>,
         prefix: &ContextKey,
     ) -> Result<Option<Vec<(ContextKey, ContextValue)>>, MerkleError> {
-        let prefixed_tree = self.find_tree(&root_tree, prefix)?;
+        let prefixed_tree = self.find_tree(root_tree, prefix)?;
         let mut keyvalues: Vec<(ContextKey, ContextValue)> = Vec::new();
 
         for (key, child_node) in prefixed_tree.iter() {
@@ -702,7 +1367,10 @@ impl MerkleStorage {
         let entry = Entry::Commit(new_commit.clone());
 
         self.put_to_staging_area(&hash_commit(&new_commit)?, entry.clone())?;
-        self.persist_staged_entry_to_db(&entry)?;
+        let mut encoding_stats = mem::take(&mut self.perf_stats.encoding);
+        let persisted = self.persist_staged_entry_to_db(&entry, &mut encoding_stats);
+        self.perf_stats.encoding = encoding_stats;
+        persisted?;
         self.staged = Vec::new();
         self.staged_indices = HashMap::new();
         let last_commit_hash = hash_commit(&new_commit)?;
@@ -761,7 +1429,7 @@ impl MerkleStorage {
         match &self.current_stage_tree {
             None => {
                 let tree = Tree::new();
-                self.current_stage_tree = Some(tree.clone());
+                self.current_stage_tree = Some(Arc::new(tree.clone()));
                 let hash = hash_tree(&tree)?;
                 self.current_stage_tree_hash = Some(hash);
                 self.put_to_staging_area(&hash, Entry::Tree(tree))?;
@@ -829,7 +1497,7 @@ impl MerkleStorage {
                     let new_hash;
                     if let Entry::Tree(root) = root {
                         //TODO: assert that source_tree isn't Tree::new() ?
-                        let source_tree = self.find_tree(&root, &copy.from_key)?;
+                        let source_tree = self.find_tree(Arc::new(root), &copy.from_key)?;
                         let source_tree_hash = hash_tree(&source_tree)?;
                         new_hash = self.compute_new_root_with_change(
                             &root_hash,
@@ -1073,45 +1741,92 @@ impl MerkleStorage {
         }
     }
 
-    /// Find tree by path and return a copy. Return an empty tree if no tree under this path exists or if a blob
-    /// (= value) is encountered along the way.
+    /// Find tree by path, the tree is shared (not copied). Return an empty tree if no tree under this path exists
+    /// or if a blob (= value) is encountered along the way.
     ///
     /// # Arguments
     ///
-    /// * `root` - reference to a tree in which we search
+    /// * `root` - tree in which we search
     /// * `key` - sought path
-    fn find_tree(&self, root: &Tree, key: &[String]) -> Result<Tree, MerkleError> {
-        // terminate recursion if end of path was reached
-        if key.is_empty() {
-            return Ok(root.clone());
+    fn find_tree(&self, root: Arc<Tree>, key: &[String]) -> Result<Arc<Tree>, MerkleError> {
+        let mut tree = root;
+        for chunk in key {
+            // first get node at key
+            let child_node = match tree.get(chunk) {
+                Some(child_node) => child_node.entry_hash,
+                None => return Ok(Arc::new(Tree::new())),
+            };
+
+            // get entry by hash (from staged area or DB)
+            tree = match self.get_entry_shared(&child_node)? {
+                SharedEntry::Tree(tree) => tree,
+                SharedEntry::Blob(_) => return Ok(Arc::new(Tree::new())),
+                SharedEntry::Commit { .. } => {
+                    return Err(MerkleError::FoundUnexpectedStructure {
+                        sought: "Tree/Blob".to_string(),
+                        found: "commit".to_string(),
+                    })
+                }
+            };
         }
+        Ok(tree)
+    }
 
-        // first get node at key
-        let child_node = match root.get(key.first().unwrap()) {
-            Some(hash) => hash,
-            None => {
-                return Ok(Tree::new());
-            }
-        };
+    /// Find entry (value or tree) under the key. Return None if there is nothing under the key
+    /// or if a blob (= value) is encountered along the way.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - reference to a tree in which we search
+    /// * `key` - sought path
+    fn find_entry(&self, root: &Tree, key: &[String]) -> Result<Option<Entry>, MerkleError> {
+        let mut entry = Entry::Tree(root.clone());
+        for chunk in key {
+            let child_node = match &entry {
+                Entry::Tree(tree) => match tree.get(chunk) {
+                    Some(child_node) => child_node.entry_hash,
+                    None => return Ok(None),
+                },
+                Entry::Blob(_) => return Ok(None),
+                Entry::Commit { .. } => {
+                    return Err(MerkleError::FoundUnexpectedStructure {
+                        sought: "Tree/Blob".to_string(),
+                        found: "commit".to_string(),
+                    })
+                }
+            };
+            entry = self.get_entry(&child_node)?;
+        }
+        Ok(Some(entry))
+    }
 
-        // get entry by hash (from staged area or DB)
-        match self.get_entry(&child_node.entry_hash)? {
-            Entry::Tree(tree) => self.find_tree(&tree, &key[1..]),
-            Entry::Blob(_) => Ok(Tree::new()),
-            Entry::Commit { .. } => Err(MerkleError::FoundUnexpectedStructure {
-                sought: "Tree/Blob".to_string(),
-                found: "commit".to_string(),
-            }),
+    /// Find hash of the entry (value or tree) under the key. Return None if there is nothing
+    /// under the key or if a blob (= value) is encountered along the way.
+    fn find_entry_hash(
+        &self,
+        root_hash: &EntryHash,
+        key: &[String],
+    ) -> Result<Option<EntryHash>, MerkleError> {
+        let mut hash = *root_hash;
+        for chunk in key {
+            match self.get_entry_shared(&hash)? {
+                SharedEntry::Tree(tree) => match tree.get(chunk) {
+                    Some(child_node) => hash = child_node.entry_hash,
+                    None => return Ok(None),
+                },
+                _ => return Ok(None),
+            }
         }
+        Ok(Some(hash))
     }
 
     /// Get latest staged tree. If it's empty, init genesis  and return genesis root.
-    fn get_staged_root(&mut self) -> Result<Tree, MerkleError> {
+    fn get_staged_root(&mut self) -> Result<Arc<Tree>, MerkleError> {
         match &self.current_stage_tree {
             None => {
                 let tree = Tree::new();
                 self.put_to_staging_area(&hash_tree(&tree)?, Entry::Tree(tree.clone()))?;
-                Ok(tree)
+                Ok(Arc::new(tree))
             }
             Some(tree) => Ok(tree.clone()),
         }
@@ -1139,15 +1854,25 @@ impl MerkleStorage {
     }
 
     /// Persists an entry and its descendants from staged area to database on disk.
-    fn persist_staged_entry_to_db(&self, entry: &Entry) -> Result<(), MerkleError> {
+    fn persist_staged_entry_to_db(
+        &self,
+        entry: &Entry,
+        encoding_stats: &mut EncodingStats,
+    ) -> Result<(), MerkleError> {
         let mut batch = WriteBatch::default(); // batch containing DB key values to persist
+        let mut trees = Vec::new(); // persisted trees are likely to be read by the next blocks
 
         // build list of entries to be persisted
-        self.get_entries_recursively(entry, &mut batch)?;
+        self.get_entries_recursively(entry, &mut batch, &mut trees, encoding_stats)?;
 
         // atomically write all entries in one batch to DB
         self.db.write_batch(batch)?;
 
+        // only trees stored in DB can be cached
+        for (hash, tree) in trees {
+            self.cache_tree(&hash, tree);
+        }
+
         Ok(())
     }
 
@@ -1156,10 +1881,17 @@ impl MerkleStorage {
         &self,
         entry: &Entry,
         batch: &mut WriteBatch,
+        trees: &mut Vec<(EntryHash, Arc<Tree>)>,
+        encoding_stats: &mut EncodingStats,
     ) -> Result<(), MerkleError> {
         // add entry to batch
-        self.db
-            .put_batch(batch, &self.hash_entry(entry)?, &bincode::serialize(entry)?)?;
+        let hash = self.hash_entry(entry)?;
+        let encoded = merkle_encoding::encode_entry(entry)?;
+        encoding_stats.add(entry, &encoded)?;
+        self.db.put_batch(batch, &hash, &encoded)?;
+        if let Entry::Tree(tree) = entry {
+            trees.push((hash, Arc::new(tree.clone())));
+        }
 
         match entry {
             Entry::Blob(_) => Ok(()),
@@ -1170,7 +1902,9 @@ impl MerkleStorage {
                     .map(
                         |(_, child_node)| match self.staged_get(&child_node.entry_hash) {
                             None => Ok(()),
-                            Some(entry) => self.get_entries_recursively(entry, batch),
+                            Some(entry) => {
+                                self.get_entries_recursively(entry, batch, trees, encoding_stats)
+                            }
                         },
                     )
                     .find_map(|res| match res {
@@ -1181,7 +1915,7 @@ impl MerkleStorage {
             }
             Entry::Commit(commit) => match self.get_entry(&commit.root_hash) {
                 Err(err) => Err(err),
-                Ok(entry) => self.get_entries_recursively(&entry, batch),
+                Ok(entry) => self.get_entries_recursively(&entry, batch, trees, encoding_stats),
             },
         }
     }
@@ -1194,14 +1928,15 @@ impl MerkleStorage {
         }
     }
 
-    fn get_tree(&self, hash: &EntryHash) -> Result<Tree, MerkleError> {
-        match self.get_entry(hash)? {
-            Entry::Tree(tree) => Ok(tree),
-            Entry::Blob(_) => Err(MerkleError::FoundUnexpectedStructure {
+    /// Get tree shared with the tree cache, callers, which change the tree, have to copy it
+    fn get_tree(&self, hash: &EntryHash) -> Result<Arc<Tree>, MerkleError> {
+        match self.get_entry_shared(hash)? {
+            SharedEntry::Tree(tree) => Ok(tree),
+            SharedEntry::Blob(_) => Err(MerkleError::FoundUnexpectedStructure {
                 sought: "tree".to_string(),
                 found: "blob".to_string(),
             }),
-            Entry::Commit { .. } => Err(MerkleError::FoundUnexpectedStructure {
+            SharedEntry::Commit { .. } => Err(MerkleError::FoundUnexpectedStructure {
                 sought: "tree".to_string(),
                 found: "commit".to_string(),
             }),
@@ -1222,31 +1957,73 @@ impl MerkleStorage {
         }
     }
 
+    /// Get entry from tree cache or look up in DB (decoded trees are cached), the tree is a copy,
+    /// which can be changed (e.g. in staging area)
     fn get_entry_db(&self, hash: &EntryHash) -> Result<Entry, MerkleError> {
+        Ok(match self.get_entry_db_shared(hash)? {
+            SharedEntry::Tree(tree) => Entry::Tree(Tree::clone(&tree)),
+            SharedEntry::Blob(blob) => Entry::Blob(blob),
+            SharedEntry::Commit(commit) => Entry::Commit(commit),
+        })
+    }
+
+    /// Get entry from tree cache or look up in DB (decoded trees are cached and shared with the cache)
+    fn get_entry_db_shared(&self, hash: &EntryHash) -> Result<SharedEntry, MerkleError> {
+        // only the Arc is cloned under the lock
+        let cached = self
+            .tree_cache
+            .lock()
+            .expect("lock poisoning")
+            .get(hash)
+            .cloned();
+        if let Some(tree) = cached {
+            return Ok(SharedEntry::Tree(tree));
+        }
+
         let entry_bytes = self.db.get(hash)?;
         match entry_bytes {
             None => Err(MerkleError::EntryNotFound {
                 hash: HashType::ContextHash.hash_to_b58check(hash),
             }),
-            Some(entry_bytes) => Ok(bincode::deserialize(&entry_bytes)?),
+            Some(entry_bytes) => {
+                let entry = SharedEntry::from(merkle_encoding::decode_entry(&entry_bytes)?);
+                if let SharedEntry::Tree(tree) = &entry {
+                    // commits and blobs are not cached, so only trees count as misses
+                    let size = tree_size(tree);
+                    let mut tree_cache = self.tree_cache.lock().expect("lock poisoning");
+                    tree_cache.record_miss();
+                    tree_cache.put(*hash, tree.clone(), size);
+                }
+                Ok(entry)
+            }
         }
     }
+
     /// Get entry from staging area or look up in DB if not found
     fn get_entry(&self, hash: &EntryHash) -> Result<Entry, MerkleError> {
         match self.staged_get(hash) {
-            None => {
-                let entry_bytes = self.db.get(hash)?;
-                match entry_bytes {
-                    None => Err(MerkleError::EntryNotFound {
-                        hash: HashType::ContextHash.hash_to_b58check(hash),
-                    }),
-                    Some(entry_bytes) => Ok(bincode::deserialize(&entry_bytes)?),
-                }
-            }
+            None => self.get_entry_db(hash),
             Some(entry) => Ok(entry.clone()),
         }
     }
 
+    /// Get entry for reading only from staging area or look up in DB if not found,
+    /// trees from DB are not copied
+    fn get_entry_shared(&self, hash: &EntryHash) -> Result<SharedEntry, MerkleError> {
+        match self.staged_get(hash) {
+            None => self.get_entry_db_shared(hash),
+            Some(entry) => Ok(SharedEntry::from(entry.clone())),
+        }
+    }
+
+    fn cache_tree(&self, hash: &EntryHash, tree: Arc<Tree>) {
+        let size = tree_size(&tree);
+        self.tree_cache
+            .lock()
+            .expect("lock poisoning")
+            .put(*hash, tree, size);
+    }
+
     fn get_non_leaf(&self, hash: EntryHash) -> Node {
         Node {
             node_kind: NodeKind::NonLeaf,
@@ -1331,6 +2108,7 @@ impl MerkleStorage {
                 }
             }
         }
+        perf.tree_cache = self.tree_cache.lock().expect("lock poisoning").stats();
         Ok(MerkleStorageStats {
             rocksdb_stats: db_stats,
             perf_stats: perf,
@@ -1395,14 +2173,137 @@ impl MerkleStorage {
     }
 }
 
+/// Creates read-only snapshots of committed contexts.
+///
+/// Reader shares the database and the tree cache with the [MerkleStorage], but not its staging area,
+/// so it does not need the lock of the storage and readers do not block the writer.
+#[derive(Clone)]
+pub struct MerkleReader {
+    db: Arc<MerkleStorageKV>,
+    tree_cache: Arc<TreeCache>,
+}
+
+impl MerkleReader {
+    /// Snapshot of the committed context, fails with [MerkleError::EntryNotFound] for unknown commit
+    pub fn snapshot(&self, commit_hash: &EntryHash) -> Result<MerkleSnapshot, MerkleError> {
+        let storage =
+            MerkleStorage::with_shared_tree_cache(self.db.clone(), self.tree_cache.clone());
+        let root_hash = storage.get_commit(commit_hash)?.root_hash;
+        Ok(MerkleSnapshot {
+            storage,
+            commit_hash: *commit_hash,
+            root_hash,
+        })
+    }
+}
+
+/// Read-only view of the committed context.
+///
+/// Committed entries are never changed, so snapshots can be read concurrently (from more threads)
+/// while new contexts are committed. Reads are not included in the latency stats of the storage.
+pub struct MerkleSnapshot {
+    /// storage with empty staging area, only its read methods are used
+    storage: MerkleStorage,
+    commit_hash: EntryHash,
+    root_hash: EntryHash,
+}
+
+impl MerkleSnapshot {
+    pub fn commit_hash(&self) -> &EntryHash {
+        &self.commit_hash
+    }
+
+    /// Get value of the key, see [MerkleStorage::get_history]
+    pub fn get(&self, key: &ContextKey) -> Result<ContextValue, MerkleError> {
+        let root = self.storage.get_tree(&self.root_hash)?;
+        self.storage.get_from_tree(root, key)
+    }
+
+    /// Check if value exists
+    pub fn mem(&self, key: &ContextKey) -> Result<bool, MerkleError> {
+        let root = self.storage.get_tree(&self.root_hash)?;
+        self.storage.value_exists(root, key)
+    }
+
+    /// Check if directory exists
+    pub fn dirmem(&self, key: &ContextKey) -> Result<bool, MerkleError> {
+        let root = self.storage.get_tree(&self.root_hash)?;
+        self.storage.directory_exists(root, key)
+    }
+
+    /// See [MerkleStorage::get_key_values_by_prefix]
+    pub fn get_key_values_by_prefix(
+        &self,
+        prefix: &ContextKey,
+    ) -> Result<Option<Vec<(ContextKey, ContextValue)>>, MerkleError> {
+        let root_tree = self.storage.get_tree(&self.root_hash)?;
+        self.storage._get_key_values_by_prefix(root_tree, prefix)
+    }
+
+    /// See [MerkleStorage::get_context_tree_by_prefix]
+    pub fn get_context_tree_by_prefix(
+        &self,
+        prefix: &ContextKey,
+        depth: Option<usize>,
+    ) -> Result<StringTreeEntry, MerkleError> {
+        self.storage
+            ._get_context_tree_by_prefix(&self.commit_hash, prefix, depth)
+    }
+
+    /// See [MerkleStorage::get_merkle_proof]
+    pub fn get_merkle_proof(
+        &self,
+        key: &ContextKey,
+    ) -> Result<(Option<ContextValue>, MerkleProof), MerkleError> {
+        self.storage._get_merkle_proof(&self.commit_hash, key)
+    }
+
+    /// Values under the prefix which differ between the (older) commit and this snapshot,
+    /// see [MerkleStorage::get_context_diff]
+    pub fn get_context_diff_from(
+        &self,
+        from_commit_hash: &EntryHash,
+        prefix: &ContextKey,
+        limit: Option<usize>,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        self.storage
+            ._get_context_diff(from_commit_hash, &self.commit_hash, prefix, limit)
+    }
+
+    /// All values which differ between the base commit (empty context if None) and this snapshot,
+    /// see [MerkleStorage::get_context_changes]
+    pub fn get_context_changes_from(
+        &self,
+        base_commit_hash: Option<&EntryHash>,
+    ) -> Result<Vec<ContextValueDiff>, MerkleError> {
+        self.storage
+            ._get_context_changes(base_commit_hash, &self.commit_hash)
+    }
+
+    /// Walks history of the key from this snapshot back to the ancestor, see [MerkleStorage::walk_key_history],
+    /// the cursor has to start at the commit of this snapshot (see [KeyHistoryCursor::to_commit_hash])
+    pub fn walk_key_history(
+        &self,
+        cursor: &mut KeyHistoryCursor,
+        max_commits: usize,
+    ) -> Result<Vec<ContextKeyChange>, MerkleError> {
+        self.storage.walk_key_history(cursor, max_commits)
+    }
+}
+
 #[cfg(test)]
 #[allow(unused_must_use)]
 mod tests {
     // use hex;
     use assert_json_diff::assert_json_eq;
+    use blake2::digest::{Update, VariableOutput};
+    use blake2::VarBlake2b;
     use rocksdb::{Options, DB};
     use std::path::{Path, PathBuf};
-    use std::{env, fs};
+    use std::sync::RwLock;
+    use std::{env, fs, thread};
+
+    use merkle_proof::HASH_LEN;
 
     use super::*;
 
@@ -2182,5 +3083,488 @@ mod tests {
             )
             .unwrap()
         );
+
+        // value under prefix
+        assert_json_eq!(
+            serde_json::json!("0102"),
+            serde_json::to_value(
+                storage
+                    .get_context_tree_by_prefix(
+                        &commit.as_ref().unwrap(),
+                        &vec!["data".to_string(), "c".to_string()],
+                        None
+                    )
+                    .unwrap()
+            )
+            .unwrap()
+        );
+
+        // nothing under prefix
+        assert!(matches!(
+            storage.get_context_tree_by_prefix(
+                &commit.as_ref().unwrap(),
+                &vec!["data".to_string(), "d".to_string()],
+                None
+            ),
+            Err(MerkleError::ValueNotFound { .. })
+        ));
+        assert!(matches!(
+            storage.get_context_tree_by_prefix(
+                &commit.as_ref().unwrap(),
+                &vec!["data".to_string(), "c".to_string(), "x".to_string()],
+                None
+            ),
+            Err(MerkleError::ValueNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_get_merkle_proof() {
+        let db_name = "ms_test_get_merkle_proof";
+        {
+            clean_db(db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let mut storage = get_storage(db_name, &cache);
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_abd: &ContextKey = &vec!["a".to_string(), "b".to_string(), "d".to_string()];
+        let key_x: &ContextKey = &vec!["x".to_string()];
+        storage.set(key_abc, &vec![1, 2]);
+        storage.set(key_abd, &vec![3, 4]);
+        storage.set(key_x, &vec![5]);
+        let commit = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        // inclusion
+        let (value, proof) = storage.get_merkle_proof(&commit, key_abc).unwrap();
+        assert_eq!(value, Some(vec![1, 2]));
+        assert_eq!(proof.end, MerkleProofEnd::Value);
+        assert_eq!(proof.steps.len(), 3);
+        assert!(proof.steps[0].siblings.contains_key("x"));
+        assert!(proof.steps[1].siblings.is_empty());
+        assert!(proof.steps[2].siblings.contains_key("d"));
+        assert!(proof.verify_inclusion(&commit, key_abc, &[1, 2]).is_ok());
+        assert!(proof.verify_inclusion(&commit, key_abc, &[3, 4]).is_err());
+        assert!(proof.verify_inclusion(&commit, key_abd, &[3, 4]).is_err());
+
+        // non-inclusion - missing key, value in the middle of the key, tree under the key
+        let non_existing_keys: Vec<ContextKey> = vec![
+            vec!["a".to_string(), "z".to_string()],
+            vec!["x".to_string(), "y".to_string()],
+            vec!["a".to_string(), "b".to_string()],
+        ];
+        for key in non_existing_keys.iter() {
+            let (value, proof) = storage.get_merkle_proof(&commit, key).unwrap();
+            assert_eq!(value, None);
+            assert!(proof.verify_non_inclusion(&commit, key).is_ok());
+            assert!(proof.verify_non_inclusion(&commit, key_abc).is_err());
+        }
+        assert!(matches!(
+            storage.get_merkle_proof(&commit, &vec![]),
+            Err(MerkleError::KeyEmpty)
+        ));
+    }
+
+    #[test]
+    fn test_get_context_diff() {
+        let db_name = "ms_test_get_context_diff";
+        {
+            clean_db(db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let mut storage = get_storage(db_name, &cache);
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_abd: &ContextKey = &vec!["a".to_string(), "b".to_string(), "d".to_string()];
+        let key_ae: &ContextKey = &vec!["a".to_string(), "e".to_string()];
+        let key_aef: &ContextKey = &vec!["a".to_string(), "e".to_string(), "f".to_string()];
+        let key_x: &ContextKey = &vec!["x".to_string()];
+        storage.set(key_abc, &vec![1]);
+        storage.set(key_abd, &vec![2]);
+        storage.set(key_ae, &vec![3]);
+        storage.set(key_x, &vec![4]);
+        let from = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        storage.set(key_abc, &vec![5]);
+        storage.delete(key_abd);
+        storage.delete(key_ae);
+        storage.set(key_aef, &vec![6]);
+        let to = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        let diff = storage.get_context_diff(&from, &to, &vec![], None).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                ContextValueDiff {
+                    key: key_abc.clone(),
+                    old_value: Some(vec![1]),
+                    new_value: Some(vec![5]),
+                },
+                ContextValueDiff {
+                    key: key_abd.clone(),
+                    old_value: Some(vec![2]),
+                    new_value: None,
+                },
+                ContextValueDiff {
+                    key: key_ae.clone(),
+                    old_value: Some(vec![3]),
+                    new_value: None,
+                },
+                ContextValueDiff {
+                    key: key_aef.clone(),
+                    old_value: None,
+                    new_value: Some(vec![6]),
+                },
+            ]
+        );
+
+        // reversed diff and diff under prefix
+        let diff = storage
+            .get_context_diff(&to, &from, &vec!["a".to_string(), "e".to_string()], None)
+            .unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                ContextValueDiff {
+                    key: key_ae.clone(),
+                    old_value: None,
+                    new_value: Some(vec![3]),
+                },
+                ContextValueDiff {
+                    key: key_aef.clone(),
+                    old_value: Some(vec![6]),
+                    new_value: None,
+                },
+            ]
+        );
+
+        // no changes
+        assert!(storage
+            .get_context_diff(&to, &to, &vec![], None)
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .get_context_diff(&from, &to, &vec!["x".to_string()], None)
+            .unwrap()
+            .is_empty());
+
+        // limited diff contains first changes only
+        let diff = storage
+            .get_context_diff(&from, &to, &vec![], Some(2))
+            .unwrap();
+        assert_eq!(
+            diff.iter().map(|diff| &diff.key).collect::<Vec<_>>(),
+            vec![key_abc, key_abd]
+        );
+        assert!(storage
+            .get_context_diff(&from, &to, &vec![], Some(0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_entries() {
+        let db_name = "ms_test_check_entries";
+        {
+            clean_db(db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let mut storage = get_storage(db_name, &cache);
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_x: &ContextKey = &vec!["x".to_string()];
+
+        storage.set(key_abc, &vec![1]);
+        let first = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+        storage.set(key_x, &vec![2]);
+        let second = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        // commit, root, a, b, blob
+        let mut checked = HashSet::new();
+        assert_eq!(
+            storage.check_entries(&first, &mut checked).unwrap(),
+            (5, vec![])
+        );
+        // only commit, new root and new blob
+        assert_eq!(
+            storage.check_entries(&second, &mut checked).unwrap(),
+            (3, vec![])
+        );
+
+        // remove blob and break tree
+        let blob_hash = hash_blob(&vec![2]).unwrap();
+        storage.db.delete(&blob_hash).unwrap();
+        let tree_hash = storage.get_commit(&first).unwrap().root_hash;
+        storage
+            .db
+            .put(
+                &tree_hash,
+                &bincode::serialize(&Entry::Blob(vec![3])).unwrap(),
+            )
+            .unwrap();
+
+        let (_, issues) = storage.check_entries(&second, &mut HashSet::new()).unwrap();
+        assert_eq!(issues, vec![EntryIssue::Missing { hash: blob_hash }]);
+        let (_, issues) = storage.check_entries(&first, &mut HashSet::new()).unwrap();
+        assert_eq!(
+            issues,
+            vec![EntryIssue::HashMismatch {
+                hash: tree_hash,
+                actual_hash: hash_blob(&vec![3]).unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_copy_context_from() {
+        let source_db_name = "ms_test_copy_context_from_source";
+        let target_db_name = "ms_test_copy_context_from_target";
+        {
+            clean_db(source_db_name);
+            clean_db(target_db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let mut source = get_storage(source_db_name, &cache);
+        let mut target = get_storage(target_db_name, &cache);
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_x: &ContextKey = &vec!["x".to_string()];
+
+        source.set(key_abc, &vec![1]);
+        let first = source
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+        source.set(key_x, &vec![2]);
+        let second = source
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        // commit, root, a, b, two blobs
+        assert_eq!(target.copy_context_from(&source, &second).unwrap(), 6);
+        assert!(target.checkout(&first).is_err());
+        target.checkout(&second).unwrap();
+        assert_eq!(target.get(key_abc).unwrap(), vec![1]);
+        assert_eq!(target.get(key_x).unwrap(), vec![2]);
+
+        // the same changes result in the same commit in both storages
+        source.set(key_x, &vec![3]);
+        target.set(key_x, &vec![3]);
+        assert_eq!(
+            source
+                .commit(0, "Tezos".to_string(), "Genesis".to_string())
+                .unwrap(),
+            target
+                .commit(0, "Tezos".to_string(), "Genesis".to_string())
+                .unwrap()
+        );
+
+        let missing = hash_blob(&vec![4]).unwrap();
+        assert!(target.copy_context_from(&source, &missing).is_err());
+    }
+
+    #[test]
+    fn test_tree_cache() {
+        let db_name = "ms_test_tree_cache";
+        {
+            clean_db(db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let mut storage = get_storage(db_name, &cache);
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        storage.set(key_abc, &vec![1]);
+        let commit = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        // persisted trees (root, a, b) are cached
+        let stats = storage.get_merkle_stats().unwrap().perf_stats.tree_cache;
+        assert_eq!(stats.entries, 3);
+        assert!(stats.size > 0);
+
+        // commit and blob are read from database, but they are not counted as misses
+        assert_eq!(storage.get_history(&commit, key_abc).unwrap(), vec![1]);
+        let stats = storage.get_merkle_stats().unwrap().perf_stats.tree_cache;
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 0);
+
+        // trees read from database are cached
+        drop(storage);
+        let mut storage = get_storage(db_name, &cache);
+        assert_eq!(storage.get_history(&commit, key_abc).unwrap(), vec![1]);
+        assert_eq!(storage.get_history(&commit, key_abc).unwrap(), vec![1]);
+        let stats = storage.get_merkle_stats().unwrap().perf_stats.tree_cache;
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 3);
+
+        // cached trees are shared by readers, not copied
+        let root_hash = storage.get_commit(&commit).unwrap().root_hash;
+        assert!(Arc::ptr_eq(
+            &storage.get_tree(&root_hash).unwrap(),
+            &storage.get_tree(&root_hash).unwrap()
+        ));
+
+        // disabled cache
+        drop(storage);
+        let mut storage = MerkleStorage::with_tree_cache(Arc::new(get_db(db_name, &cache)), 0);
+        assert_eq!(storage.get_history(&commit, key_abc).unwrap(), vec![1]);
+        let stats = storage.get_merkle_stats().unwrap().perf_stats.tree_cache;
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.hits, 0);
+    }
+
+    #[test]
+    fn test_migrate_entries_encoding() {
+        let db_name = "ms_test_migrate_entries_encoding";
+        {
+            clean_db(db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let mut storage = get_storage(db_name, &cache);
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        storage.set(key_abc, &vec![1; 1024]);
+        let commit = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+
+        // commit, root, a, b, blob are written in the compact encoding
+        let written = storage.get_merkle_stats().unwrap().perf_stats.encoding;
+        assert_eq!(written.entries, 5);
+        assert_eq!(written.compressed_blobs, 1);
+        assert!(written.saved_bytes() > 0);
+
+        // rewrite stored entries in the legacy encoding
+        let mut legacy_bytes = 0;
+        let stored: Vec<_> = storage
+            .db
+            .iterator(IteratorMode::Start)
+            .unwrap()
+            .map(|(hash, entry_bytes)| (hash.unwrap(), entry_bytes.unwrap()))
+            .collect();
+        for (hash, entry_bytes) in stored {
+            let entry = merkle_encoding::decode_entry(&entry_bytes).unwrap();
+            let legacy = bincode::serialize(&entry).unwrap();
+            legacy_bytes += legacy.len() as u64;
+            storage.db.put(&hash, &legacy).unwrap();
+        }
+
+        let stats = storage.migrate_entries_encoding().unwrap();
+        assert_eq!(stats.checked_entries, 5);
+        assert_eq!(stats.migrated.entries, 5);
+        // sizes of all migrated entries are measured
+        assert_eq!(stats.migrated.sampled_entries, 5);
+        assert_eq!(stats.migrated.sampled_legacy_bytes, legacy_bytes);
+        assert_eq!(
+            stats.migrated.saved_bytes(),
+            legacy_bytes as i64 - written.compact_bytes as i64
+        );
+        assert_eq!(stats.migrated.compact_bytes, written.compact_bytes);
+
+        // migrated entries are readable and not migrated again
+        drop(storage);
+        let mut storage = MerkleStorage::with_tree_cache(Arc::new(get_db(db_name, &cache)), 0);
+        assert_eq!(
+            storage.get_history(&commit, key_abc).unwrap(),
+            vec![1; 1024]
+        );
+        assert!(storage
+            .check_entries(&commit, &mut HashSet::new())
+            .unwrap()
+            .1
+            .is_empty());
+        let stats = storage.migrate_entries_encoding().unwrap();
+        assert_eq!(stats.checked_entries, 5);
+        assert_eq!(stats.migrated.entries, 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let db_name = "ms_test_snapshot";
+        {
+            clean_db(db_name);
+        }
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024).unwrap();
+        let storage = Arc::new(RwLock::new(get_storage(db_name, &cache)));
+        let reader = storage.read().unwrap().reader();
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_abd: &ContextKey = &vec!["a".to_string(), "b".to_string(), "d".to_string()];
+
+        let commit1 = {
+            let mut storage = storage.write().unwrap();
+            storage.set(key_abc, &vec![1]);
+            storage
+                .commit(0, "Tezos".to_string(), "Genesis".to_string())
+                .unwrap()
+        };
+
+        // snapshot is read while the writer holds the lock and stages (uncommitted) changes
+        let mut writer = storage.write().unwrap();
+        writer.set(key_abc, &vec![2]);
+        writer.set(key_abd, &vec![3]);
+
+        let snapshot = thread::spawn({
+            let reader = reader.clone();
+            let key_abc = key_abc.clone();
+            let key_abd = key_abd.clone();
+            move || {
+                let snapshot = reader.snapshot(&commit1).unwrap();
+                assert_eq!(snapshot.get(&key_abc).unwrap(), vec![1]);
+                assert!(snapshot.mem(&key_abc).unwrap());
+                assert!(!snapshot.mem(&key_abd).unwrap());
+                assert!(snapshot.dirmem(&vec!["a".to_string()]).unwrap());
+                snapshot
+            }
+        })
+        .join()
+        .unwrap();
+
+        let commit2 = writer
+            .commit(0, "Tezos".to_string(), "Block 1".to_string())
+            .unwrap();
+        drop(writer);
+
+        // existing snapshot is not affected by the new commit
+        assert_eq!(snapshot.get(key_abc).unwrap(), vec![1]);
+
+        let snapshot2 = reader.snapshot(&commit2).unwrap();
+        assert_eq!(snapshot2.get(key_abc).unwrap(), vec![2]);
+        assert_eq!(
+            snapshot2
+                .get_key_values_by_prefix(&vec!["a".to_string()])
+                .unwrap()
+                .map(|values| values.len()),
+            Some(2)
+        );
+        assert_eq!(
+            snapshot2
+                .get_context_diff_from(snapshot.commit_hash(), &vec!["a".to_string()], None)
+                .unwrap(),
+            storage
+                .write()
+                .unwrap()
+                .get_context_diff(&commit1, &commit2, &vec!["a".to_string()], None)
+                .unwrap()
+        );
+
+        // unknown commit
+        assert!(matches!(
+            reader.snapshot(&[0; HASH_LEN]),
+            Err(MerkleError::EntryNotFound { .. })
+        ));
     }
 }
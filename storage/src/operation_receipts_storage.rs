@@ -0,0 +1,566 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Receipts of applied operations (statuses, balance updates and originated contracts) extracted
+//! from the operations metadata json returned by the protocol, indexed by the operation hash and by the account.
+//!
+//! Receipts of all applied blocks are indexed (also blocks of forks), receipts are stored per block,
+//! so the operation applied by several blocks has several receipts. Reads are resolved against the
+//! chain of the requested head - only receipts of the blocks of this chain are returned, so nothing
+//! has to be re-indexed after the reorg.
+
+use std::collections::BTreeSet;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crypto::base58::FromBase58CheckError;
+use crypto::hash::{BlockHash, HashType, OperationHash};
+use tezos_messages::base::signature_public_key_hash::ConversionError;
+use tezos_messages::Head;
+
+use crate::context_action_storage::{contract_id_to_contract_address_for_index, ContractAddress};
+use crate::num_from_slice;
+use crate::persistent::backend::WriteBatch;
+use crate::persistent::codec::range_from_idx_len;
+use crate::persistent::database::{Direction, IteratorMode};
+use crate::persistent::{
+    BincodeEncoded, Decoder, Encoder, KeyValueColumn, KeyValueSchema, KeyValueStoreWithSchema,
+    PersistentStorage, SchemaError,
+};
+use crate::{BlockMetaStorage, StorageError};
+
+pub type OperationReceiptsStorageKV =
+    dyn KeyValueStoreWithSchema<OperationReceiptsStorage> + Sync + Send;
+
+/// Change of the balance of the contract (or of the frozen balance of the delegate)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BalanceUpdate {
+    /// contract or freezer
+    pub kind: String,
+    pub contract: Option<String>,
+    pub delegate: Option<String>,
+    /// deposits, fees or rewards (for freezer)
+    pub category: Option<String>,
+    pub cycle: Option<i64>,
+    pub change: i64,
+}
+
+/// Result of one content (or internal operation) of the operation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OperationContentReceipt {
+    pub kind: String,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    /// applied, failed, backtracked or skipped (only manager operations have status)
+    pub status: Option<String>,
+    /// internal operation emitted by the smart contract
+    pub internal: bool,
+    pub balance_updates: Vec<BalanceUpdate>,
+    pub originated_contracts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OperationReceipt {
+    pub block_hash: BlockHash,
+    pub level: i32,
+    pub validation_pass: u8,
+    /// index of the operation in the validation pass
+    pub operation_index: u32,
+    pub contents: Vec<OperationContentReceipt>,
+}
+
+impl BincodeEncoded for OperationReceipt {}
+
+impl OperationReceipt {
+    /// Accounts (contracts and delegates) affected by the operation
+    pub fn accounts(&self) -> BTreeSet<&str> {
+        let mut accounts = BTreeSet::new();
+        for content in &self.contents {
+            accounts.extend(content.source.as_deref());
+            accounts.extend(content.destination.as_deref());
+            accounts.extend(content.originated_contracts.iter().map(String::as_str));
+            for balance_update in &content.balance_updates {
+                accounts.extend(balance_update.contract.as_deref());
+                accounts.extend(balance_update.delegate.as_deref());
+            }
+        }
+        accounts
+    }
+}
+
+/// Operation receipts storage, receipts are stored by the operation hash and the block hash
+#[derive(Clone)]
+pub struct OperationReceiptsStorage {
+    kv: Arc<OperationReceiptsStorageKV>,
+    by_account_index: OperationReceiptsByAccountIndex,
+    block_meta_storage: BlockMetaStorage,
+}
+
+impl OperationReceiptsStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            kv: persistent_storage.kv(),
+            by_account_index: OperationReceiptsByAccountIndex::new(persistent_storage.kv()),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+        }
+    }
+
+    /// Extracts receipts from the operations metadata json (of the applied block) and stores them,
+    /// returns count of stored receipts
+    pub fn index_block(
+        &self,
+        block_hash: &BlockHash,
+        level: i32,
+        operations_proto_metadata_json: &str,
+    ) -> Result<usize, OperationReceiptsError> {
+        let receipts = extract_receipts(block_hash, level, operations_proto_metadata_json)?;
+        // receipts and their account index entries are stored atomically
+        let mut batch = WriteBatch::default();
+        for (operation_hash, receipt) in &receipts {
+            self.kv.put_batch(
+                &mut batch,
+                &OperationReceiptKey::new(operation_hash, block_hash),
+                receipt,
+            )?;
+            for account in receipt.accounts() {
+                // accounts are extracted from the json, unsupported ones (e.g. implicit accounts of unknown curves) are skipped
+                if let Ok(account) = contract_id_to_contract_address_for_index(account) {
+                    self.by_account_index.put_batch(
+                        &mut batch,
+                        &OperationReceiptsByAccountKey::new(
+                            &account,
+                            level,
+                            operation_hash,
+                            block_hash,
+                        ),
+                    )?;
+                }
+            }
+        }
+        self.kv.write_batch(batch)?;
+        Ok(receipts.len())
+    }
+
+    /// Receipt of the operation applied by the block of the chain of the `head`
+    pub fn get(
+        &self,
+        operation_hash: &OperationHash,
+        head: &Head,
+    ) -> Result<Option<OperationReceipt>, StorageError> {
+        let key =
+            OperationReceiptKey::new(operation_hash, &[0; OperationReceiptKey::LEN_BLOCK_HASH]);
+        for (_, receipt) in self.kv.prefix_iterator(&key)? {
+            let receipt = receipt?;
+            if self
+                .block_meta_storage
+                .is_on_chain(head, &receipt.block_hash, receipt.level)?
+            {
+                return Ok(Some(receipt));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Receipts of operations affecting the account (tz.. or KT1..) ordered by level, starting at `from_level`,
+    /// only operations applied by the blocks of the chain of the `head` are returned
+    pub fn get_by_account(
+        &self,
+        account: &str,
+        from_level: Option<i32>,
+        limit: usize,
+        head: &Head,
+    ) -> Result<Vec<(OperationHash, OperationReceipt)>, OperationReceiptsError> {
+        let account = contract_id_to_contract_address_for_index(account)?;
+        let mut receipts = Vec::new();
+        for key in self
+            .by_account_index
+            .get_by_account_iterator(&account, from_level.unwrap_or(0))?
+        {
+            let key = key?;
+            if receipts.len() >= limit || key.level > *head.level() {
+                break;
+            }
+            // receipts of the blocks of forks are skipped
+            if !self
+                .block_meta_storage
+                .is_on_chain(head, &key.block_hash, key.level)?
+            {
+                continue;
+            }
+            if let Some(receipt) = self.kv.get(&OperationReceiptKey::new(
+                &key.operation_hash,
+                &key.block_hash,
+            ))? {
+                receipts.push((key.operation_hash, receipt));
+            }
+        }
+        Ok(receipts)
+    }
+}
+
+impl KeyValueSchema for OperationReceiptsStorage {
+    type Key = OperationReceiptKey;
+    type Value = OperationReceipt;
+
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_fixed_prefix(OperationReceiptKey::LEN_OPERATION_HASH)
+    }
+
+    #[inline]
+    fn name() -> &'static str {
+        "operation_receipts_storage"
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct OperationReceiptKey {
+    operation_hash: OperationHash,
+    block_hash: BlockHash,
+}
+
+impl OperationReceiptKey {
+    const LEN_OPERATION_HASH: usize = HashType::OperationHash.size();
+    const LEN_BLOCK_HASH: usize = HashType::BlockHash.size();
+    const LEN_TOTAL: usize = Self::LEN_OPERATION_HASH + Self::LEN_BLOCK_HASH;
+
+    pub fn new(operation_hash: &[u8], block_hash: &[u8]) -> Self {
+        Self {
+            operation_hash: operation_hash.to_vec(),
+            block_hash: block_hash.to_vec(),
+        }
+    }
+}
+
+/// Decoder for `OperationReceiptKey`
+///
+/// * bytes layout `[operation_hash(32)][block_hash(32)]`
+impl Decoder for OperationReceiptKey {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if Self::LEN_TOTAL == bytes.len() {
+            Ok(OperationReceiptKey {
+                operation_hash: bytes[..Self::LEN_OPERATION_HASH].to_vec(),
+                block_hash: bytes[Self::LEN_OPERATION_HASH..].to_vec(),
+            })
+        } else {
+            Err(SchemaError::DecodeError)
+        }
+    }
+}
+
+/// Encoder for `OperationReceiptKey`
+///
+/// * bytes layout `[operation_hash(32)][block_hash(32)]`
+impl Encoder for OperationReceiptKey {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        if self.operation_hash.len() != Self::LEN_OPERATION_HASH
+            || self.block_hash.len() != Self::LEN_BLOCK_HASH
+        {
+            return Err(SchemaError::EncodeError);
+        }
+        let mut result = Vec::with_capacity(Self::LEN_TOTAL);
+        result.extend(&self.operation_hash);
+        result.extend(&self.block_hash);
+        Ok(result)
+    }
+}
+
+/// Index of operation receipts by the affected account
+///
+/// Index is composed from:
+/// * contract address
+/// * level
+/// * operation hash
+/// * block hash
+#[derive(Clone)]
+pub struct OperationReceiptsByAccountIndex {
+    kv: Arc<OperationReceiptsByAccountIndexKV>,
+}
+
+pub type OperationReceiptsByAccountIndexKV =
+    dyn KeyValueStoreWithSchema<OperationReceiptsByAccountIndex> + Sync + Send;
+
+impl OperationReceiptsByAccountIndex {
+    fn new(kv: Arc<OperationReceiptsByAccountIndexKV>) -> Self {
+        Self { kv }
+    }
+
+    #[inline]
+    fn put_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &OperationReceiptsByAccountKey,
+    ) -> Result<(), StorageError> {
+        self.kv
+            .put_batch(batch, key, &())
+            .map_err(StorageError::from)
+    }
+
+    /// Returns index keys of the account from the level
+    fn get_by_account_iterator<'a>(
+        &'a self,
+        account: &ContractAddress,
+        from_level: i32,
+    ) -> Result<
+        impl Iterator<Item = Result<OperationReceiptsByAccountKey, StorageError>> + 'a,
+        StorageError,
+    > {
+        let from_key = OperationReceiptsByAccountKey::new(
+            account,
+            from_level,
+            &[0; OperationReceiptsByAccountKey::LEN_OPERATION_HASH],
+            &[0; OperationReceiptsByAccountKey::LEN_BLOCK_HASH],
+        );
+        let account = account.clone();
+        Ok(self
+            .kv
+            .iterator(IteratorMode::From(&from_key, Direction::Forward))?
+            .map(|(key, _)| key.map_err(StorageError::from))
+            // errors are passed through to be propagated by the caller
+            .take_while(move |key| match key {
+                Ok(key) => key.account == account,
+                Err(_) => true,
+            }))
+    }
+}
+
+impl KeyValueSchema for OperationReceiptsByAccountIndex {
+    type Key = OperationReceiptsByAccountKey;
+    type Value = ();
+
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name())
+            .with_fixed_prefix(OperationReceiptsByAccountKey::LEN_ACCOUNT)
+    }
+
+    fn name() -> &'static str {
+        "operation_receipts_by_account_storage"
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct OperationReceiptsByAccountKey {
+    account: ContractAddress,
+    level: i32,
+    operation_hash: OperationHash,
+    block_hash: BlockHash,
+}
+
+impl OperationReceiptsByAccountKey {
+    const LEN_ACCOUNT: usize = 22;
+    const LEN_LEVEL: usize = mem::size_of::<i32>();
+    const LEN_OPERATION_HASH: usize = HashType::OperationHash.size();
+    const LEN_BLOCK_HASH: usize = HashType::BlockHash.size();
+    const LEN_TOTAL: usize =
+        Self::LEN_ACCOUNT + Self::LEN_LEVEL + Self::LEN_OPERATION_HASH + Self::LEN_BLOCK_HASH;
+
+    const IDX_ACCOUNT: usize = 0;
+    const IDX_LEVEL: usize = Self::IDX_ACCOUNT + Self::LEN_ACCOUNT;
+    const IDX_OPERATION_HASH: usize = Self::IDX_LEVEL + Self::LEN_LEVEL;
+    const IDX_BLOCK_HASH: usize = Self::IDX_OPERATION_HASH + Self::LEN_OPERATION_HASH;
+
+    const RANGE_ACCOUNT: Range<usize> = range_from_idx_len(Self::IDX_ACCOUNT, Self::LEN_ACCOUNT);
+    const RANGE_OPERATION_HASH: Range<usize> =
+        range_from_idx_len(Self::IDX_OPERATION_HASH, Self::LEN_OPERATION_HASH);
+    const RANGE_BLOCK_HASH: Range<usize> =
+        range_from_idx_len(Self::IDX_BLOCK_HASH, Self::LEN_BLOCK_HASH);
+
+    pub fn new(account: &[u8], level: i32, operation_hash: &[u8], block_hash: &[u8]) -> Self {
+        Self {
+            account: account.to_vec(),
+            level,
+            operation_hash: operation_hash.to_vec(),
+            block_hash: block_hash.to_vec(),
+        }
+    }
+}
+
+/// Decoder for `OperationReceiptsByAccountKey`
+///
+/// * bytes layout `[account(22)][level(4)][operation_hash(32)][block_hash(32)]`
+impl Decoder for OperationReceiptsByAccountKey {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if Self::LEN_TOTAL == bytes.len() {
+            Ok(OperationReceiptsByAccountKey {
+                account: bytes[Self::RANGE_ACCOUNT].to_vec(),
+                level: num_from_slice!(bytes, Self::IDX_LEVEL, i32),
+                operation_hash: bytes[Self::RANGE_OPERATION_HASH].to_vec(),
+                block_hash: bytes[Self::RANGE_BLOCK_HASH].to_vec(),
+            })
+        } else {
+            Err(SchemaError::DecodeError)
+        }
+    }
+}
+
+/// Encoder for `OperationReceiptsByAccountKey`
+///
+/// * bytes layout `[account(22)][level(4)][operation_hash(32)][block_hash(32)]`
+impl Encoder for OperationReceiptsByAccountKey {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        if self.account.len() != Self::LEN_ACCOUNT
+            || self.operation_hash.len() != Self::LEN_OPERATION_HASH
+            || self.block_hash.len() != Self::LEN_BLOCK_HASH
+        {
+            return Err(SchemaError::EncodeError);
+        }
+        let mut result = Vec::with_capacity(Self::LEN_TOTAL);
+        result.extend(&self.account);
+        result.extend(&self.level.to_be_bytes());
+        result.extend(&self.operation_hash);
+        result.extend(&self.block_hash);
+        Ok(result)
+    }
+}
+
+/// Extracts receipts of all operations from the operations metadata json (list of validation passes with operations)
+pub fn extract_receipts(
+    block_hash: &BlockHash,
+    level: i32,
+    operations_proto_metadata_json: &str,
+) -> Result<Vec<(OperationHash, OperationReceipt)>, OperationReceiptsError> {
+    let validation_passes: Vec<Vec<Value>> = serde_json::from_str(operations_proto_metadata_json)?;
+
+    let mut receipts = Vec::new();
+    for (validation_pass, operations) in validation_passes.iter().enumerate() {
+        for (operation_index, operation) in operations.iter().enumerate() {
+            let operation_hash = match operation["hash"].as_str() {
+                Some(hash) => HashType::OperationHash.b58check_to_hash(hash)?,
+                None => return Err(OperationReceiptsError::MissingOperationHash),
+            };
+
+            let mut contents = Vec::new();
+            for content in operation["contents"].as_array().into_iter().flatten() {
+                let metadata = &content["metadata"];
+                contents.push(content_receipt(
+                    content,
+                    &metadata["operation_result"],
+                    metadata,
+                    false,
+                ));
+                for internal in metadata["internal_operation_results"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                {
+                    contents.push(content_receipt(
+                        internal,
+                        &internal["result"],
+                        &Value::Null,
+                        true,
+                    ));
+                }
+            }
+
+            receipts.push((
+                operation_hash,
+                OperationReceipt {
+                    block_hash: block_hash.clone(),
+                    level,
+                    validation_pass: validation_pass as u8,
+                    operation_index: operation_index as u32,
+                    contents,
+                },
+            ));
+        }
+    }
+    Ok(receipts)
+}
+
+/// Balance updates are collected from the metadata (fees, deposits, rewards) and from the result of the operation
+fn content_receipt(
+    content: &Value,
+    result: &Value,
+    metadata: &Value,
+    internal: bool,
+) -> OperationContentReceipt {
+    let string = |value: &Value| value.as_str().map(str::to_string);
+    let mut balance_updates: Vec<_> = balance_updates(&metadata["balance_updates"]).collect();
+    balance_updates.extend(balance_updates(&result["balance_updates"]));
+
+    OperationContentReceipt {
+        kind: string(&content["kind"]).unwrap_or_default(),
+        source: string(&content["source"]),
+        // delegation (or endorsement) is related to the delegate
+        destination: string(&content["destination"])
+            .or_else(|| string(&content["delegate"]))
+            .or_else(|| string(&metadata["delegate"])),
+        status: string(&result["status"]),
+        internal,
+        balance_updates,
+        originated_contracts: result["originated_contracts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(string)
+            .collect(),
+    }
+}
+
+fn balance_updates(value: &Value) -> impl Iterator<Item = BalanceUpdate> + '_ {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|update| BalanceUpdate {
+            kind: update["kind"].as_str().unwrap_or_default().to_string(),
+            contract: update["contract"].as_str().map(str::to_string),
+            delegate: update["delegate"].as_str().map(str::to_string),
+            category: update["category"].as_str().map(str::to_string),
+            cycle: update["cycle"].as_i64(),
+            change: update["change"]
+                .as_str()
+                .and_then(|change| change.parse().ok())
+                .unwrap_or_default(),
+        })
+}
+
+#[derive(Debug, Fail)]
+pub enum OperationReceiptsError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Invalid operations metadata json: {}", error)]
+    InvalidJson { error: serde_json::Error },
+    #[fail(display = "Operation hash is missing in operations metadata json")]
+    MissingOperationHash,
+    #[fail(display = "Invalid hash: {}", error)]
+    InvalidHash { error: FromBase58CheckError },
+    #[fail(display = "Invalid account: {}", error)]
+    InvalidAccount { error: ConversionError },
+}
+
+impl From<StorageError> for OperationReceiptsError {
+    fn from(error: StorageError) -> Self {
+        OperationReceiptsError::StorageError { error }
+    }
+}
+
+impl From<crate::persistent::DBError> for OperationReceiptsError {
+    fn from(error: crate::persistent::DBError) -> Self {
+        OperationReceiptsError::StorageError {
+            error: error.into(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for OperationReceiptsError {
+    fn from(error: serde_json::Error) -> Self {
+        OperationReceiptsError::InvalidJson { error }
+    }
+}
+
+impl From<FromBase58CheckError> for OperationReceiptsError {
+    fn from(error: FromBase58CheckError) -> Self {
+        OperationReceiptsError::InvalidHash { error }
+    }
+}
+
+impl From<ConversionError> for OperationReceiptsError {
+    fn from(error: ConversionError) -> Self {
+        OperationReceiptsError::InvalidAccount { error }
+    }
+}
@@ -4,16 +4,14 @@
 use std::collections::HashSet;
 use std::sync::Arc;
 
-use rocksdb::{Cache, ColumnFamilyDescriptor, MergeOperands};
-
 use crypto::hash::{BlockHash, ChainId, HashType};
 use tezos_messages::p2p::encoding::prelude::*;
 
 use crate::num_from_slice;
 use crate::persistent::database::{IteratorMode, IteratorWithSchema};
 use crate::persistent::{
-    default_table_options, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema,
-    PersistentStorage, SchemaError,
+    Decoder, Encoder, KeyValueColumn, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage,
+    SchemaError,
 };
 use crate::{BlockHeaderWithHash, StorageError};
 
@@ -110,14 +108,8 @@ impl KeyValueSchema for OperationsMetaStorage {
     type Key = BlockHash;
     type Value = Meta;
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_merge_operator(
-            "operations_meta_storage_merge_operator",
-            merge_meta_value,
-            None,
-        );
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_merge_operator(merge_meta_value)
     }
 
     #[inline]
@@ -129,7 +121,7 @@ impl KeyValueSchema for OperationsMetaStorage {
 fn merge_meta_value(
     _new_key: &[u8],
     existing_val: Option<&[u8]>,
-    operands: &mut MergeOperands,
+    operands: &mut dyn Iterator<Item = &[u8]>,
 ) -> Option<Vec<u8>> {
     let mut result = existing_val.map(|v| v.to_vec());
 
@@ -3,17 +3,19 @@
 
 use std::sync::Arc;
 
-use rocksdb::{Cache, ColumnFamilyDescriptor, SliceTransform};
+use serde::{Deserialize, Serialize};
 
-use crypto::hash::{BlockHash, HashType};
-use tezos_messages::p2p::binary_message::BinaryMessage;
+use crypto::hash::{BlockHash, HashType, OperationHash};
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
 use tezos_messages::p2p::encoding::prelude::*;
+use tezos_messages::Head;
 
+use crate::persistent::backend::WriteBatch;
 use crate::persistent::{
-    default_table_options, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema,
+    BincodeEncoded, Decoder, Encoder, KeyValueColumn, KeyValueSchema, KeyValueStoreWithSchema,
     PersistentStorage, SchemaError,
 };
-use crate::StorageError;
+use crate::{BlockMetaStorage, IteratorMode, StorageError};
 
 pub type OperationsStorageKV = dyn KeyValueStoreWithSchema<OperationsStorage> + Sync + Send;
 
@@ -24,17 +26,28 @@ pub trait OperationsStorageReader: Sync + Send {
         &self,
         block_hash: &BlockHash,
     ) -> Result<Vec<OperationsForBlocksMessage>, StorageError>;
+
+    /// Location of the operation in the block of the chain of the `head`
+    fn get_operation_location(
+        &self,
+        operation_hash: &OperationHash,
+        head: &Head,
+    ) -> Result<Option<OperationLocation>, StorageError>;
 }
 
 #[derive(Clone)]
 pub struct OperationsStorage {
     kv: Arc<OperationsStorageKV>,
+    by_hash_index: OperationsByHashIndex,
+    block_meta_storage: BlockMetaStorage,
 }
 
 impl OperationsStorage {
     pub fn new(persistent_storage: &PersistentStorage) -> Self {
         Self {
             kv: persistent_storage.kv(),
+            by_hash_index: OperationsByHashIndex::new(persistent_storage.kv()),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
         }
     }
 
@@ -47,13 +60,52 @@ impl OperationsStorage {
         self.put(&key, &message)
     }
 
+    /// Stores operations and indexes them by operation hash (in one batch).
     #[inline]
     pub fn put(
         &self,
         key: &OperationKey,
         value: &OperationsForBlocksMessage,
     ) -> Result<(), StorageError> {
-        self.kv.put(key, value).map_err(StorageError::from)
+        let mut batch = WriteBatch::default();
+        self.kv.put_batch(&mut batch, key, value)?;
+        self.index_by_hash(&mut batch, key, value)?;
+        self.kv.write_batch(batch).map_err(StorageError::from)
+    }
+
+    /// Rebuilds `operation_hash -> location` index from all stored operations,
+    /// so databases created before the index existed can be backfilled.
+    ///
+    /// Returns count of indexed operations.
+    pub fn reindex_by_hash(&self) -> Result<usize, StorageError> {
+        let mut indexed = 0;
+        for (key, value) in self.kv.iterator(IteratorMode::Start)? {
+            let mut batch = WriteBatch::default();
+            indexed += self.index_by_hash(&mut batch, &key?, &value?)?;
+            self.kv.write_batch(batch)?;
+        }
+        Ok(indexed)
+    }
+
+    fn index_by_hash(
+        &self,
+        batch: &mut WriteBatch,
+        key: &OperationKey,
+        value: &OperationsForBlocksMessage,
+    ) -> Result<usize, StorageError> {
+        for (operation_index, operation) in value.operations().iter().enumerate() {
+            let location = OperationLocation {
+                block_hash: key.block_hash.clone(),
+                validation_pass: key.validation_pass,
+                operation_index: operation_index as u32,
+            };
+            self.by_hash_index.put_batch(
+                batch,
+                &OperationLocationKey::new(&operation.message_hash()?, &key.block_hash),
+                &location,
+            )?;
+        }
+        Ok(value.operations().len())
     }
 }
 
@@ -82,19 +134,36 @@ impl OperationsStorageReader for OperationsStorage {
 
         Ok(operations)
     }
+
+    fn get_operation_location(
+        &self,
+        operation_hash: &OperationHash,
+        head: &Head,
+    ) -> Result<Option<OperationLocation>, StorageError> {
+        // operation can be stored by several blocks (forks), the location of the block of the chain is returned
+        for location in self.by_hash_index.get(operation_hash)? {
+            let location = location?;
+            let level = match self.block_meta_storage.get(&location.block_hash)? {
+                Some(meta) => meta.level(),
+                None => continue,
+            };
+            if self
+                .block_meta_storage
+                .is_on_chain(head, &location.block_hash, level)?
+            {
+                return Ok(Some(location));
+            }
+        }
+        Ok(None)
+    }
 }
 
 impl KeyValueSchema for OperationsStorage {
     type Key = OperationKey;
     type Value = OperationsForBlocksMessage;
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
-            HashType::BlockHash.size(),
-        ));
-        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_fixed_prefix(HashType::BlockHash.size())
     }
 
     #[inline]
@@ -154,6 +223,112 @@ impl Encoder for OperationKey {
     }
 }
 
+/// Position of the operation in the stored operations of the block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OperationLocation {
+    pub block_hash: BlockHash,
+    pub validation_pass: u8,
+    pub operation_index: u32,
+}
+
+impl BincodeEncoded for OperationLocation {}
+
+/// Index operations as `(operation_hash, block_hash) -> location`.
+#[derive(Clone)]
+pub struct OperationsByHashIndex {
+    kv: Arc<OperationsByHashIndexKV>,
+}
+
+pub type OperationsByHashIndexKV = dyn KeyValueStoreWithSchema<OperationsByHashIndex> + Sync + Send;
+
+impl OperationsByHashIndex {
+    fn new(kv: Arc<OperationsByHashIndexKV>) -> Self {
+        Self { kv }
+    }
+
+    #[inline]
+    fn put_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &OperationLocationKey,
+        location: &OperationLocation,
+    ) -> Result<(), StorageError> {
+        self.kv
+            .put_batch(batch, key, location)
+            .map_err(StorageError::from)
+    }
+
+    /// Returns locations of the operation in all blocks, which contain the operation
+    #[inline]
+    fn get<'a>(
+        &'a self,
+        operation_hash: &OperationHash,
+    ) -> Result<impl Iterator<Item = Result<OperationLocation, StorageError>> + 'a, StorageError>
+    {
+        let key = OperationLocationKey::new(operation_hash, &[0; HashType::BlockHash.size()]);
+        Ok(self
+            .kv
+            .prefix_iterator(&key)?
+            .map(|(_, location)| location.map_err(StorageError::from)))
+    }
+}
+
+impl KeyValueSchema for OperationsByHashIndex {
+    type Key = OperationLocationKey;
+    type Value = OperationLocation;
+
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_fixed_prefix(HashType::OperationHash.size())
+    }
+
+    #[inline]
+    fn name() -> &'static str {
+        "operations_by_hash_storage"
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct OperationLocationKey {
+    operation_hash: OperationHash,
+    block_hash: BlockHash,
+}
+
+impl OperationLocationKey {
+    pub fn new(operation_hash: &[u8], block_hash: &[u8]) -> Self {
+        OperationLocationKey {
+            operation_hash: operation_hash.to_vec(),
+            block_hash: block_hash.to_vec(),
+        }
+    }
+}
+
+/// Layout of the `OperationLocationKey` is:
+///
+/// * bytes layout: `[operation_hash(32)][block_hash(32)]`
+impl Decoder for OperationLocationKey {
+    #[inline]
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != HashType::OperationHash.size() + HashType::BlockHash.size() {
+            return Err(SchemaError::DecodeError);
+        }
+        Ok(OperationLocationKey {
+            operation_hash: bytes[0..HashType::OperationHash.size()].to_vec(),
+            block_hash: bytes[HashType::OperationHash.size()..].to_vec(),
+        })
+    }
+}
+
+impl Encoder for OperationLocationKey {
+    #[inline]
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut value =
+            Vec::with_capacity(HashType::OperationHash.size() + HashType::BlockHash.size());
+        value.extend(&self.operation_hash);
+        value.extend(&self.block_hash);
+        Ok(value)
+    }
+}
+
 impl Decoder for OperationsForBlocksMessage {
     #[inline]
     fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
@@ -0,0 +1,175 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::RwLock;
+
+use crate::persistent::backend::{
+    BackendIterator, BackendIteratorMode, BatchOperation, KeyValuePair, KeyValueStoreBackend,
+    WriteBatch,
+};
+use crate::persistent::database::{DBError, Direction, RocksDBStats};
+use crate::persistent::schema::KeyValueColumn;
+
+type Column = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Key-value backend holding all data in ordered in-memory maps, intended for tests
+pub struct InMemoryBackend {
+    columns: HashMap<&'static str, KeyValueColumn>,
+    data: RwLock<HashMap<&'static str, Column>>,
+}
+
+impl InMemoryBackend {
+    pub fn new<I: IntoIterator<Item = KeyValueColumn>>(columns: I) -> Self {
+        let columns: HashMap<_, _> = columns.into_iter().map(|c| (c.name(), c)).collect();
+        let data = columns.keys().map(|name| (*name, Column::new())).collect();
+        Self {
+            columns,
+            data: RwLock::new(data),
+        }
+    }
+
+    fn column(&self, column: &'static str) -> Result<&KeyValueColumn, DBError> {
+        self.columns
+            .get(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })
+    }
+
+    /// Iterators do not hold the lock, so they operate on a snapshot of matching entries
+    fn collect<'b, I>(iter: I) -> BackendIterator<'static>
+    where
+        I: Iterator<Item = (&'b Vec<u8>, &'b Vec<u8>)>,
+    {
+        let entries: Vec<KeyValuePair> = iter
+            .map(|(k, v)| (k.clone().into_boxed_slice(), v.clone().into_boxed_slice()))
+            .collect();
+        Box::new(entries.into_iter().map(Ok))
+    }
+}
+
+impl KeyValueStoreBackend for InMemoryBackend {
+    fn put(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let mut data = self.data.write()?;
+        let data = data
+            .get_mut(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+        data.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, column: &'static str, key: &[u8]) -> Result<(), DBError> {
+        let mut data = self.data.write()?;
+        let data = data
+            .get_mut(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+        data.remove(key);
+        Ok(())
+    }
+
+    fn merge(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let merge_operator = self.column(column)?.merge_operator();
+        let mut data = self.data.write()?;
+        let data = data
+            .get_mut(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+
+        let merged = match merge_operator {
+            Some(merge) => merge(
+                key,
+                data.get(key).map(|v| v.as_slice()),
+                &mut std::iter::once(value),
+            ),
+            None => Some(value.to_vec()),
+        };
+        match merged {
+            Some(merged) => data.insert(key.to_vec(), merged),
+            None => data.remove(key),
+        };
+        Ok(())
+    }
+
+    fn get(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let data = self.data.read()?;
+        let data = data
+            .get(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+        Ok(data.get(key).cloned())
+    }
+
+    fn contains(&self, column: &'static str, key: &[u8]) -> Result<bool, DBError> {
+        let data = self.data.read()?;
+        let data = data
+            .get(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+        Ok(data.contains_key(key))
+    }
+
+    fn iterator<'a>(
+        &'a self,
+        column: &'static str,
+        mode: BackendIteratorMode,
+    ) -> Result<BackendIterator<'a>, DBError> {
+        let data = self.data.read()?;
+        let data = data
+            .get(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+
+        let iter = match mode {
+            BackendIteratorMode::Start => Self::collect(data.iter()),
+            BackendIteratorMode::End => Self::collect(data.iter().rev()),
+            BackendIteratorMode::From(key, Direction::Forward) => {
+                Self::collect(data.range::<[u8], _>((Bound::Included(key), Bound::Unbounded)))
+            }
+            BackendIteratorMode::From(key, Direction::Reverse) => Self::collect(
+                data.range::<[u8], _>((Bound::Unbounded, Bound::Included(key)))
+                    .rev(),
+            ),
+        };
+        Ok(iter)
+    }
+
+    fn prefix_iterator<'a>(
+        &'a self,
+        column: &'static str,
+        key: &[u8],
+    ) -> Result<BackendIterator<'a>, DBError> {
+        let prefix_len = self.column(column)?.fixed_prefix_len();
+        let data = self.data.read()?;
+        let data = data
+            .get(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })?;
+
+        let prefix = &key[..prefix_len.unwrap_or(0).min(key.len())];
+        Ok(Self::collect(
+            data.range::<[u8], _>((Bound::Included(key), Bound::Unbounded))
+                .take_while(|(k, _)| k.starts_with(prefix)),
+        ))
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
+        let mut data = self.data.write()?;
+        // check all columns first, so the batch is applied either whole or not at all
+        let batch: Vec<_> = batch.into_iter().collect();
+        if let Some((column, _)) = batch.iter().find(|(c, _)| !data.contains_key(c)) {
+            return Err(DBError::MissingColumnFamily { name: column });
+        }
+        for (column, operation) in batch {
+            if let Some(data) = data.get_mut(column) {
+                match operation {
+                    BatchOperation::Put { key, value } => data.insert(key, value),
+                    BatchOperation::Delete { key } => data.remove(&key),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), DBError> {
+        Ok(())
+    }
+
+    fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError> {
+        Ok(RocksDBStats::default())
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+use std::str::FromStr;
+
+use rocksdb::{Cache, ColumnFamilyDescriptor};
+
+use crate::persistent::database::{DBError, Direction, RocksDBStats};
+use crate::persistent::schema::{KeyValueColumn, KeyValueSchema};
+use crate::persistent::{open_kv, DbConfiguration};
+
+pub use in_memory_backend::InMemoryBackend;
+#[cfg(feature = "sled-backend")]
+pub use sled_backend::SledBackend;
+
+pub mod in_memory_backend;
+pub mod rocksdb_backend;
+#[cfg(feature = "sled-backend")]
+pub mod sled_backend;
+
+/// Raw key-value pair as returned by backend iterators
+pub type KeyValuePair = (Box<[u8]>, Box<[u8]>);
+
+/// Iterator over raw key-value pairs of a single column, failures of the backend are yielded as errors
+pub type BackendIterator<'a> = Box<dyn Iterator<Item = Result<KeyValuePair, DBError>> + 'a>;
+
+/// Backend iterator mode, from start to end, from end to start or from specific raw key to end/start
+pub enum BackendIteratorMode<'a> {
+    Start,
+    End,
+    From(&'a [u8], Direction),
+}
+
+/// Single write of the [WriteBatch]
+pub enum BatchOperation {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+}
+
+/// Set of writes (in any columns), which are applied to the backend at once and atomically
+#[derive(Default)]
+pub struct WriteBatch {
+    operations: Vec<(&'static str, BatchOperation)>,
+}
+
+impl WriteBatch {
+    pub fn put(&mut self, column: &'static str, key: Vec<u8>, value: Vec<u8>) {
+        self.operations
+            .push((column, BatchOperation::Put { key, value }));
+    }
+
+    pub fn delete(&mut self, column: &'static str, key: Vec<u8>) {
+        self.operations
+            .push((column, BatchOperation::Delete { key }));
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = (&'static str, BatchOperation);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.operations.into_iter()
+    }
+}
+
+/// Raw key-value database engine, operating on encoded keys and values grouped in named columns.
+///
+/// Every implementor gets [KeyValueStoreWithSchema](crate::persistent::KeyValueStoreWithSchema)
+/// for all schemas, so storages do not depend on the concrete database engine.
+pub trait KeyValueStoreBackend: Send + Sync {
+    fn put(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError>;
+
+    fn delete(&self, column: &'static str, key: &[u8]) -> Result<(), DBError>;
+
+    /// Combine `value` with the stored value using merge operator of the column
+    fn merge(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError>;
+
+    fn get(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DBError>;
+
+    fn contains(&self, column: &'static str, key: &[u8]) -> Result<bool, DBError>;
+
+    fn iterator<'a>(
+        &'a self,
+        column: &'static str,
+        mode: BackendIteratorMode,
+    ) -> Result<BackendIterator<'a>, DBError>;
+
+    /// Iterate from `key` while entries share the fixed prefix of the column with `key`.
+    /// If column has no fixed prefix, iterate from `key` to the end.
+    fn prefix_iterator<'a>(
+        &'a self,
+        column: &'static str,
+        key: &[u8],
+    ) -> Result<BackendIterator<'a>, DBError>;
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError>;
+
+    fn flush(&self) -> Result<(), DBError>;
+
+    fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError>;
+}
+
+/// Key-value database engine used by the node
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum KeyValueStoreBackendType {
+    RocksDb,
+    Sled,
+    InMemory,
+}
+
+impl FromStr for KeyValueStoreBackendType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rocksdb" => Ok(KeyValueStoreBackendType::RocksDb),
+            "sled" => Ok(KeyValueStoreBackendType::Sled),
+            "inmem" | "in-memory" => Ok(KeyValueStoreBackendType::InMemory),
+            _ => Err(format!("Invalid key-value store backend: {}", s)),
+        }
+    }
+}
+
+/// Key-value store with the database engine selected at runtime
+pub struct KeyValueStore {
+    backend: Box<dyn KeyValueStoreBackend>,
+    backend_type: KeyValueStoreBackendType,
+}
+
+impl KeyValueStore {
+    pub fn new<B: KeyValueStoreBackend + 'static>(
+        backend: B,
+        backend_type: KeyValueStoreBackendType,
+    ) -> Self {
+        Self {
+            backend: Box::new(backend),
+            backend_type,
+        }
+    }
+
+    #[inline]
+    pub fn backend_type(&self) -> KeyValueStoreBackendType {
+        self.backend_type
+    }
+}
+
+impl From<rocksdb::DB> for KeyValueStore {
+    fn from(db: rocksdb::DB) -> Self {
+        KeyValueStore::new(db, KeyValueStoreBackendType::RocksDb)
+    }
+}
+
+impl KeyValueStoreBackend for KeyValueStore {
+    #[inline]
+    fn put(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        self.backend.put(column, key, value)
+    }
+
+    #[inline]
+    fn delete(&self, column: &'static str, key: &[u8]) -> Result<(), DBError> {
+        self.backend.delete(column, key)
+    }
+
+    #[inline]
+    fn merge(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        self.backend.merge(column, key, value)
+    }
+
+    #[inline]
+    fn get(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.backend.get(column, key)
+    }
+
+    #[inline]
+    fn contains(&self, column: &'static str, key: &[u8]) -> Result<bool, DBError> {
+        self.backend.contains(column, key)
+    }
+
+    #[inline]
+    fn iterator<'a>(
+        &'a self,
+        column: &'static str,
+        mode: BackendIteratorMode,
+    ) -> Result<BackendIterator<'a>, DBError> {
+        self.backend.iterator(column, mode)
+    }
+
+    #[inline]
+    fn prefix_iterator<'a>(
+        &'a self,
+        column: &'static str,
+        key: &[u8],
+    ) -> Result<BackendIterator<'a>, DBError> {
+        self.backend.prefix_iterator(column, key)
+    }
+
+    #[inline]
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
+        self.backend.write_batch(batch)
+    }
+
+    #[inline]
+    fn flush(&self) -> Result<(), DBError> {
+        self.backend.flush()
+    }
+
+    #[inline]
+    fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError> {
+        self.backend.get_mem_use_stats()
+    }
+}
+
+/// Column to be opened in the key-value store, see [open_kv_store]
+#[derive(Clone, Copy)]
+pub struct KeyValueStoreColumn {
+    column: KeyValueColumn,
+    cf_descriptor: fn(&Cache) -> ColumnFamilyDescriptor,
+}
+
+impl KeyValueStoreColumn {
+    pub fn of<S: KeyValueSchema>() -> Self {
+        Self {
+            column: S::column(),
+            cf_descriptor: S::descriptor,
+        }
+    }
+}
+
+/// Open key-value store at given path with the backend selected by configuration
+///
+/// # Arguments
+/// * `path` - Path to open database (ignored by in-memory backend)
+/// * `columns` - Columns to be opened
+/// * `cache` - Shared RocksDB block cache, it must live at least as long as the returned store
+/// * `cfg` - Database configuration, selects the backend
+///
+/// sled backend is available only in builds with the `sled-backend` feature, [DBError::BackendNotEnabled] is returned otherwise
+pub fn open_kv_store<P, I>(
+    path: P,
+    columns: I,
+    cache: &Cache,
+    cfg: &DbConfiguration,
+) -> Result<KeyValueStore, DBError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = KeyValueStoreColumn>,
+{
+    let columns = columns.into_iter();
+    let backend_type = cfg.backend();
+    let kv = match backend_type {
+        KeyValueStoreBackendType::RocksDb => KeyValueStore::new(
+            open_kv(path, columns.map(|c| (c.cf_descriptor)(cache)), cfg)?,
+            backend_type,
+        ),
+        #[cfg(feature = "sled-backend")]
+        KeyValueStoreBackendType::Sled => KeyValueStore::new(
+            SledBackend::open(path, columns.map(|c| c.column))?,
+            backend_type,
+        ),
+        #[cfg(not(feature = "sled-backend"))]
+        KeyValueStoreBackendType::Sled => {
+            return Err(DBError::BackendNotEnabled {
+                backend: "sled",
+                feature: "sled-backend",
+            })
+        }
+        KeyValueStoreBackendType::InMemory => KeyValueStore::new(
+            InMemoryBackend::new(columns.map(|c| c.column)),
+            backend_type,
+        ),
+    };
+    Ok(kv)
+}
@@ -0,0 +1,138 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use rocksdb::{
+    Cache, ColumnFamily, ColumnFamilyDescriptor, MergeOperands, SliceTransform, WriteOptions, DB,
+};
+
+use crate::persistent::backend::{
+    BackendIterator, BackendIteratorMode, BatchOperation, KeyValueStoreBackend, WriteBatch,
+};
+use crate::persistent::database::{DBError, RocksDBStats};
+use crate::persistent::default_table_options;
+use crate::persistent::schema::KeyValueSchema;
+
+/// Create RocksDB column family descriptor from the column configuration of the schema
+pub fn column_family_descriptor<S: KeyValueSchema>(cache: &Cache) -> ColumnFamilyDescriptor {
+    let column = S::column();
+    let mut cf_opts = default_table_options(cache);
+    if column.merge_operator().is_some() {
+        cf_opts.set_merge_operator(
+            &format!("{}_merge_operator", column.name()),
+            merge_operator::<S>,
+            None,
+        );
+    }
+    if let Some(prefix_len) = column.fixed_prefix_len() {
+        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(prefix_len));
+        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+    }
+    ColumnFamilyDescriptor::new(column.name(), cf_opts)
+}
+
+/// RocksDB requires plain function as merge operator, so the schema one is resolved statically
+fn merge_operator<S: KeyValueSchema>(
+    key: &[u8],
+    existing_val: Option<&[u8]>,
+    mut operands: &mut MergeOperands,
+) -> Option<Vec<u8>> {
+    let merge = S::column()
+        .merge_operator()
+        .expect("Merge operator is registered only for columns which define it");
+    merge(key, existing_val, &mut operands)
+}
+
+fn default_write_options() -> WriteOptions {
+    let mut opts = WriteOptions::default();
+    opts.set_sync(false);
+    opts
+}
+
+fn cf_handle<'a>(db: &'a DB, column: &'static str) -> Result<&'a ColumnFamily, DBError> {
+    db.cf_handle(column)
+        .ok_or(DBError::MissingColumnFamily { name: column })
+}
+
+impl KeyValueStoreBackend for DB {
+    fn put(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let cf = cf_handle(self, column)?;
+        self.put_cf_opt(cf, key, value, &default_write_options())
+            .map_err(DBError::from)
+    }
+
+    fn delete(&self, column: &'static str, key: &[u8]) -> Result<(), DBError> {
+        let cf = cf_handle(self, column)?;
+        self.delete_cf_opt(cf, key, &default_write_options())
+            .map_err(DBError::from)
+    }
+
+    fn merge(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let cf = cf_handle(self, column)?;
+        self.merge_cf_opt(cf, key, value, &default_write_options())
+            .map_err(DBError::from)
+    }
+
+    fn get(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let cf = cf_handle(self, column)?;
+        self.get_cf(cf, key).map_err(DBError::from)
+    }
+
+    fn contains(&self, column: &'static str, key: &[u8]) -> Result<bool, DBError> {
+        let cf = cf_handle(self, column)?;
+        let val = self.get_pinned_cf(cf, key)?;
+        Ok(val.is_some())
+    }
+
+    fn iterator<'a>(
+        &'a self,
+        column: &'static str,
+        mode: BackendIteratorMode,
+    ) -> Result<BackendIterator<'a>, DBError> {
+        let cf = cf_handle(self, column)?;
+        let iter = match mode {
+            BackendIteratorMode::Start => self.iterator_cf(cf, rocksdb::IteratorMode::Start),
+            BackendIteratorMode::End => self.iterator_cf(cf, rocksdb::IteratorMode::End),
+            BackendIteratorMode::From(key, direction) => {
+                self.iterator_cf(cf, rocksdb::IteratorMode::From(key, direction.into()))
+            }
+        };
+        Ok(Box::new(iter.map(Ok)))
+    }
+
+    fn prefix_iterator<'a>(
+        &'a self,
+        column: &'static str,
+        key: &[u8],
+    ) -> Result<BackendIterator<'a>, DBError> {
+        let cf = cf_handle(self, column)?;
+        Ok(Box::new(self.prefix_iterator_cf(cf, key).map(Ok)))
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for (column, operation) in batch {
+            let cf = cf_handle(self, column)?;
+            match operation {
+                BatchOperation::Put { key, value } => rocks_batch.put_cf(cf, &key, &value),
+                BatchOperation::Delete { key } => rocks_batch.delete_cf(cf, &key),
+            }
+        }
+        self.write_opt(rocks_batch, &default_write_options())?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), DBError> {
+        DB::flush(self).map_err(DBError::from)
+    }
+
+    fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError> {
+        let memory_usage_stats = rocksdb::perf::get_memory_usage_stats(Some(&[&self]), None)?;
+
+        Ok(RocksDBStats {
+            mem_table_total: memory_usage_stats.mem_table_total,
+            mem_table_unflushed: memory_usage_stats.mem_table_unflushed,
+            mem_table_readers_total: memory_usage_stats.mem_table_readers_total,
+            cache_total: memory_usage_stats.cache_total,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use sled::transaction::TransactionResult;
+use sled::{Batch, Db, IVec, Transactional, Tree};
+
+use crate::persistent::backend::{
+    BackendIterator, BackendIteratorMode, BatchOperation, KeyValuePair, KeyValueStoreBackend,
+    WriteBatch,
+};
+use crate::persistent::database::{DBError, Direction, RocksDBStats};
+use crate::persistent::schema::KeyValueColumn;
+
+/// Key-value backend based on the sled embedded database, every column is stored in separate tree
+pub struct SledBackend {
+    db: Db,
+    trees: HashMap<&'static str, (Tree, KeyValueColumn)>,
+}
+
+impl SledBackend {
+    /// Open sled database at given path and open a tree for every column
+    pub fn open<P, I>(path: P, columns: I) -> Result<Self, DBError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = KeyValueColumn>,
+    {
+        let db = sled::open(path.as_ref().join("sled"))?;
+        let mut trees = HashMap::new();
+        for column in columns {
+            let tree = db.open_tree(column.name())?;
+            if let Some(merge) = column.merge_operator() {
+                tree.set_merge_operator(
+                    move |key: &[u8], existing: Option<&[u8]>, operand: &[u8]| {
+                        merge(key, existing, &mut std::iter::once(operand))
+                    },
+                );
+            }
+            trees.insert(column.name(), (tree, column));
+        }
+        Ok(Self { db, trees })
+    }
+
+    fn tree(&self, column: &'static str) -> Result<&(Tree, KeyValueColumn), DBError> {
+        self.trees
+            .get(column)
+            .ok_or(DBError::MissingColumnFamily { name: column })
+    }
+}
+
+/// Failures of sled iterators are passed to the caller
+fn to_backend_iterator<'a, I>(iter: I) -> BackendIterator<'a>
+where
+    I: Iterator<Item = sled::Result<(IVec, IVec)>> + 'a,
+{
+    Box::new(iter.map(|item| item.map(to_key_value_pair).map_err(DBError::from)))
+}
+
+fn to_key_value_pair((key, value): (IVec, IVec)) -> KeyValuePair {
+    (
+        key.to_vec().into_boxed_slice(),
+        value.to_vec().into_boxed_slice(),
+    )
+}
+
+impl KeyValueStoreBackend for SledBackend {
+    fn put(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let (tree, _) = self.tree(column)?;
+        tree.insert(key, value)?;
+        Ok(())
+    }
+
+    fn delete(&self, column: &'static str, key: &[u8]) -> Result<(), DBError> {
+        let (tree, _) = self.tree(column)?;
+        tree.remove(key)?;
+        Ok(())
+    }
+
+    fn merge(&self, column: &'static str, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let (tree, column) = self.tree(column)?;
+        if column.merge_operator().is_some() {
+            tree.merge(key, value)?;
+        } else {
+            tree.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let (tree, _) = self.tree(column)?;
+        Ok(tree.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn contains(&self, column: &'static str, key: &[u8]) -> Result<bool, DBError> {
+        let (tree, _) = self.tree(column)?;
+        Ok(tree.contains_key(key)?)
+    }
+
+    fn iterator<'a>(
+        &'a self,
+        column: &'static str,
+        mode: BackendIteratorMode,
+    ) -> Result<BackendIterator<'a>, DBError> {
+        let (tree, _) = self.tree(column)?;
+        let iter = match mode {
+            BackendIteratorMode::Start => to_backend_iterator(tree.iter()),
+            BackendIteratorMode::End => to_backend_iterator(tree.iter().rev()),
+            BackendIteratorMode::From(key, Direction::Forward) => {
+                to_backend_iterator(tree.range(key..))
+            }
+            BackendIteratorMode::From(key, Direction::Reverse) => {
+                to_backend_iterator(tree.range(..=key).rev())
+            }
+        };
+        Ok(iter)
+    }
+
+    fn prefix_iterator<'a>(
+        &'a self,
+        column: &'static str,
+        key: &[u8],
+    ) -> Result<BackendIterator<'a>, DBError> {
+        let (tree, column) = self.tree(column)?;
+        let prefix = key[..column.fixed_prefix_len().unwrap_or(0).min(key.len())].to_vec();
+        Ok(Box::new(to_backend_iterator(tree.range(key..)).take_while(
+            move |item| match item {
+                Ok((k, _)) => k.starts_with(&prefix),
+                Err(_) => true,
+            },
+        )))
+    }
+
+    /// Sled batches are atomic per tree only, so batches of all columns are applied in a single transaction
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
+        let mut batches: HashMap<&'static str, Batch> = HashMap::new();
+        for (column, operation) in batch {
+            self.tree(column)?;
+            let batch = batches.entry(column).or_default();
+            match operation {
+                BatchOperation::Put { key, value } => batch.insert(key, value),
+                BatchOperation::Delete { key } => batch.remove(key),
+            }
+        }
+        if batches.is_empty() {
+            return Ok(());
+        }
+
+        let mut trees = Vec::with_capacity(batches.len());
+        let mut tree_batches = Vec::with_capacity(batches.len());
+        for (column, batch) in batches {
+            let (tree, _) = self.tree(column)?;
+            trees.push(tree);
+            tree_batches.push(batch);
+        }
+        // closure can be run again on conflict with a concurrent transaction
+        let result: TransactionResult<(), sled::Error> = trees.as_slice().transaction(|trees| {
+            for (tree, batch) in trees.iter().zip(&tree_batches) {
+                tree.apply_batch(batch)?;
+            }
+            Ok(())
+        });
+        result?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), DBError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError> {
+        Ok(RocksDBStats::default())
+    }
+}
@@ -2,20 +2,27 @@
 // SPDX-License-Identifier: MIT
 
 use std::marker::PhantomData;
+use std::sync::PoisonError;
 
 use failure::Fail;
-use rocksdb::{DBIterator, Error, WriteBatch, WriteOptions, DB};
+use rocksdb::Error;
 use serde::Serialize;
+#[cfg(feature = "sled-backend")]
+use sled::transaction::TransactionError;
 
+use crate::persistent::backend::{
+    BackendIterator, BackendIteratorMode, KeyValueStoreBackend, WriteBatch,
+};
 use crate::persistent::codec::{Decoder, Encoder, SchemaError};
 use crate::persistent::schema::KeyValueSchema;
 
-#[derive(Serialize, Debug, Clone)]
+/// Memory usage statistics, backends other than RocksDB report zeros
+#[derive(Serialize, Debug, Clone, Default)]
 pub struct RocksDBStats {
-    mem_table_total: u64,
-    mem_table_unflushed: u64,
-    mem_table_readers_total: u64,
-    cache_total: u64,
+    pub(crate) mem_table_total: u64,
+    pub(crate) mem_table_unflushed: u64,
+    pub(crate) mem_table_readers_total: u64,
+    pub(crate) cache_total: u64,
 }
 
 /// Possible errors for schema
@@ -25,8 +32,23 @@ pub enum DBError {
     SchemaError { error: SchemaError },
     #[fail(display = "RocksDB error: {}", error)]
     RocksDBError { error: Error },
+    #[cfg(feature = "sled-backend")]
+    #[fail(display = "Sled error: {}", error)]
+    SledError { error: sled::Error },
+    #[fail(
+        display = "Key-value store backend {} is not enabled in this build, rebuild with the cargo feature {}",
+        backend, feature
+    )]
+    BackendNotEnabled {
+        backend: &'static str,
+        feature: &'static str,
+    },
     #[fail(display = "Column family {} is missing", name)]
     MissingColumnFamily { name: &'static str },
+    #[fail(display = "Thread synchronization error")]
+    SynchronizationError,
+    #[fail(display = "Iterator failed: {}", reason)]
+    IteratorError { reason: String },
 }
 
 impl From<SchemaError> for DBError {
@@ -41,6 +63,30 @@ impl From<Error> for DBError {
     }
 }
 
+#[cfg(feature = "sled-backend")]
+impl From<sled::Error> for DBError {
+    fn from(error: sled::Error) -> Self {
+        DBError::SledError { error }
+    }
+}
+
+#[cfg(feature = "sled-backend")]
+impl From<TransactionError> for DBError {
+    fn from(error: TransactionError) -> Self {
+        match error {
+            TransactionError::Abort(error) | TransactionError::Storage(error) => {
+                DBError::SledError { error }
+            }
+        }
+    }
+}
+
+impl<T> From<PoisonError<T>> for DBError {
+    fn from(_: PoisonError<T>) -> Self {
+        DBError::SynchronizationError
+    }
+}
+
 impl slog::Value for DBError {
     fn serialize(
         &self,
@@ -52,7 +98,7 @@ impl slog::Value for DBError {
     }
 }
 
-/// Custom trait extending RocksDB to better handle and enforce database schema
+/// Custom trait extending key-value store backends to better handle and enforce database schema
 pub trait KeyValueStoreWithSchema<S: KeyValueSchema> {
     /// Insert new key value pair into the database. If key already exists, method will fail
     ///
@@ -83,7 +129,7 @@ pub trait KeyValueStoreWithSchema<S: KeyValueSchema> {
     /// Read all entries in database.
     ///
     /// # Arguments
-    /// * `mode` - Reading mode, specified by RocksDB, From start to end, from end to start, or from
+    /// * `mode` - Reading mode, From start to end, from end to start, or from
     /// arbitrary position to end.
     fn iterator(&self, mode: IteratorMode<S>) -> Result<IteratorWithSchema<S>, DBError>;
 
@@ -111,6 +157,12 @@ pub trait KeyValueStoreWithSchema<S: KeyValueSchema> {
         value: &S::Value,
     ) -> Result<(), DBError>;
 
+    /// Insert deletion of the key into WriteBatch.
+    ///
+    /// # Arguments
+    /// * `key` - Value of key specified by schema
+    fn delete_batch(&self, batch: &mut WriteBatch, key: &S::Key) -> Result<(), DBError>;
+
     /// Write batch into DB atomically
     ///
     /// # Arguments
@@ -121,64 +173,45 @@ pub trait KeyValueStoreWithSchema<S: KeyValueSchema> {
     fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError>;
 }
 
-impl<S: KeyValueSchema> KeyValueStoreWithSchema<S> for DB {
+impl<S: KeyValueSchema, B: KeyValueStoreBackend + ?Sized> KeyValueStoreWithSchema<S> for B {
     fn put(&self, key: &S::Key, value: &S::Value) -> Result<(), DBError> {
         let key = key.encode()?;
         let value = value.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
-        self.put_cf_opt(cf, &key, &value, &default_write_options())
-            .map_err(DBError::from)
+        KeyValueStoreBackend::put(self, S::name(), &key, &value)
     }
 
     fn delete(&self, key: &S::Key) -> Result<(), DBError> {
         let key = key.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
-        self.delete_cf_opt(cf, &key, &default_write_options())
-            .map_err(DBError::from)
+        KeyValueStoreBackend::delete(self, S::name(), &key)
     }
 
     fn merge(&self, key: &S::Key, value: &S::Value) -> Result<(), DBError> {
         let key = key.encode()?;
         let value = value.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
-        self.merge_cf_opt(cf, &key, &value, &default_write_options())
-            .map_err(DBError::from)
+        KeyValueStoreBackend::merge(self, S::name(), &key, &value)
     }
 
     fn get(&self, key: &S::Key) -> Result<Option<S::Value>, DBError> {
         let key = key.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
-        self.get_cf(cf, &key)
-            .map_err(DBError::from)?
+        KeyValueStoreBackend::get(self, S::name(), &key)?
             .map(|value| S::Value::decode(&value))
             .transpose()
             .map_err(DBError::from)
     }
 
     fn iterator(&self, mode: IteratorMode<S>) -> Result<IteratorWithSchema<S>, DBError> {
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
         let iter = match mode {
-            IteratorMode::Start => self.iterator_cf(cf, rocksdb::IteratorMode::Start),
-            IteratorMode::End => self.iterator_cf(cf, rocksdb::IteratorMode::End),
-            IteratorMode::From(key, direction) => self.iterator_cf(
-                cf,
-                rocksdb::IteratorMode::From(&key.encode()?, direction.into()),
-            ),
+            IteratorMode::Start => {
+                KeyValueStoreBackend::iterator(self, S::name(), BackendIteratorMode::Start)?
+            }
+            IteratorMode::End => {
+                KeyValueStoreBackend::iterator(self, S::name(), BackendIteratorMode::End)?
+            }
+            IteratorMode::From(key, direction) => KeyValueStoreBackend::iterator(
+                self,
+                S::name(),
+                BackendIteratorMode::From(&key.encode()?, direction),
+            )?,
         };
 
         Ok(IteratorWithSchema(iter, PhantomData))
@@ -186,24 +219,15 @@ impl<S: KeyValueSchema> KeyValueStoreWithSchema<S> for DB {
 
     fn prefix_iterator(&self, key: &S::Key) -> Result<IteratorWithSchema<S>, DBError> {
         let key = key.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
         Ok(IteratorWithSchema(
-            self.prefix_iterator_cf(cf, key),
+            KeyValueStoreBackend::prefix_iterator(self, S::name(), &key)?,
             PhantomData,
         ))
     }
 
     fn contains(&self, key: &S::Key) -> Result<bool, DBError> {
         let key = key.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
-        let val = self.get_pinned_cf(cf, &key)?;
-        Ok(val.is_some())
+        KeyValueStoreBackend::contains(self, S::name(), &key)
     }
 
     fn put_batch(
@@ -212,51 +236,44 @@ impl<S: KeyValueSchema> KeyValueStoreWithSchema<S> for DB {
         key: &S::Key,
         value: &S::Value,
     ) -> Result<(), DBError> {
-        let key = key.encode()?;
-        let value = value.encode()?;
-        let cf = self
-            .cf_handle(S::name())
-            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
-
-        batch.put_cf(cf, &key, &value);
+        batch.put(S::name(), key.encode()?, value.encode()?);
+        Ok(())
+    }
 
+    fn delete_batch(&self, batch: &mut WriteBatch, key: &S::Key) -> Result<(), DBError> {
+        batch.delete(S::name(), key.encode()?);
         Ok(())
     }
 
     fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
-        self.write_opt(batch, &default_write_options())?;
-        Ok(())
+        KeyValueStoreBackend::write_batch(self, batch)
     }
 
     fn get_mem_use_stats(&self) -> Result<RocksDBStats, DBError> {
-        let memory_usage_stats = rocksdb::perf::get_memory_usage_stats(Some(&[&self]), None)?;
-
-        Ok(RocksDBStats {
-            mem_table_total: memory_usage_stats.mem_table_total,
-            mem_table_unflushed: memory_usage_stats.mem_table_unflushed,
-            mem_table_readers_total: memory_usage_stats.mem_table_readers_total,
-            cache_total: memory_usage_stats.cache_total,
-        })
+        KeyValueStoreBackend::get_mem_use_stats(self)
     }
 }
 
-fn default_write_options() -> WriteOptions {
-    let mut opts = WriteOptions::default();
-    opts.set_sync(false);
-    opts
-}
-
 /// Database iterator extended by specific schema
-pub struct IteratorWithSchema<'a, S: KeyValueSchema>(DBIterator<'a>, PhantomData<S>);
+pub struct IteratorWithSchema<'a, S: KeyValueSchema>(BackendIterator<'a>, PhantomData<S>);
 
 impl<'a, S: KeyValueSchema> Iterator for IteratorWithSchema<'a, S> {
-    type Item = (Result<S::Key, SchemaError>, Result<S::Value, SchemaError>);
+    type Item = (Result<S::Key, DBError>, Result<S::Value, DBError>);
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        self.0
-            .next()
-            .map(|(k, v)| (S::Key::decode(&k), S::Value::decode(&v)))
+        match self.0.next()? {
+            Ok((k, v)) => Some((
+                S::Key::decode(&k).map_err(DBError::from),
+                S::Value::decode(&v).map_err(DBError::from),
+            )),
+            Err(error) => {
+                // neither key nor value can be read and failed backend iterator is not continued
+                self.0 = Box::new(std::iter::empty());
+                let reason = error.to_string();
+                Some((Err(error), Err(DBError::IteratorError { reason })))
+            }
+        }
     }
 }
 
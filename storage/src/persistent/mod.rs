@@ -7,26 +7,34 @@ use std::sync::{Arc, RwLock};
 use derive_builder::Builder;
 use rocksdb::{BlockBasedOptions, Cache, ColumnFamilyDescriptor, Options, DB};
 
+pub use backend::{
+    open_kv_store, KeyValueStore, KeyValueStoreBackend, KeyValueStoreBackendType,
+    KeyValueStoreColumn,
+};
 pub use codec::{BincodeEncoded, Codec, Decoder, Encoder, SchemaError};
 pub use commit_log::{CommitLogError, CommitLogRef, CommitLogWithSchema, CommitLogs, Location};
 pub use database::{DBError, KeyValueStoreWithSchema};
-pub use schema::{CommitLogDescriptor, CommitLogSchema, KeyValueSchema};
+pub use schema::{CommitLogDescriptor, CommitLogSchema, KeyValueColumn, KeyValueSchema};
 
-use crate::merkle_storage::MerkleStorage;
+use crate::merkle_storage::{MerkleStorage, DEFAULT_TREE_CACHE_CAPACITY};
 use crate::persistent::sequence::Sequences;
 
+pub mod backend;
 pub mod codec;
 pub mod commit_log;
 pub mod database;
 pub mod schema;
 pub mod sequence;
 
-/// Rocksdb database system configuration
-/// - [max_num_of_threads] - if not set, num of cpus is used
+/// Key-value database system configuration
+/// - [max_num_of_threads] - if not set, num of cpus is used (RocksDB only)
+/// - [backend] - database engine, RocksDB by default
 #[derive(Builder, Debug, Clone)]
 pub struct DbConfiguration {
     #[builder(default = "None")]
     max_threads: Option<usize>,
+    #[builder(default = "KeyValueStoreBackendType::RocksDb")]
+    backend: KeyValueStoreBackendType,
 }
 
 impl Default for DbConfiguration {
@@ -35,6 +43,13 @@ impl Default for DbConfiguration {
     }
 }
 
+impl DbConfiguration {
+    #[inline]
+    pub fn backend(&self) -> KeyValueStoreBackendType {
+        self.backend
+    }
+}
+
 /// Open RocksDB database at given path with specified Column Family configurations
 ///
 /// # Arguments
@@ -116,7 +131,7 @@ where
 #[derive(Clone)]
 pub struct PersistentStorage {
     /// key-value store
-    kv: Arc<DB>,
+    kv: Arc<KeyValueStore>,
     /// commit log store
     clog: Arc<CommitLogs>,
     /// autoincrement  id generators
@@ -126,18 +141,30 @@ pub struct PersistentStorage {
 }
 
 impl PersistentStorage {
-    pub fn new(kv: Arc<DB>, clog: Arc<CommitLogs>) -> Self {
+    pub fn new(kv: Arc<KeyValueStore>, clog: Arc<CommitLogs>) -> Self {
+        Self::with_merkle_tree_cache(kv, clog, DEFAULT_TREE_CACHE_CAPACITY)
+    }
+
+    /// Creates storage with cache of decoded merkle trees limited to `tree_cache_capacity` bytes
+    pub fn with_merkle_tree_cache(
+        kv: Arc<KeyValueStore>,
+        clog: Arc<CommitLogs>,
+        tree_cache_capacity: usize,
+    ) -> Self {
         let seq = Arc::new(Sequences::new(kv.clone(), 1000));
         Self {
             clog,
             kv: kv.clone(),
             seq,
-            merkle: Arc::new(RwLock::new(MerkleStorage::new(kv))),
+            merkle: Arc::new(RwLock::new(MerkleStorage::with_tree_cache(
+                kv,
+                tree_cache_capacity,
+            ))),
         }
     }
 
     #[inline]
-    pub fn kv(&self) -> Arc<DB> {
+    pub fn kv(&self) -> Arc<KeyValueStore> {
         self.kv.clone()
     }
 
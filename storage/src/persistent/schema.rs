@@ -3,16 +3,78 @@
 
 use rocksdb::{Cache, ColumnFamilyDescriptor};
 
+use crate::persistent::backend::rocksdb_backend;
 use crate::persistent::codec::Codec;
-use crate::persistent::default_table_options;
+
+/// Backend-agnostic merge operator.
+///
+/// Receives the key, the currently stored value (if any) and the list of merge operands,
+/// which are to be applied in the given order. Returns the new value to be stored.
+pub type MergeOperator = fn(
+    key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &mut dyn Iterator<Item = &[u8]>,
+) -> Option<Vec<u8>>;
+
+/// Backend-agnostic column configuration, every key-value backend uses it to set up the column
+#[derive(Clone, Copy)]
+pub struct KeyValueColumn {
+    name: &'static str,
+    merge_operator: Option<MergeOperator>,
+    fixed_prefix_len: Option<usize>,
+}
+
+impl KeyValueColumn {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            merge_operator: None,
+            fixed_prefix_len: None,
+        }
+    }
+
+    /// Values written by `merge` are combined with the stored value by the `merge_operator`
+    pub fn with_merge_operator(mut self, merge_operator: MergeOperator) -> Self {
+        self.merge_operator = Some(merge_operator);
+        self
+    }
+
+    /// Prefix iterator stays within the first `len` bytes of the encoded key
+    pub fn with_fixed_prefix(mut self, len: usize) -> Self {
+        self.fixed_prefix_len = Some(len);
+        self
+    }
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    #[inline]
+    pub fn merge_operator(&self) -> Option<MergeOperator> {
+        self.merge_operator
+    }
+
+    #[inline]
+    pub fn fixed_prefix_len(&self) -> Option<usize> {
+        self.fixed_prefix_len
+    }
+}
 
 /// This trait extends basic column family by introducing Codec types safety and enforcement
 pub trait KeyValueSchema {
     type Key: Codec;
     type Value: Codec;
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        ColumnFamilyDescriptor::new(Self::name(), default_table_options(cache))
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name())
+    }
+
+    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor
+    where
+        Self: Sized,
+    {
+        rocksdb_backend::column_family_descriptor::<Self>(cache)
     }
 
     fn name() -> &'static str;
@@ -5,14 +5,13 @@ use std::sync::Arc;
 
 use failure::Fail;
 use failure::_core::marker::PhantomData;
-use rocksdb::{Cache, ColumnFamilyDescriptor, SliceTransform};
 use serde::{Deserialize, Serialize};
 
 use crate::num_from_slice;
 use crate::persistent::database::IteratorWithSchema;
 use crate::persistent::sequence::SequenceError;
 use crate::persistent::{
-    default_table_options, BincodeEncoded, Codec, DBError, Decoder, Encoder, KeyValueSchema,
+    BincodeEncoded, Codec, DBError, Decoder, Encoder, KeyValueColumn, KeyValueSchema,
     KeyValueStoreWithSchema, SchemaError,
 };
 use crate::skip_list::{TryExtend, LEVEL_BASE};
@@ -150,11 +149,8 @@ impl KeyValueSchema for ListValue {
     type Key = ListValueKey;
     type Value = Vec<u8>;
 
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
-        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(ListValueKey::LEN_ID));
-        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
-        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    fn column() -> KeyValueColumn {
+        KeyValueColumn::new(Self::name()).with_fixed_prefix(ListValueKey::LEN_ID)
     }
 
     fn name() -> &'static str {
@@ -257,10 +253,8 @@ impl<'a, K: Decoder, V: Decoder> Iterator for IterPrefix<'a, K, V> {
     type Item = Result<(K, V), SkipListError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next: Option<(
-            Result<ListValueKey, SchemaError>,
-            Result<Vec<u8>, SchemaError>,
-        )> = self.inner.next();
+        let next: Option<(Result<ListValueKey, DBError>, Result<Vec<u8>, DBError>)> =
+            self.inner.next();
         if let Some((Ok(ListValueKey { key, .. }), _)) = &next {
             if !key.starts_with(&self.prefix) {
                 return None;
@@ -272,16 +266,11 @@ impl<'a, K: Decoder, V: Decoder> Iterator for IterPrefix<'a, K, V> {
 }
 
 fn extract_and_decode<K: Decoder, V: Decoder>(
-    value: Option<(
-        Result<ListValueKey, SchemaError>,
-        Result<Vec<u8>, SchemaError>,
-    )>,
+    value: Option<(Result<ListValueKey, DBError>, Result<Vec<u8>, DBError>)>,
 ) -> Option<Result<(K, V), SkipListError>> {
     value.map(|(k, v)| {
-        k.map(|k| k.key)
-            .and_then(|key| K::decode(&key))
-            .and_then(|k| v.and_then(|value| V::decode(&value)).map(|v| (k, v)))
-            .map_err(SkipListError::from)
+        let (k, v) = (k?, v?);
+        Ok((K::decode(&k.key)?, V::decode(&v)?))
     })
 }
 
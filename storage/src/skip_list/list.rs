@@ -9,7 +9,9 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
 use crate::persistent::sequence::SequenceGenerator;
-use crate::persistent::{BincodeEncoded, Codec, KeyValueSchema, KeyValueStoreWithSchema};
+use crate::persistent::{
+    BincodeEncoded, Codec, KeyValueSchema, KeyValueStore, KeyValueStoreWithSchema,
+};
 use crate::skip_list::content::{ListValueDatabase, NodeHeader, SkipListId};
 use crate::skip_list::lane::{Lane, LaneDatabase, TypedLane};
 use crate::skip_list::{SkipListError, TryExtend, LEVEL_BASE};
@@ -41,7 +43,7 @@ impl DatabaseBackedSkipList {
     /// Create new list in given database
     pub fn new(
         list_id: SkipListId,
-        db: Arc<rocksdb::DB>,
+        db: Arc<KeyValueStore>,
         sequence_gen: Arc<SequenceGenerator>,
     ) -> Result<Self, SkipListError> {
         let value_db: Arc<ListValueDatabase> = db.clone();
@@ -147,6 +149,30 @@ impl DatabaseBackedSkipList {
 
         Ok(Some(results.into_iter().collect()))
     }
+
+    /// Remove lane nodes of the node at given index, which were left by the removed
+    /// or not completely pushed node, so they are not extended by the next push.
+    fn clear_node(&self, index: usize) -> Result<(), SkipListError> {
+        let mut pos = NodeHeader::new(self.list_id, 0, index);
+        self.lane_db.delete(&pos)?;
+        while pos.is_edge_node() {
+            pos = pos.higher();
+            self.lane_db.delete(&pos)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the last node from the list, e.g. node which was pushed, but is not referenced by the caller.
+    /// Values of the node are left in the database, but they are not reachable anymore.
+    pub fn pop(&mut self) -> Result<(), SkipListError> {
+        if self.state.len == 0 {
+            return Ok(());
+        }
+
+        self.state.len -= 1;
+        self.list_db.put(&self.list_id, &self.state)?;
+        self.clear_node(self.state.len)
+    }
 }
 
 pub trait SkipList {
@@ -239,6 +265,8 @@ where
     /// Push new value into the end of the list. Beware, this is operation is
     /// not thread safe and should be handled with care !!!
     fn push(&mut self, value: &BTreeMap<K, V>) -> Result<(), SkipListError> {
+        self.clear_node(self.state.len)?;
+
         let mut lane = self.lane(0);
         let mut pos = NodeHeader::new(self.list_id, lane.level(), self.state.len);
 
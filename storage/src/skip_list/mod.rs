@@ -18,7 +18,7 @@
 //! * State re-creation for first 16 blocks can be done simply by traversing faster lanes (L1), and applying
 //! aggregated changes on lane descend {S015, S1215}.
 
-pub use crate::skip_list::content::{Bucket, ListValue, SkipListError};
+pub use crate::skip_list::content::{Bucket, ListValue, SkipListError, SkipListId};
 pub use crate::skip_list::lane::{Lane, TypedLane};
 pub use crate::skip_list::list::{DatabaseBackedSkipList, SkipList, TypedSkipList};
 
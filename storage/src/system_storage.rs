@@ -29,6 +29,7 @@ impl SystemStorage {
     const CHAIN_ID: &'static str = "chain_id";
     const DB_VERSION: &'static str = "db_version";
     const CHAIN_NAME: &'static str = "chain_name";
+    const OPERATIONS_INDEXED_BY_HASH: &'static str = "operations_indexed_by_hash";
 
     pub fn new(kv: Arc<SystemStorageKv>) -> Self {
         SystemStorage { kv }
@@ -96,6 +97,28 @@ impl SystemStorage {
             )
             .map_err(StorageError::from)
     }
+
+    /// Returns true, if operations stored by older versions were already indexed by operation hash
+    #[inline]
+    pub fn is_operations_indexed_by_hash(&self) -> Result<bool, StorageError> {
+        self.kv
+            .get(&Self::OPERATIONS_INDEXED_BY_HASH.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Integer(value)) => value != 0,
+                _ => false,
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_operations_indexed_by_hash(&mut self) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &Self::OPERATIONS_INDEXED_BY_HASH.to_string(),
+                &SystemValue::Integer(1),
+            )
+            .map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for SystemStorage {
@@ -0,0 +1,103 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Fixtures shared by the storage tests, not every test uses all of them.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use failure::Error;
+use slog::{Discard, Logger};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
+use storage::persistent::PersistentStorage;
+use storage::{context_key, BlockHeaderWithHash, BlockMetaStorage};
+use tezos_context::channel::ContextAction;
+use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+pub fn chain_id() -> ChainId {
+    vec![1, 2, 3, 4]
+}
+
+pub fn block(
+    block_hash: BlockHash,
+    level: i32,
+    predecessor: BlockHash,
+    context_hash: ContextHash,
+) -> Result<BlockHeaderWithHash, Error> {
+    Ok(BlockHeaderWithHash {
+        hash: block_hash,
+        header: Arc::new(
+            BlockHeaderBuilder::default()
+                .level(level)
+                .proto(0)
+                .predecessor(predecessor)
+                .timestamp(5_635_634)
+                .validation_pass(0)
+                .operations_hash(
+                    HashType::OperationListListHash.b58check_to_hash(
+                        "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc",
+                    )?,
+                )
+                .fitness(vec![])
+                .context(context_hash)
+                .protocol_data(vec![])
+                .build()
+                .unwrap(),
+        ),
+    })
+}
+
+/// Stores metadata and predecessors index of the blocks `(block_hash, level, predecessor)`
+pub fn store_blocks(
+    persistent_storage: &PersistentStorage,
+    blocks: &[(&BlockHash, i32, &BlockHash)],
+) -> Result<(), Error> {
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let log = Logger::root(Discard, slog::o!());
+    let chain_id = chain_id();
+    for (block_hash, level, predecessor) in blocks {
+        let block = block(
+            block_hash.to_vec(),
+            *level,
+            predecessor.to_vec(),
+            vec![0; 32],
+        )?;
+        let meta = block_meta_storage.put_block_header(&block, &chain_id, &log)?;
+        block_meta_storage.store_predecessors(&block.hash, &meta)?;
+    }
+    Ok(())
+}
+
+pub fn set(block_hash: &BlockHash, key: &str, value: u8, ignored: bool) -> ContextAction {
+    ContextAction::Set {
+        key: context_key!(key),
+        value: vec![value],
+        operation_hash: None,
+        block_hash: Some(block_hash.clone()),
+        context_hash: None,
+        value_as_json: None,
+        start_time: 0.0,
+        end_time: 0.0,
+        ignored,
+    }
+}
+
+pub fn commit(
+    block_hash: &BlockHash,
+    parent_context_hash: Option<&ContextHash>,
+    new_context_hash: &ContextHash,
+    date: i64,
+) -> ContextAction {
+    ContextAction::Commit {
+        parent_context_hash: parent_context_hash.cloned(),
+        block_hash: Some(block_hash.clone()),
+        new_context_hash: new_context_hash.clone(),
+        author: "Tezos".to_string(),
+        message: "Block".to_string(),
+        date,
+        parents: vec![],
+        start_time: 0.0,
+        end_time: 0.0,
+    }
+}
@@ -1,14 +1,18 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryInto;
 use std::env;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use crypto::hash::{ContextHash, HashType};
 use storage::context::{ContextApi, TezedgeContext};
+use storage::context_history::ContextHistoryByPositionIndex;
+use storage::merkle_storage::{ContextKey, EntryHash, KeyHistoryCursor, MerkleError};
+use storage::persistent::KeyValueStoreWithSchema;
 use storage::tests_common::TmpStorage;
-use storage::{context_key, BlockHeaderWithHash, BlockStorage};
+use storage::{context_key, BlockHeaderWithHash, BlockStorage, ContextHistoryIndex};
 use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
 
 #[test]
@@ -419,6 +423,347 @@ pub fn test_context_copy() -> Result<(), failure::Error> {
     Ok(())
 }
 
+#[test]
+pub fn test_walk_key_history() -> Result<(), failure::Error> {
+    // prepare temp storage
+    let tmp_storage = TmpStorage::create(test_storage_dir_path("__context:test_walk_key_history"))
+        .expect("Storage error");
+    let persistent_storage = tmp_storage.storage();
+    let block = dummy_block("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe", 0)?;
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(&persistent_storage),
+        persistent_storage.merkle(),
+    );
+
+    // data/x is changed in every commit, data/a only in some commits
+    context.set(&None, &context_key!("data/a"), &vec![0])?;
+    let mut context_hashes: Vec<EntryHash> = vec![];
+    for i in 0..20u8 {
+        match i {
+            5 | 12 => context.set(&None, &context_key!("data/a"), &vec![i])?,
+            15 => context.delete_to_diff(&None, &context_key!("data/a"))?,
+            _ => (),
+        }
+        if i > 0 {
+            context.set(&None, &context_key!("data/x"), &vec![i])?;
+        }
+        let context_hash = context.commit(
+            &block.hash,
+            &None,
+            "Tezos".to_string(),
+            format!("Block {}", i),
+            i64::from(i),
+        )?;
+        context_hashes.push(context_hash.as_slice().try_into()?);
+    }
+
+    let merkle = persistent_storage.merkle();
+    let merkle = merkle.read().unwrap();
+    let walk = |key: ContextKey,
+                from: usize,
+                to: usize,
+                step: usize|
+     -> Result<Vec<(usize, Option<Vec<u8>>)>, MerkleError> {
+        let mut cursor = KeyHistoryCursor::new(key, context_hashes[from], context_hashes[to], step);
+        let mut history = vec![];
+        // small batches to check continuation of the walk
+        while !cursor.is_finished() {
+            for change in merkle.walk_key_history(&mut cursor, 3)? {
+                let index = context_hashes
+                    .iter()
+                    .position(|hash| hash == &change.commit_hash)
+                    .unwrap();
+                history.push((index, change.value));
+            }
+        }
+        Ok(history)
+    };
+
+    // only changes are returned (newest first), together with the value in the `from` commit
+    assert_eq!(
+        walk(context_key!("data/a"), 0, 19, 1)?,
+        vec![
+            (15, None),
+            (12, Some(vec![12])),
+            (5, Some(vec![5])),
+            (0, Some(vec![0]))
+        ]
+    );
+    assert_eq!(
+        walk(context_key!("data/a"), 6, 14, 1)?,
+        vec![(12, Some(vec![12])), (6, Some(vec![5]))]
+    );
+    assert_eq!(walk(context_key!("data/a/b"), 0, 19, 1)?, vec![(0, None)]);
+
+    // every 5th commit (counted from `to`) is checked
+    assert_eq!(
+        walk(context_key!("data/x"), 0, 19, 5)?,
+        vec![
+            (19, Some(vec![19])),
+            (14, Some(vec![14])),
+            (9, Some(vec![9])),
+            (4, Some(vec![4])),
+            (0, None)
+        ]
+    );
+
+    // `from` must be an ancestor of `to`
+    assert!(matches!(
+        walk(context_key!("data/a"), 19, 0, 1),
+        Err(MerkleError::CommitNotAncestor { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_context_history_index() -> Result<(), failure::Error> {
+    // prepare temp storage
+    let tmp_storage = TmpStorage::create(test_storage_dir_path(
+        "__context:test_context_history_index",
+    ))
+    .expect("Storage error");
+    let persistent_storage = tmp_storage.storage();
+    let block = dummy_block("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe", 0)?;
+    let history_index = ContextHistoryIndex::new(&persistent_storage);
+
+    // context without index is used as a reference
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(&persistent_storage),
+        persistent_storage.merkle(),
+    );
+    let indexed_context = TezedgeContext::new(
+        BlockStorage::new(&persistent_storage),
+        persistent_storage.merkle(),
+    )
+    .with_history_index(ContextHistoryIndex::new(&persistent_storage));
+
+    // genesis
+    context.set(&None, &context_key!("data/a"), &vec![1])?;
+    context.set(&None, &context_key!("data/b"), &vec![2])?;
+    let genesis_hash = context.commit(
+        &block.hash,
+        &None,
+        "Tezos".to_string(),
+        "Genesis".to_string(),
+        0,
+    )?;
+
+    // commit not indexed yet
+    assert!(history_index
+        .get_key(&genesis_hash, &context_key!("data/a"))?
+        .is_none());
+    assert!(history_index.index_commit(&genesis_hash)?);
+    assert!(!history_index.index_commit(&genesis_hash)?);
+
+    // modify, remove and add values and then change one value in every commit (builds higher lanes of skip list)
+    context.set(&None, &context_key!("data/a"), &vec![3])?;
+    context.delete_to_diff(&None, &context_key!("data/b"))?;
+    context.set(&None, &context_key!("data/c/d"), &vec![4])?;
+    let mut context_hashes = vec![genesis_hash.clone()];
+    for i in 1..100u8 {
+        context.set(&None, &context_key!("data/x"), &vec![i])?;
+        let context_hash = context.commit(
+            &block.hash,
+            &None,
+            "Tezos".to_string(),
+            format!("Block {}", i),
+            i64::from(i),
+        )?;
+        assert!(history_index.index_commit(&context_hash)?);
+        context_hashes.push(context_hash);
+    }
+    assert_eq!(history_index.len()?, 100);
+
+    assert_eq!(
+        history_index.get_key(&genesis_hash, &context_key!("data/b"))?,
+        Some(Some(vec![2]))
+    );
+    assert_eq!(
+        history_index.get_key(&genesis_hash, &context_key!("data/x"))?,
+        Some(None)
+    );
+    assert_eq!(
+        history_index.get_key(&context_hashes[1], &context_key!("data/b"))?,
+        Some(None)
+    );
+
+    // index returns the same values as merkle storage
+    for (i, context_hash) in context_hashes.iter().enumerate() {
+        for key in &[
+            context_key!("data/a"),
+            context_key!("data/b"),
+            context_key!("data/c/d"),
+            context_key!("data/x"),
+        ] {
+            assert_eq!(
+                indexed_context.get_key_from_history(context_hash, key)?,
+                context.get_key_from_history(context_hash, key)?,
+                "key: {:?}, commit: {}",
+                key,
+                i
+            );
+        }
+        if i > 0 {
+            assert_eq!(
+                history_index.get_key(context_hash, &context_key!("data/x"))?,
+                Some(Some(vec![i as u8]))
+            );
+            assert_eq!(
+                history_index.get_key(context_hash, &context_key!("data/a"))?,
+                Some(Some(vec![3]))
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_context_history_index_backfill() -> Result<(), failure::Error> {
+    // prepare temp storage
+    let tmp_storage = TmpStorage::create(test_storage_dir_path(
+        "__context:test_context_history_index_backfill",
+    ))
+    .expect("Storage error");
+    let persistent_storage = tmp_storage.storage();
+    let block_storage = BlockStorage::new(&persistent_storage);
+    let history_index = ContextHistoryIndex::new(&persistent_storage);
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(&persistent_storage),
+        persistent_storage.merkle(),
+    );
+
+    // applied chain of blocks, every block sets its own level
+    let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+    for level in 0..=5u8 {
+        context.set(&None, &context_key!("data/level"), &vec![level])?;
+        let block_hash = vec![level; 32];
+        let context_hash = context.commit(
+            &block_hash,
+            &None,
+            "Tezos".to_string(),
+            format!("Block {}", level),
+            i64::from(level),
+        )?;
+        let predecessor = match blocks.last() {
+            Some(predecessor) => predecessor.hash.clone(),
+            None => block_hash.clone(),
+        };
+        let block = BlockHeaderWithHash {
+            hash: block_hash,
+            header: Arc::new(
+                BlockHeaderBuilder::default()
+                    .level(i32::from(level))
+                    .proto(0)
+                    .predecessor(predecessor)
+                    .timestamp(5_635_634)
+                    .validation_pass(0)
+                    .operations_hash(HashType::OperationListListHash.b58check_to_hash(
+                        "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc",
+                    )?)
+                    .fitness(vec![])
+                    .context(context_hash)
+                    .protocol_data(vec![])
+                    .build()
+                    .unwrap(),
+            ),
+        };
+        block_storage.put_block_header(&block)?;
+        blocks.push(block);
+    }
+
+    // empty index is started with genesis context
+    assert_eq!(history_index.backfill(&block_storage, &blocks[3].hash)?, 3);
+    assert_eq!(history_index.len()?, 4);
+
+    // only the blocks over the last indexed one are indexed
+    assert_eq!(history_index.backfill(&block_storage, &blocks[5].hash)?, 2);
+    assert_eq!(history_index.backfill(&block_storage, &blocks[5].hash)?, 0);
+    assert_eq!(history_index.len()?, 6);
+
+    for (level, block) in blocks.iter().enumerate() {
+        assert_eq!(
+            history_index.get_key(block.header.context(), &context_key!("data/level"))?,
+            Some(Some(vec![level as u8]))
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_context_history_index_orphaned_node() -> Result<(), failure::Error> {
+    // prepare temp storage
+    let tmp_storage = TmpStorage::create(test_storage_dir_path(
+        "__context:test_context_history_index_orphaned_node",
+    ))
+    .expect("Storage error");
+    let persistent_storage = tmp_storage.storage();
+    let block = dummy_block("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe", 0)?;
+    let history_index = ContextHistoryIndex::new(&persistent_storage);
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(&persistent_storage),
+        persistent_storage.merkle(),
+    );
+
+    context.set(&None, &context_key!("data/a"), &vec![1])?;
+    let genesis_hash = context.commit(
+        &block.hash,
+        &None,
+        "Tezos".to_string(),
+        "Genesis".to_string(),
+        0,
+    )?;
+    assert!(history_index.index_commit(&genesis_hash)?);
+
+    context.set(&None, &context_key!("data/b"), &vec![2])?;
+    let orphaned_hash = context.commit(
+        &block.hash,
+        &None,
+        "Tezos".to_string(),
+        "Orphaned".to_string(),
+        1,
+    )?;
+    assert!(history_index.index_commit(&orphaned_hash)?);
+
+    // simulate crash after the node was pushed to the skip list, but before the commit was stored
+    let kv = persistent_storage.kv();
+    KeyValueStoreWithSchema::<ContextHistoryByPositionIndex>::delete(kv.as_ref(), &1)?;
+    KeyValueStoreWithSchema::<ContextHistoryIndex>::delete(kv.as_ref(), &orphaned_hash)?;
+    assert_eq!(
+        history_index.get_key(&orphaned_hash, &context_key!("data/b"))?,
+        None
+    );
+
+    // orphaned node is replaced by the next indexed commit
+    context.delete_to_diff(&None, &context_key!("data/b"))?;
+    context.set(&None, &context_key!("data/c"), &vec![3])?;
+    let context_hash = context.commit(
+        &block.hash,
+        &None,
+        "Tezos".to_string(),
+        "Block".to_string(),
+        2,
+    )?;
+    assert!(history_index.index_commit(&context_hash)?);
+    assert_eq!(history_index.len()?, 2);
+    assert_eq!(
+        history_index.get_key(&context_hash, &context_key!("data/a"))?,
+        Some(Some(vec![1]))
+    );
+    assert_eq!(
+        history_index.get_key(&context_hash, &context_key!("data/b"))?,
+        Some(None)
+    );
+    assert_eq!(
+        history_index.get_key(&context_hash, &context_key!("data/c"))?,
+        Some(Some(vec![3]))
+    );
+
+    Ok(())
+}
+
 fn dummy_block(block_hash: &str, level: i32) -> Result<BlockHeaderWithHash, failure::Error> {
     Ok(BlockHeaderWithHash {
         hash: HashType::BlockHash.b58check_to_hash(block_hash)?,
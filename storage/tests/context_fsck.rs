@@ -0,0 +1,245 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use failure::Error;
+use slog::{Discard, Logger};
+
+use crypto::hash::ChainId;
+use storage::chain_meta_storage::ChainMetaStorage;
+use storage::context::{ContextApi, TezedgeContext};
+use storage::context_fsck::{self, ContextFsckError};
+use storage::merkle_storage::MerkleStorage;
+use storage::persistent::{KeyValueStoreWithSchema, PersistentStorage};
+use storage::tests_common::TmpStorage;
+use storage::{context_key, BlockHeaderWithHash, BlockStorage, ContextActionStorage};
+use tezos_context::channel::ContextAction;
+use tezos_messages::Head;
+
+mod common;
+
+#[test]
+fn test_load_blocks() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__context_fsck_load_blocks")?;
+    let persistent_storage = tmp_storage.storage();
+    let chain_id = common::chain_id();
+    let blocks = apply_blocks(&chain_id, persistent_storage)?;
+
+    let loaded = context_fsck::load_blocks(&chain_id, 1, Some(2), persistent_storage)?;
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded.first_block_hash(), Some(&blocks[1].hash));
+    assert_eq!(
+        loaded
+            .map(|block| block.map(|block| block.hash))
+            .collect::<Result<Vec<_>, _>>()?,
+        vec![blocks[1].hash.clone(), blocks[2].hash.clone()]
+    );
+
+    // up to the current head
+    let loaded = context_fsck::load_blocks(&chain_id, 0, None, persistent_storage)?;
+    assert_eq!(loaded.len(), blocks.len());
+
+    assert!(matches!(
+        context_fsck::load_blocks(&vec![0, 0, 0, 0], 0, None, persistent_storage),
+        Err(ContextFsckError::MissingCurrentHead)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_check_and_repair_context() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__context_fsck_check_and_repair_context")?;
+    let persistent_storage = tmp_storage.storage();
+    let log = Logger::root(Discard, slog::o!());
+    let chain_id = common::chain_id();
+    let blocks = apply_blocks(&chain_id, persistent_storage)?;
+
+    let report = context_fsck::check_context(&chain_id, 0, None, persistent_storage, &log)?;
+    assert!(report.is_ok());
+    assert_eq!(report.checked_blocks, blocks.len());
+    // every block adds commit, root tree, "data" tree and blob
+    assert_eq!(report.checked_entries, 4 * blocks.len());
+
+    // remove blob introduced by the block 2
+    let blob_hash = merkle_proof::hash_blob(&[2])?;
+    KeyValueStoreWithSchema::<MerkleStorage>::delete(persistent_storage.kv().as_ref(), &blob_hash)?;
+
+    let report = context_fsck::check_context(&chain_id, 0, None, persistent_storage, &log)?;
+    assert_eq!(report.broken_blocks.len(), 1);
+    assert_eq!(report.broken_blocks[0].block_hash, blocks[2].hash);
+    assert_eq!(report.issues_count(), 1);
+    assert_eq!(report.broken_blocks[0].issues[0].hash(), &blob_hash);
+
+    // block is re-applied from the stored context actions
+    assert_eq!(
+        context_fsck::repair_context(&report, persistent_storage, &log)?,
+        vec![blocks[2].hash.clone()]
+    );
+    assert!(context_fsck::check_context(&chain_id, 0, None, persistent_storage, &log)?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_context_without_actions() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__context_fsck_repair_context_without_actions")?;
+    let persistent_storage = tmp_storage.storage();
+    let log = Logger::root(Discard, slog::o!());
+    let chain_id = common::chain_id();
+    apply_blocks(&chain_id, persistent_storage)?;
+
+    // actions of genesis are not stored, blobs of the genesis and the block 2 are removed
+    for value in &[0, 2] {
+        KeyValueStoreWithSchema::<MerkleStorage>::delete(
+            persistent_storage.kv().as_ref(),
+            &merkle_proof::hash_blob(&[*value])?,
+        )?;
+    }
+    let report = context_fsck::check_context(&chain_id, 0, None, persistent_storage, &log)?;
+    assert_eq!(report.broken_blocks.len(), 2);
+
+    // nothing is re-applied
+    assert!(matches!(
+        context_fsck::repair_context(&report, persistent_storage, &log),
+        Err(ContextFsckError::MissingContextActions { .. })
+    ));
+    let report = context_fsck::check_context(&chain_id, 0, None, persistent_storage, &log)?;
+    assert_eq!(report.broken_blocks.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_replay_context_action() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__context_fsck_replay_context_action")?;
+    let persistent_storage = tmp_storage.storage();
+    let block_hash = vec![1; 32];
+    BlockStorage::new(persistent_storage).put_block_header(&common::block(
+        block_hash.clone(),
+        0,
+        block_hash.clone(),
+        vec![0; 32],
+    )?)?;
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(persistent_storage),
+        persistent_storage.merkle(),
+    );
+
+    // ignored and read actions do not modify the context
+    assert_eq!(
+        context_fsck::replay_context_action(
+            &mut context,
+            &common::set(&block_hash, "data/level", 1, true)
+        )?,
+        None
+    );
+    assert_eq!(
+        context_fsck::replay_context_action(
+            &mut context,
+            &ContextAction::Get {
+                key: context_key!("data/level"),
+                value: vec![1],
+                operation_hash: None,
+                block_hash: Some(block_hash.clone()),
+                context_hash: None,
+                value_as_json: None,
+                start_time: 0.0,
+                end_time: 0.0,
+            }
+        )?,
+        None
+    );
+    assert!(!context.mem(&context_key!("data/level"))?);
+
+    assert_eq!(
+        context_fsck::replay_context_action(
+            &mut context,
+            &common::set(&block_hash, "data/level", 1, false)
+        )?,
+        None
+    );
+    assert_eq!(context.get_key(&context_key!("data/level"))?, vec![1]);
+
+    // commit returns the new context hash
+    let context_hash = context_fsck::replay_context_action(
+        &mut context,
+        &common::commit(&block_hash, None, &vec![0; 32], 0),
+    )?
+    .expect("commit is applied");
+    assert_eq!(
+        context.get_key_from_history(&context_hash, &context_key!("data/level"))?,
+        Some(vec![1])
+    );
+
+    Ok(())
+}
+
+/// Applies blocks 0..=3 (every one sets its level to `data/level`), stores their headers, actions (except genesis)
+/// and sets the last one as the current head
+fn apply_blocks(
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Vec<BlockHeaderWithHash>, Error> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let mut context_action_storage = ContextActionStorage::new(persistent_storage);
+    let mut context = TezedgeContext::new(
+        BlockStorage::new(persistent_storage),
+        persistent_storage.merkle(),
+    );
+
+    let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+    for level in 0..=3u8 {
+        let block_hash = vec![level; 32];
+        let parent_context_hash = blocks.last().map(|block| block.header.context().clone());
+        context.set(
+            &parent_context_hash,
+            &context_key!("data/level"),
+            &vec![level],
+        )?;
+        let context_hash = context.commit(
+            &block_hash,
+            &None,
+            "Tezos".to_string(),
+            "Block".to_string(),
+            i64::from(level),
+        )?;
+
+        let predecessor = match blocks.last() {
+            Some(predecessor) => predecessor.hash.clone(),
+            None => block_hash.clone(),
+        };
+        let block = common::block(
+            block_hash.clone(),
+            i32::from(level),
+            predecessor,
+            context_hash.clone(),
+        )?;
+        block_storage.put_block_header(&block)?;
+        block_storage.assign_to_context(&block.hash, &context_hash)?;
+
+        if let Some(parent_context_hash) = parent_context_hash {
+            context_action_storage.put_action(
+                &block_hash,
+                common::set(&block_hash, "data/level", level, false),
+            )?;
+            context_action_storage.put_action(
+                &block_hash,
+                common::commit(
+                    &block_hash,
+                    Some(&parent_context_hash),
+                    &context_hash,
+                    i64::from(level),
+                ),
+            )?;
+        }
+        blocks.push(block);
+    }
+
+    let head = blocks.last().expect("blocks are applied");
+    ChainMetaStorage::new(persistent_storage).set_current_head(
+        chain_id,
+        Head::new(head.hash.clone(), head.header.level(), vec![]),
+    )?;
+
+    Ok(blocks)
+}
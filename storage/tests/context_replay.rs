@@ -0,0 +1,279 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryInto;
+
+use failure::Error;
+use slog::{Discard, Logger};
+
+use crypto::hash::HashType;
+use storage::context::{ContextApi, TezedgeContext};
+use storage::context_replay::{self, ContextReplayError};
+use storage::merkle_storage::EntryHash;
+use storage::tests_common::TmpStorage;
+use storage::{context_key, BlockHeaderWithHash, BlockStorage, ContextActionStorage, StorageError};
+use tezos_context::channel::ContextAction;
+
+mod common;
+
+#[test]
+fn test_replay_blocks() -> Result<(), Error> {
+    let source_storage = TmpStorage::create("__context_replay_source")?;
+    let target_storage = TmpStorage::create("__context_replay_target")?;
+    let source = source_storage.storage();
+    let target = target_storage.storage();
+    let log = Logger::root(Discard, slog::o!());
+
+    let genesis = dummy_block("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe", 0)?;
+    let block_1 = dummy_block("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET", 1)?;
+    let block_2 = dummy_block("BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ", 2)?;
+    let source_block_storage = BlockStorage::new(source);
+    let target_block_storage = BlockStorage::new(target);
+    for block in &[&genesis, &block_1, &block_2] {
+        source_block_storage.put_block_header(block)?;
+        target_block_storage.put_block_header(block)?;
+    }
+
+    // apply blocks to the source context
+    let mut source_context = TezedgeContext::new(BlockStorage::new(source), source.merkle());
+    source_context.set(&None, &context_key!("data/a"), &vec![1])?;
+    let genesis_context_hash = source_context.commit(
+        &genesis.hash,
+        &None,
+        "Tezos".to_string(),
+        "Genesis".to_string(),
+        0,
+    )?;
+    source_context.set(&None, &context_key!("data/b"), &vec![2])?;
+    let block_1_context_hash = source_context.commit(
+        &block_1.hash,
+        &Some(genesis_context_hash.clone()),
+        "Tezos".to_string(),
+        "Block".to_string(),
+        1,
+    )?;
+
+    // record actions of the blocks
+    let mut context_action_storage = ContextActionStorage::new(source);
+    context_action_storage.put_action(
+        &block_1.hash,
+        ContextAction::Get {
+            key: context_key!("data/a"),
+            value: vec![1],
+            operation_hash: None,
+            block_hash: Some(block_1.hash.clone()),
+            context_hash: None,
+            value_as_json: None,
+            start_time: 0.0,
+            end_time: 0.0,
+        },
+    )?;
+    context_action_storage.put_action(
+        &block_1.hash,
+        common::set(&block_1.hash, "data/b", 2, false),
+    )?;
+    context_action_storage
+        .put_action(&block_1.hash, common::set(&block_1.hash, "data/c", 3, true))?;
+    context_action_storage.put_action(
+        &block_1.hash,
+        ContextAction::Mem {
+            key: context_key!("data/b"),
+            value: false,
+            operation_hash: None,
+            block_hash: Some(block_1.hash.clone()),
+            context_hash: None,
+            start_time: 0.0,
+            end_time: 0.0,
+        },
+    )?;
+    context_action_storage.put_action(
+        &block_1.hash,
+        common::commit(
+            &block_1.hash,
+            Some(&genesis_context_hash),
+            &block_1_context_hash,
+            1,
+        ),
+    )?;
+    context_action_storage.put_action(
+        &block_2.hash,
+        common::set(&block_2.hash, "data/c", 3, false),
+    )?;
+    context_action_storage.put_action(
+        &block_2.hash,
+        common::commit(
+            &block_2.hash,
+            Some(&block_1_context_hash),
+            &genesis_context_hash,
+            2,
+        ),
+    )?;
+
+    // replay to the target context from the copied genesis context
+    let genesis_entry_hash: EntryHash = genesis_context_hash.as_slice().try_into()?;
+    target
+        .merkle()
+        .read()
+        .unwrap()
+        .copy_context_from(&source.merkle().read().unwrap(), &genesis_entry_hash)?;
+    let mut target_context = TezedgeContext::new(BlockStorage::new(target), target.merkle());
+
+    let report = context_replay::replay_blocks(
+        loaded(&[&block_1]),
+        &context_action_storage,
+        &mut target_context,
+        &log,
+    )?;
+    assert_eq!(report.replayed_blocks, 1);
+    // checkout, get, set, mem and commit, ignored set is skipped
+    assert_eq!(report.replayed_actions(), 5);
+    assert_eq!(report.read_mismatches, 1);
+    assert_eq!(report.latencies["Set"].count, 1);
+    assert_eq!(report.latencies["Commit"].count, 1);
+    assert_eq!(
+        target_context.get_key_from_history(&block_1_context_hash, &context_key!("data/b"))?,
+        Some(vec![2])
+    );
+
+    // recorded commit hash does not match
+    match context_replay::replay_blocks(
+        loaded(&[&block_1, &block_2]),
+        &context_action_storage,
+        &mut target_context,
+        &log,
+    ) {
+        Err(ContextReplayError::InvalidContextHash { block_hash, .. }) => {
+            assert_eq!(
+                block_hash,
+                HashType::BlockHash.hash_to_b58check(&block_2.hash)
+            )
+        }
+        result => panic!("Unexpected replay result: {:?}", result),
+    }
+
+    // actions of genesis are not stored
+    assert!(matches!(
+        context_replay::replay_blocks(
+            loaded(&[&genesis]),
+            &context_action_storage,
+            &mut target_context,
+            &log,
+        ),
+        Err(ContextReplayError::MissingContextActions { .. })
+    ));
+
+    // commit of genesis has no parent context to replay from
+    context_action_storage.put_action(
+        &genesis.hash,
+        common::set(&genesis.hash, "data/a", 1, false),
+    )?;
+    context_action_storage.put_action(
+        &genesis.hash,
+        ContextAction::Commit {
+            parent_context_hash: None,
+            block_hash: Some(genesis.hash.clone()),
+            new_context_hash: genesis_context_hash.clone(),
+            author: "Tezos".to_string(),
+            message: "Genesis".to_string(),
+            date: 0,
+            parents: vec![],
+            start_time: 0.0,
+            end_time: 0.0,
+        },
+    )?;
+    match context_replay::replay_blocks(
+        loaded(&[&genesis]),
+        &context_action_storage,
+        &mut target_context,
+        &log,
+    ) {
+        Err(ContextReplayError::MissingParentContext { block_hash }) => {
+            assert_eq!(
+                block_hash,
+                HashType::BlockHash.hash_to_b58check(&genesis.hash)
+            )
+        }
+        result => panic!("Unexpected replay result: {:?}", result),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_replay_blocks_commit_without_block_hash() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__context_replay_commit_without_block_hash")?;
+    let storage = tmp_storage.storage();
+    let log = Logger::root(Discard, slog::o!());
+
+    let genesis = dummy_block("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe", 0)?;
+    let block_1 = dummy_block("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET", 1)?;
+    let block_storage = BlockStorage::new(storage);
+    for block in &[&genesis, &block_1] {
+        block_storage.put_block_header(block)?;
+    }
+    let mut context = TezedgeContext::new(BlockStorage::new(storage), storage.merkle());
+    context.set(&None, &context_key!("data/a"), &vec![1])?;
+    let genesis_context_hash = context.commit(
+        &genesis.hash,
+        &None,
+        "Tezos".to_string(),
+        "Genesis".to_string(),
+        0,
+    )?;
+
+    // recorded commit is verified even if it has no block hash (and so it is not applied)
+    let mut context_action_storage = ContextActionStorage::new(storage);
+    context_action_storage.put_action(
+        &block_1.hash,
+        common::set(&block_1.hash, "data/b", 2, false),
+    )?;
+    context_action_storage.put_action(
+        &block_1.hash,
+        ContextAction::Commit {
+            parent_context_hash: Some(genesis_context_hash.clone()),
+            block_hash: None,
+            new_context_hash: genesis_context_hash,
+            author: "Tezos".to_string(),
+            message: "Block".to_string(),
+            date: 1,
+            parents: vec![],
+            start_time: 0.0,
+            end_time: 0.0,
+        },
+    )?;
+    match context_replay::replay_blocks(
+        loaded(&[&block_1]),
+        &context_action_storage,
+        &mut context,
+        &log,
+    ) {
+        Err(ContextReplayError::InvalidContextHash {
+            block_hash, found, ..
+        }) => {
+            assert_eq!(
+                block_hash,
+                HashType::BlockHash.hash_to_b58check(&block_1.hash)
+            );
+            assert_eq!(found, "<not committed>");
+        }
+        result => panic!("Unexpected replay result: {:?}", result),
+    }
+
+    Ok(())
+}
+
+/// Blocks as loaded from the storage
+fn loaded(blocks: &[&BlockHeaderWithHash]) -> Vec<Result<BlockHeaderWithHash, StorageError>> {
+    blocks.iter().map(|block| Ok((*block).clone())).collect()
+}
+
+fn dummy_block(block_hash: &str, level: i32) -> Result<BlockHeaderWithHash, Error> {
+    common::block(
+        HashType::BlockHash.b58check_to_hash(block_hash)?,
+        level,
+        HashType::BlockHash
+            .b58check_to_hash("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+        HashType::ContextHash
+            .b58check_to_hash("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd")?,
+    )
+}
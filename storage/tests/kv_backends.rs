@@ -0,0 +1,217 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use failure::Error;
+
+use crypto::hash::HashType;
+use storage::block_meta_storage::Meta;
+use storage::persistent::backend::{BackendIteratorMode, WriteBatch};
+use storage::persistent::{KeyValueSchema, KeyValueStoreBackend, KeyValueStoreBackendType};
+use storage::tests_common::TmpStorage;
+use storage::*;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[cfg(feature = "sled-backend")]
+const BACKENDS: [KeyValueStoreBackendType; 3] = [
+    KeyValueStoreBackendType::RocksDb,
+    KeyValueStoreBackendType::Sled,
+    KeyValueStoreBackendType::InMemory,
+];
+#[cfg(not(feature = "sled-backend"))]
+const BACKENDS: [KeyValueStoreBackendType; 2] = [
+    KeyValueStoreBackendType::RocksDb,
+    KeyValueStoreBackendType::InMemory,
+];
+
+fn create_storage(name: &str, backend: KeyValueStoreBackendType) -> Result<TmpStorage, Error> {
+    TmpStorage::create_with_backend(format!("__kv_backend_{}_{:?}", name, backend), backend)
+}
+
+#[test]
+fn block_storage_on_all_backends() -> Result<(), Error> {
+    for backend in BACKENDS.iter() {
+        let tmp_storage = create_storage("block_storage", *backend)?;
+        let storage = BlockStorage::new(tmp_storage.storage());
+
+        let block_header = make_test_block_header()?;
+        storage.put_block_header(&block_header)?;
+
+        assert_eq!(
+            Some(block_header.clone()),
+            storage.get(&block_header.hash)?,
+            "backend: {:?}",
+            backend
+        );
+        assert_eq!(
+            vec![block_header.clone()],
+            storage.get_multiple_without_json(&block_header.hash, 10)?,
+            "backend: {:?}",
+            backend
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn block_meta_merge_on_all_backends() -> Result<(), Error> {
+    let predecessor = HashType::BlockHash
+        .b58check_to_hash("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?;
+    let block_hash = HashType::BlockHash
+        .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+
+    for backend in BACKENDS.iter() {
+        let tmp_storage = create_storage("block_meta", *backend)?;
+        let storage = BlockMetaStorage::new(tmp_storage.storage());
+
+        storage.put(&block_hash, &Meta::new(false, None, 1, vec![1, 2, 3, 4]))?;
+        storage.put(
+            &block_hash,
+            &Meta::new(true, Some(predecessor.clone()), 1, vec![1, 2, 3, 4]),
+        )?;
+
+        let meta = storage.get(&block_hash)?.expect("meta not found");
+        assert!(meta.is_applied(), "backend: {:?}", backend);
+        assert_eq!(
+            &Some(predecessor.clone()),
+            meta.predecessor(),
+            "backend: {:?}",
+            backend
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn operations_prefix_iterator_on_all_backends() -> Result<(), Error> {
+    let block_hash_1 = HashType::BlockHash
+        .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+    let block_hash_2 = HashType::BlockHash
+        .b58check_to_hash("BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ")?;
+
+    for backend in BACKENDS.iter() {
+        let tmp_storage = create_storage("operations", *backend)?;
+        let storage = OperationsStorage::new(tmp_storage.storage());
+
+        for (block_hash, validation_pass) in &[
+            (&block_hash_1, 2),
+            (&block_hash_2, 0),
+            (&block_hash_1, 0),
+            (&block_hash_1, 1),
+        ] {
+            storage.put_operations(&OperationsForBlocksMessage::new(
+                OperationsForBlock::new((*block_hash).clone(), *validation_pass),
+                Path::Op,
+                vec![],
+            ))?;
+        }
+
+        let operations = storage.get_operations(&block_hash_1)?;
+        assert_eq!(3, operations.len(), "backend: {:?}", backend);
+        for (i, operation) in operations.iter().enumerate() {
+            assert_eq!(&block_hash_1, operation.operations_for_block().hash());
+            assert_eq!(i as i8, operation.operations_for_block().validation_pass());
+        }
+        assert_eq!(
+            1,
+            storage.get_operations(&block_hash_2)?.len(),
+            "backend: {:?}",
+            backend
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn merkle_storage_on_all_backends() -> Result<(), Error> {
+    let key_a: Vec<String> = vec!["data".into(), "a".into(), "x".into()];
+    let key_b: Vec<String> = vec!["data".into(), "b".into()];
+    let mut commit_hashes = Vec::new();
+
+    for backend in BACKENDS.iter() {
+        let tmp_storage = create_storage("merkle", *backend)?;
+        let merkle = tmp_storage.storage().merkle();
+        let mut merkle = merkle.write().unwrap();
+
+        merkle.set(&key_a, &vec![1, 2, 3])?;
+        let first = merkle.commit(0, "tezedge".to_string(), "first".to_string())?;
+        merkle.set(&key_b, &vec![4, 5])?;
+        merkle.delete(&key_a)?;
+        let second = merkle.commit(1, "tezedge".to_string(), "second".to_string())?;
+
+        merkle.checkout(&first)?;
+        assert_eq!(vec![1, 2, 3], merkle.get(&key_a)?);
+        assert!(!merkle.mem(&key_b)?, "backend: {:?}", backend);
+
+        merkle.checkout(&second)?;
+        assert_eq!(vec![4, 5], merkle.get(&key_b)?);
+        assert!(!merkle.mem(&key_a)?, "backend: {:?}", backend);
+
+        commit_hashes.push((first, second));
+    }
+
+    // the same content has to result in the same hashes regardless of the backend
+    assert!(commit_hashes.windows(2).all(|w| w[0] == w[1]));
+    Ok(())
+}
+
+#[test]
+fn write_batch_on_all_backends() -> Result<(), Error> {
+    let (meta_column, system_column) = (BlockMetaStorage::name(), SystemStorage::name());
+
+    for backend in BACKENDS.iter() {
+        let tmp_storage = create_storage("write_batch", *backend)?;
+        let kv = tmp_storage.storage().kv();
+        KeyValueStoreBackend::put(kv.as_ref(), system_column, &[1], &[1])?;
+
+        // puts and deletes in multiple columns
+        let mut batch = WriteBatch::default();
+        batch.put(meta_column, vec![2], vec![2]);
+        batch.put(system_column, vec![3], vec![3]);
+        batch.delete(system_column, vec![1]);
+        KeyValueStoreBackend::write_batch(kv.as_ref(), batch)?;
+        assert_eq!(
+            Some(vec![2]),
+            KeyValueStoreBackend::get(kv.as_ref(), meta_column, &[2])?,
+            "backend: {:?}",
+            backend
+        );
+        let system_entries =
+            KeyValueStoreBackend::iterator(kv.as_ref(), system_column, BackendIteratorMode::Start)?
+                .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            vec![(vec![3].into_boxed_slice(), vec![3].into_boxed_slice())],
+            system_entries,
+            "backend: {:?}",
+            backend
+        );
+
+        // batch with unknown column is not applied at all
+        let mut batch = WriteBatch::default();
+        batch.put(meta_column, vec![4], vec![4]);
+        batch.put("unknown_column", vec![4], vec![4]);
+        assert!(
+            KeyValueStoreBackend::write_batch(kv.as_ref(), batch).is_err(),
+            "backend: {:?}",
+            backend
+        );
+        assert!(
+            !KeyValueStoreBackend::contains(kv.as_ref(), meta_column, &[4])?,
+            "backend: {:?}",
+            backend
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sled-backend"))]
+#[test]
+fn sled_backend_not_enabled() {
+    assert!(create_storage("sled_not_enabled", KeyValueStoreBackendType::Sled).is_err());
+}
+
+fn make_test_block_header() -> Result<BlockHeaderWithHash, Error> {
+    let message_bytes = hex::decode("00006d6e0102dd00defaf70c53e180ea148b349a6feb4795610b2abc7b07fe91ce50a90814000000005c1276780432bc1d3a28df9a67b363aa1638f807214bb8987e5f9c0abcbd69531facffd1c80000001100000001000000000800000000000c15ef15a6f54021cb353780e2847fb9c546f1d72c1dc17c3db510f45553ce501ce1de000000000003c762c7df00a856b8bfcaf0676f069f825ca75f37f2bee9fe55ba109cec3d1d041d8c03519626c0c0faa557e778cb09d2e0c729e8556ed6a7a518c84982d1f2682bc6aa753f")?;
+    let block_header = BlockHeaderWithHash::new(BlockHeader::from_bytes(message_bytes)?)?;
+    Ok(block_header)
+}
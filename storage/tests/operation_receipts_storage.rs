@@ -0,0 +1,227 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use failure::Error;
+use serde_json::json;
+
+use crypto::hash::HashType;
+use storage::tests_common::TmpStorage;
+use storage::OperationReceiptsStorage;
+use tezos_messages::Head;
+
+mod common;
+
+const SOURCE: &str = "tz1Y68Da76MHixYhJhyU36bVh7a8C9UmtvrR";
+const BAKER: &str = "tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17";
+const ORIGINATED: &str = "KT1NrjjM791v7cyo6VGy7rrzB3Dg3p1mQki3";
+const OPERATION_1: &str = "oneev3QMfQJd2fEM3zQPdFwwPY1aohkxffttrK8Qrswemnh2nML";
+const OPERATION_2: &str = "onf6ZPF8hsU6q6D7ZmLtjquoNqKh7N8Xr5vW8WzP1yYueNyCF86";
+
+fn operations_json(operation_hash: &str) -> String {
+    json!([
+        [],
+        [],
+        [],
+        [{
+            "hash": operation_hash,
+            "contents": [{
+                "kind": "origination",
+                "source": SOURCE,
+                "metadata": {
+                    "balance_updates": [
+                        { "kind": "contract", "contract": SOURCE, "change": "-1420" },
+                        { "kind": "freezer", "category": "fees", "delegate": BAKER, "cycle": 7, "change": "1420" }
+                    ],
+                    "operation_result": {
+                        "status": "applied",
+                        "balance_updates": [
+                            { "kind": "contract", "contract": SOURCE, "change": "-257000" }
+                        ],
+                        "originated_contracts": [ORIGINATED]
+                    },
+                    "internal_operation_results": [{
+                        "kind": "transaction",
+                        "source": ORIGINATED,
+                        "destination": SOURCE,
+                        "result": { "status": "backtracked" }
+                    }]
+                }
+            }]
+        }]
+    ])
+    .to_string()
+}
+
+#[test]
+fn test_index_block_receipts() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__operation_receipts_index_block")?;
+    let storage = OperationReceiptsStorage::new(tmp_storage.storage());
+
+    let block_hash_1 = HashType::BlockHash
+        .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+    let block_hash_2 = HashType::BlockHash
+        .b58check_to_hash("BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ")?;
+    let genesis_hash = vec![4; 32];
+    common::store_blocks(
+        tmp_storage.storage(),
+        &[
+            (&genesis_hash, 4, &genesis_hash),
+            (&block_hash_1, 5, &genesis_hash),
+            (&block_hash_2, 6, &block_hash_1),
+        ],
+    )?;
+    let head = Head::new(block_hash_2.clone(), 6, vec![]);
+
+    assert_eq!(
+        storage.index_block(&block_hash_1, 5, &operations_json(OPERATION_1))?,
+        1
+    );
+    assert_eq!(
+        storage.index_block(&block_hash_2, 6, &operations_json(OPERATION_2))?,
+        1
+    );
+
+    let operation_hash = HashType::OperationHash.b58check_to_hash(OPERATION_1)?;
+    let receipt = storage
+        .get(&operation_hash, &head)?
+        .expect("receipt not found");
+    assert_eq!(receipt.block_hash, block_hash_1);
+    assert_eq!(receipt.level, 5);
+    assert_eq!(receipt.validation_pass, 3);
+    assert_eq!(receipt.operation_index, 0);
+    assert_eq!(receipt.contents.len(), 2);
+
+    let origination = &receipt.contents[0];
+    assert_eq!(origination.status.as_deref(), Some("applied"));
+    assert_eq!(
+        origination.originated_contracts,
+        vec![ORIGINATED.to_string()]
+    );
+    assert_eq!(
+        origination
+            .balance_updates
+            .iter()
+            .map(|update| update.change)
+            .collect::<Vec<_>>(),
+        vec![-1420, 1420, -257000]
+    );
+    assert_eq!(origination.balance_updates[1].cycle, Some(7));
+    let internal = &receipt.contents[1];
+    assert!(internal.internal);
+    assert_eq!(internal.status.as_deref(), Some("backtracked"));
+
+    // all affected accounts are indexed, ordered by level
+    for account in &[SOURCE, BAKER, ORIGINATED] {
+        let receipts = storage.get_by_account(account, None, 10, &head)?;
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].1.level, 5);
+        assert_eq!(receipts[1].1.level, 6);
+    }
+    let receipts = storage.get_by_account(SOURCE, Some(6), 10, &head)?;
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(
+        receipts[0].0,
+        HashType::OperationHash.b58check_to_hash(OPERATION_2)?
+    );
+    assert_eq!(storage.get_by_account(SOURCE, None, 1, &head)?.len(), 1);
+    assert!(storage
+        .get_by_account("tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx", None, 10, &head)?
+        .is_empty());
+
+    // operations of blocks above the head are not returned
+    let head_1 = Head::new(block_hash_1.clone(), 5, vec![]);
+    assert_eq!(storage.get_by_account(SOURCE, None, 10, &head_1)?.len(), 1);
+    assert!(storage
+        .get(
+            &HashType::OperationHash.b58check_to_hash(OPERATION_2)?,
+            &head_1
+        )?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_index_block_receipts_of_fork() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__operation_receipts_index_block_of_fork")?;
+    let storage = OperationReceiptsStorage::new(tmp_storage.storage());
+
+    // genesis <- block_5 <- block_6
+    //         <- fork_5
+    let genesis_hash = vec![4; 32];
+    let block_hash_5 = vec![5; 32];
+    let block_hash_6 = vec![6; 32];
+    let fork_hash_5 = vec![15; 32];
+    common::store_blocks(
+        tmp_storage.storage(),
+        &[
+            (&genesis_hash, 4, &genesis_hash),
+            (&block_hash_5, 5, &genesis_hash),
+            (&block_hash_6, 6, &block_hash_5),
+            (&fork_hash_5, 5, &genesis_hash),
+        ],
+    )?;
+    let main_head = Head::new(block_hash_6.clone(), 6, vec![]);
+    let fork_head = Head::new(fork_hash_5.clone(), 5, vec![]);
+
+    // operation 1 is applied by both branches, the fork is applied as the last one
+    storage.index_block(&block_hash_5, 5, &operations_json(OPERATION_1))?;
+    storage.index_block(&block_hash_6, 6, &operations_json(OPERATION_2))?;
+    storage.index_block(&fork_hash_5, 5, &operations_json(OPERATION_1))?;
+
+    let operation_hash_1 = HashType::OperationHash.b58check_to_hash(OPERATION_1)?;
+    let operation_hash_2 = HashType::OperationHash.b58check_to_hash(OPERATION_2)?;
+
+    // receipts are resolved by the chain of the head
+    assert_eq!(
+        storage
+            .get(&operation_hash_1, &main_head)?
+            .map(|receipt| receipt.block_hash),
+        Some(block_hash_5.clone())
+    );
+    assert_eq!(
+        storage
+            .get(&operation_hash_1, &fork_head)?
+            .map(|receipt| receipt.block_hash),
+        Some(fork_hash_5.clone())
+    );
+    assert!(storage.get(&operation_hash_2, &fork_head)?.is_none());
+
+    let receipts = storage.get_by_account(SOURCE, None, 10, &main_head)?;
+    assert_eq!(
+        receipts
+            .iter()
+            .map(|(operation_hash, receipt)| (operation_hash.clone(), receipt.block_hash.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (operation_hash_1.clone(), block_hash_5),
+            (operation_hash_2, block_hash_6),
+        ]
+    );
+    let receipts = storage.get_by_account(SOURCE, None, 10, &fork_head)?;
+    assert_eq!(
+        receipts
+            .iter()
+            .map(|(operation_hash, receipt)| (operation_hash.clone(), receipt.block_hash.clone()))
+            .collect::<Vec<_>>(),
+        vec![(operation_hash_1, fork_hash_5)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_index_block_invalid_json() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__operation_receipts_invalid_json")?;
+    let storage = OperationReceiptsStorage::new(tmp_storage.storage());
+    let block_hash = HashType::BlockHash
+        .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+
+    assert!(storage.index_block(&block_hash, 1, "{}").is_err());
+    assert!(storage
+        .index_block(&block_hash, 1, &json!([[{ "contents": [] }]]).to_string())
+        .is_err());
+    assert_eq!(storage.index_block(&block_hash, 1, "[]")?, 0);
+
+    Ok(())
+}
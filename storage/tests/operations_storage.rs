@@ -4,9 +4,14 @@
 use failure::Error;
 
 use crypto::hash::HashType;
+use storage::persistent::KeyValueStoreWithSchema;
 use storage::tests_common::TmpStorage;
 use storage::*;
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
 use tezos_messages::p2p::encoding::prelude::*;
+use tezos_messages::Head;
+
+mod common;
 
 #[test]
 fn test_get_operations() -> Result<(), Error> {
@@ -98,3 +103,189 @@ fn test_get_operations() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_get_operation_location() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__op_storage_get_operation_location")?;
+
+    let block_hash_1 = HashType::BlockHash
+        .b58check_to_hash("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+    let block_hash_2 = HashType::BlockHash
+        .b58check_to_hash("BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ")?;
+    let genesis_hash = vec![0; 32];
+    common::store_blocks(
+        tmp_storage.storage(),
+        &[
+            (&genesis_hash, 0, &genesis_hash),
+            (&block_hash_1, 1, &genesis_hash),
+            (&block_hash_2, 2, &block_hash_1),
+        ],
+    )?;
+    let head = Head::new(block_hash_2.clone(), 2, vec![]);
+    let operation_1 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff2")?;
+    let operation_2 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff3")?;
+    let operation_3 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff4")?;
+
+    let storage = OperationsStorage::new(tmp_storage.storage());
+    storage.put_operations(&OperationsForBlocksMessage::new(
+        OperationsForBlock::new(block_hash_1.clone(), 3),
+        Path::Op,
+        vec![operation_1.clone(), operation_2.clone()],
+    ))?;
+    storage.put_operations(&OperationsForBlocksMessage::new(
+        OperationsForBlock::new(block_hash_2.clone(), 0),
+        Path::Op,
+        vec![operation_3.clone()],
+    ))?;
+
+    let expected = [
+        (&operation_1, &block_hash_1, 3, 0),
+        (&operation_2, &block_hash_1, 3, 1),
+        (&operation_3, &block_hash_2, 0, 0),
+    ];
+    for (operation, block_hash, validation_pass, operation_index) in &expected {
+        let location = storage
+            .get_operation_location(&operation.message_hash()?, &head)?
+            .expect("operation location not found");
+        assert_eq!(&location.block_hash, *block_hash);
+        assert_eq!(location.validation_pass, *validation_pass);
+        assert_eq!(location.operation_index, *operation_index);
+    }
+    assert!(storage
+        .get_operation_location(
+            &HashType::OperationHash
+                .b58check_to_hash("oneev3QMfQJd2fEM3zQPdFwwPY1aohkxffttrK8Qrswemnh2nML")?,
+            &head
+        )?
+        .is_none());
+
+    // operations of blocks above the head are not found
+    assert!(storage
+        .get_operation_location(
+            &operation_3.message_hash()?,
+            &Head::new(block_hash_1, 1, vec![])
+        )?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_get_operation_location_of_fork() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__op_storage_get_operation_location_of_fork")?;
+
+    // genesis <- block_1 <- block_2
+    //                    <- fork_2
+    let genesis_hash = vec![0; 32];
+    let block_hash_1 = vec![1; 32];
+    let block_hash_2 = vec![2; 32];
+    let fork_hash_2 = vec![12; 32];
+    common::store_blocks(
+        tmp_storage.storage(),
+        &[
+            (&genesis_hash, 0, &genesis_hash),
+            (&block_hash_1, 1, &genesis_hash),
+            (&block_hash_2, 2, &block_hash_1),
+            (&fork_hash_2, 2, &block_hash_1),
+        ],
+    )?;
+    let operation_1 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff2")?;
+    let operation_2 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff3")?;
+
+    // operation 1 is included by both branches on the different position, the fork is stored as the last one
+    let storage = OperationsStorage::new(tmp_storage.storage());
+    storage.put_operations(&OperationsForBlocksMessage::new(
+        OperationsForBlock::new(block_hash_2.clone(), 3),
+        Path::Op,
+        vec![operation_1.clone()],
+    ))?;
+    storage.put_operations(&OperationsForBlocksMessage::new(
+        OperationsForBlock::new(fork_hash_2.clone(), 3),
+        Path::Op,
+        vec![operation_2.clone(), operation_1.clone()],
+    ))?;
+
+    let location = storage
+        .get_operation_location(
+            &operation_1.message_hash()?,
+            &Head::new(block_hash_2.clone(), 2, vec![]),
+        )?
+        .expect("operation location not found");
+    assert_eq!(location.block_hash, block_hash_2);
+    assert_eq!(location.operation_index, 0);
+
+    let location = storage
+        .get_operation_location(
+            &operation_1.message_hash()?,
+            &Head::new(fork_hash_2.clone(), 2, vec![]),
+        )?
+        .expect("operation location not found");
+    assert_eq!(location.block_hash, fork_hash_2);
+    assert_eq!(location.operation_index, 1);
+
+    assert!(storage
+        .get_operation_location(
+            &operation_2.message_hash()?,
+            &Head::new(block_hash_2, 2, vec![])
+        )?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_reindex_by_hash() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__op_storage_reindex_by_hash")?;
+
+    let genesis_hash = vec![0; 32];
+    let block_hash_1 = vec![1; 32];
+    common::store_blocks(
+        tmp_storage.storage(),
+        &[
+            (&genesis_hash, 0, &genesis_hash),
+            (&block_hash_1, 1, &genesis_hash),
+        ],
+    )?;
+    let head = Head::new(block_hash_1.clone(), 1, vec![]);
+    let operation_1 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff2")?;
+    let operation_2 = operation("000008c387fa065a181d45d47a9b78ddc77e92a881779ff3")?;
+
+    // operations stored by older versions (without the index)
+    let message = OperationsForBlocksMessage::new(
+        OperationsForBlock::new(block_hash_1.clone(), 3),
+        Path::Op,
+        vec![operation_1.clone(), operation_2.clone()],
+    );
+    KeyValueStoreWithSchema::<OperationsStorage>::put(
+        tmp_storage.storage().kv().as_ref(),
+        &OperationKey::new(&block_hash_1, 3),
+        &message,
+    )?;
+
+    let storage = OperationsStorage::new(tmp_storage.storage());
+    for operation in &[&operation_1, &operation_2] {
+        assert!(storage
+            .get_operation_location(&operation.message_hash()?, &head)?
+            .is_none());
+    }
+
+    // backfill indexes all stored operations
+    assert_eq!(storage.reindex_by_hash()?, 2);
+    for (operation_index, operation) in [&operation_1, &operation_2].iter().enumerate() {
+        let location = storage
+            .get_operation_location(&operation.message_hash()?, &head)?
+            .expect("operation location not found");
+        assert_eq!(location.block_hash, block_hash_1);
+        assert_eq!(location.validation_pass, 3);
+        assert_eq!(location.operation_index, operation_index as u32);
+    }
+
+    Ok(())
+}
+
+fn operation(data: &str) -> Result<Operation, Error> {
+    Ok(Operation::from_bytes(hex::decode(format!(
+        "10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e{}",
+        data
+    ))?)?)
+}
@@ -12,7 +12,7 @@ use rand::{
 use serde::{Deserialize, Serialize};
 
 use storage::persistent::BincodeEncoded;
-use storage::skip_list::{DatabaseBackedSkipList, TypedSkipList};
+use storage::skip_list::{DatabaseBackedSkipList, SkipList, TypedSkipList};
 use storage::tests_common::TmpStorage;
 
 #[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
@@ -379,6 +379,42 @@ pub fn list_check_get_key() {
     assert_eq!(val.unwrap(), None);
 }
 
+#[test]
+pub fn list_pop() {
+    let tmp_storage = TmpStorage::create("__skip_list:list_pop").expect("Storage error");
+    let mut list = DatabaseBackedSkipList::new(
+        10,
+        tmp_storage.storage().kv(),
+        tmp_storage
+            .storage()
+            .seq()
+            .generator("__skip_list:list_pop"),
+    )
+    .expect("failed to create skip list");
+    // last node is an edge node, so it builds higher lane
+    for index in 0..=7 {
+        TypedSkipList::<i32, i32>::push(&mut list, &btreemap! { index => index })
+            .expect("failed to push value to skip list");
+    }
+    list.pop().expect("failed to pop value from skip list");
+    assert_eq!(list.len(), 7);
+    assert!(!list.contains(7));
+
+    // values of the removed node are not visible in the pushed node
+    TypedSkipList::<i32, i32>::push(&mut list, &btreemap! { 100 => 100 })
+        .expect("failed to push value to skip list");
+    assert_eq!(list.len(), 8);
+    let val: Option<i32> = list
+        .get_key(7, &7)
+        .expect("failed to get value from skip list");
+    assert!(val.is_none());
+    let val: Option<BTreeMap<i32, i32>> = list.get(7).expect("failed to get value from skip list");
+    assert_eq!(
+        val,
+        Some((0..=6).chain(100..=100).map(|i| (i, i)).collect())
+    );
+}
+
 #[test]
 pub fn skip_list_simulate_ledger() {
     let tmp_storage =
@@ -26,6 +26,7 @@ pub struct ConnectionMessage {
     versions: Vec<NetworkVersion>,
     #[get = "pub"]
     public_key: Vec<u8>,
+    #[get = "pub"]
     proof_of_work_stamp: Vec<u8>,
     message_nonce: Vec<u8>,
 }
@@ -3,6 +3,7 @@
 
 use std::fmt;
 
+use getset::Getters;
 use serde::{Deserialize, Serialize};
 
 use tezos_encoding::encoding::{Encoding, Field, HasEncoding};
@@ -11,10 +12,13 @@ use tezos_encoding::has_encoding;
 use crate::cached_data;
 use crate::p2p::binary_message::cache::BinaryDataCache;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Getters)]
 pub struct NetworkVersion {
+    #[get = "pub"]
     chain_name: String,
+    #[get = "pub"]
     distributed_db_version: u16,
+    #[get = "pub"]
     p2p_version: u16,
     #[serde(skip_serializing)]
     body: BinaryDataCache,
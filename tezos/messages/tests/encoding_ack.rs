@@ -60,3 +60,36 @@ fn can_deserialize_nack_with_list() -> Result<(), Error> {
         message,
     ))
 }
+
+#[test]
+fn can_serialize_nack_too_many_connections_with_ipv6() -> Result<(), Error> {
+    let message = AckMessage::Nack(NackInfo::new(
+        NackMotive::TooManyConnections,
+        &vec![
+            String::from("[2001:db8::1]:9732"),
+            String::from("127.0.0.1:9832"),
+        ],
+    ));
+    let serialized = hex::encode(message.as_bytes()?);
+    let expected = "01000100000028000000125b323030313a6462383a3a315d3a393733320000000e3132372e302e302e313a39383332";
+    Ok(assert_eq!(expected, &serialized))
+}
+
+#[test]
+fn can_deserialize_nack_too_many_connections_with_ipv6() -> Result<(), Error> {
+    let message_bytes = hex::decode("01000100000028000000125b323030313a6462383a3a315d3a393733320000000e3132372e302e302e313a39383332")?;
+    let message = AckMessage::from_bytes(message_bytes)?;
+    match message {
+        AckMessage::Nack(nack_info) => {
+            let addresses = nack_info
+                .potential_peers_to_connect()
+                .iter()
+                .map(|address| address.parse::<std::net::SocketAddr>())
+                .collect::<Result<Vec<_>, _>>()?;
+            assert!(addresses[0].is_ipv6());
+            assert!(addresses[1].is_ipv4());
+            Ok(())
+        }
+        _ => panic!("Expected NACK with motive, got: {:?}", message),
+    }
+}